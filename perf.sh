@@ -26,4 +26,7 @@ date; time cargo run --bin perf --features=perf -- --payload 100 --ops 10000 --t
 date; time cargo run --bin perf --features=perf -- --payload 100 --ops 10000 --threads 8 --size 100000000
 date; time cargo run --bin perf --features=perf -- --payload 100 --ops 10000 --threads 16 --size 100000000
 
+# 100-byte payload, 8 threads, half the ops buffered, followed by 50% read mix.
+date; time cargo run --bin perf --features=perf -- --payload 100 --ops 10000 --threads 8 --size 100000000 --sync-ratio 50 --mixed-ops 10000 --csv perf.csv
+
 date; valgrind --leak-check=full --show-leak-kinds=all --track-origins=yes $PERF --payload 100000 --ops 1000 --threads 1 --size 100000000 || exit $?
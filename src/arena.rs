@@ -0,0 +1,76 @@
+//! Module implement arena allocated replay, enabled with `arena` feature.
+//!
+//! Replaying millions of entries, each with its own `Vec<u8>` payload,
+//! fragments the heap when the entries are held for long. With arena
+//! replay, payloads are copied into a [bumpalo::Bump] supplied by the
+//! application, which can be reset between replays, while the decode
+//! buffers are released as soon as the entry is copied.
+
+use bumpalo::Bump;
+
+use std::{
+    fmt::{self, Display},
+    ops, result,
+};
+
+use crate::{wral::Wal, Result};
+
+/// Entry borrowed from an arena, refer to [Wal::replay_in].
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct EntryRef<'a> {
+    seqno: u64,
+    topic: &'a str,
+    op: &'a [u8],
+}
+
+impl<'a> Display for EntryRef<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> result::Result<(), fmt::Error> {
+        write!(f, "entry<seqno:{}>", self.seqno)
+    }
+}
+
+impl<'a> EntryRef<'a> {
+    #[inline]
+    pub fn to_seqno(&self) -> u64 {
+        self.seqno
+    }
+
+    /// Return the topic of this entry, empty string for default topic.
+    #[inline]
+    pub fn as_topic(&self) -> &'a str {
+        self.topic
+    }
+
+    /// Return the operation logged by this entry.
+    #[inline]
+    pub fn as_op(&self) -> &'a [u8] {
+        self.op
+    }
+}
+
+impl<S> Wal<S> {
+    /// Same as [Wal::range], but entries are allocated in `arena`. Entries
+    /// borrow from `arena`, hence the arena can be reset only after they
+    /// are dropped.
+    pub fn replay_in<'a, R>(
+        &self,
+        arena: &'a Bump,
+        range: R,
+    ) -> Result<impl Iterator<Item = Result<EntryRef<'a>>> + 'a>
+    where
+        R: ops::RangeBounds<u64>,
+    {
+        let iter = self.do_range(range, None)?.map(move |item| {
+            item.map(|entry| EntryRef {
+                seqno: entry.to_seqno(),
+                topic: arena.alloc_str(entry.as_topic()),
+                op: arena.alloc_slice_copy(entry.as_op()),
+            })
+        });
+        Ok(iter)
+    }
+}
+
+#[cfg(test)]
+#[path = "arena_test.rs"]
+mod arena_test;
@@ -0,0 +1,32 @@
+use super::*;
+use crate::{state, wral::Config};
+
+#[test]
+fn test_replay_in() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-replay-in", dir.path().as_ref());
+    config.set_journal_limit(1000).set_fsync(false);
+
+    let wal = Wal::create(config, state::NoState).unwrap();
+    for i in 0..100_u64 {
+        match i % 2 {
+            0 => wal.add_op(&i.to_be_bytes()).unwrap(),
+            _ => wal.add_op_to("odd", &i.to_be_bytes()).unwrap(),
+        };
+    }
+
+    let mut arena = Bump::new();
+    for _ in 0..2 {
+        let entries: Vec<EntryRef> =
+            wal.replay_in(&arena, 11..=20).unwrap().map(|e| e.unwrap()).collect();
+        assert_eq!(entries.len(), 10);
+        for entry in entries.iter() {
+            let i = entry.to_seqno() - 1;
+            assert_eq!(entry.as_op(), &i.to_be_bytes());
+            assert_eq!(entry.as_topic(), if i % 2 == 0 { "" } else { "odd" });
+        }
+        arena.reset();
+    }
+
+    wal.close(true).unwrap();
+}
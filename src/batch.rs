@@ -1,24 +1,120 @@
+#[cfg(any(test, feature = "testing"))]
 use arbitrary::{Arbitrary, Unstructured};
+use log::error;
 use mkit::{
     self,
-    cbor::{Cbor, FromCbor},
+    cbor::{Cbor, FromCbor, IntoCbor},
     Cborize,
 };
 
 use std::{
     cmp,
+    collections::BTreeMap,
+    convert::TryFrom,
+    ffi,
     fmt::{self, Display},
     fs,
     io::{self, Read, Seek},
     ops, result, vec,
 };
 
-use crate::{entry, state, util, Error, Result};
+use crate::{
+    blob, clock::Clock, compress, entry, middleware, spool, state, storage, tombstone,
+    util, Error, Result,
+};
+
+// Upper bound on encoded size of a batch, excluding state, entries and
+// middleware, including instance id stamped on the first batch of a
+// journal.
+const BATCH_OVERHEAD: usize = 106;
+// Upper bound on encoded size of an entry, excluding topic, tag and op.
+const ENTRY_OVERHEAD: usize = 41;
+// Upper bound on encoded size of a tagged entry in tag index, excluding
+// the tag.
+const TAG_OVERHEAD: usize = 24;
+// Flag bits for packed entries, refer to pack_entries.
+const FLAG_BLOB: u8 = 0x1;
+const FLAG_COMPRESSED: u8 = 0x2;
+const FLAG_TIMESTAMP: u8 = 0x4;
+// Capacity of pending entries is reviewed once every SHRINK_WINDOW
+// flushes, and shrunk if it is more than SHRINK_FACTOR times the largest
+// batch in that window, refer to Worker::shrink_entries.
+const SHRINK_WINDOW: usize = 16;
+const SHRINK_FACTOR: usize = 4;
+const SHRINK_MIN: usize = 64;
+
+/// Encoding of entries within a batch, refer to
+/// [Config::set_codec][crate::Config::set_codec].
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub enum Codec {
+    /// Entries are encoded as cbor items, one after the other.
+    #[default]
+    Cbor,
+    /// Entries are packed as a single byte-blob, seqnos are encoded as
+    /// varint deltas from the batch's first seqno, and topics and ops are
+    /// varint length-prefixed. Saves most of the per-entry overhead for
+    /// workloads with small ops.
+    Compact,
+}
 
 pub struct Worker<S> {
     index: Vec<Index>,
     entries: Vec<entry::Entry>,
+    // state as of the last flushed batch.
     state: S,
+    // state updated with entries that are yet to be flushed.
+    scratch: Option<S>,
+    // reusable buffer for encoding batches.
+    buf: Vec<u8>,
+    // metadata, as of the last batch carrying metadata.
+    metadata: Option<tombstone::Metadata>,
+    codec: Codec,
+    // persist tag index along with each batch.
+    tag_index: bool,
+    // secondary codec, to verify batches against, and the mismatches
+    // found since they were last taken.
+    shadow: Option<Codec>,
+    mismatches: Vec<Mismatch>,
+    // spill large ops into blob file.
+    spill: Option<blob::Spill>,
+    // compress ops larger than this many bytes.
+    compress: Option<usize>,
+    // instance id, stamped on the first batch of the journal.
+    instance: String,
+    // bytes of ops pending in `entries`, and the spool for entries
+    // pending beyond the buffer limit, entries in spool follow `entries`.
+    buffered: usize,
+    spool: Option<spool::Spool>,
+    // chain of middleware ids, to seal batch payload.
+    middleware: Vec<u32>,
+    // source of batch timestamps.
+    clock: Clock,
+    // skip fsync for batches carrying entries, refer to Journal::sync.
+    defer_sync: bool,
+    // serialized size of state, and encoded size, of the last batch
+    // flushed with entries.
+    state_size: Option<(usize, usize)>,
+    // seqno of the last entry added, and of the last entry flushed,
+    // entries are added in strictly increasing seqno order.
+    appended: Option<u64>,
+    flushed: Option<u64>,
+    // largest batch flushed, in number of entries, and flushes since the
+    // last review of capacity along with the largest batch among them.
+    high_water: usize,
+    window: (usize, usize),
+    // number of entries flushed and their rolling checksum, to be written
+    // as the journal's trailer, None if trailer is disabled.
+    checksum: Option<(u64, u32)>,
+}
+
+/// Batch whose entries, when encoded with the shadow codec, didn't decode
+/// back to identical entries. Refer to
+/// [Config::set_shadow_codec][crate::Config::set_shadow_codec].
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Mismatch {
+    pub first_seqno: u64,
+    pub last_seqno: u64,
+    pub reason: String,
 }
 
 impl<S> Worker<S> {
@@ -27,51 +123,580 @@ impl<S> Worker<S> {
             index: Vec::default(),
             entries: Vec::default(),
             state,
+            scratch: None,
+            buf: Vec::default(),
+            metadata: None,
+            codec: Codec::default(),
+            tag_index: false,
+            shadow: None,
+            mismatches: Vec::default(),
+            spill: None,
+            compress: None,
+            instance: String::default(),
+            buffered: 0,
+            spool: None,
+            middleware: Vec::default(),
+            clock: Clock::default(),
+            defer_sync: false,
+            state_size: None,
+            appended: None,
+            flushed: None,
+            high_water: 0,
+            window: (0, 0),
+            checksum: None,
+        }
+    }
+
+    pub fn set_codec(mut self, codec: Codec) -> Worker<S> {
+        self.codec = codec;
+        self
+    }
+
+    pub fn set_tag_index(mut self, tag_index: bool) -> Worker<S> {
+        self.tag_index = tag_index;
+        self
+    }
+
+    pub fn set_shadow(mut self, shadow: Option<Codec>) -> Worker<S> {
+        self.shadow = shadow;
+        self
+    }
+
+    pub fn set_spill(mut self, spill: Option<blob::Spill>) -> Worker<S> {
+        self.spill = spill;
+        self
+    }
+
+    pub fn set_compress(mut self, threshold: Option<usize>) -> Worker<S> {
+        self.compress = threshold;
+        self
+    }
+
+    pub fn set_middleware(mut self, middleware: Vec<u32>) -> Worker<S> {
+        self.middleware = middleware;
+        self
+    }
+
+    pub fn set_spool(mut self, spool: Option<spool::Spool>) -> Worker<S> {
+        self.spool = spool;
+        self
+    }
+
+    pub fn set_clock(mut self, clock: Clock) -> Worker<S> {
+        self.clock = clock;
+        self
+    }
+
+    pub fn set_defer_sync(mut self, defer_sync: bool) -> Worker<S> {
+        self.defer_sync = defer_sync;
+        self
+    }
+
+    pub fn set_trailer(mut self, trailer: bool) -> Worker<S> {
+        self.checksum = if trailer { Some((0, 0)) } else { None };
+        self
+    }
+
+    pub fn set_instance(&mut self, instance: &str) {
+        self.instance = instance.to_string();
+    }
+
+    /// Blob file, if any, is moved along with the journal to
+    /// `journal_path`.
+    pub fn relocate_spill(&mut self, journal_path: &ffi::OsStr) -> Result<()> {
+        if let Some(spill) = &mut self.spill {
+            spill.relocate(journal_path)
+        }
+        match &mut self.spool {
+            Some(spool) => spool.relocate(journal_path),
+            None => Ok(()),
         }
     }
 
+    /// Take mismatches found by shadow verification, since the last call.
+    pub fn take_mismatches(&mut self) -> Vec<Mismatch> {
+        self.mismatches.drain(..).collect()
+    }
+
+    /// Add entry that is already accepted by state, state is updated but
+    /// the action returned by state is ignored.
     pub fn add_entry(&mut self, entry: entry::Entry) -> Result<()>
     where
         S: state::State,
     {
-        self.state.on_add_entry(&entry)?;
-        self.entries.push(entry);
+        self.check_order(std::slice::from_ref(&entry))?;
+        let state = match &mut self.scratch {
+            Some(state) => state,
+            scratch @ None => scratch.get_or_insert(self.state.clone()),
+        };
+        state.on_entry(&entry)?;
+        self.push_entry(entry)
+    }
+
+    /// Add new entries, as per the action returned by state for each
+    /// entry. Entries are added all or none, if any entry is rejected
+    /// return [Error::Rejected] and leave the state as is.
+    pub fn add_entries(&mut self, entries: Vec<entry::Entry>) -> Result<()>
+    where
+        S: state::State,
+    {
+        self.check_order(&entries)?;
+        let state = match &mut self.scratch {
+            Some(state) => state,
+            scratch @ None => scratch.get_or_insert(self.state.clone()),
+        };
+        // rejected entries shall not update state, snapshot is required
+        // only to undo entries accepted ahead of a rejected one.
+        let snapshot = match entries.len() {
+            1 => None,
+            _ => Some(state.clone()),
+        };
+
+        let (n, buffered, appended) = (self.entries.len(), self.buffered, self.appended);
+        let mark = self.spool.as_ref().map(spool::Spool::to_mark);
+        let mut accepted = Vec::with_capacity(entries.len());
+        for mut entry in entries.into_iter() {
+            match state.on_entry(&entry)? {
+                state::Action::Accept => (),
+                state::Action::Transform(op) => entry.set_op(op),
+                state::Action::Reject(reason) => {
+                    if let Some(snapshot) = snapshot {
+                        *state = snapshot;
+                    }
+                    return err_at!(Rejected, msg: "seqno {} {}", entry.to_seqno(), reason);
+                }
+            }
+            accepted.push(entry);
+        }
+        for entry in accepted.into_iter() {
+            if let Err(err) = self.push_entry(entry) {
+                self.entries.truncate(n);
+                self.buffered = buffered;
+                self.appended = appended;
+                if let (Some(spool), Some(mark)) = (&mut self.spool, mark) {
+                    spool.rewind(mark).ok();
+                }
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+
+    // Pending entries are held in memory upto the buffer limit, and then
+    // spooled to file.
+    // Seqno handed out twice, say by a truncate and rewrite race, would
+    // shadow one entry with the other, fail such entries as Invalid.
+    fn check_order(&self, entries: &[entry::Entry]) -> Result<()> {
+        let mut last = self.appended;
+        for seqno in entries.iter().map(entry::Entry::to_seqno) {
+            match last {
+                Some(last) if seqno <= last => {
+                    err_at!(Invalid, msg: "seqno {} <= last seqno {}", seqno, last)?
+                }
+                _ => last = Some(seqno),
+            }
+        }
+        Ok(())
+    }
+
+    fn push_entry(&mut self, entry: entry::Entry) -> Result<()> {
+        let (seqno, size) = (entry.to_seqno(), entry.as_op().len());
+        match &mut self.spool {
+            Some(spool) if spool.is_spooled(self.buffered, size) => spool.push(entry)?,
+            _ => {
+                self.buffered += size;
+                self.entries.push(entry);
+            }
+        }
+        self.appended = Some(seqno);
+        Ok(())
+    }
+
+    // Stitch spooled entries back, after entries held in memory.
+    fn unspool(&mut self) -> Result<()> {
+        match &mut self.spool {
+            Some(spool) if !spool.is_empty() => self.entries.extend(spool.drain()?),
+            _ => (),
+        }
+        Ok(())
+    }
+
+    /// Discard pending entries and their state updates, like a failed
+    /// flush, refer to [Worker::flush].
+    pub fn discard(&mut self) -> Result<()> {
+        self.unspool()?;
+        self.entries.clear();
+        self.buffered = 0;
+        self.scratch = None;
+        self.appended = self.flushed;
         Ok(())
     }
 
-    pub fn flush(&mut self, file: &mut fs::File) -> Result<Option<Index>>
+    /// Flush pending entries as a single batch. State updates for pending
+    /// entries are committed only after the batch is written and synced. On
+    /// failure, pending entries and their state updates are discarded, and
+    /// partially written batch, if any, is truncated from the file.
+    pub fn flush<F>(&mut self, file: &mut F) -> Result<Option<Index>>
     where
         S: state::State,
+        F: storage::Storage + ?Sized,
     {
-        let fpos = err_at!(IOError, file.metadata())?.len();
-        let batch = match self.entries.len() {
-            0 => return Ok(None),
-            _ => Batch {
-                first_seqno: self.entries.first().map(entry::Entry::to_seqno).unwrap(),
-                last_seqno: self.entries.last().map(entry::Entry::to_seqno).unwrap(),
-                state: util::encode_cbor(self.state.clone())?,
-                entries: self.entries.drain(..).collect(),
+        self.unspool()?;
+        if self.entries.is_empty() {
+            return Ok(None);
+        }
+
+        let fpos = file.to_size()?;
+        let first_seqno = self.entries.first().map(entry::Entry::to_seqno).unwrap();
+        let last_seqno = self.entries.last().map(entry::Entry::to_seqno).unwrap();
+        let state = self.scratch.take().unwrap_or_else(|| self.state.clone());
+        let mut entries: Vec<entry::Entry> = self.entries.drain(..).collect();
+        self.buffered = 0;
+        self.shrink_entries(entries.len());
+        // entries failing to flush are discarded, and their seqnos reused.
+        self.appended = self.flushed;
+        // checksum is computed over entries as added, before they are
+        // compressed or spilled.
+        let checksum = match self.checksum {
+            Some((n, crc)) => Some(checksum_entries(n, crc, &entries)?),
+            None => None,
+        };
+        if let Some(threshold) = self.compress {
+            compress::compress_entries(threshold, &mut entries);
+        }
+        // ops are spilled and synced ahead of the batch referring to them.
+        let blob_size = match &mut self.spill {
+            Some(spill) => spill.spill(&mut entries)?,
+            None => None,
+        };
+        if let Some(shadow) = self.shadow {
+            if let Err(err) = verify_shadow(shadow, first_seqno, &entries) {
+                let reason = err.to_string();
+                error!(
+                    target: "wral",
+                    "shadow {:?} mismatch for {}..={} {}", shadow, first_seqno, last_seqno, reason
+                );
+                self.mismatches.push(Mismatch { first_seqno, last_seqno, reason });
+            }
+        }
+        let topics = Topic::from_entries(&entries);
+        // offsets locate entries only when they are neither packed nor sealed.
+        let tags = match self.codec {
+            Codec::Cbor if self.tag_index && self.middleware.is_empty() => {
+                Tag::from_entries(&entries, true)?
+            }
+            _ => Tag::from_entries(&entries, false)?,
+        };
+        let (packed, entries) = match self.codec {
+            Codec::Cbor => (Vec::default(), entries),
+            Codec::Compact => (pack_entries(first_seqno, &entries), Vec::default()),
+        };
+        let (sealed, packed, entries) = match self.seal(packed, entries) {
+            Ok(item) => item,
+            Err(err) => {
+                if let (Some(spill), Some(size)) = (&mut self.spill, blob_size) {
+                    spill.truncate(size)
+                }
+                return Err(err);
+            }
+        };
+
+        let timestamp = self.clock.to_nanos();
+        let state_bytes = util::encode_cbor(state.clone())?;
+        let state_size = state_bytes.len();
+        let batch = Batch {
+            first_seqno,
+            last_seqno,
+            state: state_bytes,
+            timestamp,
+            instance: self.to_batch_instance(),
+            tombstones: Vec::default(),
+            masks: Vec::default(),
+            tags: match self.tag_index {
+                true => tags.clone(),
+                false => Vec::default(),
             },
+            trailer: Vec::default(),
+            middleware: match sealed.is_empty() {
+                true => Vec::default(),
+                false => self.middleware.clone(),
+            },
+            sealed,
+            packed,
+            entries,
         };
+        let sync = !self.defer_sync;
+        let length = match Self::write_batch(file, &mut self.buf, batch, sync) {
+            Ok(length) => length,
+            Err(err) => {
+                file.truncate(fpos).ok();
+                if let (Some(spill), Some(size)) = (&mut self.spill, blob_size) {
+                    spill.truncate(size)
+                }
+                return Err(err);
+            }
+        };
+        self.state = state;
+        self.state_size = Some((state_size, length));
+        self.flushed = Some(last_seqno);
+        self.appended = self.flushed;
+        self.checksum = checksum;
+
+        let index = Index::new(fpos, length, first_seqno, last_seqno)
+            .set_topics(topics)
+            .set_tags(tags)
+            .set_timestamp(timestamp);
+        self.index.push(index.clone());
 
-        let first_seqno = batch.first_seqno;
-        let last_seqno = batch.last_seqno;
-        let length = {
-            let data = util::encode_cbor(batch)?;
-            util::sync_write(file, &data)?;
-            data.len()
+        Ok(Some(index))
+    }
+
+    /// Flush pending entries as a single batch, if the file size stays
+    /// within `limit`, else flush as many entries as would fit. At least
+    /// one entry is flushed into an empty file. Return false if entries
+    /// are left pending, callers are expected to rotate the file and
+    /// flush them there.
+    pub fn flush_upto<F>(&mut self, file: &mut F, limit: usize) -> Result<bool>
+    where
+        S: state::State,
+        F: storage::Storage + ?Sized,
+    {
+        self.unspool()?;
+        if self.entries.is_empty() {
+            return Ok(true);
+        }
+
+        let fpos = err_at!(FailConvert, usize::try_from(file.to_size()?))?;
+        // size of the state is same for every split, approximately.
+        let base = {
+            let state = self.scratch.as_ref().unwrap_or(&self.state);
+            fpos + BATCH_OVERHEAD + util::encode_cbor(state.clone())?.len()
+        };
+        // cheap upper bound, payload bytes encode to atmost 2 bytes each.
+        let size = self.entries.iter().fold(base, |acc, e| {
+            let n = e.as_topic().len() + e.as_tag().len() + (self.to_op_size(e) * 2);
+            acc + ENTRY_OVERHEAD + n + self.to_tag_overhead(e)
+        });
+        let n = match size {
+            size if size <= limit => self.entries.len(),
+            _ => {
+                let (mut size, mut n) = (base, 0);
+                for entry in self.entries.iter() {
+                    size += match self.to_op_size(entry) {
+                        n if n == entry.as_op().len() => {
+                            util::encode_cbor(entry.clone())?.len()
+                        }
+                        n => {
+                            ENTRY_OVERHEAD
+                                + entry.as_topic().len()
+                                + entry.as_tag().len()
+                                + n
+                        }
+                    };
+                    size += self.to_tag_overhead(entry);
+                    if size > limit {
+                        break;
+                    }
+                    n += 1;
+                }
+                n
+            }
         };
 
-        let index = Index::new(fpos, length, first_seqno, last_seqno);
+        match n {
+            n if n == self.entries.len() => {
+                self.flush(file)?;
+                Ok(true)
+            }
+            0 if fpos > 0 => Ok(false),
+            n => {
+                let rest = self.entries.split_off(cmp::max(n, 1));
+                self.buffered = self.entries.iter().map(|e| e.as_op().len()).sum();
+                let mut state = self.state.clone();
+                for entry in self.entries.iter() {
+                    state.on_entry(entry)?;
+                }
+                self.scratch = Some(state);
+                self.flush(file)?;
+                for entry in rest.into_iter() {
+                    self.add_entry(entry)?;
+                }
+                Ok(false)
+            }
+        }
+    }
+
+    /// Write metadata as a batch without entries, pending entries must be
+    /// flushed before calling this. `seqno` is the last seqno handed out
+    /// so far, so that the batch's index does not break the seqno order.
+    pub fn flush_metadata<F>(
+        &mut self,
+        file: &mut F,
+        metadata: tombstone::Metadata,
+        seqno: u64,
+    ) -> Result<Index>
+    where
+        S: state::State,
+        F: storage::Storage + ?Sized,
+    {
+        let spooled = self.spool.as_ref().map(spool::Spool::len).unwrap_or(0);
+        if !self.entries.is_empty() || spooled > 0 {
+            err_at!(Fatal, msg: "{} entries pending", self.entries.len() + spooled)?
+        }
+
+        let fpos = file.to_size()?;
+        let timestamp = self.clock.to_nanos();
+        let batch = Batch {
+            first_seqno: seqno,
+            last_seqno: seqno,
+            state: util::encode_cbor(self.state.clone())?,
+            timestamp,
+            instance: self.to_batch_instance(),
+            tombstones: metadata.tombstones.clone(),
+            masks: metadata.masks.clone(),
+            tags: Vec::default(),
+            trailer: Vec::default(),
+            middleware: Vec::default(),
+            sealed: Vec::default(),
+            packed: Vec::default(),
+            entries: Vec::default(),
+        };
+        let length = match Self::write_batch(file, &mut self.buf, batch, true) {
+            Ok(length) => length,
+            Err(err) => {
+                file.truncate(fpos).ok();
+                return Err(err);
+            }
+        };
+        self.metadata = Some(metadata);
+
+        let index = Index::new(fpos, length, seqno, seqno).set_timestamp(timestamp);
+        self.index.push(index.clone());
+
+        Ok(index)
+    }
+
+    /// Write the number of entries flushed so far and their rolling
+    /// checksum, as a batch without entries, that shall be the last batch
+    /// in the journal. Entries pending, if any, are not covered. Skipped if
+    /// trailer is disabled, or if no entries were flushed.
+    pub fn flush_trailer<F>(&mut self, file: &mut F) -> Result<Option<Index>>
+    where
+        S: state::State,
+        F: storage::Storage + ?Sized,
+    {
+        let (n_entries, checksum, seqno) = match (self.checksum, self.flushed) {
+            (Some((n, crc)), Some(seqno)) if n > 0 => (n, crc, seqno),
+            _ => return Ok(None),
+        };
+
+        let fpos = file.to_size()?;
+        let timestamp = self.clock.to_nanos();
+        let batch = Batch {
+            first_seqno: seqno,
+            last_seqno: seqno,
+            state: util::encode_cbor(self.state.clone())?,
+            timestamp,
+            instance: self.to_batch_instance(),
+            tombstones: Vec::default(),
+            masks: Vec::default(),
+            tags: Vec::default(),
+            trailer: vec![Trailer { n_entries, checksum: u64::from(checksum) }],
+            middleware: Vec::default(),
+            sealed: Vec::default(),
+            packed: Vec::default(),
+            entries: Vec::default(),
+        };
+        let length = match Self::write_batch(file, &mut self.buf, batch, true) {
+            Ok(length) => length,
+            Err(err) => {
+                file.truncate(fpos).ok();
+                return Err(err);
+            }
+        };
+        self.checksum = None;
+
+        let index = Index::new(fpos, length, seqno, seqno).set_timestamp(timestamp);
         self.index.push(index.clone());
 
         Ok(Some(index))
     }
+
+    // Seal packed and cbor entries, as the payload of the batch, through
+    // the middleware chain. Return sealed payload, packed and entries, as
+    // they shall be written in the batch.
+    fn seal(
+        &self,
+        packed: Vec<u8>,
+        entries: Vec<entry::Entry>,
+    ) -> Result<(Vec<u8>, Vec<u8>, Vec<entry::Entry>)> {
+        seal_payload(&self.middleware, packed, entries)
+    }
+
+    // Instance id is carried only by the first batch of the journal.
+    fn to_batch_instance(&self) -> String {
+        match self.index.is_empty() {
+            true => self.instance.clone(),
+            false => String::default(),
+        }
+    }
+
+    // Upper bound on size of op as written in the batch, spilled ops are
+    // replaced by a reference. Ops are compressed before spilling, and are
+    // not spilled if they shrink within the spill threshold.
+    fn to_op_size(&self, entry: &entry::Entry) -> usize {
+        match &self.spill {
+            Some(spill) if spill.is_spilled(entry) && self.compress.is_some() => {
+                cmp::max(spill.to_threshold(), blob::REF_SIZE)
+            }
+            Some(spill) if spill.is_spilled(entry) => blob::REF_SIZE,
+            _ => entry.as_op().len(),
+        }
+    }
+
+    // Upper bound on bytes added to tag index for `entry`.
+    fn to_tag_overhead(&self, entry: &entry::Entry) -> usize {
+        match entry.as_tag() {
+            tag if self.tag_index && !tag.is_empty() => TAG_OVERHEAD + tag.len(),
+            _ => 0,
+        }
+    }
+
+    fn write_batch<F>(
+        file: &mut F,
+        buf: &mut Vec<u8>,
+        batch: Batch,
+        sync: bool,
+    ) -> Result<usize>
+    where
+        F: storage::Storage + ?Sized,
+    {
+        buf.clear();
+        let n = util::encode_cbor_into(batch, buf)?;
+        file.append(buf)?;
+        if sync {
+            file.sync()?;
+        }
+        Ok(n)
+    }
 }
 
 impl<S> Worker<S> {
+    pub fn to_first_seqno(&self) -> Option<u64> {
+        match self.index.first() {
+            Some(index) => Some(index.first_seqno),
+            None => {
+                self.entries.first().map(entry::Entry::to_seqno).or_else(|| {
+                    self.spool.as_ref().and_then(spool::Spool::to_first_seqno)
+                })
+            }
+        }
+    }
+
     pub fn to_last_seqno(&self) -> Option<u64> {
+        let spooled = self.spool.as_ref().and_then(spool::Spool::to_last_seqno);
         match self.entries.len() {
+            _ if spooled.is_some() => spooled,
             0 => self.index.last().map(|index| index.last_seqno),
             _ => self.entries.last().map(entry::Entry::to_seqno),
         }
@@ -81,8 +706,17 @@ impl<S> Worker<S> {
         self.index.clone()
     }
 
-    pub fn to_entries(&self) -> Vec<entry::Entry> {
-        self.entries.clone()
+    pub fn as_index(&self) -> &[Index] {
+        &self.index
+    }
+
+    /// Return pending entries, including the spooled ones.
+    pub fn to_entries(&self) -> Result<Vec<entry::Entry>> {
+        let mut entries = self.entries.clone();
+        if let Some(spool) = &self.spool {
+            entries.extend(spool.read()?);
+        }
+        Ok(entries)
     }
 
     pub fn len_batches(&self) -> usize {
@@ -96,8 +730,44 @@ impl<S> Worker<S> {
         self.state.clone()
     }
 
-    pub fn unwrap(self) -> (Vec<Index>, Vec<entry::Entry>, S) {
-        (self.index, self.entries, self.state)
+    pub fn to_metadata(&self) -> Option<tombstone::Metadata> {
+        self.metadata.clone()
+    }
+
+    /// Return the serialized size of state, and the encoded size of the
+    /// batch, for the last batch flushed with entries, if any.
+    pub fn to_state_size(&self) -> Option<(usize, usize)> {
+        self.state_size
+    }
+
+    /// Return the largest batch flushed, in number of entries, and the
+    /// capacity currently held for pending entries.
+    pub fn to_high_water(&self) -> (usize, usize) {
+        (self.high_water, self.entries.capacity())
+    }
+
+    // Account a batch of `n` entries. Capacity of pending entries grows
+    // to the largest batch, and is shrunk back, to twice the largest batch
+    // in the last window, once batches stay well below it.
+    fn shrink_entries(&mut self, n: usize) {
+        self.high_water = cmp::max(self.high_water, n);
+        let (flushes, largest) = self.window;
+        let (flushes, largest) = (flushes + 1, cmp::max(largest, n));
+        if flushes < SHRINK_WINDOW {
+            self.window = (flushes, largest);
+            return;
+        }
+        self.window = (0, 0);
+
+        let capacity = self.entries.capacity();
+        if capacity > SHRINK_MIN && capacity > largest.saturating_mul(SHRINK_FACTOR) {
+            self.entries.shrink_to(cmp::max(largest.saturating_mul(2), SHRINK_MIN));
+        }
+    }
+
+    pub fn unwrap(mut self) -> Result<(Vec<Index>, Vec<entry::Entry>, S)> {
+        self.unspool()?;
+        Ok((self.index, self.entries, self.state))
     }
 }
 
@@ -110,10 +780,33 @@ pub struct Batch {
     last_seqno: u64,
     // state as serialized bytes, shall be in cbor format.
     state: Vec<u8>,
-    // list of entries in this batch.
+    // time the batch was written, in nanoseconds since UNIX_EPOCH.
+    timestamp: u64,
+    // instance id of the Wal, carried by the first batch of a journal,
+    // empty otherwise.
+    instance: String,
+    // metadata, purge history and masked seqno ranges, is carried only by
+    // batches without entries, and the latest one supersedes the others.
+    tombstones: Vec<tombstone::Tombstone>,
+    masks: Vec<tombstone::Mask>,
+    // tag index for tagged entries, if enabled, refer to
+    // Config::set_tag_index.
+    tags: Vec<Tag>,
+    // entry count and checksum over the journal's entries, carried only by
+    // the last batch of a rotated journal, refer to Config::set_journal_trailer.
+    trailer: Vec<Trailer>,
+    // middleware chain the payload is sealed with, and the sealed payload,
+    // packed and entries fields encoded one after the other. Empty if the
+    // batch is not sealed, refer to Config::set_middleware.
+    middleware: Vec<u32>,
+    sealed: Vec<u8>,
+    // entries packed with Codec::Compact, empty otherwise.
+    packed: Vec<u8>,
+    // list of entries in this batch, shall be the last field.
     entries: Vec<entry::Entry>,
 }
 
+#[cfg(any(test, feature = "testing"))]
 impl arbitrary::Arbitrary for Batch {
     fn arbitrary(u: &mut Unstructured) -> arbitrary::Result<Self> {
         let mut entries: Vec<entry::Entry> = u.arbitrary()?;
@@ -127,6 +820,15 @@ impl arbitrary::Arbitrary for Batch {
             first_seqno,
             last_seqno,
             state: u.arbitrary()?,
+            timestamp: u.arbitrary()?,
+            instance: u.arbitrary()?,
+            tombstones: Vec::default(),
+            masks: Vec::default(),
+            tags: Vec::default(),
+            trailer: Vec::default(),
+            middleware: Vec::default(),
+            sealed: Vec::default(),
+            packed: Vec::default(),
             entries,
         };
         Ok(batch)
@@ -152,14 +854,37 @@ impl Ord for Batch {
 }
 
 impl Batch {
-    const ID: u32 = 0x0;
+    const ID: u32 = 0x1;
 
+    /// Same as `from_cbor`, but batches encoded in the layout prior to
+    /// batch ID 0x1 are also decoded, with the new fields left empty.
+    pub fn decode(value: Cbor) -> Result<Batch> {
+        let items = Vec::<Cbor>::from_cbor(value)?;
+        let id_v0: Cbor =
+            mkit::cbor::Tag::from_identifier(BatchV0::ID.into_cbor()?).into();
+        let v0 = items.first() == Some(&id_v0);
+        let value = items.into_cbor()?;
+        match v0 {
+            true => Ok(BatchV0::from_cbor(value)?.into()),
+            false => Ok(Batch::from_cbor(value)?),
+        }
+    }
+
+    #[allow(dead_code)]
     pub fn from_index(index: Index, file: &mut fs::File) -> Result<Batch> {
+        let len = err_at!(IOError, file.metadata())?.len();
+        let length = err_at!(FailConvert, u64::try_from(index.length))?;
+        if length > len.saturating_sub(index.fpos) {
+            err_at!(
+                Corrupted,
+                msg: "batch at {} length {} beyond file size {}", index.fpos, length, len
+            )?
+        }
         err_at!(IOError, file.seek(io::SeekFrom::Start(index.fpos)))?;
         let mut buf = vec![0; index.length];
         err_at!(IOError, file.read_exact(&mut buf))?;
         let (value, _) = Cbor::decode(&mut buf.as_slice())?;
-        Ok(Batch::from_cbor(value)?)
+        Batch::decode(value)
     }
 
     #[inline]
@@ -167,6 +892,63 @@ impl Batch {
         self.state.to_vec()
     }
 
+    /// Return the time the batch was written, in nanoseconds since
+    /// UNIX_EPOCH.
+    #[inline]
+    pub fn to_timestamp(&self) -> u64 {
+        self.timestamp
+    }
+
+    /// Return the instance id carried by this batch, empty string if none.
+    pub fn as_instance(&self) -> &str {
+        &self.instance
+    }
+
+    /// Return metadata carried by this batch, if any.
+    pub fn to_metadata(&self) -> Option<tombstone::Metadata> {
+        match self.entries.is_empty() && self.packed.is_empty() && self.trailer.is_empty()
+        {
+            true => Some(tombstone::Metadata {
+                tombstones: self.tombstones.clone(),
+                masks: self.masks.clone(),
+            }),
+            false => None,
+        }
+    }
+
+    /// Return the number of entries and their rolling checksum, if this
+    /// batch is a journal trailer, refer to [checksum_entries].
+    pub fn to_trailer(&self) -> Option<(u64, u32)> {
+        let trailer = self.trailer.first()?;
+        Some((trailer.n_entries, u32::try_from(trailer.checksum).ok()?))
+    }
+
+    /// Return tag index persisted with this batch, empty if the batch was
+    /// written without tag index.
+    pub fn to_tags(&self) -> Vec<Tag> {
+        self.tags.clone()
+    }
+
+    /// Return entries in this batch, unpacking them if required.
+    pub fn into_entries(self) -> Result<Vec<entry::Entry>> {
+        let batch = self.unseal()?;
+        match batch.packed.is_empty() {
+            true => Ok(batch.entries),
+            false => Unpack::new(batch.first_seqno, batch.packed).collect(),
+        }
+    }
+
+    /// Return the number of entries in this batch, packed and sealed
+    /// entries are decoded to validate them.
+    pub fn len_entries(&self) -> Result<usize> {
+        match self.packed.is_empty() {
+            _ if !self.middleware.is_empty() => Ok(self.clone().into_entries()?.len()),
+            true => Ok(self.entries.len()),
+            false => Unpack::new(self.first_seqno, self.packed.clone())
+                .try_fold(0, |n, entry| entry.map(|_| n + 1)),
+        }
+    }
+
     #[inline]
     pub fn to_first_seqno(&self) -> u64 {
         self.first_seqno
@@ -177,11 +959,75 @@ impl Batch {
         self.last_seqno
     }
 
+    /// Re-encode entries in this batch with `codec`, compressing ops
+    /// larger than `compress` bytes, if not None, and decompressing them
+    /// otherwise. Seqnos, state, timestamp, instance id and middleware chain
+    /// are retained, metadata batches, trailers and spilled ops are left
+    /// as is.
+    pub fn recode(self, codec: Codec, compress: Option<usize>) -> Result<Batch> {
+        // metadata batches and trailers are never sealed.
+        let bare = self.to_metadata().is_some() || !self.trailer.is_empty();
+        if self.middleware.is_empty() && bare {
+            return Ok(self);
+        }
+        let middleware = self.middleware.clone();
+        let tag_index = !self.tags.is_empty();
+        let mut batch = self.unseal()?;
+        let packed = std::mem::take(&mut batch.packed);
+        let mut entries = match packed.is_empty() {
+            true => std::mem::take(&mut batch.entries),
+            false => Unpack::new(batch.first_seqno, packed).collect::<Result<_>>()?,
+        };
+
+        entries = entries
+            .into_iter()
+            .map(|e| if e.is_blob() { Ok(e) } else { compress::decompress_entry(e) })
+            .collect::<Result<Vec<entry::Entry>>>()?;
+        if let Some(threshold) = compress {
+            compress::compress_entries(threshold, &mut entries);
+        }
+        batch.tags = match codec {
+            _ if !tag_index => Vec::default(),
+            Codec::Cbor if middleware.is_empty() => Tag::from_entries(&entries, true)?,
+            _ => Tag::from_entries(&entries, false)?,
+        };
+        let (packed, entries) = match codec {
+            Codec::Cbor => (Vec::default(), entries),
+            Codec::Compact => (pack_entries(batch.first_seqno, &entries), Vec::default()),
+        };
+        let (sealed, packed, entries) = seal_payload(&middleware, packed, entries)?;
+        batch.middleware = match sealed.is_empty() {
+            true => Vec::default(),
+            false => middleware,
+        };
+        batch.sealed = sealed;
+        batch.packed = packed;
+        batch.entries = entries;
+
+        Ok(batch)
+    }
+
+    // Replace sealed payload with packed and entries fields.
+    fn unseal(mut self) -> Result<Batch> {
+        if self.middleware.is_empty() {
+            return Ok(self);
+        }
+        let sealed = std::mem::take(&mut self.sealed);
+        let data = middleware::unseal(&self.middleware, sealed)?;
+        let mut reader = data.as_slice();
+        self.packed = Cbor::decode(&mut reader)?.0.into_bytes()?;
+        self.entries = Vec::<entry::Entry>::from_cbor(Cbor::decode(&mut reader)?.0)?;
+        self.middleware = Vec::default();
+        Ok(self)
+    }
+
+    #[allow(dead_code)]
     pub fn into_iter(
         self,
         range: ops::RangeInclusive<u64>,
     ) -> vec::IntoIter<entry::Entry> {
-        self.entries
+        self.into_entries()
+            .unwrap_or_default()
             .into_iter()
             .filter(|e| range.contains(&e.to_seqno()))
             .collect::<Vec<entry::Entry>>()
@@ -189,8 +1035,375 @@ impl Batch {
     }
 }
 
-/// Index of batches on disk.
-#[derive(Debug, Clone, Eq, PartialEq, Arbitrary)]
+/// Batch layout prior to batch ID 0x1, that is, before batches carried
+/// timestamp, instance id, metadata, tag index, trailer, middleware and
+/// packed entries. Such batches decode with those fields left empty.
+#[derive(Debug, Clone, Eq, PartialEq, Cborize)]
+pub(crate) struct BatchV0 {
+    pub(crate) first_seqno: u64,
+    pub(crate) last_seqno: u64,
+    pub(crate) state: Vec<u8>,
+    pub(crate) entries: Vec<entry::EntryV0>,
+}
+
+impl BatchV0 {
+    // number of cbor items, including the ID, in an encoded BatchV0.
+    const N_FIELDS: u64 = 5;
+    const ID: u32 = 0x0;
+}
+
+impl From<BatchV0> for Batch {
+    fn from(batch: BatchV0) -> Batch {
+        Batch {
+            first_seqno: batch.first_seqno,
+            last_seqno: batch.last_seqno,
+            state: batch.state,
+            timestamp: 0,
+            instance: String::default(),
+            tombstones: Vec::default(),
+            masks: Vec::default(),
+            tags: Vec::default(),
+            trailer: Vec::default(),
+            middleware: Vec::default(),
+            sealed: Vec::default(),
+            packed: Vec::default(),
+            entries: batch.entries.into_iter().map(entry::Entry::from).collect(),
+        }
+    }
+}
+
+/// Trailer of a rotated journal, carrying the number of entries in the
+/// journal and their rolling checksum, refer to [checksum_entries].
+#[derive(Debug, Clone, Default, Eq, PartialEq, Cborize)]
+pub struct Trailer {
+    n_entries: u64,
+    // CRC-32, widened to u64.
+    checksum: u64,
+}
+
+impl Trailer {
+    const ID: u32 = 0x0;
+}
+
+/// Extend the rolling checksum `crc`, over `n` entries seen so far, with
+/// `entries`. Checksum covers seqno and op of each entry, compressed ops
+/// are checksummed in their original form, spilled ops must be rehydrated
+/// by the caller. Return the updated count and checksum.
+pub fn checksum_entries(
+    mut n: u64,
+    mut crc: u32,
+    entries: &[entry::Entry],
+) -> Result<(u64, u32)> {
+    for entry in entries.iter() {
+        crc = util::crc32_update(crc, &entry.to_seqno().to_be_bytes());
+        crc = match entry.is_compressed() {
+            true => util::crc32_update(crc, &compress::decompress(entry.as_op())?),
+            false => util::crc32_update(crc, entry.as_op()),
+        };
+        n += 1;
+    }
+    Ok((n, crc))
+}
+
+// Seal packed and cbor entries, as the payload of a batch, through the
+// `middleware` chain, refer to Worker::seal.
+fn seal_payload(
+    middleware: &[u32],
+    packed: Vec<u8>,
+    entries: Vec<entry::Entry>,
+) -> Result<(Vec<u8>, Vec<u8>, Vec<entry::Entry>)> {
+    if middleware.is_empty() {
+        return Ok((Vec::default(), packed, entries));
+    }
+    // packed entries are encoded as cbor bytes, same as the packed field.
+    let mut data: Vec<u8> = vec![];
+    Cbor::bytes_into_cbor(packed)?.encode(&mut data)?;
+    data.extend_from_slice(&util::encode_cbor(entries)?);
+    let sealed = middleware::seal(middleware, data)?;
+    Ok((sealed, Vec::default(), Vec::default()))
+}
+
+// Encode `entries` as a batch using `codec`, and verify that they decode
+// back to identical entries.
+fn verify_shadow(codec: Codec, first_seqno: u64, entries: &[entry::Entry]) -> Result<()> {
+    let last_seqno = entries.last().map(entry::Entry::to_seqno).unwrap_or(first_seqno);
+    let (packed, items) = match codec {
+        Codec::Cbor => (Vec::default(), entries.to_vec()),
+        Codec::Compact => (pack_entries(first_seqno, entries), Vec::default()),
+    };
+    let batch = Batch {
+        first_seqno,
+        last_seqno,
+        state: Vec::default(),
+        timestamp: 0,
+        instance: String::default(),
+        tombstones: Vec::default(),
+        masks: Vec::default(),
+        tags: Vec::default(),
+        trailer: Vec::default(),
+        middleware: Vec::default(),
+        sealed: Vec::default(),
+        packed,
+        entries: items,
+    };
+    let data = util::encode_cbor(batch)?;
+    let (val, _) = Cbor::decode(&mut data.as_slice())?;
+    let items = Batch::decode(val)?.into_entries()?;
+
+    if items.len() != entries.len() {
+        err_at!(Invalid, msg: "decoded {} entries, expected {}", items.len(), entries.len())?
+    }
+    for (item, entry) in items.iter().zip(entries.iter()) {
+        let ok = item.to_seqno() == entry.to_seqno()
+            && item.as_topic() == entry.as_topic()
+            && item.as_tag() == entry.as_tag()
+            && item.to_timestamp() == entry.to_timestamp()
+            && item.as_op() == entry.as_op();
+        if !ok {
+            err_at!(Invalid, msg: "entry {} decoded differently", entry.to_seqno())?
+        }
+    }
+    Ok(())
+}
+
+// Pack entries for Codec::Compact. Each entry is encoded as varint seqno
+// delta from the previous entry, starting from `first_seqno`, followed by
+// varint length-prefixed topic and tag, a flag byte, varint timestamp if
+// FLAG_TIMESTAMP is set, and varint length-prefixed op. Flag byte carry
+// FLAG_BLOB, FLAG_COMPRESSED and FLAG_TIMESTAMP bits.
+fn pack_entries(first_seqno: u64, entries: &[entry::Entry]) -> Vec<u8> {
+    let size: usize = entries
+        .iter()
+        .map(|e| e.as_topic().len() + e.as_tag().len() + e.as_op().len())
+        .sum();
+    let mut buf = Vec::with_capacity(size + (entries.len() * 4));
+    let mut seqno = first_seqno;
+    for entry in entries.iter() {
+        util::encode_varint(entry.to_seqno() - seqno, &mut buf);
+        seqno = entry.to_seqno();
+        util::encode_varint(entry.as_topic().len() as u64, &mut buf);
+        buf.extend_from_slice(entry.as_topic().as_bytes());
+        util::encode_varint(entry.as_tag().len() as u64, &mut buf);
+        buf.extend_from_slice(entry.as_tag().as_bytes());
+        let blob = u8::from(entry.is_blob()) * FLAG_BLOB;
+        let compressed = u8::from(entry.is_compressed()) * FLAG_COMPRESSED;
+        let timestamp = u8::from(entry.to_timestamp() > 0) * FLAG_TIMESTAMP;
+        buf.push(blob | compressed | timestamp);
+        if timestamp > 0 {
+            util::encode_varint(entry.to_timestamp(), &mut buf);
+        }
+        util::encode_varint(entry.as_op().len() as u64, &mut buf);
+        buf.extend_from_slice(entry.as_op());
+    }
+    buf
+}
+
+// Iterate over entries packed by [pack_entries].
+struct Unpack {
+    data: Vec<u8>,
+    off: usize,
+    seqno: u64,
+}
+
+impl Unpack {
+    fn new(first_seqno: u64, data: Vec<u8>) -> Unpack {
+        Unpack { data, off: 0, seqno: first_seqno }
+    }
+
+    fn decode_bytes(&mut self) -> Result<Vec<u8>> {
+        let n = util::decode_varint(&self.data, &mut self.off)?;
+        let n = err_at!(FailConvert, usize::try_from(n))?;
+        match self.data.get(self.off..self.off.saturating_add(n)) {
+            Some(bytes) => {
+                self.off += n;
+                Ok(bytes.to_vec())
+            }
+            None => err_at!(FailConvert, msg: "truncated packed entry at {}", self.off),
+        }
+    }
+
+    fn decode_entry(&mut self) -> Result<entry::Entry> {
+        let delta = util::decode_varint(&self.data, &mut self.off)?;
+        self.seqno = match self.seqno.checked_add(delta) {
+            Some(seqno) => seqno,
+            None => err_at!(FailConvert, msg: "packed seqno overflow at {}", self.off)?,
+        };
+        let topic = err_at!(FailConvert, String::from_utf8(self.decode_bytes()?))?;
+        let tag = err_at!(FailConvert, String::from_utf8(self.decode_bytes()?))?;
+        let flags = match self.data.get(self.off) {
+            Some(flags)
+                if flags & !(FLAG_BLOB | FLAG_COMPRESSED | FLAG_TIMESTAMP) == 0 =>
+            {
+                *flags
+            }
+            Some(flags) => err_at!(FailConvert, msg: "invalid entry flags {}", flags)?,
+            None => err_at!(FailConvert, msg: "truncated packed entry at {}", self.off)?,
+        };
+        self.off += 1;
+        let timestamp = match flags & FLAG_TIMESTAMP {
+            0 => 0,
+            _ => util::decode_varint(&self.data, &mut self.off)?,
+        };
+        let op = self.decode_bytes()?;
+        let mut entry = entry::Entry::new_topic(self.seqno, topic, Vec::default())
+            .set_timestamp(timestamp);
+        entry.set_compressed(Vec::default(), flags & FLAG_COMPRESSED != 0);
+        entry.set_blob(op, flags & FLAG_BLOB != 0);
+        Ok(entry.set_tag(tag))
+    }
+}
+
+impl Iterator for Unpack {
+    type Item = Result<entry::Entry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.off >= self.data.len() {
+            return None;
+        }
+        match self.decode_entry() {
+            Ok(entry) => Some(Ok(entry)),
+            Err(err) => {
+                self.off = self.data.len();
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+/// Iterate over entries of a batch on disk, decoding one entry at a time,
+/// so that memory footprint is bounded by a single entry, irrespective of
+/// the batch size. Packed entries are loaded into memory as a whole.
+pub struct BatchIter {
+    range: ops::RangeInclusive<u64>,
+    reader: Box<dyn io::Read + Send>,
+    // number of entries yet to be decoded from reader.
+    remaining: u64,
+    // number of bytes yet to be read from reader.
+    limit: u64,
+    // entries packed with Codec::Compact.
+    packed: Option<Unpack>,
+}
+
+impl BatchIter {
+    pub fn from_index(
+        index: &Index,
+        file: &fs::File,
+        range: ops::RangeInclusive<u64>,
+    ) -> Result<BatchIter> {
+        let mut file = err_at!(IOError, file.try_clone())?;
+        err_at!(IOError, file.seek(io::SeekFrom::Start(index.fpos)))?;
+        let length = err_at!(FailConvert, u64::try_from(index.length))?;
+        let mut reader: Box<dyn io::Read + Send> =
+            Box::new(io::BufReader::new(file).take(length));
+
+        // batch is encoded as an array of fields, with middleware, sealed
+        // payload, packed entries and entries as the last four fields,
+        // skip everything before that. Batches in BatchV0 layout carry
+        // entries as the last field.
+        let n_fields = util::decode_array_hdr(&mut reader)?;
+        let mut limit = length.saturating_sub(util::array_hdr_len(n_fields));
+        let mut decode = |reader: &mut Box<dyn io::Read + Send>| -> Result<Cbor> {
+            let (value, n) = util::decode_cbor(reader, limit)?;
+            limit = limit.saturating_sub(n as u64);
+            Ok(value)
+        };
+        let (ids, sealed) = match n_fields {
+            BatchV0::N_FIELDS => {
+                for _ in 1..n_fields {
+                    decode(&mut reader)?;
+                }
+                (Vec::default(), Vec::default())
+            }
+            _ => {
+                for _ in 4..n_fields {
+                    decode(&mut reader)?;
+                }
+                let ids = Vec::<u32>::from_cbor(decode(&mut reader)?)?;
+                (ids, decode(&mut reader)?.into_bytes()?)
+            }
+        };
+        let mut limit = match ids.is_empty() {
+            true => limit,
+            false => {
+                let data = middleware::unseal(&ids, sealed)?;
+                let limit = err_at!(FailConvert, u64::try_from(data.len()))?;
+                reader = Box::new(io::Cursor::new(data));
+                limit
+            }
+        };
+        let packed = match n_fields {
+            BatchV0::N_FIELDS => None,
+            _ => {
+                let (value, n) = util::decode_cbor(&mut reader, limit)?;
+                limit = limit.saturating_sub(n as u64);
+                match value.into_bytes()? {
+                    data if data.is_empty() => None,
+                    data => Some(Unpack::new(index.first_seqno, data)),
+                }
+            }
+        };
+        let remaining = util::decode_array_hdr(&mut reader)?;
+        let limit = limit.saturating_sub(util::array_hdr_len(remaining));
+        // every entry takes atleast a byte.
+        if remaining > limit {
+            err_at!(
+                Corrupted,
+                msg: "batch at {} claims {} entries in {} bytes", index.fpos, remaining, limit
+            )?
+        }
+
+        Ok(BatchIter { range, reader, remaining, limit, packed })
+    }
+
+    fn decode_entry(&mut self) -> Option<Result<entry::Entry>> {
+        if let Some(packed) = self.packed.as_mut() {
+            return packed.next();
+        }
+        match self.remaining {
+            0 => None,
+            _ => {
+                self.remaining -= 1;
+                let entry = match util::decode_cbor(&mut self.reader, self.limit) {
+                    Ok((value, n)) => {
+                        self.limit = self.limit.saturating_sub(n as u64);
+                        entry::Entry::decode(value)
+                    }
+                    Err(err) => Err(err),
+                };
+                Some(entry)
+            }
+        }
+    }
+}
+
+impl Iterator for BatchIter {
+    type Item = Result<entry::Entry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(entry) = self.decode_entry() {
+            match entry {
+                Ok(entry) if entry.to_seqno() > *self.range.end() => break,
+                Ok(entry) if self.range.contains(&entry.to_seqno()) => {
+                    return Some(Ok(entry));
+                }
+                Ok(_) => (),
+                Err(err) => {
+                    self.remaining = 0;
+                    self.packed = None;
+                    return Some(Err(err));
+                }
+            }
+        }
+        self.remaining = 0;
+        self.packed = None;
+        None
+    }
+}
+
+/// Index of batches on disk. Each index locate a single batch within its
+/// journal file, as a byte range, along with the seqno span of its entries.
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(any(test, feature = "testing"), derive(Arbitrary))]
 pub struct Index {
     // offset in file, where the batch starts.
     fpos: u64,
@@ -200,11 +1413,80 @@ pub struct Index {
     first_seqno: u64,
     // last seqno in the batch.
     last_seqno: u64,
+    // seqno span for each named topic in the batch.
+    topics: Vec<Topic>,
+    // seqnos, and offsets if persisted, of tagged entries in the batch.
+    tags: Vec<Tag>,
+    // time the batch was written, in nanoseconds since UNIX_EPOCH.
+    timestamp: u64,
 }
 
 impl Index {
     pub fn new(fpos: u64, length: usize, first_seqno: u64, last_seqno: u64) -> Index {
-        Index { fpos, length, first_seqno, last_seqno }
+        Index {
+            fpos,
+            length,
+            first_seqno,
+            last_seqno,
+            topics: Vec::default(),
+            tags: Vec::default(),
+            timestamp: 0,
+        }
+    }
+
+    pub fn set_topics(mut self, topics: Vec<Topic>) -> Index {
+        self.topics = topics;
+        self
+    }
+
+    pub fn set_tags(mut self, tags: Vec<Tag>) -> Index {
+        self.tags = tags;
+        self
+    }
+
+    pub fn set_timestamp(mut self, timestamp: u64) -> Index {
+        self.timestamp = timestamp;
+        self
+    }
+
+    /// Return the index for `tag` in this batch, if the batch has entries,
+    /// whose seqno fall within `range`, carrying the tag.
+    pub fn to_tag(&self, tag: &str, range: &ops::RangeInclusive<u64>) -> Option<&Tag> {
+        let tag = self.tags.iter().find(|t| t.tag == tag)?;
+        match tag.seqnos.iter().any(|seqno| range.contains(seqno)) {
+            true => Some(tag),
+            false => None,
+        }
+    }
+
+    /// Iterate over distinct tags of entries in this batch.
+    pub fn iter_tags(&self) -> impl Iterator<Item = &str> {
+        self.tags.iter().map(|t| t.tag.as_str())
+    }
+
+    /// Return whether this batch may contain entries for `topic`, whose
+    /// seqno fall within `range`. Default topic is not indexed.
+    pub fn contains_topic(&self, topic: &str, range: &ops::RangeInclusive<u64>) -> bool {
+        if topic.is_empty() {
+            return true;
+        }
+        self.topics.iter().any(|t| {
+            t.name == topic
+                && t.first_seqno <= *range.end()
+                && *range.start() <= t.last_seqno
+        })
+    }
+
+    /// Return the file offset at which the batch starts.
+    #[inline]
+    pub fn to_fpos(&self) -> u64 {
+        self.fpos
+    }
+
+    /// Return the length of the batch in bytes, starting from fpos.
+    #[inline]
+    pub fn to_length(&self) -> usize {
+        self.length
     }
 
     #[inline]
@@ -216,6 +1498,133 @@ impl Index {
     pub fn to_last_seqno(&self) -> u64 {
         self.last_seqno
     }
+
+    /// Return the time the batch was written, in nanoseconds since
+    /// UNIX_EPOCH.
+    #[inline]
+    pub fn to_timestamp(&self) -> u64 {
+        self.timestamp
+    }
+}
+
+/// Seqno span of a named topic within a batch.
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(any(test, feature = "testing"), derive(Arbitrary))]
+pub struct Topic {
+    name: String,
+    first_seqno: u64,
+    last_seqno: u64,
+}
+
+impl Topic {
+    pub fn from_entries(entries: &[entry::Entry]) -> Vec<Topic> {
+        let mut topics: BTreeMap<&str, (u64, u64)> = BTreeMap::default();
+        for entry in entries.iter().filter(|e| !e.as_topic().is_empty()) {
+            let seqno = entry.to_seqno();
+            let span = topics.entry(entry.as_topic()).or_insert((seqno, seqno));
+            span.1 = seqno;
+        }
+        topics
+            .into_iter()
+            .map(|(name, (first_seqno, last_seqno))| Topic {
+                name: name.to_string(),
+                first_seqno,
+                last_seqno,
+            })
+            .collect()
+    }
+}
+
+/// Seqnos of entries carrying a tag within a batch. When persisted with
+/// the batch, also carry the offset of each entry from the start of the
+/// batch's entries, so that tagged entries can be read without decoding the
+/// entire batch.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Cborize)]
+#[cfg_attr(any(test, feature = "testing"), derive(Arbitrary))]
+pub struct Tag {
+    tag: String,
+    seqnos: Vec<u64>,
+    // byte offset of each entry, empty if entries can't be located.
+    offsets: Vec<u64>,
+}
+
+impl Tag {
+    const ID: u32 = 0x0;
+
+    /// Build tag index for `entries`, computing the offset of each entry
+    /// if `offsets` is true.
+    pub fn from_entries(entries: &[entry::Entry], offsets: bool) -> Result<Vec<Tag>> {
+        let mut tags: BTreeMap<&str, Tag> = BTreeMap::default();
+        let mut fpos = 0_u64;
+        for entry in entries.iter() {
+            let n = match offsets {
+                true => util::encode_cbor(entry.clone())?.len() as u64,
+                false => 0,
+            };
+            if !entry.as_tag().is_empty() {
+                let tag = tags.entry(entry.as_tag()).or_insert_with(|| Tag {
+                    tag: entry.as_tag().to_string(),
+                    ..Tag::default()
+                });
+                tag.seqnos.push(entry.to_seqno());
+                if offsets {
+                    tag.offsets.push(fpos);
+                }
+            }
+            fpos += n;
+        }
+        Ok(tags.into_values().collect())
+    }
+
+    /// Read entries from batch located by `index`, whose seqno fall within
+    /// `range`, using offsets. Return None if offsets are not available.
+    pub fn read_entries(
+        &self,
+        index: &Index,
+        file: &fs::File,
+        range: &ops::RangeInclusive<u64>,
+    ) -> Option<Result<Vec<entry::Entry>>> {
+        match self.offsets.len() {
+            0 => None,
+            _ => Some(self.do_read_entries(index, file, range)),
+        }
+    }
+
+    fn do_read_entries(
+        &self,
+        index: &Index,
+        file: &fs::File,
+        range: &ops::RangeInclusive<u64>,
+    ) -> Result<Vec<entry::Entry>> {
+        let mut file = err_at!(IOError, file.try_clone())?;
+        err_at!(IOError, file.seek(io::SeekFrom::Start(index.fpos)))?;
+        let length = err_at!(FailConvert, u64::try_from(index.length))?;
+        let mut reader = io::BufReader::new(&mut file).take(length);
+
+        // locate the start of entries, which is the last field.
+        let n_fields = util::decode_array_hdr(&mut reader)?;
+        let mut fpos = util::array_hdr_len(n_fields);
+        for _ in 1..n_fields {
+            fpos += util::decode_cbor(&mut reader, length.saturating_sub(fpos))?.1 as u64;
+        }
+        let n_entries = util::decode_array_hdr(&mut reader)?;
+        fpos += util::array_hdr_len(n_entries);
+        drop(reader);
+
+        let mut entries = vec![];
+        for (seqno, offset) in self.seqnos.iter().zip(self.offsets.iter()) {
+            if !range.contains(seqno) {
+                continue;
+            }
+            let off = index.fpos + fpos + offset;
+            err_at!(IOError, file.seek(io::SeekFrom::Start(off)))?;
+            let limit = length.saturating_sub(fpos + offset);
+            let (value, _) =
+                util::decode_cbor(&mut io::BufReader::new(&mut file), limit)?;
+            entries.push(entry::Entry::from_cbor(value)?);
+        }
+        Ok(entries)
+    }
 }
 
 #[cfg(test)]
@@ -18,10 +18,114 @@ fn test_index() {
     assert_eq!(index.to_first_seqno(), index.first_seqno);
     assert_eq!(index.to_first_seqno(), index.first_seqno);
 
-    let val = Index::new(index.fpos, index.length, index.first_seqno, index.last_seqno);
+    let val = Index::new(index.fpos, index.length, index.first_seqno, index.last_seqno)
+        .set_topics(index.topics.clone())
+        .set_tags(index.tags.clone());
     assert_eq!(index, val);
 }
 
+#[test]
+fn test_batch_tags() {
+    use crate::state;
+
+    let seed: u64 = random();
+    println!("test_batch_tags {}", seed);
+    let mut rng = StdRng::seed_from_u64(seed);
+
+    let tags = ["", "red", "green", "blue"];
+    let entries: Vec<entry::Entry> = (1..500)
+        .map(|seqno| {
+            let tag = tags[rng.gen::<usize>() % tags.len()];
+            let op = (0..rng.gen::<usize>() % 20).map(|_| rng.gen::<u8>()).collect();
+            entry::Entry::new(seqno, op).set_tag(tag.to_string())
+        })
+        .collect();
+
+    for tag_index in [true, false].iter() {
+        let mut file = tempfile::tempfile().unwrap();
+        let mut worker = Worker::new(state::NoState).set_tag_index(*tag_index);
+        for entry in entries.iter() {
+            worker.add_entry(entry.clone()).unwrap();
+        }
+        let index = worker.flush(&mut file).unwrap().unwrap();
+        assert!(index.to_tag("", &(1..=500)).is_none());
+        assert!(index.to_tag("black", &(1..=500)).is_none());
+
+        let (start, end) = (100, 400);
+        for tag in tags[1..].iter() {
+            let refs: Vec<entry::Entry> = entries
+                .iter()
+                .filter(|e| e.as_tag() == *tag && (start..=end).contains(&e.to_seqno()))
+                .cloned()
+                .collect();
+            let t = index.to_tag(tag, &(start..=end)).unwrap();
+            match t.read_entries(&index, &file, &(start..=end)) {
+                Some(items) => {
+                    assert!(tag_index);
+                    assert_eq!(items.unwrap(), refs);
+                }
+                None => assert!(!tag_index),
+            }
+        }
+
+        let batch = Batch::from_index(index, &mut file).unwrap();
+        assert_eq!(batch.to_tags().is_empty(), !tag_index);
+        assert_eq!(batch.into_entries().unwrap(), entries);
+    }
+}
+
+#[test]
+fn test_batch_middleware() {
+    use crate::middleware::{MIDDLEWARE_CHECKSUM, MIDDLEWARE_COMPRESS};
+    use crate::state;
+
+    let seed: u64 = random();
+    println!("test_batch_middleware {}", seed);
+    let mut rng = StdRng::seed_from_u64(seed);
+
+    let tags = ["", "red", "green"];
+    let entries: Vec<entry::Entry> = (1..500)
+        .map(|seqno| {
+            let tag = tags[rng.gen::<usize>() % tags.len()];
+            let op = vec![(seqno % 7) as u8; rng.gen::<usize>() % 20];
+            entry::Entry::new(seqno, op).set_tag(tag.to_string())
+        })
+        .collect();
+
+    for codec in [Codec::Cbor, Codec::Compact].iter() {
+        let mut file = tempfile::tempfile().unwrap();
+        let mut worker = Worker::new(state::NoState)
+            .set_codec(*codec)
+            .set_tag_index(true)
+            .set_middleware(vec![MIDDLEWARE_COMPRESS, MIDDLEWARE_CHECKSUM]);
+        for entry in entries.iter() {
+            worker.add_entry(entry.clone()).unwrap();
+        }
+        let index = worker.flush(&mut file).unwrap().unwrap();
+
+        // sealed entries can't be located within the batch.
+        let t = index.to_tag("red", &(1..=500)).unwrap();
+        assert!(t.read_entries(&index, &file, &(1..=500)).is_none());
+
+        let (start, end) = (100, 400);
+        let items: Vec<entry::Entry> = BatchIter::from_index(&index, &file, start..=end)
+            .unwrap()
+            .map(|e| e.unwrap())
+            .collect();
+        let refs: Vec<entry::Entry> = entries
+            .iter()
+            .filter(|e| (start..=end).contains(&e.to_seqno()))
+            .cloned()
+            .collect();
+        assert_eq!(items, refs, "{:?}", codec);
+
+        let batch = Batch::from_index(index, &mut file).unwrap();
+        assert!(batch.packed.is_empty() && batch.entries.is_empty());
+        assert_eq!(batch.len_entries().unwrap(), entries.len());
+        assert_eq!(batch.into_entries().unwrap(), entries);
+    }
+}
+
 #[test]
 fn test_batch() {
     let seed: u64 = random();
@@ -53,6 +157,7 @@ fn test_batch() {
         assert_eq!(n, m);
         assert_eq!(cbor, val);
 
+        assert_eq!(Batch::decode(val.clone()).unwrap(), batch);
         let rbatch = Batch::from_cbor(val).unwrap();
         assert_eq!(batch, rbatch);
     }
@@ -88,6 +193,8 @@ fn test_worker() {
 
     let mut index = vec![];
     let mut all_entries = vec![];
+    // entries are added in increasing seqno order, with random gaps.
+    let mut seqno = 0_u64;
     for _i in 0..1000 {
         let mut entries = vec![];
         let n = rng.gen::<u8>();
@@ -97,12 +204,19 @@ fn test_worker() {
                 let mut uns = Unstructured::new(&bytes);
                 uns.arbitrary().unwrap()
             };
+            seqno += 1 + u64::from(rng.gen::<u8>() % 4);
+            let entry = entry::EntryBuilder::new(seqno)
+                .topic(entry.as_topic())
+                .tag(entry.as_tag())
+                .timestamp(entry.to_timestamp())
+                .payload(entry.as_op().to_vec())
+                .build();
             worker.add_entry(entry.clone()).unwrap();
             entries.push(entry.clone());
             all_entries.push(entry);
         }
 
-        assert_eq!(entries, worker.to_entries());
+        assert_eq!(entries, worker.to_entries().unwrap());
         if n > 0 {
             assert_eq!(entries.last().map(|e| e.to_seqno()), worker.to_last_seqno())
         }
@@ -119,13 +233,510 @@ fn test_worker() {
     assert_eq!(index, worker.to_index());
     let entries = index
         .iter()
-        .map(|x| {
+        .flat_map(|x| {
             Batch::from_index(x.clone(), &mut file)
                 .unwrap()
                 .into_iter(0..=u64::MAX)
                 .collect::<Vec<entry::Entry>>()
         })
-        .flatten()
         .collect::<Vec<entry::Entry>>();
     assert_eq!(entries, all_entries)
 }
+
+#[test]
+fn test_batch_iter() {
+    use crate::state;
+
+    let seed: u64 = random();
+    println!("test_batch_iter {}", seed);
+    let mut rng = StdRng::seed_from_u64(seed);
+
+    let mut file = tempfile::tempfile().unwrap();
+    let mut worker = Worker::new(state::NoState);
+
+    let entries: Vec<entry::Entry> =
+        (1..10_000).map(|seqno| entry::Entry::new(seqno, vec![0; 16])).collect();
+    for entry in entries.iter() {
+        worker.add_entry(entry.clone()).unwrap();
+    }
+    let index = worker.flush(&mut file).unwrap().unwrap();
+
+    for _i in 0..100 {
+        let start = rng.gen::<u64>() % 10_100;
+        let end = start + (rng.gen::<u64>() % 10_100);
+        let items: Vec<entry::Entry> = BatchIter::from_index(&index, &file, start..=end)
+            .unwrap()
+            .map(|x| x.unwrap())
+            .collect();
+        let refs: Vec<entry::Entry> = entries
+            .iter()
+            .filter(|e| (start..=end).contains(&e.to_seqno()))
+            .cloned()
+            .collect();
+        assert_eq!(items, refs);
+    }
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, Cborize)]
+struct Count {
+    n: u64,
+}
+
+impl Count {
+    const ID: u32 = 0x0;
+}
+
+impl crate::state::State for Count {
+    fn on_add_entry(&mut self, _: &entry::Entry) -> Result<()> {
+        self.n += 1;
+        Ok(())
+    }
+}
+
+#[test]
+fn test_worker_rollback() {
+    let ntf = tempfile::NamedTempFile::new().unwrap();
+    let mut file = ntf.reopen().unwrap();
+
+    let mut worker = Worker::new(Count::default());
+    for seqno in 1..=10 {
+        worker.add_entry(entry::Entry::new(seqno, vec![1, 2, 3])).unwrap();
+    }
+    assert_eq!(worker.to_state(), Count { n: 0 });
+    worker.flush(&mut file).unwrap().unwrap();
+    assert_eq!(worker.to_state(), Count { n: 10 });
+    let len = file.metadata().unwrap().len();
+
+    // flush into a read-only file shall fail.
+    let mut rdfile = std::fs::OpenOptions::new().read(true).open(ntf.path()).unwrap();
+    for seqno in 11..=20 {
+        worker.add_entry(entry::Entry::new(seqno, vec![1, 2, 3])).unwrap();
+    }
+    assert!(worker.flush(&mut rdfile).is_err());
+    assert_eq!(worker.to_state(), Count { n: 10 });
+    assert_eq!(worker.to_entries().unwrap(), vec![]);
+    assert_eq!(worker.to_last_seqno(), Some(10));
+    assert_eq!(file.metadata().unwrap().len(), len);
+
+    worker.add_entry(entry::Entry::new(11, vec![1, 2, 3])).unwrap();
+    worker.flush(&mut file).unwrap().unwrap();
+    assert_eq!(worker.to_state(), Count { n: 11 });
+    assert_eq!(worker.len_batches(), 2);
+}
+
+// Reject ops starting with 0, transform ops starting with 1, count the rest.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Cborize)]
+struct Gate {
+    n: u64,
+}
+
+impl Gate {
+    const ID: u32 = 0x0;
+}
+
+impl crate::state::State for Gate {
+    fn on_entry(&mut self, entry: &entry::Entry) -> Result<state::Action> {
+        match entry.as_op().first() {
+            Some(0) => Ok(state::Action::Reject("zero".to_string())),
+            Some(1) => {
+                self.n += 1;
+                Ok(state::Action::Transform(vec![2; entry.as_op().len()]))
+            }
+            _ => {
+                self.n += 1;
+                Ok(state::Action::Accept)
+            }
+        }
+    }
+}
+
+#[test]
+fn test_worker_add_entries() {
+    let ntf = tempfile::NamedTempFile::new().unwrap();
+    let mut file = ntf.reopen().unwrap();
+
+    let mut worker = Worker::new(Gate::default());
+    worker.add_entries(vec![entry::Entry::new(1, vec![1, 1])]).unwrap();
+    match worker.add_entries(vec![entry::Entry::new(2, vec![0])]) {
+        Err(Error::Rejected(_, _)) => (),
+        res => panic!("expected Rejected {:?}", res),
+    }
+    let entries = vec![
+        entry::Entry::new(2, vec![3]),
+        entry::Entry::new(3, vec![1]),
+        entry::Entry::new(4, vec![0]),
+    ];
+    assert!(worker.add_entries(entries).is_err());
+    let entries = vec![entry::Entry::new(2, vec![3]), entry::Entry::new(3, vec![1])];
+    worker.add_entries(entries).unwrap();
+
+    let ops: Vec<Vec<u8>> =
+        worker.to_entries().unwrap().into_iter().map(|e| e.unwrap().1).collect();
+    assert_eq!(ops, vec![vec![2, 2], vec![3], vec![2]]);
+    worker.flush(&mut file).unwrap().unwrap();
+    assert_eq!(worker.to_state(), Gate { n: 3 });
+}
+
+#[test]
+fn test_worker_seqno_order() {
+    let ntf = tempfile::NamedTempFile::new().unwrap();
+    let mut file = ntf.reopen().unwrap();
+
+    let mut worker = Worker::new(Count::default());
+    for seqno in 1..=3 {
+        worker.add_entry(entry::Entry::new(seqno, vec![1])).unwrap();
+    }
+    let invalid: Vec<Vec<u64>> = vec![vec![3], vec![2], vec![4, 4], vec![5, 4]];
+    for seqnos in invalid.into_iter() {
+        let entries = seqnos.iter().map(|s| entry::Entry::new(*s, vec![2])).collect();
+        match worker.add_entries(entries) {
+            Err(Error::Invalid(_, _)) => (),
+            res => panic!("expected Invalid {:?} {:?}", seqnos, res),
+        }
+    }
+    assert!(worker.add_entry(entry::Entry::new(1, vec![2])).is_err());
+    assert_eq!(worker.to_entries().unwrap().len(), 3);
+    worker.flush(&mut file).unwrap().unwrap();
+    assert_eq!(worker.to_state(), Count { n: 3 });
+
+    assert!(worker.add_entry(entry::Entry::new(3, vec![2])).is_err());
+    worker.add_entries(vec![entry::Entry::new(10, vec![3])]).unwrap();
+    worker.discard().unwrap();
+    worker.add_entry(entry::Entry::new(4, vec![3])).unwrap();
+    worker.flush(&mut file).unwrap().unwrap();
+    assert_eq!(worker.to_last_seqno(), Some(4));
+}
+
+#[test]
+fn test_worker_shrink() {
+    let ntf = tempfile::NamedTempFile::new().unwrap();
+    let mut file = ntf.reopen().unwrap();
+
+    let mut worker = Worker::new(Count::default());
+    let mut seqno = 0;
+    for _i in 0..1000 {
+        seqno += 1;
+        worker.add_entry(entry::Entry::new(seqno, vec![1])).unwrap();
+    }
+    worker.flush(&mut file).unwrap().unwrap();
+    let (high_water, capacity) = worker.to_high_water();
+    assert_eq!(high_water, 1000);
+    assert!(capacity >= 1000, "{}", capacity);
+
+    // small batches, capacity is reviewed once every SHRINK_WINDOW flushes,
+    // and the first window includes the large batch.
+    for i in 1..(2 * SHRINK_WINDOW) {
+        seqno += 1;
+        worker.add_entry(entry::Entry::new(seqno, vec![2])).unwrap();
+        worker.flush(&mut file).unwrap().unwrap();
+        let (_, capacity) = worker.to_high_water();
+        match i {
+            i if i < (2 * SHRINK_WINDOW - 1) => {
+                assert!(capacity >= 1000, "{} {}", i, capacity)
+            }
+            _ => assert!(capacity <= SHRINK_MIN * 2, "{}", capacity),
+        }
+    }
+    assert_eq!(worker.to_high_water().0, 1000);
+    assert_eq!(worker.to_state(), Count { n: 999 + 2 * SHRINK_WINDOW as u64 });
+
+    // capacity close to recent batches is retained.
+    for _i in 0..SHRINK_WINDOW {
+        for _j in 0..SHRINK_MIN {
+            seqno += 1;
+            worker.add_entry(entry::Entry::new(seqno, vec![3])).unwrap();
+        }
+        worker.flush(&mut file).unwrap().unwrap();
+    }
+    assert!(worker.to_high_water().1 >= SHRINK_MIN);
+}
+
+#[test]
+fn test_worker_spool() {
+    let dir = tempfile::tempdir().unwrap();
+    let journal_path = dir.path().join("test-journal-0.dat").into_os_string();
+    let spool_path = crate::files::make_spool_filename(&journal_path);
+    let mut file = fs::File::create(&journal_path).unwrap();
+
+    let spool = spool::Spool::new(10, &journal_path);
+    let mut worker = Worker::new(Gate::default()).set_spool(Some(spool));
+    for seqno in 1..=20 {
+        worker.add_entries(vec![entry::Entry::new(seqno, vec![3; 4])]).unwrap();
+    }
+    // two ops fit within the limit, rest are spooled.
+    assert_eq!(worker.entries.len(), 2);
+    assert!(fs::metadata(&spool_path).unwrap().len() > 0);
+    assert_eq!(worker.to_first_seqno(), Some(1));
+    assert_eq!(worker.to_last_seqno(), Some(20));
+
+    // rejected entries are not spooled.
+    let entries = vec![entry::Entry::new(21, vec![1; 4]), entry::Entry::new(22, vec![0])];
+    assert!(worker.add_entries(entries).is_err());
+    let seqnos: Vec<u64> =
+        worker.to_entries().unwrap().iter().map(|e| e.to_seqno()).collect();
+    assert_eq!(seqnos, (1..=20).collect::<Vec<u64>>());
+
+    // spooled entries are stitched into the same batch.
+    let index = worker.flush(&mut file).unwrap().unwrap();
+    assert_eq!((index.to_first_seqno(), index.to_last_seqno()), (1, 20));
+    assert_eq!(worker.len_batches(), 1);
+    assert_eq!(worker.to_state(), Gate { n: 20 });
+    assert_eq!(fs::metadata(&spool_path).unwrap().len(), 0);
+
+    let data = fs::read(&journal_path).unwrap();
+    let (val, _) = Cbor::decode(&mut data.as_slice()).unwrap();
+    let entries = Batch::from_cbor(val).unwrap().into_entries().unwrap();
+    assert_eq!(entries.len(), 20);
+    assert!(entries.iter().all(|e| e.as_op() == [3; 4]));
+
+    std::mem::drop(worker);
+    assert!(!std::path::Path::new(&spool_path).exists());
+}
+
+#[test]
+fn test_worker_flush_upto() {
+    let ntf = tempfile::NamedTempFile::new().unwrap();
+    let mut file = ntf.reopen().unwrap();
+
+    let mut worker = Worker::new(Count::default());
+    for seqno in 1..=100 {
+        worker.add_entry(entry::Entry::new(seqno, vec![0; 32])).unwrap();
+    }
+    assert!(!worker.flush_upto(&mut file, 1000).unwrap());
+    let len = file.metadata().unwrap().len();
+    assert!(len <= 1000, "{}", len);
+    assert_eq!(worker.len_batches(), 1);
+    let n = worker.to_state().n;
+    assert!(n > 0 && n < 100, "{}", n);
+    assert_eq!(worker.to_entries().unwrap().len(), 100 - n as usize);
+
+    // nothing more fits, entries are left pending.
+    assert!(!worker.flush_upto(&mut file, 1000).unwrap());
+    assert_eq!(worker.len_batches(), 1);
+    assert_eq!(file.metadata().unwrap().len(), len);
+
+    assert!(worker.flush_upto(&mut file, usize::MAX).unwrap());
+    assert_eq!(worker.to_state(), Count { n: 100 });
+    assert_eq!(worker.len_batches(), 2);
+    assert!(worker.flush_upto(&mut file, 0).unwrap());
+
+    // at least one entry is flushed into an empty file.
+    let ntf = tempfile::NamedTempFile::new().unwrap();
+    let mut file = ntf.reopen().unwrap();
+    let mut worker = Worker::new(Count::default());
+    worker.add_entry(entry::Entry::new(1, vec![0; 2000])).unwrap();
+    worker.add_entry(entry::Entry::new(2, vec![0; 32])).unwrap();
+    assert!(!worker.flush_upto(&mut file, 1000).unwrap());
+    assert_eq!(worker.to_state(), Count { n: 1 });
+    assert_eq!(worker.to_entries().unwrap().len(), 1);
+}
+
+#[test]
+fn test_batch_compact() {
+    use crate::state;
+
+    let seed: u64 = random();
+    println!("test_batch_compact {}", seed);
+    let mut rng = StdRng::seed_from_u64(seed);
+
+    let entries: Vec<entry::Entry> = (1..1000)
+        .map(|seqno| {
+            let topic = if seqno % 3 == 0 { "topic" } else { "" };
+            let tag = if seqno % 5 == 0 { "tag" } else { "" };
+            let op = (0..rng.gen::<usize>() % 20).map(|_| rng.gen::<u8>()).collect();
+            entry::EntryBuilder::new(seqno * 7)
+                .topic(topic)
+                .tag(tag)
+                .timestamp(if seqno % 2 == 0 { seqno * 1000 } else { 0 })
+                .payload(op)
+                .build()
+        })
+        .collect();
+
+    let mut sizes = vec![];
+    for codec in [Codec::Cbor, Codec::Compact].iter() {
+        let mut file = tempfile::tempfile().unwrap();
+        let mut worker = Worker::new(state::NoState).set_codec(*codec);
+        for entry in entries.iter() {
+            worker.add_entry(entry.clone()).unwrap();
+        }
+        let index = worker.flush(&mut file).unwrap().unwrap();
+        sizes.push(index.to_length());
+
+        let (start, end) = (700, 5000);
+        let items: Vec<(u64, String, Vec<u8>)> =
+            BatchIter::from_index(&index, &file, start..=end)
+                .unwrap()
+                .map(|e| e.unwrap())
+                .map(|e| (e.to_seqno(), e.as_topic().to_string(), e.as_op().to_vec()))
+                .collect();
+        let refs: Vec<(u64, String, Vec<u8>)> = entries
+            .iter()
+            .filter(|e| (start..=end).contains(&e.to_seqno()))
+            .map(|e| (e.to_seqno(), e.as_topic().to_string(), e.as_op().to_vec()))
+            .collect();
+        assert_eq!(items, refs);
+
+        let batch = Batch::from_index(index, &mut file).unwrap();
+        assert_eq!(batch.len_entries().unwrap(), entries.len());
+        assert!(batch.to_metadata().is_none());
+        let items = batch.into_entries().unwrap();
+        for (item, entry) in items.iter().zip(entries.iter()) {
+            assert_eq!(item.to_seqno(), entry.to_seqno());
+            assert_eq!(item.as_topic(), entry.as_topic());
+            assert_eq!(item.as_tag(), entry.as_tag());
+            assert_eq!(item.to_timestamp(), entry.to_timestamp());
+            assert_eq!(item.as_op(), entry.as_op());
+        }
+    }
+    assert!(sizes[1] < sizes[0], "{:?}", sizes);
+
+    // shadow codec decode to identical entries.
+    for (codec, shadow) in
+        [(Codec::Cbor, Codec::Compact), (Codec::Compact, Codec::Cbor)].iter()
+    {
+        let mut file = tempfile::tempfile().unwrap();
+        let mut worker =
+            Worker::new(state::NoState).set_codec(*codec).set_shadow(Some(*shadow));
+        for entry in entries.iter() {
+            worker.add_entry(entry.clone()).unwrap();
+        }
+        worker.flush(&mut file).unwrap().unwrap();
+        assert!(worker.take_mismatches().is_empty());
+        verify_shadow(*shadow, entries[0].to_seqno(), &entries).unwrap();
+    }
+
+    // corrupted packed entries fail to decode.
+    let mut data = pack_entries(7, &entries[..10]);
+    data.truncate(data.len() - 1);
+    let items: Vec<Result<entry::Entry>> = Unpack::new(7, data).collect();
+    assert_eq!(items.len(), 10);
+    assert!(items[9].is_err());
+
+    let mut buf = vec![];
+    for val in [0, 1, 127, 128, 300, u64::MAX].iter() {
+        buf.clear();
+        util::encode_varint(*val, &mut buf);
+        let mut off = 0;
+        assert_eq!(util::decode_varint(&buf, &mut off).unwrap(), *val);
+        assert_eq!(off, buf.len());
+    }
+}
+
+#[test]
+fn test_batch_recode() {
+    use crate::middleware::MIDDLEWARE_CHECKSUM;
+    use crate::state;
+
+    let seed: u64 = random();
+    println!("test_batch_recode {}", seed);
+    let mut rng = StdRng::seed_from_u64(seed);
+
+    let tags = ["", "red"];
+    let entries: Vec<entry::Entry> = (1..200)
+        .map(|seqno| {
+            let tag = tags[rng.gen::<usize>() % tags.len()];
+            let op = vec![(seqno % 7) as u8; rng.gen::<usize>() % 200];
+            entry::Entry::new(seqno, op).set_tag(tag.to_string())
+        })
+        .collect();
+    let decompress = |items: Vec<entry::Entry>| -> Vec<entry::Entry> {
+        items.into_iter().map(|e| compress::decompress_entry(e).unwrap()).collect()
+    };
+
+    let formats =
+        [(Codec::Cbor, None), (Codec::Compact, Some(50)), (Codec::Cbor, Some(0))];
+    for middleware in [vec![], vec![MIDDLEWARE_CHECKSUM]].iter() {
+        for (codec, compress) in formats.iter() {
+            let mut file = tempfile::tempfile().unwrap();
+            let mut worker = Worker::new(state::NoState)
+                .set_codec(*codec)
+                .set_compress(*compress)
+                .set_tag_index(true)
+                .set_middleware(middleware.clone());
+            for entry in entries.iter() {
+                worker.add_entry(entry.clone()).unwrap();
+            }
+            let index = worker.flush(&mut file).unwrap().unwrap();
+            let batch = Batch::from_index(index, &mut file).unwrap();
+
+            for (to_codec, to_compress) in formats.iter() {
+                let item = batch.clone().recode(*to_codec, *to_compress).unwrap();
+                assert_eq!(item.to_first_seqno(), batch.to_first_seqno());
+                assert_eq!(item.to_last_seqno(), batch.to_last_seqno());
+                assert_eq!(item.to_state(), batch.to_state());
+                assert_eq!(item.to_timestamp(), batch.to_timestamp());
+                assert_eq!(item.middleware, batch.middleware);
+                let packed = !middleware.is_empty() || *to_codec == Codec::Cbor;
+                assert_eq!(item.packed.is_empty(), packed);
+                assert!(!item.to_tags().is_empty());
+
+                let items = item.into_entries().unwrap();
+                let compressed = items.iter().any(|e| e.is_compressed());
+                assert_eq!(compressed, to_compress.is_some(), "{:?}", to_compress);
+                assert_eq!(decompress(items), entries);
+            }
+        }
+    }
+
+    // metadata batches are recoded as is.
+    let mut file = tempfile::tempfile().unwrap();
+    let mut worker = Worker::new(state::NoState);
+    let metadata = tombstone::Metadata::default();
+    let index = worker.flush_metadata(&mut file, metadata, 10).unwrap();
+    let batch = Batch::from_index(index, &mut file).unwrap();
+    assert_eq!(batch.clone().recode(Codec::Compact, Some(0)).unwrap(), batch);
+}
+
+#[test]
+fn test_batch_from_index_corrupted() {
+    let dir = tempfile::tempdir().unwrap();
+    let file_path = dir.path().join("test-batch-from-index-corrupted");
+    fs::write(&file_path, [0_u8; 16]).unwrap();
+    let mut file = fs::File::open(&file_path).unwrap();
+
+    let index = Index::new(8, usize::MAX, 1, 1);
+    match Batch::from_index(index, &mut file) {
+        Err(Error::Corrupted(_, _)) => (),
+        res => panic!("expected Corrupted, {:?}", res),
+    }
+}
+
+#[test]
+fn test_batch_v0() {
+    let entries: Vec<entry::EntryV0> = (1..=3)
+        .map(|seqno| entry::EntryV0 { seqno, op: vec![seqno as u8; 4] })
+        .collect();
+    let batch = BatchV0 {
+        first_seqno: 1,
+        last_seqno: 3,
+        state: vec![0x80],
+        entries,
+    };
+    let data = util::encode_cbor(batch).unwrap();
+
+    let (val, _) = Cbor::decode(&mut data.as_slice()).unwrap();
+    assert!(Batch::from_cbor(val.clone()).is_err());
+    let batch = Batch::decode(val).unwrap();
+    assert_eq!((batch.to_first_seqno(), batch.to_last_seqno()), (1, 3));
+    assert_eq!(batch.to_state(), vec![0x80]);
+    assert_eq!(batch.to_timestamp(), 0);
+    assert_eq!(batch.as_instance(), "");
+    assert!(batch.to_metadata().is_none());
+    let refs: Vec<entry::Entry> =
+        (1..=3).map(|seqno| entry::Entry::new(seqno, vec![seqno as u8; 4])).collect();
+    assert_eq!(batch.clone().into_entries().unwrap(), refs);
+
+    let mut file = tempfile::tempfile().unwrap();
+    util::sync_write(&mut file, &data).unwrap();
+    let index = Index::new(0, data.len(), 1, 3);
+    assert_eq!(Batch::from_index(index.clone(), &mut file).unwrap(), batch);
+    let items: Vec<entry::Entry> = BatchIter::from_index(&index, &file, 2..=3)
+        .unwrap()
+        .map(|x| x.unwrap())
+        .collect();
+    assert_eq!(items, refs[1..].to_vec());
+    for (item, entry) in items.iter().zip(refs[1..].iter()) {
+        assert_eq!(item.as_op(), entry.as_op());
+    }
+}
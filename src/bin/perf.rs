@@ -1,7 +1,7 @@
-use rand::prelude::random;
+use rand::{prelude::random, rngs::StdRng, Rng, SeedableRng};
 use structopt::StructOpt;
 
-use std::time;
+use std::{fs, io::Write, time};
 
 use wral::{self};
 
@@ -14,6 +14,11 @@ pub struct Opt {
     #[structopt(long = "name", default_value = "wral-perf")]
     name: String,
 
+    // run against an existing directory, instead of a tempdir, the instance
+    // is loaded if it exists and is not purged after the run.
+    #[structopt(long = "dir")]
+    dir: Option<String>,
+
     #[structopt(long = "ops", default_value = "1000000")] // default 1M
     ops: usize,
 
@@ -28,18 +33,124 @@ pub struct Opt {
 
     #[structopt(long = "nosync")]
     nosync: bool,
+
+    // percentage of ops acknowledged only after they are durable, remaining
+    // ops are written via a buffered writer.
+    #[structopt(long = "sync-ratio", default_value = "100")]
+    sync_ratio: u8,
+
+    // capacity of buffered writer, in number of ops.
+    #[structopt(long = "buffer", default_value = "100")]
+    buffer: usize,
+
+    // ops per thread in the mixed read/write phase, after the write phase.
+    #[structopt(long = "mixed-ops", default_value = "0")]
+    mixed_ops: usize,
+
+    // percentage of reads in the mixed read/write phase.
+    #[structopt(long = "read-ratio", default_value = "50")]
+    read_ratio: u8,
+
+    // write latency report, one row per phase per thread, to csv file.
+    #[structopt(long = "csv")]
+    csv: Option<String>,
+}
+
+// Latency samples for a phase, from a single thread.
+struct Latency {
+    phase: &'static str,
+    id: Option<usize>,
+    elapsed: time::Duration,
+    samples: Vec<time::Duration>,
+}
+
+impl Latency {
+    fn new(phase: &'static str, id: usize) -> Latency {
+        Latency {
+            phase,
+            id: Some(id),
+            elapsed: time::Duration::default(),
+            samples: vec![],
+        }
+    }
+
+    fn merge(phase: &'static str, items: &[Latency]) -> Latency {
+        let items = items.iter().filter(|l| l.phase == phase);
+        let mut latency = Latency {
+            phase,
+            id: None,
+            elapsed: time::Duration::default(),
+            samples: vec![],
+        };
+        for item in items {
+            latency.elapsed = latency.elapsed.max(item.elapsed);
+            latency.samples.extend_from_slice(&item.samples);
+        }
+        latency
+    }
+
+    fn percentile(&self, p: f64) -> time::Duration {
+        let mut samples = self.samples.clone();
+        samples.sort_unstable();
+        match samples.len() {
+            0 => time::Duration::default(),
+            n => samples[(((n as f64) * p) as usize).min(n - 1)],
+        }
+    }
+
+    fn to_thread(&self) -> String {
+        match self.id {
+            Some(id) => format!("{:02}", id),
+            None => "all".to_string(),
+        }
+    }
+
+    fn print(&self) {
+        println!(
+            "{}-{} took {:?} for {} ops, p50 {:?} p99 {:?} p999 {:?}",
+            self.phase,
+            self.to_thread(),
+            self.elapsed,
+            self.samples.len(),
+            self.percentile(0.5),
+            self.percentile(0.99),
+            self.percentile(0.999),
+        );
+    }
+
+    fn to_csv(&self) -> String {
+        format!(
+            "{},{},{},{},{},{},{}\n",
+            self.phase,
+            self.to_thread(),
+            self.samples.len(),
+            self.elapsed.as_micros(),
+            self.percentile(0.5).as_micros(),
+            self.percentile(0.99).as_micros(),
+            self.percentile(0.999).as_micros(),
+        )
+    }
 }
 
 fn main() {
-    let dir = tempfile::tempdir().unwrap();
+    let tmp_dir = tempfile::tempdir().unwrap();
     let opts = Opt::from_args();
     let seed = opts.seed.unwrap_or_else(random);
+    println!("seed {}", seed);
 
-    let mut config = wral::Config::new(&opts.name, dir.path().as_os_str());
+    let dir = match &opts.dir {
+        Some(dir) => {
+            fs::create_dir_all(dir).unwrap();
+            dir.into()
+        }
+        None => tmp_dir.path().as_os_str().to_os_string(),
+    };
+    let mut config = wral::Config::new(&opts.name, &dir);
     config.set_journal_limit(opts.journal_limit).set_fsync(!opts.nosync);
     println!("{:?}", config);
 
-    let wal = wral::Wal::create(config, wral::NoState).unwrap();
+    let wal = wral::Wal::open(config, wral::OpenMode::CreateOrLoad).unwrap();
+    let mut latencies = vec![];
 
     let mut writers = vec![];
     for id in 0..opts.threads {
@@ -50,14 +161,18 @@ fn main() {
 
     let mut entries: Vec<Vec<wral::Entry>> = vec![];
     for handle in writers {
-        entries.push(handle.join().unwrap());
+        let (items, latency) = handle.join().unwrap();
+        entries.push(items);
+        latencies.push(latency);
     }
     let mut entries: Vec<wral::Entry> = entries.into_iter().flatten().collect();
     entries.sort_by_key(|a| a.to_seqno());
 
+    // seqnos continue from the loaded instance, if any.
     let n = entries.len() as u64;
+    let first = entries.first().map(|e| e.to_seqno()).unwrap_or(1);
     let sum = entries.iter().map(|e| e.to_seqno()).sum::<u64>();
-    assert_eq!(sum, (n * (n + 1)) / 2);
+    assert_eq!(sum, (n * (2 * first + n - 1)) / 2);
 
     let mut readers = vec![];
     for id in 0..opts.threads {
@@ -65,32 +180,125 @@ fn main() {
         let entries = entries.clone();
         readers.push(std::thread::spawn(move || reader(id, wal, entries)));
     }
-
     for handle in readers {
-        handle.join().unwrap();
+        latencies.push(handle.join().unwrap());
+    }
+
+    if opts.mixed_ops > 0 {
+        let mut mixers = vec![];
+        for id in 0..opts.threads {
+            let wal = wal.clone();
+            let opts = opts.clone();
+            mixers.push(std::thread::spawn(move || mixed(id, wal, opts, seed)));
+        }
+        for handle in mixers {
+            latencies.extend(handle.join().unwrap());
+        }
     }
 
-    wal.close(true).unwrap();
+    let phases = ["w", "r", "mr", "mw"];
+    let merged: Vec<Latency> = phases
+        .iter()
+        .map(|phase| Latency::merge(phase, &latencies))
+        .filter(|l| !l.samples.is_empty())
+        .collect();
+    merged.iter().for_each(Latency::print);
+
+    if let Some(csv) = &opts.csv {
+        let mut file = fs::File::create(csv).unwrap();
+        file.write_all(b"phase,thread,ops,elapsed_us,p50_us,p99_us,p999_us\n").unwrap();
+        for latency in latencies.iter().chain(merged.iter()) {
+            file.write_all(latency.to_csv().as_bytes()).unwrap();
+        }
+    }
+
+    wal.close(opts.dir.is_none()).unwrap();
 }
 
-fn writer(id: usize, wal: wral::Wal, opts: Opt, _seed: u128) -> Vec<wral::Entry> {
+fn writer(
+    id: usize,
+    wal: wral::Wal,
+    opts: Opt,
+    seed: u128,
+) -> (Vec<wral::Entry>, Latency) {
+    let mut rng = StdRng::seed_from_u64((seed as u64).wrapping_add(id as u64));
     let start = time::Instant::now();
+    let mut latency = Latency::new("w", id);
 
     let mut entries = vec![];
+    let mut bw = wal.buffered_writer(opts.buffer);
     let op = vec![0; opts.payload];
     for _i in 0..opts.ops {
-        let seqno = wal.add_op(&op).unwrap();
-        entries.push(wral::Entry::new(seqno, op.clone()));
+        let begin = time::Instant::now();
+        let seqnos = match rng.gen_range(0..100) < opts.sync_ratio {
+            true => {
+                let seqno = wal.add_op(&op).unwrap();
+                Some(seqno..=seqno)
+            }
+            false => bw.add_op(&op).unwrap(),
+        };
+        latency.samples.push(begin.elapsed());
+
+        // buffered writer return seqnos for all ops flushed together.
+        if let Some(seqnos) = seqnos {
+            entries.extend(seqnos.map(|seqno| wral::Entry::new(seqno, op.clone())));
+        }
+    }
+    if let Some(seqnos) = bw.flush().unwrap() {
+        entries.extend(seqnos.map(|seqno| wral::Entry::new(seqno, op.clone())));
     }
 
-    println!("w-{:02} took {:?} to write {} ops", id, start.elapsed(), opts.ops);
-    entries
+    latency.elapsed = start.elapsed();
+    latency.print();
+    (entries, latency)
 }
 
-fn reader(id: usize, wal: wral::Wal, entries: Vec<wral::Entry>) {
+fn reader(id: usize, wal: wral::Wal, entries: Vec<wral::Entry>) -> Latency {
     let start = time::Instant::now();
-    let items: Vec<wral::Entry> = wal.iter().unwrap().map(|x| x.unwrap()).collect();
+    let mut latency = Latency::new("r", id);
+
+    let range = match (entries.first(), entries.last()) {
+        (Some(first), Some(last)) => first.to_seqno()..=last.to_seqno(),
+        _ => return latency,
+    };
+    let mut items = vec![];
+    let mut begin = time::Instant::now();
+    for item in wal.range(range).unwrap() {
+        items.push(item.unwrap());
+        latency.samples.push(begin.elapsed());
+        begin = time::Instant::now();
+    }
     assert_eq!(items, entries);
 
-    println!("r-{:02} took {:?} to iter {} ops", id, start.elapsed(), items.len());
+    latency.elapsed = start.elapsed();
+    latency.print();
+    latency
+}
+
+fn mixed(id: usize, wal: wral::Wal, opts: Opt, seed: u128) -> Vec<Latency> {
+    let mut rng = StdRng::seed_from_u64((seed as u64).wrapping_add(id as u64));
+    let start = time::Instant::now();
+    let mut rlatency = Latency::new("mr", id);
+    let mut wlatency = Latency::new("mw", id);
+
+    let op = vec![0; opts.payload];
+    for _i in 0..opts.mixed_ops {
+        let begin = time::Instant::now();
+        if rng.gen_range(0..100) < opts.read_ratio {
+            let seqno = rng.gen_range(0..=wal.durable_seqno().unwrap());
+            wal.range(seqno..=seqno).unwrap().for_each(|e| {
+                e.unwrap();
+            });
+            rlatency.samples.push(begin.elapsed());
+        } else {
+            wal.add_op(&op).unwrap();
+            wlatency.samples.push(begin.elapsed());
+        }
+    }
+
+    rlatency.elapsed = start.elapsed();
+    wlatency.elapsed = start.elapsed();
+    rlatency.print();
+    wlatency.print();
+    vec![rlatency, wlatency]
 }
@@ -0,0 +1,191 @@
+//! Module implement external storage for large ops, refer to
+//! [Config::set_blob_spill][crate::Config::set_blob_spill].
+//!
+//! Ops larger than the threshold are appended to a blob file, kept next
+//! to the journal file, and the entry logged in the journal carries a
+//! fixed size reference to it.
+
+use std::{
+    convert::TryFrom,
+    ffi, fs,
+    io::{Read, Seek, SeekFrom},
+};
+
+use crate::{entry, files, util, Error, Result};
+
+/// Size of an encoded [Ref].
+pub const REF_SIZE: usize = 20;
+
+/// Reference to an op in blob file, encoded as big-endian fpos, length
+/// and crc32 of the op.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Ref {
+    pub fpos: u64,
+    pub length: u64,
+    pub crc: u32,
+}
+
+impl Ref {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(REF_SIZE);
+        buf.extend_from_slice(&self.fpos.to_be_bytes());
+        buf.extend_from_slice(&self.length.to_be_bytes());
+        buf.extend_from_slice(&self.crc.to_be_bytes());
+        buf
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Ref> {
+        if data.len() != REF_SIZE {
+            err_at!(FailConvert, msg: "blob ref of {} bytes", data.len())?
+        }
+        let mut fpos = [0_u8; 8];
+        let mut length = [0_u8; 8];
+        let mut crc = [0_u8; 4];
+        fpos.copy_from_slice(&data[..8]);
+        length.copy_from_slice(&data[8..16]);
+        crc.copy_from_slice(&data[16..]);
+        Ok(Ref {
+            fpos: u64::from_be_bytes(fpos),
+            length: u64::from_be_bytes(length),
+            crc: u32::from_be_bytes(crc),
+        })
+    }
+}
+
+/// Write side of blob file, for the active journal. Blob file is created
+/// only when the first op is spilled.
+pub struct Spill {
+    threshold: usize,
+    file_path: ffi::OsString,
+    file: Option<fs::File>,
+}
+
+impl Spill {
+    pub fn new(threshold: usize, journal_path: &ffi::OsStr) -> Spill {
+        Spill {
+            threshold,
+            file_path: files::make_blob_filename(journal_path),
+            file: None,
+        }
+    }
+
+    /// Blob file is moved along with its journal, to `journal_path`.
+    pub fn relocate(&mut self, journal_path: &ffi::OsStr) {
+        self.file_path = files::make_blob_filename(journal_path);
+        self.file = None;
+    }
+
+    pub fn to_threshold(&self) -> usize {
+        self.threshold
+    }
+
+    /// Return whether `entry`'s op shall be spilled into blob file.
+    pub fn is_spilled(&self, entry: &entry::Entry) -> bool {
+        !entry.is_blob() && entry.as_op().len() > self.threshold
+    }
+
+    /// Append ops larger than threshold to blob file, sync the file, and
+    /// replace the ops with their reference. Return the size of blob file
+    /// before spilling, for [Spill::truncate], None if nothing is spilled.
+    pub fn spill(&mut self, entries: &mut [entry::Entry]) -> Result<Option<u64>> {
+        if !entries.iter().any(|e| self.is_spilled(e)) {
+            return Ok(None);
+        }
+
+        let file = match &mut self.file {
+            Some(file) => file,
+            file @ None => {
+                let mut opts = fs::OpenOptions::new();
+                let fd = opts.append(true).create(true).open(&self.file_path);
+                file.get_or_insert(err_at!(IOError, fd, "{:?}", self.file_path)?)
+            }
+        };
+        let size = err_at!(IOError, file.metadata())?.len();
+
+        let (mut fpos, mut refs) = (size, vec![]);
+        for (i, entry) in entries.iter().enumerate() {
+            if self.threshold < entry.as_op().len() && !entry.is_blob() {
+                let op = entry.as_op();
+                let length = err_at!(FailConvert, u64::try_from(op.len()))?;
+                let item = Ref { fpos, length, crc: util::crc32(op) };
+                if let Err(err) = util::write_all(file, op) {
+                    file.set_len(size).ok();
+                    return Err(err);
+                }
+                fpos += length;
+                refs.push((i, item));
+            }
+        }
+        if let Err(err) = err_at!(IOError, file.sync_all()) {
+            file.set_len(size).ok();
+            return Err(err);
+        }
+
+        for (i, item) in refs.into_iter() {
+            entries[i].set_blob(item.encode(), true);
+        }
+        Ok(Some(size))
+    }
+
+    /// Discard ops spilled after blob file was `size` bytes, when the
+    /// batch referring to them failed.
+    pub fn truncate(&mut self, size: u64) {
+        if let Some(file) = &mut self.file {
+            file.set_len(size).ok();
+        }
+    }
+}
+
+/// Read side of blob file, opened only when an entry refers to it.
+pub struct Reader {
+    file_path: ffi::OsString,
+    file: Option<fs::File>,
+}
+
+impl Reader {
+    pub fn new(journal_path: &ffi::OsStr) -> Reader {
+        Reader {
+            file_path: files::make_blob_filename(journal_path),
+            file: None,
+        }
+    }
+
+    /// Replace reference in `entry` with the op it refers to, after
+    /// verifying the op's checksum. Entries that are not spilled are
+    /// returned as is.
+    pub fn rehydrate(&mut self, mut entry: entry::Entry) -> Result<entry::Entry> {
+        if !entry.is_blob() {
+            return Ok(entry);
+        }
+
+        let item = Ref::decode(entry.as_op())?;
+        let file = match &mut self.file {
+            Some(file) => file,
+            file @ None => {
+                let fd = fs::OpenOptions::new().read(true).open(&self.file_path);
+                file.get_or_insert(err_at!(IOError, fd, "{:?}", self.file_path)?)
+            }
+        };
+        let len = err_at!(IOError, file.metadata(), "{:?}", self.file_path)?.len();
+        if item.length > len.saturating_sub(item.fpos) {
+            err_at!(
+                Corrupted,
+                msg: "blob for seqno {} at {} length {} beyond file size {} in {:?}",
+                entry.to_seqno(), item.fpos, item.length, len, self.file_path
+            )?
+        }
+        let mut op = vec![0; err_at!(FailConvert, usize::try_from(item.length))?];
+        err_at!(IOError, file.seek(SeekFrom::Start(item.fpos)))?;
+        err_at!(IOError, file.read_exact(&mut op), "{:?}", self.file_path)?;
+        if util::crc32(&op) != item.crc {
+            err_at!(
+                Invalid,
+                msg: "blob checksum mismatch for seqno {} in {:?}",
+                entry.to_seqno(), self.file_path
+            )?
+        }
+
+        entry.set_blob(op, false);
+        Ok(entry)
+    }
+}
@@ -0,0 +1,79 @@
+//! Module implement bloom filter over user tags of a journal, refer to
+//! [Wal::find_by_tag][crate::Wal::find_by_tag].
+//!
+//! Filter is persisted in the manifest along with the journal's seqno
+//! span, so that journals without a matching tag are skipped without
+//! consulting their batch index. Hashes are derived from CRC-32 of the
+//! tag, and stay the same across processes and releases.
+
+use crate::util;
+
+// Bits per distinct tag, for a false positive rate of about 1%.
+const BITS_PER_TAG: usize = 10;
+// Number of hashes per tag.
+const HASHES: u32 = 7;
+
+/// Bloom filter, encoded as number of hashes followed by the bit-array.
+/// Filter without any bits is empty, and contains no tag.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Bloom {
+    bits: Vec<u8>,
+}
+
+impl Bloom {
+    /// Build filter over `tags`, empty tags are ignored.
+    pub fn from_tags<'a, I>(tags: I) -> Bloom
+    where
+        I: Iterator<Item = &'a str>,
+    {
+        let mut tags: Vec<&str> = tags.filter(|tag| !tag.is_empty()).collect();
+        tags.sort_unstable();
+        tags.dedup();
+
+        let n = (tags.len() * BITS_PER_TAG).div_ceil(8);
+        let mut bloom = Bloom { bits: vec![0; n] };
+        for tag in tags.into_iter() {
+            for bit in bloom.to_bits(tag).collect::<Vec<usize>>() {
+                bloom.bits[bit / 8] |= 1 << (bit % 8);
+            }
+        }
+        bloom
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Option<Bloom> {
+        match data.split_first() {
+            Some((k, bits)) if u32::from(*k) == HASHES => {
+                Some(Bloom { bits: bits.to_vec() })
+            }
+            _ => None,
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(self.bits.len() + 1);
+        data.push(HASHES as u8);
+        data.extend_from_slice(&self.bits);
+        data
+    }
+
+    /// Return false if `tag` is definitely not in the filter.
+    pub fn contains(&self, tag: &str) -> bool {
+        !self.bits.is_empty()
+            && self.to_bits(tag).all(|bit| self.bits[bit / 8] & (1 << (bit % 8)) != 0)
+    }
+
+    // Kirsch-Mitzenmacher double hashing.
+    fn to_bits<'a>(&'a self, tag: &'a str) -> impl Iterator<Item = usize> + 'a {
+        let h1 = util::crc32(tag.as_bytes());
+        let h2 = util::crc32_update(h1, tag.as_bytes()) | 1;
+        let m = (self.bits.len() * 8) as u64;
+        (0..HASHES).map(move |i| {
+            let h = u64::from(h1).wrapping_add(u64::from(i).wrapping_mul(u64::from(h2)));
+            (h % m) as usize
+        })
+    }
+}
+
+#[cfg(test)]
+#[path = "bloom_test.rs"]
+mod bloom_test;
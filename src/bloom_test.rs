@@ -0,0 +1,21 @@
+use super::*;
+
+#[test]
+fn test_bloom() {
+    let tags: Vec<String> = (0..1000).map(|i| format!("user-{}", i)).collect();
+    let bloom = Bloom::from_tags(tags.iter().map(|t| t.as_str()));
+    assert!(tags.iter().all(|t| bloom.contains(t)));
+
+    let n = (1000..11000).filter(|i| bloom.contains(&format!("user-{}", i))).count();
+    assert!(n < 300, "false positives {}", n);
+
+    let val = Bloom::from_bytes(&bloom.to_bytes()).unwrap();
+    assert_eq!(val, bloom);
+    assert_eq!(Bloom::from_bytes(&[]), None);
+    assert_eq!(Bloom::from_bytes(&[HASHES as u8 + 1, 0xff]), None);
+
+    let bloom = Bloom::from_tags(vec!["", ""].into_iter());
+    assert!(!bloom.contains(""));
+    assert!(!bloom.contains("user-1"));
+    assert_eq!(Bloom::from_bytes(&bloom.to_bytes()), Some(bloom));
+}
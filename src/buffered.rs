@@ -0,0 +1,83 @@
+//! Module implement a client handle that batch ops locally, refer to
+//! [Wal::buffered_writer].
+
+use log::error;
+
+use std::{mem, ops};
+
+use crate::{wral::Wal, Result};
+
+/// Buffered handle to a [Wal] instance, created by [Wal::buffered_writer].
+///
+/// Ops are accumulated locally and added to the Wal as a single request,
+/// with contiguous seqnos, which cuts down channel traffic for workloads
+/// generating lots of tiny ops on a single thread. Buffered ops are not
+/// durable, nor visible to readers, until they are flushed. Dropping the
+/// handle shall flush buffered ops, and log errors if any, applications
+/// that care about failures should call [BufferedWriter::flush] instead.
+pub struct BufferedWriter<'a, S> {
+    wal: &'a Wal<S>,
+    capacity: usize,
+    ops: Vec<(String, Vec<u8>)>,
+}
+
+impl<'a, S> BufferedWriter<'a, S> {
+    pub(crate) fn new(wal: &'a Wal<S>, capacity: usize) -> BufferedWriter<'a, S> {
+        let capacity = capacity.max(1);
+        BufferedWriter { wal, capacity, ops: Vec::with_capacity(capacity) }
+    }
+
+    /// Buffer an operation, refer to [Wal::add_op]. If this fills up the
+    /// buffer, buffered ops are flushed and their seqnos are returned.
+    pub fn add_op(&mut self, op: &[u8]) -> Result<Option<ops::RangeInclusive<u64>>> {
+        self.add_op_to("", op)
+    }
+
+    /// Buffer an operation for `topic`, refer to [Wal::add_op_to]. If this
+    /// fills up the buffer, buffered ops are flushed and their seqnos are
+    /// returned.
+    pub fn add_op_to(
+        &mut self,
+        topic: &str,
+        op: &[u8],
+    ) -> Result<Option<ops::RangeInclusive<u64>>> {
+        self.ops.push((topic.to_string(), op.to_vec()));
+        match self.ops.len() {
+            n if n >= self.capacity => self.flush(),
+            _ => Ok(None),
+        }
+    }
+
+    /// Add buffered ops to the Wal, return their seqnos. Return None if
+    /// there was nothing to flush. On failure, buffered ops are discarded.
+    pub fn flush(&mut self) -> Result<Option<ops::RangeInclusive<u64>>> {
+        match self.ops.len() {
+            0 => Ok(None),
+            _ => {
+                let ops = mem::replace(&mut self.ops, Vec::with_capacity(self.capacity));
+                Ok(Some(self.wal.do_add_ops(ops)?))
+            }
+        }
+    }
+
+    /// Return the number of buffered ops.
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+}
+
+impl<'a, S> Drop for BufferedWriter<'a, S> {
+    fn drop(&mut self) {
+        if let Err(err) = self.flush() {
+            error!(target: "wral", "buffered writer, flush on drop failed: {}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+#[path = "buffered_test.rs"]
+mod buffered_test;
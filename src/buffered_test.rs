@@ -0,0 +1,49 @@
+use super::*;
+
+use crate::{state, wral::Config};
+
+#[test]
+fn test_buffered_writer() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-buffered-writer", dir.path().as_ref());
+    config.set_journal_limit(1000).set_fsync(false).set_client_batch_limit(4);
+
+    let wal = Wal::create(config.clone(), state::NoState).unwrap();
+    assert_eq!(wal.add_op(&[0]).unwrap(), 1);
+    {
+        let mut bw = wal.buffered_writer(10);
+        assert_eq!(bw.flush().unwrap(), None);
+        for i in 1..10_u8 {
+            assert_eq!(bw.add_op_to("topic", &[i]).unwrap(), None);
+        }
+        assert_eq!(bw.len(), 9);
+        assert_eq!(wal.add_op(&[100]).unwrap(), 2);
+        assert_eq!(bw.add_op(&[10]).unwrap(), Some(3..=12));
+        assert!(bw.is_empty());
+
+        bw.add_op(&[11]).unwrap();
+        bw.add_op(&[12]).unwrap();
+        assert_eq!(bw.flush().unwrap(), Some(13..=14));
+
+        bw.add_op(&[13]).unwrap();
+    }
+    assert_eq!(wal.add_op(&[101]).unwrap(), 16);
+
+    let ops: Vec<u8> = wal.iter().unwrap().map(|e| e.unwrap().unwrap().1[0]).collect();
+    let mut want = vec![0, 100];
+    want.extend(1..14_u8);
+    want.push(101);
+    assert_eq!(ops, want);
+    let n = wal.range_topic("topic", ..).unwrap().count();
+    assert_eq!(n, 9);
+
+    wal.close(false).unwrap();
+
+    let wal: Wal = Wal::open(config, crate::OpenMode::ReadOnly).unwrap();
+    assert!(wal.buffered_writer(10).add_op(&[0]).is_ok());
+    match wal.buffered_writer(1).add_op(&[0]) {
+        Err(crate::Error::ReadOnly(_, _)) => (),
+        res => panic!("expected ReadOnly {:?}", res),
+    }
+    wal.close(false).unwrap();
+}
@@ -0,0 +1,35 @@
+//! Module re-export the serialization traits required by [State], so that
+//! applications can implement them without depending on `mkit`, and
+//! without breaking when their `mkit` version differs from this package's.
+//!
+//! ```
+//! use wral::cbor::{Cbor, FromCbor, IntoCbor};
+//!
+//! #[derive(Clone, Default)]
+//! struct Count(u64);
+//!
+//! impl IntoCbor for Count {
+//!     fn into_cbor(self) -> wral::cbor::Result<Cbor> {
+//!         self.0.into_cbor()
+//!     }
+//! }
+//!
+//! impl FromCbor for Count {
+//!     fn from_cbor(val: Cbor) -> wral::cbor::Result<Count> {
+//!         Ok(Count(u64::from_cbor(val)?))
+//!     }
+//! }
+//!
+//! impl wral::State for Count {}
+//! ```
+//!
+//! Code generated by the [Cborize] derive refers to `mkit` by its crate
+//! name, applications using it shall depend on the `mkit` version
+//! re-exported as [wral::mkit][crate::mkit].
+//!
+//! [State]: crate::State
+
+pub use mkit::{
+    cbor::{Cbor, FromCbor, IntoCbor},
+    Cborize, Error, Result,
+};
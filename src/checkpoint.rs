@@ -0,0 +1,112 @@
+//! Module implement state checkpoints, refer to
+//! [Config::set_state_checkpoint][crate::Config::set_state_checkpoint].
+//!
+//! Writer periodically persists the application state, as of the latest
+//! durable seqno, under `dir/{name}.state`. On load, the checkpoint is
+//! preferred over the state carried by the last batch, when it is as new
+//! or newer, so that a state that fails to decode from the last batch of
+//! a journal doesn't have to be reset.
+
+use log::debug;
+use mkit::{
+    cbor::{Cbor, FromCbor, IntoCbor},
+    Cborize,
+};
+
+use std::{ffi, fs, path};
+
+use crate::{files, util, Error, Result};
+
+/// State of a Wal instance, as of `seqno` in `epoch`.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Cborize)]
+pub struct Checkpoint {
+    // name of the Wal instance.
+    name: String,
+    // seqno epoch of `seqno`.
+    epoch: u64,
+    // state is as of this seqno, inclusive.
+    seqno: u64,
+    // state as serialized bytes, in cbor format.
+    state: Vec<u8>,
+}
+
+impl Checkpoint {
+    const ID: u32 = 0x0;
+
+    pub fn new<S>(name: &str, epoch: u64, seqno: u64, state: S) -> Result<Checkpoint>
+    where
+        S: IntoCbor,
+    {
+        Ok(Checkpoint {
+            name: name.to_string(),
+            epoch,
+            seqno,
+            state: util::encode_cbor(state)?,
+        })
+    }
+
+    /// Load checkpoint from `dir`, return None if there is no checkpoint
+    /// file for `name`.
+    pub fn load(dir: &ffi::OsStr, name: &str) -> Result<Option<Checkpoint>> {
+        let file_path = Self::to_file_path(dir, name);
+        if !file_path.exists() {
+            return Ok(None);
+        }
+
+        let data = err_at!(IOError, fs::read(&file_path))?;
+        let (val, _) = Cbor::decode(&mut data.as_slice())?;
+        let checkpoint = Checkpoint::from_cbor(val)?;
+        if checkpoint.name != name {
+            err_at!(Invalid, msg: "checkpoint {:?} for {}", file_path, checkpoint.name)?
+        }
+
+        debug!(target: "wral", "loaded checkpoint {:?}", file_path);
+        Ok(Some(checkpoint))
+    }
+
+    /// Persist checkpoint under `dir`, atomically replacing the older one.
+    pub fn save(&self, dir: &ffi::OsStr) -> Result<()> {
+        let file_path = Self::to_file_path(dir, &self.name);
+        let data = util::encode_cbor(self.clone())?;
+        util::atomic_write(&file_path, &data)
+    }
+
+    /// Remove checkpoint file for `name` under `dir`, if present.
+    pub fn purge(dir: &ffi::OsStr, name: &str) -> Result<()> {
+        let file_path = Self::to_file_path(dir, name);
+        if file_path.exists() {
+            debug!(target: "wral", "purging {:?} ...", file_path);
+            err_at!(IOError, fs::remove_file(&file_path))?;
+        }
+        Ok(())
+    }
+
+    pub fn set_name(&mut self, name: &str) {
+        self.name = name.to_string();
+    }
+
+    pub fn to_epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    pub fn to_seqno(&self) -> u64 {
+        self.seqno
+    }
+
+    pub fn to_state<S>(&self) -> Result<S>
+    where
+        S: FromCbor,
+    {
+        let (val, _) = Cbor::decode(&mut self.state.as_slice())?;
+        Ok(S::from_cbor(val)?)
+    }
+
+    fn to_file_path(dir: &ffi::OsStr, name: &str) -> path::PathBuf {
+        let file = files::make_state_filename(name);
+        [dir, &file].iter().collect()
+    }
+}
+
+#[cfg(test)]
+#[path = "checkpoint_test.rs"]
+mod checkpoint_test;
@@ -0,0 +1,27 @@
+use super::*;
+
+#[test]
+fn test_checkpoint() {
+    let dir = tempfile::tempdir().unwrap();
+    let name = "test-checkpoint";
+
+    assert_eq!(Checkpoint::load(dir.path().as_ref(), name).unwrap(), None);
+
+    let mut cp = Checkpoint::new(name, 2, 100, "hello".to_string()).unwrap();
+    cp.save(dir.path().as_ref()).unwrap();
+    let val = Checkpoint::load(dir.path().as_ref(), name).unwrap().unwrap();
+    assert_eq!(val, cp);
+    assert_eq!((val.to_epoch(), val.to_seqno()), (2, 100));
+    assert_eq!(val.to_state::<String>().unwrap(), "hello".to_string());
+
+    cp.set_name("other");
+    cp.save(dir.path().as_ref()).unwrap();
+    let file = files::make_state_filename("other");
+    fs::rename(dir.path().join(file), dir.path().join(files::make_state_filename(name)))
+        .unwrap();
+    assert!(Checkpoint::load(dir.path().as_ref(), name).is_err());
+
+    Checkpoint::purge(dir.path().as_ref(), name).unwrap();
+    assert_eq!(Checkpoint::load(dir.path().as_ref(), name).unwrap(), None);
+    Checkpoint::purge(dir.path().as_ref(), name).unwrap();
+}
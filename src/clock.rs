@@ -0,0 +1,74 @@
+//! Module implement the source of timestamps and instance ids, refer to
+//! [Config::set_clock][crate::Config::set_clock].
+
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicU64, Ordering::SeqCst},
+        Arc,
+    },
+};
+
+use crate::util;
+
+/// Source of timestamps, stamped on batches and tombstones, and of
+/// instance ids, stamped on journals and manifest.
+///
+/// Default clock reads the system time and generates random instance ids.
+/// With the `testing` feature, a deterministic clock can be created via
+/// `Clock::fixed`, so that journals written by the same sequence of
+/// operations are byte-identical across runs, for golden-file tests of
+/// the on-disk format. Seqnos of a fresh instance always start from 1.
+#[derive(Clone, Default)]
+pub struct Clock {
+    // next reading and the step between readings, for a fixed clock.
+    fixed: Option<(Arc<AtomicU64>, u64)>,
+}
+
+impl fmt::Debug for Clock {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.fixed {
+            Some((next, step)) => {
+                write!(f, "Clock::fixed<next:{},step:{}>", next.load(SeqCst), step)
+            }
+            None => write!(f, "Clock::system"),
+        }
+    }
+}
+
+impl Clock {
+    /// Clock reading the system time.
+    pub fn system() -> Clock {
+        Clock::default()
+    }
+
+    /// Deterministic clock, whose first reading is `start`, in nanoseconds
+    /// since UNIX_EPOCH, and every subsequent reading is advanced by `step`.
+    /// Instance ids are derived from the clock reading. Clones of the clock
+    /// share the same readings.
+    #[cfg(any(test, feature = "testing"))]
+    pub fn fixed(start: u64, step: u64) -> Clock {
+        Clock {
+            fixed: Some((Arc::new(AtomicU64::new(start)), step)),
+        }
+    }
+
+    /// Return the current time in nanoseconds since UNIX_EPOCH.
+    pub fn to_nanos(&self) -> u64 {
+        match &self.fixed {
+            Some((next, step)) => next.fetch_add(*step, SeqCst),
+            None => util::unix_nanos(),
+        }
+    }
+
+    /// Return a new instance id, a random UUID for the system clock.
+    pub fn new_uuid(&self) -> String {
+        match &self.fixed {
+            Some(_) => {
+                let nanos = self.to_nanos();
+                util::format_uuid(nanos, util::crc32(&nanos.to_be_bytes()).into())
+            }
+            None => util::new_uuid(),
+        }
+    }
+}
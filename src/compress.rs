@@ -0,0 +1,123 @@
+//! Module implement compression for individual ops, refer to
+//! [Config::set_entry_compression][crate::Config::set_entry_compression].
+//!
+//! Ops are compressed with a byte oriented LZ77 scheme. Compressed op is
+//! encoded as varint length of the op, followed by a sequence of varint
+//! literal-length, literals, varint match-length and varint match-offset.
+//! Sequence ends with a match-length of ZERO.
+
+use std::convert::TryFrom;
+
+use crate::{entry, util, Error, Result};
+
+// Shortest match worth encoding.
+const MIN_MATCH: usize = 4;
+// Hash table of 16K positions, for finding matches.
+const HASH_BITS: u32 = 14;
+
+/// Compress ops larger than `threshold` bytes, ops that don't shrink are
+/// left as is. Ops already compressed, or spilled, are skipped.
+pub fn compress_entries(threshold: usize, entries: &mut [entry::Entry]) {
+    for entry in entries.iter_mut() {
+        let skip = entry.is_compressed() || entry.is_blob();
+        if skip || entry.as_op().len() <= threshold {
+            continue;
+        }
+        let op = compress(entry.as_op());
+        if op.len() < entry.as_op().len() {
+            entry.set_compressed(op, true)
+        }
+    }
+}
+
+/// Replace compressed op in `entry` with the original op. Entries that
+/// are not compressed are returned as is.
+pub fn decompress_entry(mut entry: entry::Entry) -> Result<entry::Entry> {
+    if entry.is_compressed() {
+        let op = decompress(entry.as_op())?;
+        entry.set_compressed(op, false);
+    }
+    Ok(entry)
+}
+
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() / 2 + 16);
+    util::encode_varint(data.len() as u64, &mut out);
+
+    let mut table = vec![usize::MAX; 1 << HASH_BITS];
+    let (mut off, mut anchor) = (0, 0);
+    while off + MIN_MATCH <= data.len() {
+        let h = hash(&data[off..off + MIN_MATCH]);
+        let cand = table[h];
+        table[h] = off;
+        if cand == usize::MAX
+            || data[cand..cand + MIN_MATCH] != data[off..off + MIN_MATCH]
+        {
+            off += 1;
+            continue;
+        }
+
+        let mut n = MIN_MATCH;
+        while off + n < data.len() && data[cand + n] == data[off + n] {
+            n += 1;
+        }
+        util::encode_varint((off - anchor) as u64, &mut out);
+        out.extend_from_slice(&data[anchor..off]);
+        util::encode_varint(n as u64, &mut out);
+        util::encode_varint((off - cand) as u64, &mut out);
+        off += n;
+        anchor = off;
+    }
+    util::encode_varint((data.len() - anchor) as u64, &mut out);
+    out.extend_from_slice(&data[anchor..]);
+    util::encode_varint(0, &mut out);
+
+    out
+}
+
+pub fn decompress(data: &[u8]) -> Result<Vec<u8>> {
+    let mut off = 0;
+    let size = to_usize(util::decode_varint(data, &mut off)?)?;
+    // size is not trusted for allocation, until the op is decoded.
+    let mut out: Vec<u8> = Vec::with_capacity(size.min(data.len().saturating_mul(4)));
+    loop {
+        let n = to_usize(util::decode_varint(data, &mut off)?)?;
+        match data.get(off..off.saturating_add(n)) {
+            Some(literals) if n <= size - out.len() => out.extend_from_slice(literals),
+            _ => err_at!(FailConvert, msg: "invalid literals {} at {}", n, off)?,
+        }
+        off += n;
+
+        let n = to_usize(util::decode_varint(data, &mut off)?)?;
+        if n == 0 {
+            break;
+        }
+        let dist = to_usize(util::decode_varint(data, &mut off)?)?;
+        if dist == 0 || dist > out.len() || n > size - out.len() {
+            err_at!(FailConvert, msg: "invalid match {}/{} at {}", n, dist, off)?
+        }
+        // match can overlap with the bytes it produces.
+        let start = out.len() - dist;
+        for i in 0..n {
+            out.push(out[start + i]);
+        }
+    }
+
+    if out.len() != size || off != data.len() {
+        err_at!(FailConvert, msg: "decompressed {}/{} bytes", out.len(), size)?
+    }
+    Ok(out)
+}
+
+fn hash(data: &[u8]) -> usize {
+    let key = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+    (key.wrapping_mul(2_654_435_761) >> (32 - HASH_BITS)) as usize
+}
+
+fn to_usize(n: u64) -> Result<usize> {
+    err_at!(FailConvert, usize::try_from(n))
+}
+
+#[cfg(test)]
+#[path = "compress_test.rs"]
+mod compress_test;
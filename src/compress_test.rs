@@ -0,0 +1,55 @@
+use rand::{prelude::random, rngs::StdRng, Rng, SeedableRng};
+
+use super::*;
+
+#[test]
+fn test_compress() {
+    let seed: u64 = random();
+    println!("test_compress {}", seed);
+    let mut rng = StdRng::seed_from_u64(seed);
+
+    let mut samples: Vec<Vec<u8>> =
+        vec![vec![], vec![1], vec![7; 10_000], b"abcabcabc".to_vec()];
+    for _i in 0..100 {
+        let n = rng.gen::<usize>() % 10_000;
+        // random bytes from a small alphabet, so that some of them repeat.
+        let alphabet = 1 + (rng.gen::<u8>() % 255);
+        samples.push((0..n).map(|_| rng.gen::<u8>() % alphabet).collect());
+    }
+
+    for data in samples.into_iter() {
+        let cdata = compress(&data);
+        assert_eq!(decompress(&cdata).unwrap(), data);
+        if data.len() > 1000 && data.iter().all(|b| *b == data[0]) {
+            assert!(cdata.len() < 100, "{}", cdata.len());
+        }
+        // truncated and corrupted data fail to decompress.
+        if !cdata.is_empty() {
+            assert!(decompress(&cdata[..cdata.len() - 1]).is_err());
+        }
+        let mut bad = cdata.clone();
+        bad.push(0);
+        assert!(decompress(&bad).is_err());
+    }
+}
+
+#[test]
+fn test_compress_entries() {
+    let mut entries = vec![
+        entry::Entry::new(1, vec![1; 100]),
+        entry::Entry::new(2, vec![2; 10]),
+        entry::Entry::new(3, (0..=255).collect()),
+    ];
+    let refs = entries.clone();
+    compress_entries(50, &mut entries);
+
+    let flags: Vec<bool> = entries.iter().map(|e| e.is_compressed()).collect();
+    assert_eq!(flags, vec![true, false, false]);
+    assert!(entries[0].as_op().len() < 100);
+
+    for (entry, refe) in entries.into_iter().zip(refs) {
+        let entry = decompress_entry(entry).unwrap();
+        assert!(!entry.is_compressed());
+        assert_eq!(entry.as_op(), refe.as_op());
+    }
+}
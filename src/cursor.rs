@@ -0,0 +1,120 @@
+//! Module implement replay cursors, refer to
+//! [Wal::replay_from_cursor][crate::Wal::replay_from_cursor].
+
+use log::debug;
+use mkit::{
+    cbor::{Cbor, FromCbor},
+    Cborize,
+};
+
+use std::{ffi, fs, path};
+
+use crate::{files, util, wral::Config, Error, Result};
+
+/// Replay progress of a consumer, persisted next to the journals under
+/// `dir/{name}-cursor-{consumer}.cbor`, and atomically replaced on every
+/// commit.
+#[derive(Debug, Clone)]
+pub struct ReplayCursor {
+    dir: ffi::OsString,
+    progress: Progress,
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq, Cborize)]
+struct Progress {
+    // name of the Wal instance.
+    name: String,
+    // name of the consumer replaying the Wal instance.
+    consumer: String,
+    // seqno epoch of `seqno`.
+    epoch: u64,
+    // entries upto and including this seqno are applied.
+    seqno: u64,
+}
+
+impl Progress {
+    const ID: u32 = 0x0;
+}
+
+impl ReplayCursor {
+    /// Load the cursor for `consumer` of the Wal instance configured by
+    /// `config`. A new cursor, that is yet to apply any entry, is returned
+    /// if there is none on disk.
+    pub fn open(config: &Config, consumer: &str) -> Result<ReplayCursor> {
+        if consumer.is_empty() {
+            err_at!(Invalid, msg: "empty consumer name for {:?}", config.name)?
+        }
+
+        let dir = config.dir.clone();
+        let file_path = Self::to_file_path(&dir, &config.name, consumer);
+        let progress = match file_path.exists() {
+            true => {
+                let data = err_at!(IOError, fs::read(&file_path))?;
+                let (val, _) = Cbor::decode(&mut data.as_slice())?;
+                let progress = Progress::from_cbor(val)?;
+                if progress.name != config.name || progress.consumer != consumer {
+                    err_at!(
+                        Invalid,
+                        msg: "cursor {:?} for {}/{}", file_path, progress.name, progress.consumer
+                    )?
+                }
+                debug!(target: "wral", "loaded cursor {:?}", file_path);
+                progress
+            }
+            false => Progress {
+                name: config.name.clone(),
+                consumer: consumer.to_string(),
+                epoch: 0,
+                seqno: 0,
+            },
+        };
+
+        Ok(ReplayCursor { dir, progress })
+    }
+
+    /// Return the name of the consumer.
+    pub fn as_consumer(&self) -> &str {
+        &self.progress.consumer
+    }
+
+    /// Return the seqno epoch of the last applied entry.
+    pub fn to_epoch(&self) -> u64 {
+        self.progress.epoch
+    }
+
+    /// Return the seqno of the last applied entry, ZERO if none.
+    pub fn to_seqno(&self) -> u64 {
+        self.progress.seqno
+    }
+
+    /// Record that entries upto and including `seqno`, in `epoch`, are
+    /// applied. Cursor is persisted before returning.
+    pub fn commit(&mut self, epoch: u64, seqno: u64) -> Result<()> {
+        let mut progress = self.progress.clone();
+        progress.epoch = epoch;
+        progress.seqno = seqno;
+
+        let file_path = self.as_file_path();
+        util::atomic_write(&file_path, &util::encode_cbor(progress.clone())?)?;
+        self.progress = progress;
+        Ok(())
+    }
+
+    /// Remove the cursor from disk, say after the consumer is retired.
+    pub fn remove(self) -> Result<()> {
+        let file_path = self.as_file_path();
+        if file_path.exists() {
+            err_at!(IOError, fs::remove_file(&file_path))?;
+        }
+        Ok(())
+    }
+
+    fn as_file_path(&self) -> path::PathBuf {
+        Self::to_file_path(&self.dir, &self.progress.name, &self.progress.consumer)
+    }
+
+    fn to_file_path(dir: &ffi::OsStr, name: &str, consumer: &str) -> path::PathBuf {
+        let file = files::make_cursor_filename(name, consumer);
+        [dir, &file].iter().collect()
+    }
+}
@@ -0,0 +1,167 @@
+//! Module implement anti-entropy between replicas, refer to
+//! [Wal::diff][crate::Wal::diff].
+//!
+//! Replicas exchange a list of [BatchMeta], seqno span of a batch along
+//! with a hash of its entries. Batch boundaries are local to a replica,
+//! hence hashes are compared by re-hashing local entries over the spans
+//! reported by the remote.
+
+use mkit::Cborize;
+
+use std::ops;
+
+use crate::{entry, util, Error, Result};
+
+/// Seqno span of a batch and the hash of entries within it, refer to
+/// [Wal::batch_meta][crate::Wal::batch_meta].
+#[derive(Debug, Clone, Default, Eq, PartialEq, Cborize)]
+pub struct BatchMeta {
+    /// First seqno in the batch.
+    pub first_seqno: u64,
+    /// Last seqno in the batch.
+    pub last_seqno: u64,
+    /// CRC-32 over the seqno, topic, tag and op of entries in the batch.
+    pub hash: u32,
+}
+
+impl BatchMeta {
+    const ID: u32 = 0x0;
+
+    pub fn to_range(&self) -> ops::RangeInclusive<u64> {
+        self.first_seqno..=self.last_seqno
+    }
+}
+
+/// Local entries to be shipped to a remote replica, refer to
+/// [Wal::diff][crate::Wal::diff]. Ranges are sorted and disjoint.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct ShipPlan {
+    /// Seqno spans of local batches not covered by any remote batch.
+    pub missing: Vec<ops::RangeInclusive<u64>>,
+    /// Seqno spans of remote batches whose hash differ from local entries
+    /// in the same span.
+    pub mismatched: Vec<ops::RangeInclusive<u64>>,
+}
+
+impl ShipPlan {
+    /// Return whether the remote is in sync with local entries.
+    pub fn is_empty(&self) -> bool {
+        self.missing.is_empty() && self.mismatched.is_empty()
+    }
+
+    /// Return missing and mismatched spans, merged into sorted disjoint
+    /// ranges, that can be read using [Wal::range][crate::Wal::range].
+    pub fn to_ranges(&self) -> Vec<ops::RangeInclusive<u64>> {
+        let mut ranges: Vec<ops::RangeInclusive<u64>> =
+            self.missing.iter().chain(self.mismatched.iter()).cloned().collect();
+        ranges.sort_by_key(|r| *r.start());
+        merge(ranges)
+    }
+}
+
+/// Hash entries falling within each of `ranges`, which must be sorted and
+/// disjoint. `entries` must be in seqno order.
+pub fn hash_ranges<I>(ranges: &[ops::RangeInclusive<u64>], entries: I) -> Result<Vec<u32>>
+where
+    I: Iterator<Item = Result<entry::Entry>>,
+{
+    let mut hashes = vec![0; ranges.len()];
+    let mut i = 0;
+    for entry in entries {
+        let entry = entry?;
+        let seqno = entry.to_seqno();
+        while i < ranges.len() && *ranges[i].end() < seqno {
+            i += 1;
+        }
+        match ranges.get(i) {
+            Some(range) if range.contains(&seqno) => {
+                hashes[i] = hash_entry(hashes[i], &entry);
+            }
+            Some(_) => (),
+            None => break,
+        }
+    }
+    Ok(hashes)
+}
+
+/// Sort remote batches and make sure they don't overlap.
+pub fn sort_remote(remote: &[BatchMeta]) -> Result<Vec<BatchMeta>> {
+    let mut remote = remote.to_vec();
+    remote.sort_by_key(|m| m.first_seqno);
+    for m in remote.iter() {
+        if m.first_seqno > m.last_seqno {
+            err_at!(Invalid, msg: "remote batch {}..={}", m.first_seqno, m.last_seqno)?
+        }
+    }
+    for w in remote.windows(2) {
+        if w[1].first_seqno <= w[0].last_seqno {
+            err_at!(
+                Invalid,
+                msg: "remote batches overlap at {}..={}", w[1].first_seqno, w[0].last_seqno
+            )?
+        }
+    }
+    Ok(remote)
+}
+
+/// Return portions of `spans` not covered by `covered`, both sorted and
+/// disjoint, merged into sorted disjoint ranges.
+pub fn subtract(
+    spans: &[ops::RangeInclusive<u64>],
+    covered: &[ops::RangeInclusive<u64>],
+) -> Vec<ops::RangeInclusive<u64>> {
+    let mut out = vec![];
+    let mut j = 0;
+    for span in spans.iter() {
+        let (mut start, end) = (*span.start(), *span.end());
+        while j < covered.len() && *covered[j].end() < start {
+            j += 1;
+        }
+        let mut k = j;
+        while start <= end {
+            match covered.get(k) {
+                Some(c) if *c.start() <= end => {
+                    if start < *c.start() {
+                        out.push(start..=(*c.start() - 1));
+                    }
+                    match c.end().checked_add(1) {
+                        Some(next) => start = start.max(next),
+                        None => break,
+                    }
+                    k += 1;
+                }
+                _ => {
+                    out.push(start..=end);
+                    break;
+                }
+            }
+        }
+    }
+    merge(out)
+}
+
+fn hash_entry(crc: u32, entry: &entry::Entry) -> u32 {
+    let mut crc = util::crc32_update(crc, &entry.to_seqno().to_be_bytes());
+    for field in [entry.as_topic().as_bytes(), entry.as_tag().as_bytes()].iter() {
+        crc = util::crc32_update(crc, &(field.len() as u64).to_be_bytes());
+        crc = util::crc32_update(crc, field);
+    }
+    util::crc32_update(crc, entry.as_op())
+}
+
+fn merge(ranges: Vec<ops::RangeInclusive<u64>>) -> Vec<ops::RangeInclusive<u64>> {
+    let mut out: Vec<ops::RangeInclusive<u64>> = vec![];
+    for r in ranges.into_iter() {
+        match out.last_mut() {
+            Some(last) if r.start().saturating_sub(1) <= *last.end() => {
+                *last = *last.start()..=(*last.end()).max(*r.end());
+            }
+            _ => out.push(r),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+#[path = "diff_test.rs"]
+mod diff_test;
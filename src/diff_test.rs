@@ -0,0 +1,37 @@
+use super::*;
+
+#[test]
+fn test_subtract() {
+    let spans = vec![1..=10, 11..=20, 30..=40, 50..=60];
+    let covered = vec![0..=3, 8..=12, 35..=35, 55..=u64::MAX];
+    let out = subtract(&spans, &covered);
+    assert_eq!(out, vec![4..=7, 13..=20, 30..=34, 36..=40, 50..=54]);
+
+    assert_eq!(subtract(&spans, &[]), vec![1..=20, 30..=40, 50..=60]);
+    assert_eq!(subtract(&spans, &[0..=u64::MAX]), vec![]);
+    assert_eq!(subtract(&[], &covered), vec![]);
+}
+
+#[test]
+fn test_ship_plan() {
+    let plan = ShipPlan {
+        missing: vec![10..=20, 41..=50],
+        mismatched: vec![1..=5, 21..=30, 45..=60],
+    };
+    assert!(!plan.is_empty());
+    assert_eq!(plan.to_ranges(), vec![1..=5, 10..=30, 41..=60]);
+    assert!(ShipPlan::default().is_empty());
+
+    let remote = vec![
+        BatchMeta { first_seqno: 5, last_seqno: 9, hash: 0 },
+        BatchMeta { first_seqno: 1, last_seqno: 4, hash: 0 },
+    ];
+    let sorted = sort_remote(&remote).unwrap();
+    assert_eq!(sorted[0].to_range(), 1..=4);
+
+    let remote = vec![
+        BatchMeta { first_seqno: 1, last_seqno: 5, hash: 0 },
+        BatchMeta { first_seqno: 5, last_seqno: 9, hash: 0 },
+    ];
+    assert!(matches!(sort_remote(&remote), Err(Error::Invalid(_, _))));
+}
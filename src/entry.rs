@@ -1,5 +1,9 @@
-use arbitrary::Arbitrary;
-use mkit::Cborize;
+#[cfg(any(test, feature = "testing"))]
+use arbitrary::{Arbitrary, Unstructured};
+use mkit::{
+    cbor::{Cbor, FromCbor, IntoCbor, Tag},
+    Cborize,
+};
 
 use std::{
     cmp,
@@ -7,14 +11,46 @@ use std::{
     result,
 };
 
+use crate::Result;
+
 /// Single Op-entry in Write-ahead-log.
-#[derive(Debug, Clone, Default, Cborize, Arbitrary)]
+#[derive(Debug, Clone, Default, Cborize)]
 pub struct Entry {
     // Index seqno for this entry. This will be monotonically
     // increasing number.
     seqno: u64,
     // Operation to be logged.
     op: Vec<u8>,
+    // Topic for this entry, empty string for default topic.
+    topic: String,
+    // User tag for this entry, empty string if untagged.
+    tag: String,
+    // Time the entry was created, in nanoseconds since UNIX_EPOCH, ZERO
+    // if not known.
+    timestamp: u64,
+    // Op is spilled to the journal's blob file, and `op` holds a
+    // reference to it.
+    blob: bool,
+    // Op is compressed, refer to compress module.
+    compressed: bool,
+}
+
+// Spilled and compressed entries are never generated, their op is not
+// arbitrary.
+#[cfg(any(test, feature = "testing"))]
+impl Arbitrary for Entry {
+    fn arbitrary(u: &mut Unstructured) -> arbitrary::Result<Self> {
+        let entry = Entry {
+            seqno: u.arbitrary()?,
+            op: u.arbitrary()?,
+            topic: u.arbitrary()?,
+            tag: u.arbitrary()?,
+            timestamp: u.arbitrary()?,
+            blob: false,
+            compressed: false,
+        };
+        Ok(entry)
+    }
 }
 
 impl Eq for Entry {}
@@ -44,11 +80,44 @@ impl Ord for Entry {
 }
 
 impl Entry {
-    const ID: u32 = 0x0;
+    const ID: u32 = 0x1;
 
     #[inline]
     pub fn new(seqno: u64, op: Vec<u8>) -> Entry {
-        Entry { seqno, op }
+        Entry {
+            seqno,
+            op,
+            topic: String::default(),
+            tag: String::default(),
+            timestamp: 0,
+            blob: false,
+            compressed: false,
+        }
+    }
+
+    #[inline]
+    pub fn new_topic(seqno: u64, topic: String, op: Vec<u8>) -> Entry {
+        Entry {
+            seqno,
+            op,
+            topic,
+            tag: String::default(),
+            timestamp: 0,
+            blob: false,
+            compressed: false,
+        }
+    }
+
+    #[inline]
+    pub fn set_tag(mut self, tag: String) -> Entry {
+        self.tag = tag;
+        self
+    }
+
+    #[inline]
+    pub(crate) fn set_timestamp(mut self, timestamp: u64) -> Entry {
+        self.timestamp = timestamp;
+        self
     }
 
     #[inline]
@@ -56,10 +125,162 @@ impl Entry {
         self.seqno
     }
 
+    /// Return the topic of this entry, empty string for default topic.
+    #[inline]
+    pub fn as_topic(&self) -> &str {
+        &self.topic
+    }
+
+    /// Return the tag of this entry, empty string if untagged.
+    #[inline]
+    pub fn as_tag(&self) -> &str {
+        &self.tag
+    }
+
+    /// Return the time this entry was created, in nanoseconds since
+    /// UNIX_EPOCH, ZERO if not known. Refer to [EntryBuilder::timestamp].
+    #[inline]
+    pub fn to_timestamp(&self) -> u64 {
+        self.timestamp
+    }
+
+    /// Return the operation logged by this entry.
+    #[inline]
+    pub fn as_op(&self) -> &[u8] {
+        &self.op
+    }
+
+    #[inline]
+    pub(crate) fn set_op(&mut self, op: Vec<u8>) {
+        self.op = op;
+    }
+
+    /// Mark `op` as a reference to the op spilled into blob file, or as
+    /// the op itself.
+    #[inline]
+    pub(crate) fn set_blob(&mut self, op: Vec<u8>, blob: bool) {
+        self.op = op;
+        self.blob = blob;
+    }
+
+    #[inline]
+    pub(crate) fn is_blob(&self) -> bool {
+        self.blob
+    }
+
+    /// Mark `op` as compressed, or as the op itself.
+    #[inline]
+    pub(crate) fn set_compressed(&mut self, op: Vec<u8>, compressed: bool) {
+        self.op = op;
+        self.compressed = compressed;
+    }
+
+    #[inline]
+    pub(crate) fn is_compressed(&self) -> bool {
+        self.compressed
+    }
+
     #[inline]
     pub fn unwrap(self) -> (u64, Vec<u8>) {
         (self.seqno, self.op)
     }
+
+    /// Same as `from_cbor`, but entries encoded in the layout prior to
+    /// entry ID 0x1 are also decoded, with topic, tag and timestamp
+    /// left as default.
+    pub fn decode(value: Cbor) -> Result<Entry> {
+        let items = Vec::<Cbor>::from_cbor(value)?;
+        let id_v0: Cbor = Tag::from_identifier(EntryV0::ID.into_cbor()?).into();
+        let v0 = items.first() == Some(&id_v0);
+        let value = items.into_cbor()?;
+        match v0 {
+            true => Ok(EntryV0::from_cbor(value)?.into()),
+            false => Ok(Entry::from_cbor(value)?),
+        }
+    }
+}
+
+/// Entry layout prior to entry ID 0x1, that is, before entries carried
+/// topic, tag, timestamp, blob and compressed fields.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Cborize)]
+pub(crate) struct EntryV0 {
+    pub(crate) seqno: u64,
+    pub(crate) op: Vec<u8>,
+}
+
+impl EntryV0 {
+    const ID: u32 = 0x0;
+}
+
+impl From<EntryV0> for Entry {
+    fn from(entry: EntryV0) -> Entry {
+        Entry::new(entry.seqno, entry.op)
+    }
+}
+
+/// Builder for [Entry], with all metadata in one place, refer to
+/// [Wal::ingest_entries][crate::Wal::ingest_entries].
+///
+/// ```
+/// use wral::EntryBuilder;
+///
+/// let (header, body) = (b"hdr:".as_ref(), b"body".as_ref());
+/// let entry = EntryBuilder::new(10)
+///     .tag("user-1")
+///     .timestamp(1_600_000_000_000_000_000)
+///     .payload_parts(&[header, body])
+///     .build();
+/// assert_eq!(entry.as_op(), b"hdr:body");
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct EntryBuilder {
+    entry: Entry,
+}
+
+impl EntryBuilder {
+    /// Start building an entry for `seqno`, with an empty op in the
+    /// default topic.
+    pub fn new(seqno: u64) -> EntryBuilder {
+        EntryBuilder { entry: Entry::new(seqno, Vec::default()) }
+    }
+
+    pub fn topic<T: Into<String>>(mut self, topic: T) -> EntryBuilder {
+        self.entry.topic = topic.into();
+        self
+    }
+
+    pub fn tag<T: Into<String>>(mut self, tag: T) -> EntryBuilder {
+        self.entry.tag = tag.into();
+        self
+    }
+
+    /// Time the entry was created, in nanoseconds since UNIX_EPOCH.
+    pub fn timestamp(mut self, timestamp: u64) -> EntryBuilder {
+        self.entry.timestamp = timestamp;
+        self
+    }
+
+    /// Use `op` as the payload, without copying it.
+    pub fn payload(mut self, op: Vec<u8>) -> EntryBuilder {
+        self.entry.op = op;
+        self
+    }
+
+    /// Gather `parts`, in order, into the payload, allocating it once,
+    /// instead of concatenating them ahead of building the entry.
+    pub fn payload_parts(mut self, parts: &[&[u8]]) -> EntryBuilder {
+        let n = parts.iter().map(|part| part.len()).sum();
+        let mut op = Vec::with_capacity(n);
+        for part in parts.iter() {
+            op.extend_from_slice(part);
+        }
+        self.entry.op = op;
+        self
+    }
+
+    pub fn build(self) -> Entry {
+        self.entry
+    }
 }
 
 #[cfg(test)]
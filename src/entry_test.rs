@@ -48,3 +48,59 @@ fn test_entry() {
         seqno = entry.seqno
     }
 }
+
+#[test]
+fn test_entry_v0() {
+    use mkit::cbor::Cbor;
+
+    let entry = EntryV0 { seqno: 10, op: b"hello".to_vec() };
+    let mut buf: Vec<u8> = vec![];
+    entry.into_cbor().unwrap().encode(&mut buf).unwrap();
+
+    let (val, _) = Cbor::decode(&mut buf.as_slice()).unwrap();
+    assert!(Entry::from_cbor(val.clone()).is_err());
+
+    let entry = Entry::decode(val).unwrap();
+    assert_eq!(entry.to_seqno(), 10);
+    assert_eq!(entry.as_op(), b"hello");
+    assert_eq!(entry.as_topic(), "");
+    assert_eq!(entry.as_tag(), "");
+    assert_eq!(entry.to_timestamp(), 0);
+    assert!(!entry.is_blob() && !entry.is_compressed());
+
+    let entry = EntryBuilder::new(11).topic("t").tag("x").payload(vec![1]).build();
+    let data = crate::util::encode_cbor(entry.clone()).unwrap();
+    let (val, _) = Cbor::decode(&mut data.as_slice()).unwrap();
+    let entr = Entry::decode(val).unwrap();
+    assert_eq!(entr, entry);
+    assert_eq!((entr.as_topic(), entr.as_tag()), ("t", "x"));
+}
+
+#[test]
+fn test_entry_builder() {
+    let entry = EntryBuilder::new(10).build();
+    assert_eq!(entry.to_seqno(), 10);
+    assert_eq!(entry.as_topic(), "");
+    assert_eq!(entry.as_tag(), "");
+    assert_eq!(entry.to_timestamp(), 0);
+    assert!(entry.as_op().is_empty());
+
+    let op = vec![1, 2, 3];
+    let ptr = op.as_ptr();
+    let entry = EntryBuilder::new(11)
+        .topic("topic")
+        .tag(String::from("tag"))
+        .timestamp(1234)
+        .payload(op)
+        .build();
+    assert_eq!(entry.as_op().as_ptr(), ptr);
+    assert_eq!(entry.as_topic(), "topic");
+    assert_eq!(entry.as_tag(), "tag");
+    assert_eq!(entry.to_timestamp(), 1234);
+
+    let parts: [&[u8]; 3] = [b"abc", b"", b"de"];
+    let entry = EntryBuilder::new(12).payload_parts(&parts).build();
+    assert_eq!(entry.as_op(), b"abcde");
+    let entry = EntryBuilder::new(12).payload_parts(&[]).build();
+    assert!(entry.as_op().is_empty());
+}
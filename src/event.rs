@@ -0,0 +1,144 @@
+//! Module implement structured events emitted by [Wal][crate::Wal]
+//! instances, refer to [Wal::events][crate::Wal::events].
+
+use std::{
+    collections::VecDeque,
+    ffi,
+    sync::{mpsc, Mutex},
+};
+
+use crate::{Error, Result, RotateCause};
+
+/// Maximum number of events held back, while there are no subscribers.
+pub(crate) const EVENT_BUFFER: usize = 1024;
+
+/// Events emitted by a [Wal][crate::Wal] instance.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum WalEvent {
+    /// A new journal file is created, on create, on load and on rotation.
+    JournalCreated { num: usize, file: ffi::OsString },
+    /// Journal `num` is archived for `cause`, new entries are appended to
+    /// journal `next`.
+    JournalRotated {
+        num: usize,
+        next: usize,
+        cause: RotateCause,
+    },
+    /// Journal file is removed from disk, after purge, or on load if it is
+    /// empty, refer to [EmptyPolicy::Remove][crate::EmptyPolicy::Remove].
+    JournalPurged { num: usize, file: ffi::OsString },
+    /// Instance is loaded from disk, with `journals` archived journals,
+    /// `batches` batches and `seqno` as the last seqno.
+    Loaded {
+        journals: usize,
+        batches: usize,
+        seqno: u64,
+    },
+    /// Journal file matching the instance name could not be loaded, and is
+    /// ignored.
+    Corruption { file: ffi::OsString },
+    /// Latest journal `file` ended with a partially written batch, from
+    /// `offset`, that was left behind by a crash. The torn `bytes` are
+    /// truncated and batches before them are loaded. Refer to
+    /// [Wal::recovery_report][crate::Wal::recovery_report].
+    TornTail {
+        file: ffi::OsString,
+        offset: u64,
+        bytes: u64,
+    },
+    /// Journal `file` has the same journal number as another journal, that
+    /// is more consistent with the manifest and seqnos. It is ignored, and
+    /// renamed to `to`, unless the instance is opened read-only.
+    Quarantined {
+        num: usize,
+        file: ffi::OsString,
+        to: Option<ffi::OsString>,
+    },
+    /// Application state persisted in journal file could not be decoded,
+    /// entries are loaded with default state. Refer to
+    /// [StatePolicy][crate::StatePolicy].
+    StateReset { file: ffi::OsString },
+    /// Batch of entries `first_seqno..=last_seqno`, when encoded with the
+    /// shadow codec, didn't decode back to identical entries. Refer to
+    /// [Config::set_shadow_codec][crate::Config::set_shadow_codec].
+    ShadowMismatch {
+        first_seqno: u64,
+        last_seqno: u64,
+        reason: String,
+    },
+    /// Writer thread is falling behind, `backlog` requests are pending.
+    BackpressureOn { backlog: usize },
+    /// Writer thread has caught up with pending requests.
+    BackpressureOff,
+    /// Archived journal `num` is verified by background maintenance,
+    /// `clean` is false if an inconsistency was found. Refer to
+    /// [Config::set_maintenance][crate::Config::set_maintenance].
+    Scrubbed {
+        num: usize,
+        file: ffi::OsString,
+        clean: bool,
+    },
+    /// File system holding `dir`, where the next journal is to be created,
+    /// has `available` bytes, short of the `required` bytes. Refer to
+    /// [Config::set_space_policy][crate::Config::set_space_policy].
+    LowSpace {
+        dir: ffi::OsString,
+        available: u64,
+        required: u64,
+    },
+    /// Application state, serialized as `size` bytes in the batch upto
+    /// `seqno`, of `length` bytes, exceeds the warning threshold. Emitted
+    /// once, till state shrinks within the threshold again. Refer to
+    /// [Config::set_state_size_warning][crate::Config::set_state_size_warning].
+    StateOversize {
+        seqno: u64,
+        size: usize,
+        length: usize,
+    },
+}
+
+// Fan-out events to subscribers. Events emitted while there are no
+// subscribers, like the ones emitted while loading an instance, are held
+// back for the first subscriber.
+pub(crate) struct Events {
+    inner: Mutex<Inner>,
+}
+
+struct Inner {
+    subscribers: Vec<mpsc::Sender<WalEvent>>,
+    // events held back, while there are no subscribers.
+    pending: VecDeque<WalEvent>,
+}
+
+impl Events {
+    pub(crate) fn new() -> Events {
+        let inner = Inner { subscribers: vec![], pending: VecDeque::default() };
+        Events { inner: Mutex::new(inner) }
+    }
+
+    pub(crate) fn subscribe(&self) -> Result<mpsc::Receiver<WalEvent>> {
+        let mut inner = err_at!(Fatal, self.inner.lock())?;
+        let (tx, rx) = mpsc::channel();
+        for event in inner.pending.drain(..) {
+            tx.send(event).ok();
+        }
+        inner.subscribers.push(tx);
+        Ok(rx)
+    }
+
+    // Emitting events never fails, subscribers that have dropped their
+    // receiver are removed.
+    pub(crate) fn emit(&self, event: WalEvent) {
+        let mut inner = match self.inner.lock() {
+            Ok(inner) => inner,
+            Err(_) => return,
+        };
+        inner.subscribers.retain(|tx| tx.send(event.clone()).is_ok());
+        if inner.subscribers.is_empty() {
+            if inner.pending.len() >= EVENT_BUFFER {
+                inner.pending.pop_front();
+            }
+            inner.pending.push_back(event);
+        }
+    }
+}
@@ -0,0 +1,140 @@
+//! Module implement exporting entries in formats that can be consumed
+//! without linking this package, refer to [Wal::export_jsonl] and
+//! [Wal::export_cbor_seq].
+//!
+//! [Wal::export_jsonl]: crate::Wal::export_jsonl
+//! [Wal::export_cbor_seq]: crate::Wal::export_cbor_seq
+
+use std::io;
+
+use crate::{entry, Error, Result};
+
+const BASE64: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+const HEX: &[u8; 16] = b"0123456789abcdef";
+
+/// Encoding of ops in JSON Lines export, refer to
+/// [Wal::export_jsonl][crate::Wal::export_jsonl].
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub enum OpEncoding {
+    /// Standard base64 alphabet, with padding.
+    #[default]
+    Base64,
+    /// Lower case hex digits, two per byte.
+    Hex,
+}
+
+/// Write `entry` as a JSON object, on a line of its own, with fields
+/// `seqno`, `timestamp`, `topic`, `tag` and `op`.
+pub fn write_jsonl<W>(entry: &entry::Entry, encoding: OpEncoding, w: &mut W) -> Result<()>
+where
+    W: io::Write,
+{
+    let mut line = format!(
+        "{{\"seqno\":{},\"timestamp\":{},\"topic\":",
+        entry.to_seqno(),
+        entry.to_timestamp()
+    );
+    push_json_str(entry.as_topic(), &mut line);
+    line.push_str(",\"tag\":");
+    push_json_str(entry.as_tag(), &mut line);
+    line.push_str(",\"op\":\"");
+    match encoding {
+        OpEncoding::Base64 => push_base64(entry.as_op(), &mut line),
+        OpEncoding::Hex => push_hex(entry.as_op(), &mut line),
+    }
+    line.push_str("\"}\n");
+
+    err_at!(IOError, w.write_all(line.as_bytes()))
+}
+
+/// Write `entry` as a CBOR map, with text keys `seqno`, `timestamp`,
+/// `topic`, `tag` and `op`, op as byte string. Items written one after
+/// the other form a CBOR sequence, RFC 8742.
+pub fn write_cbor<W>(entry: &entry::Entry, w: &mut W) -> Result<()>
+where
+    W: io::Write,
+{
+    let mut buf = Vec::with_capacity(entry.as_op().len() + 64);
+    push_head(5, 5, &mut buf);
+    push_text("seqno", &mut buf);
+    push_head(0, entry.to_seqno(), &mut buf);
+    push_text("timestamp", &mut buf);
+    push_head(0, entry.to_timestamp(), &mut buf);
+    push_text("topic", &mut buf);
+    push_text(entry.as_topic(), &mut buf);
+    push_text("tag", &mut buf);
+    push_text(entry.as_tag(), &mut buf);
+    push_text("op", &mut buf);
+    push_head(2, entry.as_op().len() as u64, &mut buf);
+    buf.extend_from_slice(entry.as_op());
+
+    err_at!(IOError, w.write_all(&buf))
+}
+
+fn push_json_str(s: &str, out: &mut String) {
+    out.push('"');
+    for ch in s.chars() {
+        match ch {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            ch if (ch as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", ch as u32)),
+            ch => out.push(ch),
+        }
+    }
+    out.push('"');
+}
+
+fn push_base64(data: &[u8], out: &mut String) {
+    for chunk in data.chunks(3) {
+        let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = (u32::from(b[0]) << 16) | (u32::from(b[1]) << 8) | u32::from(b[2]);
+        for i in 0..4 {
+            match i <= chunk.len() {
+                true => out.push(BASE64[((n >> (18 - 6 * i)) & 0x3f) as usize] as char),
+                false => out.push('='),
+            }
+        }
+    }
+}
+
+fn push_hex(data: &[u8], out: &mut String) {
+    for byte in data.iter() {
+        out.push(HEX[usize::from(byte >> 4)] as char);
+        out.push(HEX[usize::from(byte & 0xf)] as char);
+    }
+}
+
+// Initial byte of a CBOR item with `major` type, followed by `n` as the
+// argument in its shortest form.
+fn push_head(major: u8, n: u64, buf: &mut Vec<u8>) {
+    let major = major << 5;
+    match n {
+        0..=23 => buf.push(major | n as u8),
+        24..=0xff => buf.extend_from_slice(&[major | 24, n as u8]),
+        0x100..=0xffff => {
+            buf.push(major | 25);
+            buf.extend_from_slice(&(n as u16).to_be_bytes());
+        }
+        0x10000..=0xffff_ffff => {
+            buf.push(major | 26);
+            buf.extend_from_slice(&(n as u32).to_be_bytes());
+        }
+        _ => {
+            buf.push(major | 27);
+            buf.extend_from_slice(&n.to_be_bytes());
+        }
+    }
+}
+
+fn push_text(s: &str, buf: &mut Vec<u8>) {
+    push_head(3, s.len() as u64, buf);
+    buf.extend_from_slice(s.as_bytes());
+}
+
+#[cfg(test)]
+#[path = "export_test.rs"]
+mod export_test;
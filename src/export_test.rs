@@ -0,0 +1,69 @@
+use super::*;
+
+#[test]
+fn test_export_encoding() {
+    let refs = [
+        ("", "", ""),
+        ("f", "Zg==", "66"),
+        ("fo", "Zm8=", "666f"),
+        ("foo", "Zm9v", "666f6f"),
+        ("foob", "Zm9vYg==", "666f6f62"),
+        ("fooba", "Zm9vYmE=", "666f6f6261"),
+        ("foobar", "Zm9vYmFy", "666f6f626172"),
+    ];
+    for (data, b64, hex) in refs.iter() {
+        let mut out = String::default();
+        push_base64(data.as_bytes(), &mut out);
+        assert_eq!(&out, b64);
+        out.clear();
+        push_hex(data.as_bytes(), &mut out);
+        assert_eq!(&out, hex);
+    }
+
+    let mut out = String::default();
+    push_json_str("a\"b\\c\nd\u{1}é", &mut out);
+    assert_eq!(out, "\"a\\\"b\\\\c\\nd\\u0001é\"");
+
+    let refs: [(u64, &[u8]); 6] = [
+        (0, &[0x00]),
+        (23, &[0x17]),
+        (24, &[0x18, 0x18]),
+        (1000, &[0x19, 0x03, 0xe8]),
+        (1_000_000, &[0x1a, 0x00, 0x0f, 0x42, 0x40]),
+        (u64::MAX, &[0x1b, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]),
+    ];
+    for (n, bytes) in refs.iter() {
+        let mut buf = vec![];
+        push_head(0, *n, &mut buf);
+        assert_eq!(buf.as_slice(), *bytes, "{}", n);
+    }
+}
+
+#[test]
+fn test_export_entry() {
+    let entry = entry::EntryBuilder::new(10)
+        .topic("t")
+        .tag("x")
+        .timestamp(1000)
+        .payload(vec![0xde, 0xad])
+        .build();
+
+    let mut out = vec![];
+    write_jsonl(&entry, OpEncoding::Base64, &mut out).unwrap();
+    write_jsonl(&entry, OpEncoding::Hex, &mut out).unwrap();
+    let refs = concat!(
+        "{\"seqno\":10,\"timestamp\":1000,\"topic\":\"t\",\"tag\":\"x\",\"op\":\"3q0=\"}\n",
+        "{\"seqno\":10,\"timestamp\":1000,\"topic\":\"t\",\"tag\":\"x\",\"op\":\"dead\"}\n",
+    );
+    assert_eq!(String::from_utf8(out).unwrap(), refs);
+
+    let mut out = vec![];
+    write_cbor(&entry, &mut out).unwrap();
+    let mut refs = vec![0xa5];
+    refs.extend_from_slice(b"\x65seqno\x0a");
+    refs.extend_from_slice(b"\x69timestamp\x19\x03\xe8");
+    refs.extend_from_slice(b"\x65topic\x61t");
+    refs.extend_from_slice(b"\x63tag\x61x");
+    refs.extend_from_slice(b"\x62op\x42\xde\xad");
+    assert_eq!(out, refs);
+}
@@ -1,13 +1,62 @@
-use std::{ffi, path};
+use std::{
+    ffi, fs,
+    io::{self, Read},
+    path,
+};
 
-use crate::Error;
+use crate::{util, Error, Result};
 
-pub fn make_filename(name: String, num: usize) -> ffi::OsString {
-    let file = format!("{}-journal-{:03}.dat", name, num);
+/// Return the file name for journal `num`, zero padded to `width` digits.
+/// Journal numbers beyond `width` digits are formatted in full, and file
+/// names of any width are parsed back by [unwrap_filename].
+pub fn make_filename(name: String, num: usize, width: usize) -> ffi::OsString {
+    let file = format!("{}-journal-{:0width$}.dat", name, num, width = width);
     let file: &ffi::OsStr = file.as_ref();
     file.to_os_string()
 }
 
+pub fn make_manifest_filename(name: &str) -> ffi::OsString {
+    let file = format!("{}-manifest.cbor", name);
+    let file: &ffi::OsStr = file.as_ref();
+    file.to_os_string()
+}
+
+pub fn make_cursor_filename(name: &str, consumer: &str) -> ffi::OsString {
+    let file = format!("{}-cursor-{}.cbor", name, consumer);
+    let file: &ffi::OsStr = file.as_ref();
+    file.to_os_string()
+}
+
+pub fn make_state_filename(name: &str) -> ffi::OsString {
+    let file = format!("{}.state", name);
+    let file: &ffi::OsStr = file.as_ref();
+    file.to_os_string()
+}
+
+/// Return the file name, for journal at `file_path`, to move it out of the
+/// way without removing it.
+pub fn make_quarantine_filename(file_path: &ffi::OsStr) -> ffi::OsString {
+    let mut file = file_path.to_os_string();
+    file.push(".quarantine");
+    file
+}
+
+/// Return the file name of blob file, holding ops spilled from journal at
+/// `file_path`.
+pub fn make_blob_filename(file_path: &ffi::OsStr) -> ffi::OsString {
+    let mut file = file_path.to_os_string();
+    file.push(".blob");
+    file
+}
+
+/// Return the file name of spool file, holding entries pending for journal
+/// at `file_path`, refer to spool module.
+pub fn make_spool_filename(file_path: &ffi::OsStr) -> ffi::OsString {
+    let mut file = file_path.to_os_string();
+    file.push(".spool");
+    file
+}
+
 pub fn unwrap_filename(file: ffi::OsString) -> Option<(String, usize)> {
     let stem = {
         let fname = path::Path::new(path::Path::new(&file).file_name()?);
@@ -36,3 +85,98 @@ pub fn unwrap_filename(file: ffi::OsString) -> Option<(String, usize)> {
         _ => None,
     }
 }
+
+/// Return journal files of instance `name` under `dirs`, along with their
+/// journal number. Directories that don't exist hold no journals.
+pub fn find_journals(
+    dirs: &[ffi::OsString],
+    name: &str,
+) -> Result<Vec<(usize, path::PathBuf)>> {
+    let mut journals = vec![];
+    for dir in dirs.iter().filter(|dir| path::Path::new(dir).is_dir()) {
+        for item in err_at!(IOError, fs::read_dir(dir))? {
+            let file_name = err_at!(IOError, item)?.file_name();
+            match unwrap_filename(file_name.clone()) {
+                Some((nm, num)) if nm == name => {
+                    journals.push((num, [dir, &file_name].iter().collect()))
+                }
+                _ => (),
+            }
+        }
+    }
+    Ok(journals)
+}
+
+/// Move file from `src` to `dst`. If rename fails, typically because `dst`
+/// is on a different file-system, fallback to copy into a temporary file
+/// next to `dst`, fsync and rename it into place, and then remove `src`.
+/// A `dst` with same size and content as `src` is treated as already
+/// copied by an earlier attempt, so that the move can be retried.
+pub fn move_file(src: &ffi::OsStr, dst: &ffi::OsStr) -> Result<()> {
+    if path::Path::new(dst).exists() {
+        match path::Path::new(src).exists() && is_same_file(src, dst)? {
+            true => {
+                err_at!(IOError, fs::remove_file(src))?;
+                return sync_parent(src);
+            }
+            false => err_at!(Invalid, msg: "{:?} already exists", dst)?,
+        }
+    }
+
+    if fs::rename(src, dst).is_ok() {
+        return Ok(());
+    }
+
+    // `dst` is either missing or complete, a partial copy left behind by an
+    // interrupted move is overwritten by the next attempt.
+    let tmp = {
+        let mut tmp = dst.to_os_string();
+        tmp.push(".moving");
+        tmp
+    };
+    {
+        let mut from = err_at!(IOError, fs::File::open(src))?;
+        let mut to = err_at!(IOError, fs::File::create(&tmp))?;
+        err_at!(IOError, io::copy(&mut from, &mut to))?;
+        err_at!(IOError, to.sync_all())?;
+    }
+    err_at!(IOError, fs::rename(&tmp, dst))?;
+    sync_parent(dst)?;
+    err_at!(IOError, fs::remove_file(src))?;
+    sync_parent(src)
+}
+
+// Return whether files `a` and `b` have the same size and content.
+fn is_same_file(a: &ffi::OsStr, b: &ffi::OsStr) -> Result<bool> {
+    let (ma, mb) =
+        (err_at!(IOError, fs::metadata(a))?, err_at!(IOError, fs::metadata(b))?);
+    if ma.len() != mb.len() {
+        return Ok(false);
+    }
+
+    let mut fa = io::BufReader::new(err_at!(IOError, fs::File::open(a))?);
+    let mut fb = io::BufReader::new(err_at!(IOError, fs::File::open(b))?);
+    let (mut ba, mut bb) = (vec![0; 64 * 1024], vec![0; 64 * 1024]);
+    loop {
+        let n = err_at!(IOError, fa.read(&mut ba))?;
+        if n == 0 {
+            break Ok(true);
+        }
+        err_at!(IOError, fb.read_exact(&mut bb[..n]))?;
+        if ba[..n] != bb[..n] {
+            break Ok(false);
+        }
+    }
+}
+
+// Sync the directory holding `file_path`, to persist its entry.
+fn sync_parent(file_path: &ffi::OsStr) -> Result<()> {
+    match path::Path::new(file_path).parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => util::sync_dir(dir),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+#[path = "files_test.rs"]
+mod files_test;
@@ -0,0 +1,28 @@
+use super::*;
+
+#[test]
+fn test_move_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let to_path = |file: &str| dir.path().join(file).into_os_string();
+    let (src, dst) = (to_path("src.dat"), to_path("dst.dat"));
+
+    fs::write(&src, b"hello world").unwrap();
+    move_file(&src, &dst).unwrap();
+    assert!(!path::Path::new(&src).exists());
+    assert_eq!(fs::read(&dst).unwrap(), b"hello world");
+
+    // an earlier attempt copied `dst`, but failed before removing `src`.
+    fs::write(&src, b"hello world").unwrap();
+    move_file(&src, &dst).unwrap();
+    assert!(!path::Path::new(&src).exists());
+    assert_eq!(fs::read(&dst).unwrap(), b"hello world");
+
+    // `dst` is a different file.
+    fs::write(&src, b"hello wral").unwrap();
+    match move_file(&src, &dst) {
+        Err(Error::Invalid(_, _)) => (),
+        res => panic!("unexpected {:?}", res),
+    }
+    assert_eq!(fs::read(&src).unwrap(), b"hello wral");
+    assert_eq!(fs::read(&dst).unwrap(), b"hello world");
+}
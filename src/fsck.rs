@@ -0,0 +1,367 @@
+//! Module implement consistency check and repair for journals on disk.
+//!
+//! Journals don't carry footers in this format, batch boundaries are
+//! recovered by decoding batches one after the other. Trailers, if any,
+//! only carry a checksum over entries, refer to [verify_journal]. Hence
+//! [FsckLevel::RebuildFooters] rebuild the metadata that lives outside
+//! the journals, that is the manifest.
+
+use log::{debug, warn};
+
+use std::{ffi, fs, io, path};
+
+use crate::{
+    batch, blob, compress, files, journal, manifest, manifest::Manifest, util,
+    wral::Config, Error, Result,
+};
+
+/// Level of repair, used with [Wal::fsck][crate::Wal::fsck].
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum FsckLevel {
+    /// Scan journals and manifest, report inconsistencies without
+    /// modifying anything on disk.
+    ReportOnly,
+    /// In addition to reporting, truncate journals at the last valid batch,
+    /// removing torn or corrupted bytes. Journals without a single valid
+    /// batch are removed.
+    TruncateTorn,
+    /// In addition to truncating, rebuild the manifest, if it is missing
+    /// or cannot be decoded, or if its seqno epochs don't account for
+    /// seqnos restarting in the journals. Epochs in the manifest are
+    /// retained, missing epochs are inferred from the journals.
+    RebuildFooters,
+}
+
+/// Report generated by [Wal::fsck][crate::Wal::fsck].
+#[derive(Debug, Clone, Default)]
+pub struct FsckReport {
+    /// Per journal report, sorted by journal number.
+    pub journals: Vec<JournalReport>,
+    /// Manifest is present and decodable.
+    pub manifest_ok: bool,
+    /// Manifest was rebuilt by this run.
+    pub manifest_rebuilt: bool,
+    /// Discontinuity in seqno between consecutive journals of the same
+    /// epoch, as (last-seqno, next-first-seqno).
+    pub seqno_gaps: Vec<(u64, u64)>,
+}
+
+impl FsckReport {
+    /// Return true if no inconsistency was found.
+    pub fn is_clean(&self) -> bool {
+        self.manifest_ok
+            && self.seqno_gaps.is_empty()
+            && self.journals.iter().all(JournalReport::is_clean)
+    }
+}
+
+/// Consistency report for a single journal file.
+#[derive(Debug, Clone, Default)]
+pub struct JournalReport {
+    /// Journal number.
+    pub num: usize,
+    /// Location of the journal file.
+    pub file_path: ffi::OsString,
+    /// Journal file size, before repair.
+    pub file_size: u64,
+    /// Number of valid batches.
+    pub n_batches: usize,
+    /// Number of entries across valid batches.
+    pub n_entries: usize,
+    /// First seqno in this journal, if there is a valid batch.
+    pub first_seqno: Option<u64>,
+    /// Last seqno in this journal, if there is a valid batch.
+    pub last_seqno: Option<u64>,
+    /// Number of bytes after the last valid batch.
+    pub torn_bytes: u64,
+    /// Error that stopped the scan, if any.
+    pub error: Option<String>,
+    /// Journal was truncated, or removed, by this run.
+    pub repaired: bool,
+    /// Number of entries out of seqno order, or outside their batch's
+    /// seqno span. Checked only by [Wal::verify_journal][crate::Wal::verify_journal].
+    pub seqno_errors: usize,
+    /// Number of entries whose spilled op fails its checksum, or whose
+    /// compressed op cannot be decoded. Checked only by
+    /// [Wal::verify_journal][crate::Wal::verify_journal].
+    pub op_errors: usize,
+    /// Journal carries a trailer, refer to
+    /// [Config::set_journal_trailer][crate::Config::set_journal_trailer].
+    pub trailer: bool,
+    /// Number of trailers whose entry count, or checksum, doesn't match the
+    /// entries preceding them. Checked only by
+    /// [Wal::verify_journal][crate::Wal::verify_journal].
+    pub checksum_errors: usize,
+}
+
+impl JournalReport {
+    /// Return true if no inconsistency was found in this journal.
+    pub fn is_clean(&self) -> bool {
+        self.torn_bytes == 0
+            && self.error.is_none()
+            && self.seqno_errors == 0
+            && self.op_errors == 0
+            && self.checksum_errors == 0
+    }
+}
+
+pub fn fsck(config: &Config, level: FsckLevel) -> Result<FsckReport> {
+    let mut report = FsckReport::default();
+
+    let manifest = match Manifest::load(&config.dir, &config.name) {
+        Ok(manifest) => {
+            report.manifest_ok = manifest.is_some();
+            manifest
+        }
+        Err(err) => {
+            warn!(target: "wral", "fsck {:?}/{} manifest {}", config.dir, config.name, err);
+            None
+        }
+    };
+
+    for (num, file_path) in files::find_journals(&config.to_journal_dirs(), &config.name)?
+    {
+        let max = config.max_batch_entries;
+        report.journals.push(scan_journal(num, &file_path, max, false, &|| false)?)
+    }
+    report.journals.sort_by_key(|j| j.num);
+
+    // seqnos restart at every epoch, journals starting an epoch are not
+    // checked for continuity with the previous journal.
+    let epochs: Vec<usize> = match &manifest {
+        Some(manifest) => {
+            manifest.to_epochs().iter().map(|e| e.to_journal_number()).collect()
+        }
+        None => vec![],
+    };
+    let mut last_seqno: Option<u64> = None;
+    for jr in report.journals.iter() {
+        match (last_seqno, jr.first_seqno) {
+            (_, None) => continue,
+            (_, Some(_)) if epochs.contains(&jr.num) => (),
+            (Some(last), Some(first)) if first != last + 1 => {
+                report.seqno_gaps.push((last, first))
+            }
+            _ => (),
+        }
+        last_seqno = jr.last_seqno;
+    }
+
+    if level == FsckLevel::ReportOnly {
+        return Ok(report);
+    }
+
+    for jr in report.journals.iter_mut().filter(|jr| jr.torn_bytes > 0) {
+        if jr.n_batches == 0 {
+            err_at!(IOError, fs::remove_file(&jr.file_path))?;
+        } else {
+            let file = {
+                let mut opts = fs::OpenOptions::new();
+                err_at!(IOError, opts.write(true).open(&jr.file_path))?
+            };
+            err_at!(IOError, file.set_len(jr.file_size - jr.torn_bytes))?;
+            err_at!(IOError, file.sync_all())?;
+        }
+        debug!(target: "wral", "fsck repaired {:?}, {} torn bytes", jr.file_path, jr.torn_bytes);
+        jr.repaired = true;
+    }
+
+    if level == FsckLevel::RebuildFooters {
+        let known = manifest.as_ref().map(Manifest::to_epochs).unwrap_or_default();
+        let epochs = rebuild_epochs(&known, &report.journals);
+        if !report.manifest_ok || epochs != known {
+            let mut manifest = manifest.unwrap_or_else(|| Manifest::new(&config.name));
+            manifest.set_epochs(epochs)?;
+            manifest.save(&config.dir)?;
+            debug!(target: "wral", "fsck rebuilt manifest for {:?}/{}", config.dir, config.name);
+            report.manifest_rebuilt = true;
+        }
+    }
+
+    Ok(report)
+}
+
+// Epochs for `journals`, retaining `known` epochs from the manifest, and
+// inferring a new epoch at every journal whose seqnos restart without a
+// known epoch. Inferred epochs are numbered after the epoch before them,
+// and carry the last seqno of the previous epoch, as Wal::rebase does.
+fn rebuild_epochs(
+    known: &[manifest::Epoch],
+    journals: &[JournalReport],
+) -> Vec<manifest::Epoch> {
+    let mut epochs: Vec<manifest::Epoch> = vec![];
+    let mut known = known.iter().peekable();
+    let mut last_seqno: Option<u64> = None;
+    for jr in journals.iter() {
+        let first = match jr.first_seqno {
+            Some(first) => first,
+            None => continue,
+        };
+        let mut covered = false;
+        while let Some(epoch) = known.next_if(|e| e.to_journal_number() <= jr.num) {
+            covered |= epoch.to_journal_number() == jr.num;
+            epochs.push(epoch.clone());
+        }
+        match last_seqno {
+            Some(last) if first <= last && !covered => {
+                let epoch = epochs.last().map(manifest::Epoch::to_epoch).unwrap_or(0);
+                epochs.push(manifest::Epoch::new(epoch + 1, jr.num, last));
+            }
+            _ => (),
+        }
+        last_seqno = jr.last_seqno;
+    }
+    epochs.extend(known.cloned());
+    epochs
+}
+
+/// Verify journal file `num`, at `file_path`, batch by batch without
+/// holding its index in memory. In addition to decoding batches, entries
+/// are checked for seqno order, spilled ops are checked against their
+/// checksum, and compressed ops are decoded. Entries are checked against
+/// the journal's trailer, if any.
+pub fn verify_journal(num: usize, file_path: &path::Path) -> Result<JournalReport> {
+    scan_journal(num, file_path, usize::MAX, true, &|| false)
+}
+
+/// Same as [verify_journal], but check `interrupt` before every batch, and
+/// fail with [Error::Cancelled] once it returns true.
+pub fn verify_journal_until(
+    num: usize,
+    file_path: &path::Path,
+    interrupt: &dyn Fn() -> bool,
+) -> Result<JournalReport> {
+    scan_journal(num, file_path, usize::MAX, true, interrupt)
+}
+
+// Scan journal batch by batch, refer to journal::validate_batch. Bytes
+// from the first batch that can't be framed are torn, a batch that is
+// framed but fails to validate stops the scan with its error.
+fn scan_journal(
+    num: usize,
+    file_path: &path::Path,
+    max_entries: usize,
+    verify: bool,
+    interrupt: &dyn Fn() -> bool,
+) -> Result<JournalReport> {
+    let file = err_at!(IOError, fs::OpenOptions::new().read(true).open(file_path))?;
+    let file_size = err_at!(IOError, file.metadata())?.len();
+
+    let mut jr = JournalReport {
+        num,
+        file_path: file_path.as_os_str().to_os_string(),
+        file_size,
+        ..JournalReport::default()
+    };
+
+    let mut reader = io::BufReader::new(file);
+    let mut blob = blob::Reader::new(file_path.as_os_str());
+    let mut fpos = 0_u64;
+    let mut last_seqno = 0;
+    // entries verified so far and their rolling checksum.
+    let mut checksum = (0_u64, 0_u32);
+    while fpos < file_size {
+        if interrupt() {
+            err_at!(Cancelled, msg: "scan of {:?} interrupted", file_path)?
+        }
+        let batch = match util::decode_cbor(&mut reader, file_size - fpos) {
+            Ok((val, n)) => {
+                let file_path = file_path.as_os_str();
+                match journal::validate_batch(val, file_path, fpos, max_entries) {
+                    Ok((batch, n_entries)) => Ok((batch, n_entries, n)),
+                    Err(err) => {
+                        jr.error = Some(err.to_string());
+                        return Ok(jr);
+                    }
+                }
+            }
+            Err(err) => Err(err),
+        };
+        match batch {
+            Ok((batch, n_entries, n)) => {
+                jr.n_batches += 1;
+                jr.n_entries += n_entries;
+                // batches recording a purge don't carry entries.
+                if n_entries > 0 {
+                    jr.first_seqno.get_or_insert(batch.to_first_seqno());
+                    jr.last_seqno = Some(batch.to_last_seqno());
+                }
+                fpos += n as u64;
+                if let Some(trailer) = batch.to_trailer() {
+                    jr.trailer = true;
+                    if verify && trailer != checksum {
+                        warn!(
+                            target: "wral",
+                            "verify {:?} trailer {:?} != {:?}", file_path, trailer, checksum
+                        );
+                        jr.checksum_errors += 1;
+                    }
+                }
+                if verify && n_entries > 0 {
+                    last_seqno = verify_batch(
+                        &mut jr,
+                        &mut blob,
+                        last_seqno,
+                        &mut checksum,
+                        batch,
+                    )?;
+                }
+            }
+            Err(err) => {
+                jr.error = Some(err.to_string());
+                break;
+            }
+        }
+    }
+    jr.torn_bytes = file_size.saturating_sub(fpos);
+
+    Ok(jr)
+}
+
+// Check entries in `batch`, that follows a batch ending with `last_seqno`,
+// and extend the rolling `checksum` with them. Return the last seqno of
+// this batch.
+fn verify_batch(
+    jr: &mut JournalReport,
+    blob: &mut blob::Reader,
+    mut last_seqno: u64,
+    checksum: &mut (u64, u32),
+    batch: batch::Batch,
+) -> Result<u64> {
+    let span = batch.to_first_seqno()..=batch.to_last_seqno();
+    let entries = batch.into_entries()?;
+
+    let (first, last) = (entries.first(), entries.last());
+    if first.map(|e| e.to_seqno()) != Some(*span.start())
+        || last.map(|e| e.to_seqno()) != Some(*span.end())
+    {
+        warn!(target: "wral", "verify {:?} batch {:?} bad span", jr.file_path, span);
+        jr.seqno_errors += 1;
+    }
+    for entry in entries.into_iter() {
+        let seqno = entry.to_seqno();
+        if seqno <= last_seqno || !span.contains(&seqno) {
+            jr.seqno_errors += 1;
+        }
+        last_seqno = last_seqno.max(seqno);
+
+        let res = blob.rehydrate(entry).and_then(compress::decompress_entry);
+        match res {
+            Ok(entry) => {
+                let (n, crc) = *checksum;
+                *checksum =
+                    batch::checksum_entries(n, crc, std::slice::from_ref(&entry))?;
+            }
+            Err(err) => {
+                warn!(target: "wral", "verify {:?} seqno {} {}", jr.file_path, seqno, err);
+                jr.op_errors += 1;
+            }
+        }
+    }
+
+    Ok(last_seqno)
+}
+
+#[cfg(test)]
+#[path = "fsck_test.rs"]
+mod fsck_test;
@@ -0,0 +1,249 @@
+use std::io::Write;
+
+use super::*;
+use crate::{state, util, Wal};
+
+#[test]
+fn test_fsck() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-fsck", dir.path().as_ref());
+    config.set_journal_limit(1000).set_fsync(false);
+
+    let wal = Wal::create(config.clone(), state::NoState).unwrap();
+    for _i in 0..100 {
+        wal.add_op(&[0; 32]).unwrap();
+    }
+    wal.close(false).unwrap();
+
+    let report = Wal::fsck(&config, FsckLevel::ReportOnly).unwrap();
+    assert!(report.is_clean(), "{:?}", report);
+    let n: usize = report.journals.iter().map(|jr| jr.n_entries).sum();
+    assert_eq!(n, 100);
+
+    // tear the last journal with a partial write.
+    let last = report.journals.iter().rfind(|jr| jr.n_batches > 0).unwrap();
+    {
+        let mut opts = fs::OpenOptions::new();
+        let mut file = opts.append(true).open(&last.file_path).unwrap();
+        file.write_all(&[0x9f, 0x01, 0x02]).unwrap();
+    }
+    let manifest = files::make_manifest_filename(&config.name);
+    fs::remove_file(dir.path().join(manifest)).unwrap();
+
+    let report = Wal::fsck(&config, FsckLevel::ReportOnly).unwrap();
+    assert!(!report.is_clean());
+    assert!(!report.manifest_ok);
+    let jr = report.journals.iter().find(|jr| jr.num == last.num).unwrap();
+    assert_eq!(jr.torn_bytes, 3);
+    assert!(jr.error.is_some());
+
+    let report = Wal::fsck(&config, FsckLevel::RebuildFooters).unwrap();
+    assert!(report.manifest_rebuilt);
+    assert!(report.journals.iter().find(|jr| jr.num == last.num).unwrap().repaired);
+
+    let report = Wal::fsck(&config, FsckLevel::ReportOnly).unwrap();
+    assert!(report.is_clean(), "{:?}", report);
+
+    let wal: Wal = Wal::load(config).unwrap();
+    assert_eq!(wal.iter().unwrap().count(), 100);
+    wal.close(true).unwrap();
+}
+
+#[test]
+fn test_fsck_rebase() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-fsck-rebase", dir.path().as_ref());
+    config.set_journal_limit(1000).set_fsync(false);
+    let manifest_path = dir.path().join(files::make_manifest_filename(&config.name));
+    let load_manifest = || Manifest::load(&config.dir, &config.name).unwrap().unwrap();
+
+    let wal = Wal::create(config.clone(), state::NoState).unwrap();
+    for _i in 0..50 {
+        wal.add_op(&[0; 32]).unwrap();
+    }
+    let stale = fs::read(&manifest_path).unwrap();
+    wal.rebase(1).unwrap();
+    for _i in 0..50 {
+        wal.add_op(&[1; 32]).unwrap();
+    }
+    wal.close(false).unwrap();
+    let (epochs, instance) = {
+        let manifest = load_manifest();
+        (manifest.to_epochs(), manifest.as_instance().to_string())
+    };
+    assert_eq!(epochs.len(), 1);
+    assert!(Wal::fsck(&config, FsckLevel::ReportOnly).unwrap().is_clean());
+
+    // manifest is lost, epoch is inferred from seqnos restarting.
+    fs::remove_file(&manifest_path).unwrap();
+    let report = Wal::fsck(&config, FsckLevel::ReportOnly).unwrap();
+    assert!(!report.manifest_ok);
+    assert_eq!(report.seqno_gaps, vec![(50, 1)]);
+    let report = Wal::fsck(&config, FsckLevel::RebuildFooters).unwrap();
+    assert!(report.manifest_rebuilt);
+    assert_eq!(load_manifest().to_epochs(), epochs);
+    assert!(Wal::fsck(&config, FsckLevel::ReportOnly).unwrap().is_clean());
+
+    // stale manifest, from before the rebase, is missing the epoch.
+    fs::write(&manifest_path, &stale).unwrap();
+    let report = Wal::fsck(&config, FsckLevel::ReportOnly).unwrap();
+    assert!(report.manifest_ok);
+    assert_eq!(report.seqno_gaps, vec![(50, 1)]);
+    let report = Wal::fsck(&config, FsckLevel::RebuildFooters).unwrap();
+    assert!(report.manifest_rebuilt);
+    let manifest = load_manifest();
+    assert_eq!(manifest.to_epochs(), epochs);
+    assert_eq!(manifest.as_instance(), instance);
+    let report = Wal::fsck(&config, FsckLevel::RebuildFooters).unwrap();
+    assert!(report.is_clean() && !report.manifest_rebuilt, "{:?}", report);
+
+    let wal: Wal = Wal::load(config).unwrap();
+    assert_eq!(wal.epoch().unwrap(), 1);
+    let seqnos: Vec<u64> = wal.iter().unwrap().map(|e| e.unwrap().to_seqno()).collect();
+    assert_eq!(seqnos, (1..=50).collect::<Vec<u64>>());
+    assert_eq!(wal.add_op(&[2]).unwrap(), 51);
+    wal.close(true).unwrap();
+}
+
+#[test]
+fn test_verify_journal() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-verify-journal", dir.path().as_ref());
+    config.set_journal_limit(1000).set_fsync(false).set_blob_spill(Some(100));
+    config.set_entry_compression(Some(64));
+
+    let wal = Wal::create(config.clone(), state::NoState).unwrap();
+    for i in 0..100_u8 {
+        let op: Vec<u8> = match i % 4 {
+            0 => (0..200_u8).map(|j| util::crc32(&[i, j]) as u8).collect(),
+            1 => vec![i; 200],
+            _ => vec![i; 32],
+        };
+        wal.add_op(&op).unwrap();
+    }
+    let indexes = wal.indexes().unwrap();
+    assert!(indexes.len() > 2);
+
+    // archived journals are verified while the instance is open.
+    let mut n = 0;
+    for index in indexes[..indexes.len() - 1].iter() {
+        let file_path = index.to_file_path();
+        let jr = Wal::verify_journal(&config, &file_path).unwrap();
+        assert!(jr.is_clean(), "{:?}", jr);
+        assert_eq!(jr.num, index.to_journal_number());
+        n += jr.n_entries;
+    }
+    let file_path = indexes[0].to_file_path();
+    let res = verify_journal_until(0, path::Path::new(&file_path), &|| true);
+    assert!(matches!(res, Err(Error::Cancelled(_, _))), "{:?}", res);
+    wal.close(false).unwrap();
+
+    let last = indexes.last().unwrap().to_file_path();
+    n += Wal::verify_journal(&config, &last).unwrap().n_entries;
+    assert_eq!(n, 100);
+
+    let res = Wal::verify_journal(&config, dir.path().join("not-a-journal").as_os_str());
+    assert!(matches!(res, Err(Error::Invalid(_, _))));
+
+    // corrupt a spilled op.
+    let (index, blob_path) = indexes
+        .iter()
+        .find_map(|index| {
+            let blob_path = files::make_blob_filename(&index.to_file_path());
+            path::Path::new(&blob_path).exists().then_some((index, blob_path))
+        })
+        .unwrap();
+    {
+        let mut data = fs::read(&blob_path).unwrap();
+        data[10] ^= 0xFF;
+        fs::write(&blob_path, data).unwrap();
+    }
+    let jr = Wal::verify_journal(&config, &index.to_file_path()).unwrap();
+    assert!(!jr.is_clean());
+    assert_eq!(jr.op_errors, 1, "{:?}", jr);
+    assert_eq!(jr.seqno_errors, 0);
+}
+
+#[test]
+fn test_verify_journal_crafted() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-verify-journal-crafted", dir.path().as_ref());
+    config.set_fsync(false);
+
+    let wal = Wal::create(config.clone(), state::NoState).unwrap();
+    for _i in 0..10 {
+        wal.add_op(&[0; 32]).unwrap();
+    }
+    let file_path = wal.indexes().unwrap()[0].to_file_path();
+    wal.close(false).unwrap();
+    let data = fs::read(&file_path).unwrap();
+
+    // byte string, and array, claiming more bytes than the file holds.
+    let crafted: Vec<Vec<u8>> = vec![
+        vec![0x5b, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff],
+        vec![0x9b, 0x00, 0x00, 0x00, 0x00, 0xff, 0xff, 0xff, 0xff],
+        vec![0x82, 0x01, 0x5a, 0xff, 0xff, 0xff, 0xf0],
+    ];
+    for bytes in crafted.into_iter() {
+        let mut buf = data.clone();
+        buf.extend_from_slice(&bytes);
+        fs::write(&file_path, &buf).unwrap();
+
+        let jr = Wal::verify_journal(&config, &file_path).unwrap();
+        assert_eq!(jr.n_entries, 10, "{:?}", jr);
+        assert_eq!(jr.torn_bytes, bytes.len() as u64, "{:?}", jr);
+        assert!(jr.error.as_ref().unwrap().contains("Corrupted"), "{:?}", jr);
+    }
+}
+
+#[test]
+fn test_verify_journal_trailer() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-verify-trailer", dir.path().as_ref());
+    config.set_journal_limit(1000).set_fsync(false).set_blob_spill(Some(100));
+    config.set_entry_compression(Some(64)).set_journal_trailer(true);
+
+    let wal = Wal::create(config.clone(), state::NoState).unwrap();
+    for i in 0..100_u8 {
+        let op: Vec<u8> = match i % 4 {
+            0 => (0..200_u8).map(|j| util::crc32(&[i, j]) as u8).collect(),
+            1 => vec![i; 200],
+            _ => vec![i; 32],
+        };
+        wal.add_op(&op).unwrap();
+    }
+    let indexes = wal.indexes().unwrap();
+    assert!(indexes.len() > 2);
+    wal.close(false).unwrap();
+
+    // journal active at close is left without a trailer.
+    for (i, index) in indexes.iter().enumerate() {
+        let jr = Wal::verify_journal(&config, &index.to_file_path()).unwrap();
+        assert!(jr.is_clean(), "{:?}", jr);
+        assert_eq!(jr.trailer, i < indexes.len() - 1, "{:?}", jr);
+    }
+    let wal: Wal = Wal::load(config).unwrap();
+    assert_eq!(wal.iter().unwrap().count(), 100);
+    wal.close(true).unwrap();
+
+    // flip a byte of an op, batches still decode but fail the trailer.
+    let mut config = Config::new("test-verify-trailer-flip", dir.path().as_ref());
+    config.set_journal_limit(1000).set_fsync(false).set_journal_trailer(true);
+    let wal = Wal::create(config.clone(), state::NoState).unwrap();
+    // ops of small bytes encode to the same bytes, as array or as bytes.
+    for i in 0..100_u8 {
+        wal.add_op(&[if i == 0 { 7 } else { 9 }; 32]).unwrap();
+    }
+    let file_path = wal.indexes().unwrap()[0].to_file_path();
+    wal.close(false).unwrap();
+
+    let mut data = fs::read(&file_path).unwrap();
+    let off = data.windows(32).position(|w| w == [7; 32]).unwrap();
+    data[off + 10] = 6;
+    fs::write(&file_path, data).unwrap();
+
+    let jr = Wal::verify_journal(&config, &file_path).unwrap();
+    assert!(jr.trailer);
+    assert_eq!((jr.op_errors, jr.seqno_errors, jr.checksum_errors), (0, 0, 1));
+    assert!(!jr.is_clean());
+}
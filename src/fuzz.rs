@@ -0,0 +1,121 @@
+//! Module implement fuzz targets for on-disk formats, enabled with the
+//! `fuzz` feature.
+//!
+//! Targets are plain functions taking raw bytes, so they can be wired to
+//! any fuzzing harness, for example with cargo-fuzz:
+//!
+//! ```ignore
+//! libfuzzer_sys::fuzz_target!(|data: &[u8]| wral::fuzz::batch(data));
+//! ```
+//!
+//! Targets shall never panic, decode errors are ignored. Use
+//! [write_corpus] to seed the fuzzer with valid inputs.
+
+use arbitrary::{Arbitrary, Unstructured};
+use mkit::cbor::{FromCbor, IntoCbor};
+
+use std::{ffi, fs, io::Write, path};
+
+use crate::{
+    batch::Batch, files, journal, state::NoState, state::StatePolicy, util, wral,
+};
+use crate::{Error, Result};
+
+/// Name of the instance, fuzzed journal files are named for.
+const NAME: &str = "fuzz";
+/// Number of batches in a journal, generated for corpus.
+const CORPUS_BATCHES: usize = 8;
+
+/// Fuzz target for batch decoding, `data` is decoded as a single batch and
+/// its entries are unpacked.
+pub fn batch(data: &[u8]) {
+    let val = match util::decode_cbor(&mut &data[..], data.len() as u64) {
+        Ok((val, _)) => val,
+        Err(_) => return,
+    };
+    if let Ok(batch) = Batch::decode(val) {
+        batch.to_metadata();
+        batch.into_entries().ok();
+    }
+}
+
+/// Fuzz target for journal decoding, `data` is loaded as journal file,
+/// its index is built and every entry is read back via the index.
+pub fn journal(data: &[u8]) {
+    let dir = match tempfile::tempdir() {
+        Ok(dir) => dir,
+        Err(_) => return,
+    };
+    let file_path: path::PathBuf = [
+        dir.path().as_os_str(),
+        &files::make_filename(NAME.to_string(), 0, wral::JOURNAL_WIDTH),
+    ]
+    .iter()
+    .collect();
+    if fs::write(&file_path, data).is_err() {
+        return;
+    }
+
+    let policy = StatePolicy::UseDefault;
+    let jn = match journal::Journal::<NoState>::load(
+        NAME,
+        file_path.as_ref(),
+        policy,
+        usize::MAX,
+    ) {
+        Some((jn, _, _)) => jn,
+        None => return,
+    };
+    if let Ok(iter) = journal::RdJournal::from_journal(&jn, 0..=u64::MAX) {
+        iter.for_each(drop)
+    }
+}
+
+/// Write a seed corpus of `n` batches and `n` journals under `dir`,
+/// generated from [Arbitrary] implementations. Batches are written as
+/// `batch-{i}.cbor` and journals as `journal-{i}.dat`. Return the list of
+/// files written.
+pub fn write_corpus(dir: &ffi::OsStr, n: usize) -> Result<Vec<ffi::OsString>> {
+    let mut files = vec![];
+    for i in 0..n {
+        let mut journal = vec![];
+        for j in 0..CORPUS_BATCHES {
+            let bytes = entropy((i * CORPUS_BATCHES + j) as u64, 1024);
+            let mut uns = Unstructured::new(&bytes);
+            let batch: Batch = err_at!(Fatal, Batch::arbitrary(&mut uns))?;
+
+            let mut buf = vec![];
+            batch.into_cbor()?.encode(&mut buf)?;
+            if j == 0 {
+                files.push(write_file(dir, &format!("batch-{}.cbor", i), &buf)?);
+            }
+            journal.extend_from_slice(&buf);
+        }
+        files.push(write_file(dir, &format!("journal-{}.dat", i), &journal)?);
+    }
+    Ok(files)
+}
+
+fn write_file(dir: &ffi::OsStr, name: &str, data: &[u8]) -> Result<ffi::OsString> {
+    let file_path: path::PathBuf = [dir, name.as_ref()].iter().collect();
+    let mut file = err_at!(IOError, fs::File::create(&file_path))?;
+    err_at!(IOError, file.write_all(data))?;
+    Ok(file_path.into_os_string())
+}
+
+// deterministic pseudo random bytes, so that corpus is reproducible.
+fn entropy(seed: u64, n: usize) -> Vec<u8> {
+    let mut x = seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1;
+    (0..n)
+        .map(|_| {
+            x ^= x << 13;
+            x ^= x >> 7;
+            x ^= x << 17;
+            x as u8
+        })
+        .collect()
+}
+
+#[cfg(test)]
+#[path = "fuzz_test.rs"]
+mod fuzz_test;
@@ -0,0 +1,49 @@
+use rand::{prelude::random, rngs::StdRng, Rng, SeedableRng};
+
+use super::*;
+
+#[test]
+fn test_fuzz_corpus() {
+    let dir = tempfile::tempdir().unwrap();
+    let files = write_corpus(dir.path().as_os_str(), 10).unwrap();
+    assert_eq!(files.len(), 20);
+
+    for file in files.iter() {
+        let data = fs::read(file).unwrap();
+        assert!(!data.is_empty());
+        batch(&data);
+        journal(&data);
+    }
+
+    // corpus is reproducible.
+    let dir2 = tempfile::tempdir().unwrap();
+    for (a, b) in files.iter().zip(write_corpus(dir2.path().as_os_str(), 10).unwrap()) {
+        assert_eq!(fs::read(a).unwrap(), fs::read(b).unwrap());
+    }
+}
+
+#[test]
+fn test_fuzz_targets() {
+    let seed: u64 = random();
+    println!("test_fuzz_targets {}", seed);
+    let mut rng = StdRng::seed_from_u64(seed);
+
+    let dir = tempfile::tempdir().unwrap();
+    let files = write_corpus(dir.path().as_os_str(), 4).unwrap();
+    for file in files.iter() {
+        let mut data = fs::read(file).unwrap();
+        for _ in 0..100 {
+            let off = rng.gen::<usize>() % data.len();
+            data[off] = rng.gen();
+            batch(&data);
+            journal(&data[..rng.gen::<usize>() % data.len()]);
+        }
+    }
+
+    for _ in 0..100 {
+        let n = rng.gen::<usize>() % 256;
+        let data: Vec<u8> = (0..n).map(|_| rng.gen()).collect();
+        batch(&data);
+        journal(&data);
+    }
+}
@@ -1,47 +1,94 @@
-use log::{debug, error};
+use log::{debug, error, warn};
 use mkit::{
     self,
     cbor::{Cbor, FromCbor},
 };
 
 use std::{
+    collections::BTreeMap,
     convert::TryFrom,
     ffi,
     fmt::{self, Display},
-    fs, ops, path, result, vec,
+    fs,
+    io::{self, Seek},
+    ops, path, result,
+    sync::Arc,
+    vec,
 };
 
-use crate::{batch, entry, files, state, Error, Result};
+use crate::{
+    batch, blob, bloom::Bloom, compress, entry, files, fsck, manifest, spool, state,
+    state::StatePolicy, storage, tombstone, util, Error, Result,
+};
 
 pub struct Journal<S> {
     name: String,
     num: usize,
-    file_path: ffi::OsString, // dir/{name}-journal-{num}.dat
+    file_path: ffi::OsString,      // dir/{name}-journal-{num}.dat
+    mirror: Option<ffi::OsString>, // mirror-dir/{name}-journal-{num}.dat
+    // instance id stamped on the journal, None for unstamped journals.
+    instance: Option<String>,
     inner: InnerJournal<S>,
 }
 
+/// Policy for reads touching a cold journal, refer to
+/// [Config::set_thaw_policy][crate::Config::set_thaw_policy].
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub enum ThawPolicy {
+    /// Reads fail with [Error::Invalid], cold journals must be thawed
+    /// using [Wal::thaw][crate::Wal::thaw].
+    #[default]
+    Manual,
+    /// Cold journals are thawed on demand, before reading them.
+    Auto,
+}
+
+/// Policy for journal files without batches, found while loading an
+/// instance, say left behind by a crash before the first flush, refer to
+/// [Config::set_empty_policy][crate::Config::set_empty_policy].
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub enum EmptyPolicy {
+    /// Leave the file in place, it is skipped while loading and taken
+    /// over if a new journal is started with the same number.
+    #[default]
+    Keep,
+    /// Remove the file, unless the instance is opened for reading alone.
+    Remove,
+}
+
 impl<S> Display for Journal<S> {
     fn fmt(&self, f: &mut fmt::Formatter) -> result::Result<(), fmt::Error> {
         write!(f, "journal-{}-{}", self.name, self.num)
     }
 }
 
+// Only the active journal is Working, size of other variants don't matter.
+#[allow(clippy::large_enum_variant)]
 enum InnerJournal<S> {
     // Active journal, the latest journal, in the journal-set. A journal
     // set is managed by Shard.
     Working {
         worker: batch::Worker<S>,
-        file: fs::File,
+        file: storage::File,
+        options: storage::Options,
     },
     // All journals except lastest journal are archives, which means only
     // the metadata for each batch shall be stored.
     Archive {
         index: Vec<batch::Index>,
         state: S,
+        metadata: Option<tombstone::Metadata>,
+        // decoded entries of preloaded batches, keyed by batch fpos.
+        cache: BTreeMap<u64, Arc<Vec<entry::Entry>>>,
     },
     // Cold journals are colder than archives, that is, they are not
-    // required by the application, may be as frozen-backup.
-    Cold,
+    // required by the application, may be as frozen-backup. Only a stub
+    // of the journal's metadata is held in memory.
+    Cold {
+        // seqno span, if known, and file size.
+        seqnos: Option<ops::RangeInclusive<u64>>,
+        size: u64,
+    },
 }
 
 impl<S> Journal<S> {
@@ -49,32 +96,113 @@ impl<S> Journal<S> {
         name: &str,
         dir: &ffi::OsStr,
         num: usize,
+        options: &storage::Options,
         state: S,
     ) -> Result<Journal<S>> {
         let file_path: path::PathBuf = {
-            let file: ffi::OsString = files::make_filename(name.to_string(), num);
+            let file = files::make_filename(name.to_string(), num, options.journal_width);
             [dir, &file].iter().collect()
         };
 
-        fs::remove_file(&file_path).ok(); // cleanup a single journal file
-
-        let file = {
-            let mut opts = fs::OpenOptions::new();
-            err_at!(IOError, opts.append(true).create_new(true).open(&file_path))?
-        };
+        let file = storage::create(options, file_path.as_os_str())?;
         debug!(target: "wral", "start_journal {:?}", file_path);
+        // stale blob file, from a journal that was never written, is
+        // cleaned up along with the journal file.
+        let blob_path = files::make_blob_filename(file_path.as_os_str());
+        fs::remove_file(&blob_path).ok();
+        let spill = options
+            .blob_threshold
+            .map(|threshold| blob::Spill::new(threshold, file_path.as_os_str()));
+        let spool_path = files::make_spool_filename(file_path.as_os_str());
+        fs::remove_file(&spool_path).ok();
+        let spool = options
+            .buffer_limit
+            .map(|limit| spool::Spool::new(limit, file_path.as_os_str()));
 
-        Ok(Journal {
+        let mut journal = Journal {
             name: name.to_string(),
             num,
             file_path: file_path.into_os_string(),
-            inner: InnerJournal::Working { worker: batch::Worker::new(state), file },
-        })
+            mirror: None,
+            instance: None,
+            inner: InnerJournal::Working {
+                worker: batch::Worker::new(state)
+                    .set_codec(options.codec)
+                    .set_tag_index(options.tag_index)
+                    .set_shadow(options.shadow)
+                    .set_spill(spill)
+                    .set_compress(options.compress_threshold)
+                    .set_middleware(options.middleware.clone())
+                    .set_spool(spool)
+                    .set_clock(options.clock.clone())
+                    .set_defer_sync(options.defer_sync)
+                    .set_trailer(options.trailer),
+                file,
+                options: options.clone(),
+            },
+        };
+        journal.set_mirror(options.mirror.as_deref());
+
+        Ok(journal)
     }
 
-    pub fn load(name: &str, file_path: &ffi::OsStr) -> Option<(Journal<S>, S)>
+    /// Load archived journal from `file_path`, along with its state. If
+    /// the state can't be decoded, `policy` decides whether to skip the
+    /// journal or to use the default state, in which case the returned
+    /// flag is true. Batches claiming more than `max_entries` entries are
+    /// treated as corrupted.
+    pub fn load(
+        name: &str,
+        file_path: &ffi::OsStr,
+        policy: StatePolicy,
+        max_entries: usize,
+    ) -> Option<(Journal<S>, S, bool)>
     where
-        S: Clone + FromCbor,
+        S: Clone + Default + FromCbor,
+    {
+        Self::do_load(name, file_path, policy, max_entries, OnError::Fail)
+    }
+
+    /// Same as [Journal::load], but batches are loaded upto the first batch
+    /// that can't be decoded, like a batch that is being written by a live
+    /// writer from another process.
+    pub fn load_durable(
+        name: &str,
+        file_path: &ffi::OsStr,
+        policy: StatePolicy,
+        max_entries: usize,
+    ) -> Option<(Journal<S>, S, bool)>
+    where
+        S: Clone + Default + FromCbor,
+    {
+        Self::do_load(name, file_path, policy, max_entries, OnError::Stop)
+    }
+
+    /// Same as [Journal::load], but batches that can't be decoded are
+    /// skipped, by scanning forward for the next batch that decodes and
+    /// continues the seqno order. Used to rebuild the index of a journal
+    /// whose batches are partially damaged.
+    pub fn rebuild(
+        name: &str,
+        file_path: &ffi::OsStr,
+        policy: StatePolicy,
+        max_entries: usize,
+    ) -> Option<(Journal<S>, S, bool)>
+    where
+        S: Clone + Default + FromCbor,
+    {
+        Self::do_load(name, file_path, policy, max_entries, OnError::Resync)
+    }
+
+    fn do_load(
+        name: &str,
+        file_path: &ffi::OsStr,
+        policy: StatePolicy,
+        max_entries: usize,
+        on_error: OnError,
+    ) -> Option<(Journal<S>, S, bool)>
+    where
+        S: Clone + Default + FromCbor,
     {
         let os_file = path::Path::new(file_path);
         let (nm, num) = files::unwrap_filename(os_file.file_name()?.to_os_string())?;
@@ -87,20 +215,72 @@ impl<S> Journal<S> {
             err_at!(IOError, fs::OpenOptions::new().read(true).open(os_file)).ok()?;
 
         let mut state = vec![];
+        let mut metadata = None;
+        let mut instance: Option<String> = None;
         let mut index = vec![];
         let mut fpos = 0_usize;
         let len = file.metadata().ok()?.len();
 
         while u64::try_from(fpos).ok()? < len {
-            let (val, n) = Cbor::decode(&mut file).ok()?;
-            let batch = batch::Batch::from_cbor(val).ok()?;
-            index.push(batch::Index::new(
-                u64::try_from(fpos).ok()?,
-                n,
-                batch.to_first_seqno(),
-                batch.to_last_seqno(),
-            ));
+            let limit = len - u64::try_from(fpos).ok()?;
+            let batch = util::decode_cbor(&mut file, limit)
+                .and_then(|(val, n)| Ok((batch::Batch::decode(val)?, n)));
+            let (batch, n) = match batch {
+                Ok(item) => item,
+                Err(err) if on_error == OnError::Stop => {
+                    debug!(target: "wral", "partial batch {:?} at {} {}", file_path, fpos, err);
+                    break;
+                }
+                Err(err) if on_error == OnError::Resync => {
+                    let last_seqno = index.last().map(batch::Index::to_last_seqno);
+                    let from = u64::try_from(fpos).ok()?;
+                    match resync(&mut file, from + 1, len, last_seqno, max_entries) {
+                        Some(to) => {
+                            warn!(
+                                target: "wral",
+                                "skipped {:?} from {} to {}, {}", file_path, from, to, err
+                            );
+                            fpos = usize::try_from(to).ok()?;
+                            continue;
+                        }
+                        None => {
+                            warn!(target: "wral", "skipped {:?} from {}, {}", file_path, from, err);
+                            break;
+                        }
+                    }
+                }
+                Err(_) => return None,
+            };
+            let (first_seqno, last_seqno) =
+                (batch.to_first_seqno(), batch.to_last_seqno());
+            let timestamp = batch.to_timestamp();
+            if instance.is_none() && !batch.as_instance().is_empty() {
+                instance = Some(batch.as_instance().to_string());
+            }
             state = batch.to_state();
+            metadata = batch.to_metadata().or(metadata);
+            // batches written without tag index are indexed in memory.
+            let tags = batch.to_tags();
+            let entries = batch.into_entries().ok()?;
+            if entries.len() > max_entries {
+                error!(
+                    target: "wral",
+                    "corrupted batch {:?} at {}, {} entries > {}",
+                    file_path, fpos, entries.len(), max_entries
+                );
+                return None;
+            }
+            let tags = match tags.is_empty() {
+                true => batch::Tag::from_entries(&entries, false).ok()?,
+                false => tags,
+            };
+            let topics = batch::Topic::from_entries(&entries);
+            index.push(
+                batch::Index::new(u64::try_from(fpos).ok()?, n, first_seqno, last_seqno)
+                    .set_topics(topics)
+                    .set_tags(tags)
+                    .set_timestamp(timestamp),
+            );
             fpos += n
         }
 
@@ -108,7 +288,7 @@ impl<S> Journal<S> {
             return None;
         }
 
-        let state: S = match Cbor::decode(&mut state.as_slice()) {
+        let state: Option<S> = match Cbor::decode(&mut state.as_slice()) {
             Ok((state, _)) => match S::from_cbor(state) {
                 Ok(state) => Some(state),
                 Err(err) => {
@@ -120,7 +300,15 @@ impl<S> Journal<S> {
                 error!(target: "wral", "corrupted state {:?} {}", file_path, err);
                 None
             }
-        }?;
+        };
+        let (state, reset) = match (state, policy) {
+            (Some(state), _) => (state, false),
+            (None, StatePolicy::Discard) => return None,
+            (None, StatePolicy::UseDefault) => {
+                warn!(target: "wral", "default state for journal {:?}", file_path);
+                (S::default(), true)
+            }
+        };
 
         debug!(target: "wral", "load journal {:?}, loaded {} batches", file_path, index.len());
 
@@ -128,12 +316,49 @@ impl<S> Journal<S> {
             name: name.to_string(),
             num,
             file_path: file_path.to_os_string(),
-            inner: InnerJournal::Archive { index, state: state.clone() },
+            mirror: None,
+            instance,
+            inner: InnerJournal::Archive {
+                index,
+                state: state.clone(),
+                metadata,
+                cache: BTreeMap::default(),
+            },
         };
 
-        Some((journal, state))
+        Some((journal, state, reset))
     }
 
+    /// Return an archived journal without any batches, that does not
+    /// exist on disk.
+    pub fn empty_archive(
+        name: &str,
+        dir: &ffi::OsStr,
+        num: usize,
+        width: usize,
+        state: S,
+    ) -> Journal<S> {
+        let file_path: path::PathBuf = {
+            let file: ffi::OsString = files::make_filename(name.to_string(), num, width);
+            [dir, &file].iter().collect()
+        };
+        Journal {
+            name: name.to_string(),
+            num,
+            file_path: file_path.into_os_string(),
+            mirror: None,
+            instance: None,
+            inner: InnerJournal::Archive {
+                index: vec![],
+                state,
+                metadata: None,
+                cache: BTreeMap::default(),
+            },
+        }
+    }
+
+    /// Load journal from `file_path` as cold, without reading its batches,
+    /// hence its seqno span is not known.
     pub fn load_cold(name: &str, file_path: &ffi::OsStr) -> Option<Journal<S>> {
         let os_file = path::Path::new(file_path);
         let (nm, num) = files::unwrap_filename(os_file.file_name()?.to_os_string())?;
@@ -142,34 +367,172 @@ impl<S> Journal<S> {
             return None;
         }
 
+        let size = fs::metadata(os_file).map(|m| m.len()).unwrap_or(0);
         let journal = Journal {
             name: name.to_string(),
             num,
             file_path: file_path.to_os_string(),
-            inner: InnerJournal::Cold,
+            mirror: None,
+            instance: None,
+            inner: InnerJournal::Cold { seqnos: None, size },
         };
         Some(journal)
     }
 
-    pub fn into_archive(mut self) -> (Self, Vec<entry::Entry>, S)
+    /// Turn an archived journal into a cold journal, dropping its batch
+    /// index and state, while retaining its seqno span and file size.
+    pub fn into_cold(mut self) -> Journal<S> {
+        let seqnos = match (self.to_first_seqno(), self.to_last_seqno()) {
+            (Some(first), Some(last)) => Some(first..=last),
+            _ => None,
+        };
+        let size = match &self.inner {
+            InnerJournal::Archive { index, .. } => {
+                let n: u64 = index.iter().map(|i| i.to_length() as u64).sum();
+                fs::metadata(&self.file_path).map(|m| m.len()).unwrap_or(n)
+            }
+            _ => unreachable!(),
+        };
+        self.inner = InnerJournal::Cold { seqnos, size };
+        self
+    }
+
+    /// Verify a cold journal in place, batch by batch, without turning it
+    /// into an archive. Refer to [fsck::verify_journal].
+    pub fn verify(&self) -> Result<fsck::JournalReport> {
+        match &self.inner {
+            InnerJournal::Cold { .. } => {
+                let file_path = path::Path::new(&self.file_path);
+                fsck::verify_journal(self.num, file_path)
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn into_archive(mut self) -> Result<(Self, Vec<entry::Entry>, S)>
     where
         S: Clone,
     {
         let (inner, entries, state) = match self.inner {
             InnerJournal::Working { worker, .. } => {
-                let (index, entries, state) = worker.unwrap();
-                let inner = InnerJournal::Archive { index, state: state.clone() };
+                let metadata = worker.to_metadata();
+                let (index, entries, state) = worker.unwrap()?;
+                let inner = InnerJournal::Archive {
+                    index,
+                    state: state.clone(),
+                    metadata,
+                    cache: BTreeMap::default(),
+                };
                 (inner, entries, state)
             }
             _ => unreachable!(),
         };
         self.inner = inner;
-        (self, entries, state)
+        Ok((self, entries, state))
+    }
+
+    /// Move journal file under `dir`, named for `name`, keeping the
+    /// journal number, zero padded to `width` digits. Active journal is
+    /// re-opened at its new location.
+    pub fn relocate(&mut self, dir: &ffi::OsStr, name: &str, width: usize) -> Result<()> {
+        let file_path: path::PathBuf = {
+            let file = files::make_filename(name.to_string(), self.num, width);
+            [dir, &file].iter().collect()
+        };
+        let file_path = file_path.into_os_string();
+        if file_path == self.file_path {
+            return Ok(());
+        }
+
+        files::move_file(&self.file_path, &file_path)?;
+        debug!(target: "wral", "moved {:?} to {:?}", self.file_path, file_path);
+        let blob_path = files::make_blob_filename(&self.file_path);
+        if path::Path::new(&blob_path).exists() {
+            files::move_file(&blob_path, &files::make_blob_filename(&file_path))?;
+        }
+
+        // mirror copy stays in the mirror directory, renamed as `name`.
+        let mirror = match &self.mirror {
+            Some(mirror) => {
+                let dir = path::Path::new(mirror).parent().map(|d| d.as_os_str());
+                let dst = dir.and_then(|dir| storage::mirror_path(dir, &file_path));
+                match &dst {
+                    Some(dst) if dst != mirror && path::Path::new(mirror).exists() => {
+                        files::move_file(mirror, dst)?
+                    }
+                    _ => (),
+                }
+                dst
+            }
+            None => None,
+        };
+
+        if let InnerJournal::Working { worker, file, options } = &mut self.inner {
+            *file = storage::open(options, &file_path)?;
+            worker.relocate_spill(&file_path)?;
+        }
+        self.name = name.to_string();
+        self.file_path = file_path;
+        self.mirror = mirror;
+
+        Ok(())
     }
 
-    pub fn purge(self) -> Result<()> {
+    /// Rename archived journal's file over `file_path`, replacing the file
+    /// there, say the journal it was rewritten from. Blob file, if any, is
+    /// not moved, rewritten batches refer to ops spilled at `file_path`.
+    pub fn replace(&mut self, file_path: &ffi::OsStr) -> Result<()> {
+        if let InnerJournal::Working { .. } = &self.inner {
+            err_at!(Fatal, msg: "replace active journal {:?}", self.file_path)?
+        }
+        err_at!(IOError, fs::rename(&self.file_path, file_path))?;
+        debug!(target: "wral", "replaced {:?} with {:?}", file_path, self.file_path);
+        self.file_path = file_path.to_os_string();
+        Ok(())
+    }
+
+    /// Mirror copy of this journal is kept under `dir`, if not None.
+    /// Stamp instance id on the first batch written to this journal.
+    pub fn set_instance(&mut self, instance: &str) {
+        if let InnerJournal::Working { worker, .. } = &mut self.inner {
+            worker.set_instance(instance)
+        }
+        self.instance = Some(instance.to_string());
+    }
+
+    /// Return the instance id stamped on this journal, None if the journal
+    /// is not stamped.
+    pub fn to_instance(&self) -> Option<String> {
+        self.instance.clone()
+    }
+
+    pub fn set_mirror(&mut self, dir: Option<&ffi::OsStr>) {
+        self.mirror = dir.and_then(|dir| storage::mirror_path(dir, &self.file_path));
+    }
+
+    /// Remove journal file, its blob file and mirror copy. Files already
+    /// removed are skipped, so that a failed purge can be retried.
+    pub fn purge(&self) -> Result<()> {
         debug!(target: "wral", "purging {:?} ...", self.file_path);
-        err_at!(IOError, fs::remove_file(&self.file_path))?;
+        match fs::remove_file(&self.file_path) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => {
+                err_at!(IOError, Err(err), "{:?}", self.file_path)?
+            }
+            _ => (),
+        }
+        let blob_path = files::make_blob_filename(&self.file_path);
+        match fs::remove_file(&blob_path) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => {
+                err_at!(IOError, Err(err), "{:?}", blob_path)?
+            }
+            _ => (),
+        }
+        if let Some(mirror) = &self.mirror {
+            // mirror copy may be missing, if the mirror had degraded.
+            if let Err(err) = fs::remove_file(mirror) {
+                debug!(target: "wral", "failed to purge {:?}, {}", mirror, err);
+            }
+        }
         Ok(())
     }
 }
@@ -182,17 +545,113 @@ impl<S> Journal<S> {
         match &mut self.inner {
             InnerJournal::Working { worker, .. } => worker.add_entry(entry),
             InnerJournal::Archive { .. } => unreachable!(),
-            InnerJournal::Cold => unreachable!(),
+            InnerJournal::Cold { .. } => unreachable!(),
+        }
+    }
+
+    pub fn add_entries(&mut self, entries: Vec<entry::Entry>) -> Result<()>
+    where
+        S: state::State,
+    {
+        match &mut self.inner {
+            InnerJournal::Working { worker, .. } => worker.add_entries(entries),
+            InnerJournal::Archive { .. } => unreachable!(),
+            InnerJournal::Cold { .. } => unreachable!(),
         }
     }
 
+    #[allow(dead_code)]
     pub fn flush(&mut self) -> Result<()>
     where
         S: state::State,
     {
         match &mut self.inner {
-            InnerJournal::Working { worker, file } => {
-                worker.flush(file)?;
+            InnerJournal::Working { worker, file, .. } => {
+                worker.flush(file.as_mut())?;
+                Ok(())
+            }
+            // read-only instances hold an archive as their latest journal.
+            InnerJournal::Archive { .. } => Ok(()),
+            InnerJournal::Cold { .. } => unreachable!(),
+        }
+    }
+
+    /// Take mismatches found by shadow verification, refer to
+    /// [batch::Worker::take_mismatches].
+    pub fn take_mismatches(&mut self) -> Vec<batch::Mismatch> {
+        match &mut self.inner {
+            InnerJournal::Working { worker, .. } => worker.take_mismatches(),
+            _ => vec![],
+        }
+    }
+
+    /// Flush pending entries, as long as journal file stays within `limit`.
+    /// Return false if entries are left pending, refer to
+    /// [batch::Worker::flush_upto].
+    pub fn flush_upto(&mut self, limit: usize) -> Result<bool>
+    where
+        S: state::State,
+    {
+        match &mut self.inner {
+            InnerJournal::Working { worker, file, .. } => {
+                worker.flush_upto(file.as_mut(), limit)
+            }
+            InnerJournal::Archive { .. } => Ok(true),
+            InnerJournal::Cold { .. } => unreachable!(),
+        }
+    }
+
+    /// Fsync batches written to the active journal, batches carrying
+    /// entries are not synced as they are written when sync is deferred,
+    /// refer to [Config::set_fsync_interval][crate::Config::set_fsync_interval].
+    pub fn sync(&mut self) -> Result<()> {
+        match &mut self.inner {
+            InnerJournal::Working { file, .. } => file.sync(),
+            InnerJournal::Archive { .. } => Ok(()),
+            InnerJournal::Cold { .. } => unreachable!(),
+        }
+    }
+
+    /// Discard pending entries, refer to [batch::Worker::discard].
+    pub fn discard(&mut self) -> Result<()> {
+        match &mut self.inner {
+            InnerJournal::Working { worker, .. } => worker.discard(),
+            InnerJournal::Archive { .. } => Ok(()),
+            InnerJournal::Cold { .. } => unreachable!(),
+        }
+    }
+
+    /// Flush pending entries, and then persist metadata as a separate
+    /// batch. `seqno` is the last seqno handed out so far.
+    pub fn add_metadata(
+        &mut self,
+        metadata: tombstone::Metadata,
+        seqno: u64,
+    ) -> Result<()>
+    where
+        S: state::State,
+    {
+        match &mut self.inner {
+            InnerJournal::Working { worker, file, .. } => {
+                worker.flush(file.as_mut())?;
+                worker.flush_metadata(file.as_mut(), metadata, seqno)?;
+                Ok(())
+            }
+            InnerJournal::Archive { .. } => unreachable!(),
+            InnerJournal::Cold { .. } => unreachable!(),
+        }
+    }
+
+    /// Write trailer, covering entries flushed so far, as the last batch
+    /// of this journal, refer to [batch::Worker::flush_trailer]. Shall be
+    /// called only when the journal is rotated.
+    pub fn add_trailer(&mut self) -> Result<()>
+    where
+        S: state::State,
+    {
+        match &mut self.inner {
+            InnerJournal::Working { worker, file, .. } => {
+                worker.flush_trailer(file.as_mut())?;
                 Ok(())
             }
             InnerJournal::Archive { .. } => unreachable!(),
@@ -206,11 +665,45 @@ impl<S> Journal<S> {
         self.num
     }
 
+    /// Return the serialized size of state, and the encoded size of the
+    /// batch, for the last batch flushed with entries into this journal,
+    /// refer to [batch::Worker::to_state_size].
+    pub fn to_state_size(&self) -> Option<(usize, usize)> {
+        match &self.inner {
+            InnerJournal::Working { worker, .. } => worker.to_state_size(),
+            _ => None,
+        }
+    }
+
+    /// Return the largest batch flushed into this journal, in number of
+    /// entries, and the capacity held for pending entries, refer to
+    /// [batch::Worker::to_high_water].
+    pub fn to_high_water(&self) -> (usize, usize) {
+        match &self.inner {
+            InnerJournal::Working { worker, .. } => worker.to_high_water(),
+            _ => (0, 0),
+        }
+    }
+
     pub fn len_batches(&self) -> usize {
         match &self.inner {
             InnerJournal::Working { worker, .. } => worker.len_batches(),
             InnerJournal::Archive { index, .. } => index.len(),
-            InnerJournal::Cold { .. } => unreachable!(),
+            InnerJournal::Cold { .. } => 0,
+        }
+    }
+
+    pub fn is_cold(&self) -> bool {
+        matches!(&self.inner, InnerJournal::Cold { .. })
+    }
+
+    pub fn to_first_seqno(&self) -> Option<u64> {
+        match &self.inner {
+            InnerJournal::Working { worker, .. } => worker.to_first_seqno(),
+            InnerJournal::Archive { index, .. } => {
+                index.first().map(batch::Index::to_first_seqno)
+            }
+            InnerJournal::Cold { seqnos, .. } => seqnos.as_ref().map(|s| *s.start()),
         }
     }
 
@@ -221,18 +714,42 @@ impl<S> Journal<S> {
             InnerJournal::Archive { index, .. } => {
                 index.last().map(batch::Index::to_last_seqno)
             }
-            _ => None,
+            InnerJournal::Cold { seqnos, .. } => seqnos.as_ref().map(|s| *s.end()),
         }
     }
 
+    /// Return seqno span of this journal, along with a bloom filter over
+    /// its tags, None if the journal is empty.
+    pub fn to_span(&self) -> Option<manifest::Span> {
+        let (first, last) = (self.to_first_seqno()?, self.to_last_seqno()?);
+        let index = match &self.inner {
+            InnerJournal::Working { worker, .. } => worker.as_index(),
+            InnerJournal::Archive { index, .. } => index.as_slice(),
+            InnerJournal::Cold { .. } => &[],
+        };
+        let bloom = Bloom::from_tags(index.iter().flat_map(|i| i.iter_tags()));
+        Some(manifest::Span::new(self.num, first, last).set_tags(&bloom))
+    }
+
+    /// Return the time the latest batch in this journal was written, in
+    /// nanoseconds since UNIX_EPOCH, None if there are no batches or if it
+    /// was written without timestamp.
+    pub fn to_last_timestamp(&self) -> Option<u64> {
+        let index = match &self.inner {
+            InnerJournal::Working { worker, .. } => worker.as_index().last(),
+            InnerJournal::Archive { index, .. } => index.last(),
+            InnerJournal::Cold { .. } => None,
+        };
+        index.map(batch::Index::to_timestamp).filter(|ts| *ts > 0)
+    }
+
     pub fn file_size(&self) -> Result<usize> {
         let n = match &self.inner {
             InnerJournal::Working { file, .. } => {
-                let m = err_at!(IOError, file.metadata())?;
-                err_at!(FailConvert, usize::try_from(m.len()))?
+                err_at!(FailConvert, usize::try_from(file.to_size()?))?
             }
             InnerJournal::Archive { .. } => unreachable!(),
-            InnerJournal::Cold => unreachable!(),
+            InnerJournal::Cold { .. } => unreachable!(),
         };
         Ok(n)
     }
@@ -244,22 +761,275 @@ impl<S> Journal<S> {
         match &self.inner {
             InnerJournal::Working { worker, .. } => worker.to_state(),
             InnerJournal::Archive { state, .. } => state.clone(),
-            InnerJournal::Cold => unreachable!(),
+            InnerJournal::Cold { .. } => unreachable!(),
         }
     }
 
+    /// Return the latest metadata recorded in this journal, if any.
+    pub fn to_metadata(&self) -> Option<tombstone::Metadata> {
+        match &self.inner {
+            InnerJournal::Working { worker, .. } => worker.to_metadata(),
+            InnerJournal::Archive { metadata, .. } => metadata.clone(),
+            InnerJournal::Cold { .. } => None,
+        }
+    }
+
+    /// Return the directory holding the journal file.
+    pub fn to_dir(&self) -> ffi::OsString {
+        let dir = path::Path::new(&self.file_path).parent();
+        dir.map(|dir| dir.as_os_str().to_os_string()).unwrap_or_default()
+    }
+
     #[allow(dead_code)]
     pub fn to_file_path(&self) -> ffi::OsString {
         self.file_path.clone()
     }
+
+    /// Decode and cache entries for upto `n` batches, from the tail of an
+    /// archived journal. Return the number of batches preloaded.
+    pub fn preload(&mut self, n: usize) -> Result<usize> {
+        let (index, cache) = match &mut self.inner {
+            InnerJournal::Archive { index, cache, .. } => (index, cache),
+            _ => return Ok(0),
+        };
+        let skip = index.len().saturating_sub(n);
+        let mut file =
+            err_at!(IOError, fs::OpenOptions::new().read(true).open(&self.file_path))?;
+        for item in index[skip..].iter() {
+            let batch = batch::Batch::from_index(item.clone(), &mut file)?;
+            cache.insert(item.to_fpos(), Arc::new(batch.into_entries()?));
+        }
+        Ok(index.len() - skip)
+    }
+
+    pub fn to_journal_index(&self) -> JournalIndex {
+        let (index, cold) = match &self.inner {
+            InnerJournal::Working { worker, .. } => (worker.to_index(), None),
+            InnerJournal::Archive { index, .. } => (index.to_vec(), None),
+            InnerJournal::Cold { seqnos, size } => {
+                (vec![], Some((seqnos.clone(), *size)))
+            }
+        };
+        JournalIndex {
+            num: self.num,
+            file_path: self.file_path.clone(),
+            index,
+            cold,
+        }
+    }
+}
+
+// Scan forward from `fpos` for the next batch that decodes, along with its
+// entries, and continues the seqno order after `last_seqno`. Return its
+// position, with `file` positioned at the batch.
+fn resync(
+    file: &mut fs::File,
+    mut fpos: u64,
+    len: u64,
+    last_seqno: Option<u64>,
+    max_entries: usize,
+) -> Option<u64> {
+    while fpos < len {
+        file.seek(io::SeekFrom::Start(fpos)).ok()?;
+        let batch = util::decode_cbor(file, len - fpos)
+            .ok()
+            .and_then(|(v, _)| batch::Batch::decode(v).ok());
+        if let Some(batch) = batch {
+            let (first, last) = (batch.to_first_seqno(), batch.to_last_seqno());
+            // metadata batch carry the last seqno handed out.
+            let ordered = first <= last && last_seqno.is_none_or(|seqno| first >= seqno);
+            let entries = batch.into_entries();
+            if ordered && entries.is_ok_and(|entries| entries.len() <= max_entries) {
+                file.seek(io::SeekFrom::Start(fpos)).ok()?;
+                return Some(fpos);
+            }
+        }
+        fpos += 1;
+    }
+    None
+}
+
+/// Damage in a journal file, refer to [find_damage].
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Damage {
+    /// Batches upto `offset` are valid, and bytes beyond don't hold a valid
+    /// batch, that is, the last batch was partially written.
+    TornTail { offset: u64 },
+    /// Bytes at `offset` don't decode as a batch, but a valid batch, that
+    /// continues the seqno order, follows at `resume`.
+    Corrupted { offset: u64, resume: u64 },
+    /// Bytes at `offset` are framed as a cbor item, but don't decode as a
+    /// batch, and no valid batch follows. Unlike a torn tail, the item was
+    /// written in full.
+    Undecodable { offset: u64 },
+}
+
+/// Locate the first batch, in journal at `file_path`, that can't be
+/// framed as a cbor item, and tell a torn tail from corruption in the
+/// middle of the file. Return None if all batches decode.
+///
+/// A batch that is framed in full, but fails to decode, is never a torn
+/// tail. If its middleware is not registered, the batch is not damaged
+/// either, and the error is returned as is.
+pub fn find_damage(file_path: &ffi::OsStr, max_entries: usize) -> Result<Option<Damage>> {
+    let mut file = err_at!(IOError, fs::OpenOptions::new().read(true).open(file_path))?;
+    let len = err_at!(IOError, file.metadata())?.len();
+
+    let (mut fpos, mut last_seqno, mut framed) = (0_u64, None, false);
+    while fpos < len {
+        let (val, n) = match util::decode_cbor(&mut file, len - fpos) {
+            Ok((val, n)) => (val, n as u64),
+            Err(_) => break,
+        };
+        match validate_batch(val, file_path, fpos, max_entries) {
+            Ok((batch, _)) => last_seqno = Some(batch.to_last_seqno()),
+            Err(err @ Error::NotFound(_, _)) => return Err(err),
+            Err(_) => {
+                framed = true;
+                break;
+            }
+        }
+        fpos += n;
+    }
+    if fpos >= len {
+        return Ok(None);
+    }
+
+    let damage = match resync(&mut file, fpos + 1, len, last_seqno, max_entries) {
+        Some(resume) => Damage::Corrupted { offset: fpos, resume },
+        None if framed => Damage::Undecodable { offset: fpos },
+        None => Damage::TornTail { offset: fpos },
+    };
+    Ok(Some(damage))
+}
+
+/// Decode batch from `value`, framed at `fpos` in journal `file_path`, and
+/// validate its entries, unsealing and unpacking them as required. Fail
+/// if the batch doesn't decode, or claims more than `max_entries` entries.
+/// Return the batch and the number of entries in it.
+pub fn validate_batch(
+    value: Cbor,
+    file_path: &ffi::OsStr,
+    fpos: u64,
+    max_entries: usize,
+) -> Result<(batch::Batch, usize)> {
+    let batch = batch::Batch::decode(value)?;
+    let n_entries = batch.len_entries()?;
+    if n_entries > max_entries {
+        let (n, max) = (n_entries, max_entries);
+        err_at!(
+            Corrupted,
+            msg: "batch at {} in {:?}, {} entries > {}", fpos, file_path, n, max
+        )?
+    }
+    Ok((batch, n_entries))
+}
+
+// How [Journal::do_load] handles a batch that can't be decoded.
+#[derive(Clone, Copy, Eq, PartialEq)]
+enum OnError {
+    // journal fails to load.
+    Fail,
+    // batches upto the failed batch are loaded.
+    Stop,
+    // failed batches are skipped, refer to [Journal::rebuild].
+    Resync,
+}
+
+/// Snapshot of batch index for a single journal file.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct JournalIndex {
+    num: usize,
+    file_path: ffi::OsString,
+    index: Vec<batch::Index>,
+    // seqno span and file size, for cold journals whose batches are not
+    // indexed.
+    cold: Option<(Option<ops::RangeInclusive<u64>>, u64)>,
+}
+
+impl JournalIndex {
+    /// Return the journal number, journals are numbered from ZERO.
+    pub fn to_journal_number(&self) -> usize {
+        self.num
+    }
+
+    /// Return the location of journal file.
+    pub fn to_file_path(&self) -> ffi::OsString {
+        self.file_path.clone()
+    }
+
+    /// Return the number of batches in this journal.
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    /// Return the number of bytes, across batches in this journal. For
+    /// cold journals, return the file size.
+    pub fn to_size(&self) -> u64 {
+        match &self.cold {
+            Some((_, size)) => *size,
+            None => self.index.iter().map(|i| i.to_length() as u64).sum(),
+        }
+    }
+
+    /// Return true if this is a cold journal, without batch index. Refer
+    /// to [JournalIndex::to_seqnos] for the seqnos it holds.
+    pub fn is_cold(&self) -> bool {
+        self.cold.is_some()
+    }
+
+    /// Return the seqno span of this journal, None if it is empty, or if
+    /// it is a cold journal whose span is not known.
+    pub fn to_seqnos(&self) -> Option<ops::RangeInclusive<u64>> {
+        match &self.cold {
+            Some((seqnos, _)) => seqnos.clone(),
+            None => {
+                let first = self.index.first()?.to_first_seqno();
+                let last = self.index.last()?.to_last_seqno();
+                Some(first..=last)
+            }
+        }
+    }
+
+    /// Return whether `seqno` falls within this journal's seqno span.
+    pub fn contains(&self, seqno: u64) -> bool {
+        self.to_seqnos().map(|s| s.contains(&seqno)).unwrap_or(false)
+    }
+
+    /// Iterate over batch index, in file order.
+    pub fn iter(&self) -> impl Iterator<Item = &batch::Index> {
+        self.index.iter()
+    }
+}
+
+impl IntoIterator for JournalIndex {
+    type Item = batch::Index;
+    type IntoIter = vec::IntoIter<batch::Index>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.index.into_iter()
+    }
 }
 
 pub struct RdJournal {
     range: ops::RangeInclusive<u64>,
-    batch: vec::IntoIter<entry::Entry>,
+    topic: Option<String>,
+    tag: Option<String>,
+    // tagged entries read from a batch using its tag index, or entries
+    // from a preloaded batch.
+    tagged: vec::IntoIter<entry::Entry>,
+    cache: BTreeMap<u64, Arc<Vec<entry::Entry>>>,
+    batch: Option<batch::BatchIter>,
     index: vec::IntoIter<batch::Index>,
     entries: vec::IntoIter<entry::Entry>,
-    file: fs::File,
+    // opened only if there are batches to read.
+    file: Option<fs::File>,
+    // rehydrate ops spilled into blob file, None for pinned entries.
+    blob: Option<blob::Reader>,
 }
 
 impl RdJournal {
@@ -267,14 +1037,16 @@ impl RdJournal {
         journal: &Journal<S>,
         range: ops::RangeInclusive<u64>,
     ) -> Result<RdJournal> {
-        let (index, entries) = match &journal.inner {
+        let (index, entries, cache) = match &journal.inner {
             InnerJournal::Working { worker, .. } => {
-                (worker.to_index(), worker.to_entries())
+                (worker.to_index(), worker.to_entries()?, BTreeMap::default())
+            }
+            InnerJournal::Archive { index, cache, .. } => {
+                (index.to_vec(), vec![], cache.clone())
             }
-            InnerJournal::Archive { index, .. } => (index.to_vec(), vec![]),
-            InnerJournal::Cold => unreachable!(),
+            InnerJournal::Cold { .. } => unreachable!(),
         };
-        let batch: vec::IntoIter<entry::Entry> = vec![].into_iter();
+        let batch = None;
         let index = index
             .into_iter()
             .skip_while(|i| i.to_last_seqno() < *range.start())
@@ -287,12 +1059,138 @@ impl RdJournal {
             .collect::<Vec<entry::Entry>>()
             .into_iter();
 
-        let file = {
-            let mut opts = fs::OpenOptions::new();
-            err_at!(IOError, opts.read(true).open(&journal.file_path))?
+        let file = match index.len() {
+            0 => None,
+            _ => {
+                let mut opts = fs::OpenOptions::new();
+                Some(err_at!(IOError, opts.read(true).open(&journal.file_path))?)
+            }
         };
 
-        Ok(RdJournal { range, batch, index, entries, file })
+        Ok(RdJournal {
+            range,
+            topic: None,
+            tag: None,
+            tagged: vec![].into_iter(),
+            cache,
+            batch,
+            index,
+            entries,
+            file,
+            blob: Some(blob::Reader::new(&journal.file_path)),
+        })
+    }
+
+    /// Read entries, within `range`, from a journal's pinned entries.
+    pub fn from_entries(
+        entries: &[entry::Entry],
+        range: ops::RangeInclusive<u64>,
+    ) -> RdJournal {
+        let entries = entries
+            .iter()
+            .skip_while(|e| e.to_seqno() < *range.start())
+            .take_while(|e| e.to_seqno() <= *range.end())
+            .cloned()
+            .collect::<Vec<entry::Entry>>()
+            .into_iter();
+        let index = vec![].into_iter();
+        RdJournal {
+            range,
+            topic: None,
+            tag: None,
+            tagged: vec![].into_iter(),
+            cache: BTreeMap::default(),
+            batch: None,
+            index,
+            entries,
+            file: None,
+            blob: None,
+        }
+    }
+
+    /// Filter entries for `topic`, skipping batches that don't contain
+    /// the topic.
+    pub fn filter_topic(mut self, topic: &str) -> RdJournal {
+        let range = self.range.clone();
+        self.index = self
+            .index
+            .filter(|i| i.contains_topic(topic, &range))
+            .collect::<Vec<batch::Index>>()
+            .into_iter();
+        self.entries = self
+            .entries
+            .filter(|e| e.as_topic() == topic)
+            .collect::<Vec<entry::Entry>>()
+            .into_iter();
+        self.topic = Some(topic.to_string());
+        self
+    }
+
+    /// Filter entries for `tag`, skipping batches that don't contain the
+    /// tag. Batches with tag index are read entry by entry.
+    pub fn filter_tag(mut self, tag: &str) -> RdJournal {
+        let range = self.range.clone();
+        self.index = self
+            .index
+            .filter(|i| i.to_tag(tag, &range).is_some())
+            .collect::<Vec<batch::Index>>()
+            .into_iter();
+        self.entries = self
+            .entries
+            .filter(|e| e.as_tag() == tag)
+            .collect::<Vec<entry::Entry>>()
+            .into_iter();
+        self.tag = Some(tag.to_string());
+        self
+    }
+
+    fn next_entry(&mut self) -> Option<Result<entry::Entry>> {
+        loop {
+            if let Some(entry) = self.tagged.next() {
+                break Some(Ok(entry));
+            }
+            if let Some(item) = self.batch.as_mut().and_then(|b| b.next()) {
+                break Some(item);
+            }
+            self.batch = None;
+
+            let index = match self.index.next() {
+                Some(index) => index,
+                None => break self.entries.next().map(Ok),
+            };
+            if let Some(entries) = self.cache.get(&index.to_fpos()) {
+                let range = &self.range;
+                let iter = entries.iter().filter(|e| range.contains(&e.to_seqno()));
+                self.tagged = iter.cloned().collect::<Vec<entry::Entry>>().into_iter();
+                continue;
+            }
+            let file = self.file.as_ref()?;
+            let tag = self.tag.as_ref().and_then(|tag| index.to_tag(tag, &self.range));
+            match tag.and_then(|tag| tag.read_entries(&index, file, &self.range)) {
+                Some(Ok(entries)) => {
+                    self.tagged = entries.into_iter();
+                    continue;
+                }
+                Some(Err(err)) => break Some(Err(err)),
+                None => (),
+            }
+            match batch::BatchIter::from_index(&index, file, self.range.clone()) {
+                Ok(batch) => self.batch = Some(batch),
+                Err(err) => break Some(Err(err)),
+            }
+        }
+    }
+}
+
+impl RdJournal {
+    // Rehydrate op spilled into blob file, and decompress compressed op.
+    fn inflate(&mut self, entry: entry::Entry) -> Result<entry::Entry> {
+        let entry = match &mut self.blob {
+            _ if !entry.is_blob() => entry,
+            Some(blob) => blob.rehydrate(entry)?,
+            None => err_at!(Fatal, msg: "{} not rehydrated", entry)?,
+        };
+        compress::decompress_entry(entry)
     }
 }
 
@@ -300,18 +1198,13 @@ impl Iterator for RdJournal {
     type Item = Result<entry::Entry>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        match self.batch.next() {
-            Some(entry) => Some(Ok(entry)),
-            None => match self.index.next() {
-                Some(index) => match batch::Batch::from_index(index, &mut self.file) {
-                    Ok(batch) => {
-                        self.batch = batch.into_iter(self.range.clone());
-                        self.next()
-                    }
-                    Err(err) => Some(Err(err)),
-                },
-                None => self.entries.next().map(Ok),
-            },
+        loop {
+            match (self.next_entry()?, self.topic.as_ref(), self.tag.as_ref()) {
+                (Ok(entry), Some(topic), _) if entry.as_topic() != topic => (),
+                (Ok(entry), _, Some(tag)) if entry.as_tag() != tag => (),
+                (Ok(entry), _, _) => break Some(self.inflate(entry)),
+                (item, _, _) => break Some(item),
+            }
         }
     }
 }
@@ -2,7 +2,7 @@ use arbitrary::Unstructured;
 use rand::{prelude::random, rngs::StdRng, Rng, SeedableRng};
 
 use super::*;
-use crate::state;
+use crate::{state, storage::Options};
 
 #[test]
 fn test_journal() {
@@ -13,7 +13,9 @@ fn test_journal() {
     let name = "test_journal";
     let dir = tempfile::tempdir().unwrap();
     println!("test_journal {:?}", dir.path());
-    let mut jn = Journal::start(name, dir.path().as_ref(), 0, state::NoState).unwrap();
+    let mut jn =
+        Journal::start(name, dir.path().as_ref(), 0, &Options::default(), state::NoState)
+            .unwrap();
     assert_eq!(jn.to_journal_number(), 0);
     assert_eq!(jn.len_batches(), 0);
     assert_eq!(jn.to_state(), state::NoState);
@@ -57,13 +59,33 @@ fn test_journal() {
     assert_eq!(entries, jn_entries);
 
     {
-        let (load_jn, _) =
-            Journal::<state::NoState>::load(name, &jn.to_file_path()).unwrap();
+        let policy = state::StatePolicy::Discard;
+        let (load_jn, _, _) =
+            Journal::<state::NoState>::load(name, &jn.to_file_path(), policy, usize::MAX)
+                .unwrap();
         let iter = RdJournal::from_journal(&load_jn, 0..=u64::MAX).unwrap();
         let jn_entries: Vec<entry::Entry> = iter.map(|x| x.unwrap()).collect();
         let entries = entries[..offset].to_vec();
         assert_eq!(entries.len(), jn_entries.len());
         assert_eq!(entries, jn_entries);
+
+        // cold journal retains its seqno span and size.
+        let size = load_jn.to_journal_index().to_size();
+        let cold = load_jn.into_cold();
+        let (first, last) = (entries[0].to_seqno(), entries[offset - 1].to_seqno());
+        assert_eq!(cold.to_first_seqno(), Some(first));
+        assert_eq!(cold.to_last_seqno(), Some(last));
+        let index = cold.to_journal_index();
+        assert!(index.is_cold() && index.is_empty());
+        assert_eq!(index.to_seqnos(), Some(first..=last));
+        assert!(index.contains(last) && !index.contains(last + 1));
+        assert!(index.to_size() >= size);
+
+        let cold =
+            Journal::<state::NoState>::load_cold(name, &jn.to_file_path()).unwrap();
+        let index = cold.to_journal_index();
+        assert!(index.is_cold() && !index.contains(first));
+        assert_eq!(index.to_size(), size);
     }
 
     jn.purge().unwrap();
@@ -15,7 +15,9 @@
 //! enabled for every batch flush. With fsync enabled it is hard to reduce
 //! the latency, and to get better throughput applications can do concurrent
 //! writes. This is possible because [Wal] type can be cloned with underlying
-//! structure safely shared among all the clones. For example,
+//! structure safely shared among all the clones. Alternatively, when full
+//! per-batch durability is not required, fsync can be done periodically,
+//! refer to [Config::set_fsync_interval]. For example,
 //!
 //! ```ignore
 //! let wal = wral::Wal::create(config, wral::NoState).unwrap();
@@ -86,19 +88,97 @@ macro_rules! err_at {
     }};
 }
 
+#[cfg(feature = "arena")]
+mod arena;
 mod batch;
+mod blob;
+mod bloom;
+mod buffered;
+pub mod cbor;
+mod checkpoint;
+mod clock;
+mod compress;
+mod cursor;
+mod diff;
 mod entry;
+mod event;
+mod export;
 mod files;
+mod fsck;
+#[cfg(feature = "fuzz")]
+pub mod fuzz;
 mod journal;
+mod lsn;
+mod maintenance;
+mod manifest;
+mod middleware;
+mod migrate;
+mod quota;
+mod receipt;
+mod registry;
+#[cfg(feature = "replication")]
+mod replication;
+mod scoped;
+mod signal;
+mod spawner;
+mod split;
+mod spool;
 mod state;
+mod storage;
+#[cfg(feature = "async")]
+mod stream;
+#[cfg(feature = "testing")]
+pub mod testkit;
+mod tombstone;
 mod util;
 mod wral;
 mod writer;
 
-pub use crate::entry::Entry;
-pub use crate::state::{NoState, State};
+#[cfg(feature = "arena")]
+pub use crate::arena::EntryRef;
+pub use mkit;
+
+pub use crate::batch::{Codec, Index};
+pub use crate::buffered::BufferedWriter;
+pub use crate::clock::Clock;
+pub use crate::cursor::ReplayCursor;
+pub use crate::diff::{BatchMeta, ShipPlan};
+pub use crate::entry::{Entry, EntryBuilder};
+pub use crate::event::WalEvent;
+pub use crate::export::OpEncoding;
+pub use crate::fsck::{FsckLevel, FsckReport, JournalReport};
+pub use crate::journal::{EmptyPolicy, JournalIndex, ThawPolicy};
+pub use crate::lsn::Lsn;
+pub use crate::manifest::{ConfigVersion, Savepoint};
+pub use crate::middleware::{
+    register_middleware, Middleware, MIDDLEWARE_CHECKSUM, MIDDLEWARE_COMPRESS,
+    MIDDLEWARE_USER,
+};
+pub use crate::quota::Quota;
+pub use crate::receipt::Receipt;
+pub use crate::registry::{registry, Registry};
+#[cfg(feature = "replication")]
+pub use crate::replication::{Follower, Leader};
+pub use crate::scoped::ScopedWriter;
+pub use crate::signal::ShutdownSignal;
+pub use crate::spawner::{Job, Spawner};
+pub use crate::state::{Action, NoState, State, StatePolicy};
+pub use crate::storage::{Backend, FsyncInterval, MirrorPolicy, Placement, SpacePolicy};
+#[cfg(feature = "async")]
+pub use crate::stream::EntryStream;
+pub use crate::tombstone::Tombstone;
+pub use crate::wral::CloseOutcome;
 pub use crate::wral::Config;
+pub use crate::wral::ConsumerLag;
+pub use crate::wral::Estimate;
+pub use crate::wral::OpenMode;
+pub use crate::wral::ReclaimReport;
+pub use crate::wral::Stats;
 pub use crate::wral::Wal;
+pub use crate::wral::Watermarks;
+pub use crate::wral::{Health, HealthState};
+pub use crate::wral::{RecoveryReport, TornTail};
+pub use crate::wral::{RotateCause, Rotations};
 
 /// Type alias for Result return type, used by this package.
 pub type Result<T> = result::Result<T, Error>;
@@ -107,6 +187,7 @@ pub type Result<T> = result::Result<T, Error>;
 ///
 /// Each variant carries a prefix, typically identifying the
 /// error location.
+#[derive(Clone)]
 pub enum Error {
     FailConvert(String, String),
     FailCbor(String, String),
@@ -115,6 +196,19 @@ pub enum Error {
     Invalid(String, String),
     IPCFail(String, String),
     ThreadFail(String, String),
+    Overflow(String, String),
+    Timeout(String, String),
+    NotFound(String, String),
+    AlreadyExists(String, String),
+    ReadOnly(String, String),
+    Cancelled(String, String),
+    Rejected(String, String),
+    Mismatch(String, String),
+    Fenced(String, String),
+    OverQuota(String, String),
+    Corrupted(String, String),
+    NoSpace(String, String),
+    Sealed(String, String),
 }
 
 impl fmt::Display for Error {
@@ -129,6 +223,19 @@ impl fmt::Display for Error {
             Invalid(p, msg) => write!(f, "{} Invalid: {}", p, msg),
             IPCFail(p, msg) => write!(f, "{} IPCFail: {}", p, msg),
             ThreadFail(p, msg) => write!(f, "{} ThreadFail: {}", p, msg),
+            Overflow(p, msg) => write!(f, "{} Overflow: {}", p, msg),
+            Timeout(p, msg) => write!(f, "{} Timeout: {}", p, msg),
+            NotFound(p, msg) => write!(f, "{} NotFound: {}", p, msg),
+            AlreadyExists(p, msg) => write!(f, "{} AlreadyExists: {}", p, msg),
+            ReadOnly(p, msg) => write!(f, "{} ReadOnly: {}", p, msg),
+            Cancelled(p, msg) => write!(f, "{} Cancelled: {}", p, msg),
+            Rejected(p, msg) => write!(f, "{} Rejected: {}", p, msg),
+            Mismatch(p, msg) => write!(f, "{} Mismatch: {}", p, msg),
+            Fenced(p, msg) => write!(f, "{} Fenced: {}", p, msg),
+            OverQuota(p, msg) => write!(f, "{} OverQuota: {}", p, msg),
+            Corrupted(p, msg) => write!(f, "{} Corrupted: {}", p, msg),
+            NoSpace(p, msg) => write!(f, "{} NoSpace: {}", p, msg),
+            Sealed(p, msg) => write!(f, "{} Sealed: {}", p, msg),
         }
     }
 }
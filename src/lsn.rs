@@ -0,0 +1,99 @@
+//! Module implement log sequence number, a stable and human readable
+//! representation of a position in the log, refer to [Lsn].
+
+use std::{fmt, str::FromStr};
+
+use crate::{Error, Result};
+
+/// Position of an entry in the log, that can be exchanged between
+/// processes as a string.
+///
+/// Every Lsn carries the entry's seqno, and optionally the journal number
+/// and the file position of the batch holding the entry, refer to
+/// [Wal::lsn][crate::Wal::lsn]. Lsn is formatted as `{seqno}`, or as
+/// `{seqno}@{journal}:{fpos}` when it carries the position, and parsed
+/// back from the same format.
+///
+/// Lsn values are ordered by seqno, Lsn values with the same seqno are
+/// ordered with the position-less value first.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct Lsn {
+    seqno: u64,
+    // journal number and file position of the batch holding the entry.
+    position: Option<(usize, u64)>,
+}
+
+impl Lsn {
+    pub fn new(seqno: u64) -> Lsn {
+        Lsn { seqno, position: None }
+    }
+
+    /// Create Lsn for `seqno`, held by the batch at `fpos` in journal
+    /// number `num`.
+    pub fn with_position(seqno: u64, num: usize, fpos: u64) -> Lsn {
+        Lsn { seqno, position: Some((num, fpos)) }
+    }
+
+    #[inline]
+    pub fn to_seqno(&self) -> u64 {
+        self.seqno
+    }
+
+    #[inline]
+    pub fn to_journal_number(&self) -> Option<usize> {
+        self.position.map(|(num, _)| num)
+    }
+
+    #[inline]
+    pub fn to_fpos(&self) -> Option<u64> {
+        self.position.map(|(_, fpos)| fpos)
+    }
+}
+
+impl From<u64> for Lsn {
+    fn from(seqno: u64) -> Lsn {
+        Lsn::new(seqno)
+    }
+}
+
+impl From<Lsn> for u64 {
+    fn from(lsn: Lsn) -> u64 {
+        lsn.seqno
+    }
+}
+
+impl fmt::Display for Lsn {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.position {
+            Some((num, fpos)) => write!(f, "{}@{}:{}", self.seqno, num, fpos),
+            None => write!(f, "{}", self.seqno),
+        }
+    }
+}
+
+impl FromStr for Lsn {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Lsn> {
+        let (seqno, position) = match s.split_once('@') {
+            Some((seqno, position)) => match position.split_once(':') {
+                Some(position) => (seqno, Some(position)),
+                None => err_at!(Invalid, msg: "lsn {:?} without fpos", s)?,
+            },
+            None => (s, None),
+        };
+        let seqno = err_at!(Invalid, seqno.parse::<u64>(), "lsn {:?}", s)?;
+        match position {
+            Some((num, fpos)) => {
+                let num = err_at!(Invalid, num.parse::<usize>(), "lsn {:?}", s)?;
+                let fpos = err_at!(Invalid, fpos.parse::<u64>(), "lsn {:?}", s)?;
+                Ok(Lsn::with_position(seqno, num, fpos))
+            }
+            None => Ok(Lsn::new(seqno)),
+        }
+    }
+}
+
+#[cfg(test)]
+#[path = "lsn_test.rs"]
+mod lsn_test;
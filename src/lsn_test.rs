@@ -0,0 +1,44 @@
+use super::*;
+
+#[test]
+fn test_lsn_format() {
+    let lsns = vec![
+        (Lsn::new(0), "0"),
+        (Lsn::new(42), "42"),
+        (Lsn::with_position(42, 3, 1024), "42@3:1024"),
+        (Lsn::with_position(u64::MAX, 0, 0), "18446744073709551615@0:0"),
+    ];
+    for (lsn, s) in lsns.into_iter() {
+        assert_eq!(lsn.to_string(), s);
+        assert_eq!(s.parse::<Lsn>().unwrap(), lsn);
+    }
+
+    let lsn: Lsn = "42@3:1024".parse().unwrap();
+    assert_eq!(lsn.to_seqno(), 42);
+    assert_eq!(lsn.to_journal_number(), Some(3));
+    assert_eq!(lsn.to_fpos(), Some(1024));
+    assert_eq!(u64::from(lsn), 42);
+    assert_eq!(Lsn::from(42), Lsn::new(42));
+
+    for s in ["", "-1", "42@", "42@3", "42@3:", "42@:10", "a@3:10", "42@3:10:1"].iter() {
+        match s.parse::<Lsn>() {
+            Err(Error::Invalid(_, _)) => (),
+            res => panic!("expected Invalid for {:?}, {:?}", s, res),
+        }
+    }
+}
+
+#[test]
+fn test_lsn_order() {
+    let mut lsns = [
+        Lsn::with_position(20, 1, 0),
+        Lsn::new(20),
+        Lsn::with_position(10, 0, 512),
+        Lsn::new(30),
+    ];
+    lsns.sort();
+    let seqnos: Vec<u64> = lsns.iter().map(Lsn::to_seqno).collect();
+    assert_eq!(seqnos, vec![10, 20, 20, 30]);
+    assert_eq!(lsns[1], Lsn::new(20));
+    assert!(Lsn::new(10) < Lsn::with_position(11, 0, 0));
+}
@@ -0,0 +1,156 @@
+//! Module implement background maintenance, refer to
+//! [Config::set_maintenance][crate::Config::set_maintenance].
+//!
+//! Maintenance runs on its own thread, owned by the [Wal][crate::Wal]
+//! instance, and only while the writer has been idle for a configured
+//! duration. Idleness is observed via the appended seqno, once it moves
+//! the ongoing task is interrupted at the next batch boundary and resumed
+//! afresh on the next idle period.
+//!
+//! Archived journals are immutable, every archived journal is scrubbed,
+//! that is verified batch by batch, once per instance lifetime, and the
+//! outcome is emitted as [WalEvent::Scrubbed].
+
+use log::{debug, warn};
+
+use std::{
+    collections::BTreeSet,
+    ffi, path,
+    sync::{mpsc, Arc, RwLock, Weak},
+    thread, time,
+};
+
+use crate::{
+    event::{Events, WalEvent},
+    fsck, state, writer, Error, Result,
+};
+
+// Upper bound on how long the scheduler sleeps between checks.
+const POLL_INTERVAL: time::Duration = time::Duration::from_millis(100);
+
+pub struct Scheduler {
+    // dropping the sender stops the scheduler.
+    tx: mpsc::Sender<()>,
+    handle: thread::JoinHandle<()>,
+}
+
+impl Scheduler {
+    /// Start scheduler for writer `w`. Scheduler holds a weak reference,
+    /// and exits once the writer is dropped.
+    pub fn start<S>(
+        name: &str,
+        idle: time::Duration,
+        w: Weak<RwLock<writer::Writer<S>>>,
+        frontiers: Arc<writer::Frontiers>,
+        events: Arc<Events>,
+    ) -> Result<Scheduler>
+    where
+        S: state::State,
+    {
+        let (tx, rx) = mpsc::channel();
+        let worker = Worker {
+            idle,
+            w,
+            frontiers,
+            events,
+            rx,
+            scrubbed: BTreeSet::default(),
+        };
+        let handle = err_at!(
+            ThreadFail,
+            thread::Builder::new()
+                .name(format!("wral-maintenance-{}", name))
+                .spawn(move || worker.run())
+        )?;
+        Ok(Scheduler { tx, handle })
+    }
+
+    /// Stop the scheduler, interrupting the ongoing task, and wait for its
+    /// thread to exit.
+    pub fn close(self) -> Result<()> {
+        std::mem::drop(self.tx);
+        match self.handle.join() {
+            Ok(()) => Ok(()),
+            Err(err) => err_at!(ThreadFail, msg: "maintenance thread {:?}", err),
+        }
+    }
+}
+
+struct Worker<S> {
+    idle: time::Duration,
+    w: Weak<RwLock<writer::Writer<S>>>,
+    frontiers: Arc<writer::Frontiers>,
+    events: Arc<Events>,
+    rx: mpsc::Receiver<()>,
+    // archived journals scrubbed so far.
+    scrubbed: BTreeSet<usize>,
+}
+
+impl<S> Worker<S> {
+    fn run(mut self) {
+        let mut appended = self.frontiers.to_appended();
+        let mut since = time::Instant::now();
+        loop {
+            if self.frontiers.to_appended() != appended {
+                appended = self.frontiers.to_appended();
+                since = time::Instant::now();
+            }
+            let elapsed = since.elapsed();
+            let timeout = match self.idle.checked_sub(elapsed) {
+                Some(timeout) if !timeout.is_zero() => timeout.min(POLL_INTERVAL),
+                _ => match self.next_journal() {
+                    Some(Some((num, file_path))) => {
+                        self.scrub(num, &file_path, appended);
+                        continue;
+                    }
+                    Some(None) => POLL_INTERVAL,
+                    None => break,
+                },
+            };
+            match self.rx.recv_timeout(timeout) {
+                Err(mpsc::RecvTimeoutError::Timeout) => (),
+                _ => break,
+            }
+        }
+        debug!(target: "wral", "maintenance exited");
+    }
+
+    // Return the next archived journal to scrub, None if the writer is
+    // gone.
+    fn next_journal(&self) -> Option<Option<(usize, ffi::OsString)>> {
+        let w = self.w.upgrade()?;
+        let rd = w.read().ok()?;
+        let journal = rd
+            .journals
+            .iter()
+            .map(|j| (j.to_journal_number(), j.to_file_path()))
+            .find(|(num, _)| !self.scrubbed.contains(num));
+        Some(journal)
+    }
+
+    // Scrub journal `num`, interrupted when seqnos are appended beyond
+    // `appended`, or when the scheduler is stopped.
+    fn scrub(&mut self, num: usize, file_path: &ffi::OsStr, appended: u64) {
+        let interrupt = || {
+            self.frontiers.to_appended() != appended
+                || !matches!(self.rx.try_recv(), Err(mpsc::TryRecvError::Empty))
+        };
+        let file = file_path.to_os_string();
+        match fsck::verify_journal_until(num, path::Path::new(file_path), &interrupt) {
+            Ok(report) => {
+                let clean = report.is_clean();
+                if !clean {
+                    warn!(target: "wral", "scrub {:?} {:?}", file_path, report);
+                }
+                self.scrubbed.insert(num);
+                self.events.emit(WalEvent::Scrubbed { num, file, clean });
+            }
+            Err(Error::Cancelled(_, _)) => (),
+            Err(err) => {
+                // journal might have been purged, or relocated, meanwhile.
+                warn!(target: "wral", "scrub {:?} failed {}", file_path, err);
+                self.scrubbed.insert(num);
+            }
+        }
+    }
+}
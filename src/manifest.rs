@@ -0,0 +1,467 @@
+use log::debug;
+use mkit::{
+    cbor::{Cbor, FromCbor},
+    Cborize,
+};
+
+use std::{ffi, fs, ops, path, time};
+
+use crate::{bloom::Bloom, files, util, Error, Result};
+
+/// Manifest persist Wal-level metadata, that does not belong to any single
+/// journal, under `dir/{name}-manifest.cbor`.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Cborize)]
+pub struct Manifest {
+    // name of the Wal instance.
+    name: String,
+    // instance id generated on create, empty for instances created by an
+    // older release, refer to Journal::set_instance.
+    instance: String,
+    // list of seqno-epochs, in the order they were rebased.
+    epochs: Vec<Epoch>,
+    // seqno span of archived journals, sorted by journal number.
+    spans: Vec<Span>,
+    // instance is permanently read-only, refer to Wal::seal.
+    sealed: bool,
+    // versions of configuration, oldest first, refer to Wal::config_history.
+    configs: Vec<ConfigVersion>,
+    // named seqno bookmarks, in the order they were set, refer to
+    // Wal::savepoint.
+    savepoints: Vec<Savepoint>,
+    // acknowledged progress of consumers, sorted by name, refer to Wal::ack.
+    consumers: Vec<Consumer>,
+}
+
+/// Seqno epoch, recorded every time the seqno-space is rebased.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Cborize)]
+pub struct Epoch {
+    // epoch number, strictly increasing.
+    epoch: u64,
+    // first journal number in this epoch.
+    journal: u64,
+    // last seqno of the previous epoch.
+    seqno: u64,
+}
+
+impl Epoch {
+    const ID: u32 = 0x0;
+
+    pub fn new(epoch: u64, journal: usize, seqno: u64) -> Epoch {
+        Epoch { epoch, journal: journal as u64, seqno }
+    }
+
+    #[inline]
+    pub fn to_epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    #[inline]
+    pub fn to_journal_number(&self) -> usize {
+        self.journal as usize
+    }
+}
+
+/// Seqno span of an archived journal, used to skip journals that don't
+/// overlap with a range query, without consulting their batch index.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Cborize)]
+pub struct Span {
+    // journal number.
+    journal: u64,
+    // first seqno in the journal.
+    first: u64,
+    // last seqno in the journal, inclusive.
+    last: u64,
+    // bloom filter over tags in the journal, empty if not known.
+    tags: Vec<u8>,
+}
+
+impl Span {
+    const ID: u32 = 0x0;
+
+    pub fn new(journal: usize, first: u64, last: u64) -> Span {
+        Span {
+            journal: journal as u64,
+            first,
+            last,
+            tags: Vec::default(),
+        }
+    }
+
+    pub fn set_tags(mut self, bloom: &Bloom) -> Span {
+        self.tags = bloom.to_bytes();
+        self
+    }
+
+    #[inline]
+    pub fn to_journal_number(&self) -> usize {
+        self.journal as usize
+    }
+
+    #[inline]
+    pub fn to_range(&self) -> ops::RangeInclusive<u64> {
+        self.first..=self.last
+    }
+}
+
+/// Version of [Config][crate::Config], recorded in the manifest when the
+/// instance is created, and every time it is loaded or relocated with a
+/// changed configuration. Refer to
+/// [Wal::config_history][crate::Wal::config_history].
+#[derive(Debug, Clone, Default, Eq, PartialEq, Cborize)]
+pub struct ConfigVersion {
+    // nanoseconds since UNIX_EPOCH, from the instance's clock.
+    timestamp: u64,
+    // configuration parameters, sorted by name.
+    settings: Vec<Setting>,
+}
+
+/// Named bookmark at a seqno, refer to [Wal::savepoint][crate::Wal::savepoint].
+#[derive(Debug, Clone, Default, Eq, PartialEq, Cborize)]
+pub struct Savepoint {
+    name: String,
+    // seqno epoch and the last seqno handed out, when the savepoint was set.
+    epoch: u64,
+    seqno: u64,
+    // nanoseconds since UNIX_EPOCH, from the instance's clock.
+    timestamp: u64,
+}
+
+impl Savepoint {
+    const ID: u32 = 0x0;
+
+    pub(crate) fn new(name: &str, epoch: u64, seqno: u64, timestamp: u64) -> Savepoint {
+        Savepoint { name: name.to_string(), epoch, seqno, timestamp }
+    }
+
+    /// Return the name of this savepoint.
+    pub fn as_name(&self) -> &str {
+        &self.name
+    }
+
+    /// Return the seqno epoch this savepoint belongs to.
+    pub fn to_epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    /// Return the seqno of the last entry covered by this savepoint, ZERO
+    /// if it was set before the first entry of its epoch.
+    pub fn to_seqno(&self) -> u64 {
+        self.seqno
+    }
+
+    /// Return the time this savepoint was set.
+    pub fn to_timestamp(&self) -> time::SystemTime {
+        time::UNIX_EPOCH + time::Duration::from_nanos(self.timestamp)
+    }
+}
+
+// Seqno, in epoch, upto which a consumer has applied entries.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Cborize)]
+struct Consumer {
+    name: String,
+    epoch: u64,
+    seqno: u64,
+}
+
+impl Consumer {
+    const ID: u32 = 0x0;
+}
+
+// Configuration parameter and its value, formatted for display.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Cborize)]
+struct Setting {
+    name: String,
+    value: String,
+}
+
+impl Setting {
+    const ID: u32 = 0x0;
+}
+
+impl ConfigVersion {
+    const ID: u32 = 0x0;
+
+    pub(crate) fn new(timestamp: u64, settings: Vec<(String, String)>) -> ConfigVersion {
+        let mut settings: Vec<Setting> =
+            settings.into_iter().map(|(name, value)| Setting { name, value }).collect();
+        settings.sort_by(|a, b| a.name.cmp(&b.name));
+        ConfigVersion { timestamp, settings }
+    }
+
+    /// Return the time this version was recorded.
+    pub fn to_timestamp(&self) -> time::SystemTime {
+        time::UNIX_EPOCH + time::Duration::from_nanos(self.timestamp)
+    }
+
+    /// Return the value of configuration parameter `name`, named after
+    /// the field in [Config][crate::Config], say `fsync`.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        let off = self.settings.binary_search_by(|s| s.name.as_str().cmp(name)).ok()?;
+        Some(self.settings[off].value.as_str())
+    }
+
+    /// Iterate over configuration parameters as (name, value), sorted by
+    /// name.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.settings.iter().map(|s| (s.name.as_str(), s.value.as_str()))
+    }
+}
+
+impl Manifest {
+    const ID: u32 = 0x0;
+
+    pub fn new(name: &str) -> Manifest {
+        Manifest {
+            name: name.to_string(),
+            instance: String::default(),
+            epochs: Vec::default(),
+            spans: Vec::default(),
+            sealed: false,
+            configs: Vec::default(),
+            savepoints: Vec::default(),
+            consumers: Vec::default(),
+        }
+    }
+
+    /// Load manifest from `dir`, return None if there is no manifest file
+    /// for `name`.
+    pub fn load(dir: &ffi::OsStr, name: &str) -> Result<Option<Manifest>> {
+        let file_path = Self::to_file_path(dir, name);
+        if !file_path.exists() {
+            return Ok(None);
+        }
+
+        let data = err_at!(IOError, fs::read(&file_path))?;
+        let (val, _) = Cbor::decode(&mut data.as_slice())?;
+        let manifest = Manifest::from_cbor(val)?;
+        if manifest.name != name {
+            err_at!(Invalid, msg: "manifest {:?} for {}", file_path, manifest.name)?
+        }
+
+        debug!(target: "wral", "loaded manifest {:?}", file_path);
+        Ok(Some(manifest))
+    }
+
+    /// Persist manifest under `dir`, atomically replacing the older version.
+    pub fn save(&self, dir: &ffi::OsStr) -> Result<()> {
+        let file_path = Self::to_file_path(dir, &self.name);
+        let data = util::encode_cbor(self.clone())?;
+        util::atomic_write(&file_path, &data)
+    }
+
+    /// Remove manifest file for `name` under `dir`, if present.
+    pub fn purge(dir: &ffi::OsStr, name: &str) -> Result<()> {
+        let file_path = Self::to_file_path(dir, name);
+        if file_path.exists() {
+            debug!(target: "wral", "purging {:?} ...", file_path);
+            err_at!(IOError, fs::remove_file(&file_path))?;
+        }
+        Ok(())
+    }
+
+    fn to_file_path(dir: &ffi::OsStr, name: &str) -> path::PathBuf {
+        let file = files::make_manifest_filename(name);
+        [dir, &file].iter().collect()
+    }
+}
+
+impl Manifest {
+    pub fn add_epoch(&mut self, epoch: Epoch) -> Result<()> {
+        match self.epochs.last() {
+            Some(last) if last.epoch >= epoch.epoch => {
+                err_at!(Invalid, msg: "epoch {} <= {}", epoch.epoch, last.epoch)
+            }
+            None if epoch.epoch == 0 => err_at!(Invalid, msg: "epoch 0 is implicit"),
+            _ => {
+                self.epochs.push(epoch);
+                Ok(())
+            }
+        }
+    }
+
+    /// Replace seqno epochs with `epochs`, in the order they were rebased.
+    /// Fail if epoch numbers are not strictly increasing, or if journal
+    /// numbers are decreasing.
+    pub fn set_epochs(&mut self, epochs: Vec<Epoch>) -> Result<()> {
+        let mut manifest = Manifest::new(&self.name);
+        for epoch in epochs.into_iter() {
+            match manifest.epochs.last() {
+                Some(last) if last.journal > epoch.journal => err_at!(
+                    Invalid,
+                    msg: "epoch {} at journal {} < {}", epoch.epoch, epoch.journal, last.journal
+                )?,
+                _ => manifest.add_epoch(epoch)?,
+            }
+        }
+        self.epochs = manifest.epochs;
+        Ok(())
+    }
+
+    pub fn set_instance(&mut self, instance: &str) {
+        self.instance = instance.to_string();
+    }
+
+    /// Return the instance id, empty string if the instance is not yet
+    /// stamped.
+    pub fn as_instance(&self) -> &str {
+        &self.instance
+    }
+
+    pub fn set_sealed(&mut self) {
+        self.sealed = true;
+    }
+
+    /// Return whether the instance is sealed, refer to
+    /// [Wal::seal][crate::Wal::seal].
+    pub fn is_sealed(&self) -> bool {
+        self.sealed
+    }
+
+    /// Record `version` of configuration, unless its settings are the same
+    /// as the latest version. Return whether it was recorded.
+    pub fn add_config(&mut self, version: ConfigVersion) -> bool {
+        match self.configs.last() {
+            Some(last) if last.settings == version.settings => false,
+            _ => {
+                self.configs.push(version);
+                true
+            }
+        }
+    }
+
+    /// Return versions of configuration, oldest first.
+    pub fn to_configs(&self) -> Vec<ConfigVersion> {
+        self.configs.clone()
+    }
+
+    /// Set `savepoint`, replacing the older savepoint with the same name.
+    pub fn set_savepoint(&mut self, savepoint: Savepoint) {
+        self.savepoints.retain(|s| s.name != savepoint.name);
+        self.savepoints.push(savepoint);
+    }
+
+    /// Return the savepoint named `name`, if any.
+    pub fn to_savepoint(&self, name: &str) -> Option<Savepoint> {
+        self.savepoints.iter().find(|s| s.name == name).cloned()
+    }
+
+    /// Remove savepoints, in `epoch`, that are after `seqno`.
+    pub fn remove_savepoints_after(&mut self, epoch: u64, seqno: u64) {
+        self.savepoints.retain(|s| s.epoch != epoch || s.seqno <= seqno)
+    }
+
+    /// Return savepoints in the order they were set.
+    pub fn to_savepoints(&self) -> Vec<Savepoint> {
+        self.savepoints.clone()
+    }
+
+    /// Set progress of `consumer`, as (epoch, seqno), return false if it
+    /// is unchanged.
+    pub fn set_consumer(&mut self, consumer: &str, epoch: u64, seqno: u64) -> bool {
+        let val = Consumer { name: consumer.to_string(), epoch, seqno };
+        match self.consumers.binary_search_by(|c| c.name.as_str().cmp(consumer)) {
+            Ok(off) if self.consumers[off] == val => false,
+            Ok(off) => {
+                self.consumers[off] = val;
+                true
+            }
+            Err(off) => {
+                self.consumers.insert(off, val);
+                true
+            }
+        }
+    }
+
+    /// Remove `consumer`, return whether it was present.
+    pub fn remove_consumer(&mut self, consumer: &str) -> bool {
+        let n = self.consumers.len();
+        self.consumers.retain(|c| c.name != consumer);
+        self.consumers.len() < n
+    }
+
+    /// Return progress of consumers as (name, epoch, seqno), sorted by name.
+    pub fn to_consumers(&self) -> Vec<(String, u64, u64)> {
+        self.consumers.iter().map(|c| (c.name.clone(), c.epoch, c.seqno)).collect()
+    }
+
+    pub fn set_name(&mut self, name: &str) {
+        self.name = name.to_string();
+    }
+
+    /// Return the current epoch, None if seqno-space was never rebased.
+    pub fn to_epoch(&self) -> Option<Epoch> {
+        self.epochs.last().cloned()
+    }
+
+    /// Return the epoch to which journal numbered `num` belongs.
+    pub fn to_epoch_of(&self, num: usize) -> u64 {
+        let mut epochs = self.epochs.iter().rev();
+        match epochs.find(|e| e.to_journal_number() <= num) {
+            Some(epoch) => epoch.epoch,
+            None => 0,
+        }
+    }
+
+    pub fn to_epochs(&self) -> Vec<Epoch> {
+        self.epochs.clone()
+    }
+}
+
+impl Manifest {
+    /// Replace spans for all archived journals, return whether they have
+    /// changed.
+    pub fn set_spans(&mut self, mut spans: Vec<Span>) -> bool {
+        spans.sort_by_key(|s| s.journal);
+        let changed = spans != self.spans;
+        self.spans = spans;
+        changed
+    }
+
+    /// Add span for a newly archived journal, replacing older span for
+    /// the same journal.
+    pub fn add_span(&mut self, span: Span) {
+        self.spans.retain(|s| s.journal != span.journal);
+        self.spans.push(span);
+        self.spans.sort_by_key(|s| s.journal);
+    }
+
+    /// Remove span for a purged journal.
+    pub fn remove_span(&mut self, num: usize) {
+        self.spans.retain(|s| s.to_journal_number() != num)
+    }
+
+    /// Renumber journals after `num` by `by`, when journal `num` is split
+    /// into `by + 1` journals.
+    pub fn shift_journals(&mut self, num: usize, by: usize) {
+        let (num, by) = (num as u64, by as u64);
+        for epoch in self.epochs.iter_mut().filter(|e| e.journal > num) {
+            epoch.journal += by
+        }
+        for span in self.spans.iter_mut().filter(|s| s.journal > num) {
+            span.journal += by
+        }
+    }
+
+    /// Return seqno span of archived journal `num`, if known.
+    pub fn to_span(&self, num: usize) -> Option<ops::RangeInclusive<u64>> {
+        let off = self.spans.binary_search_by_key(&(num as u64), |s| s.journal).ok()?;
+        Some(self.spans[off].to_range())
+    }
+
+    /// Return false if archived journal `num` definitely has no entry
+    /// tagged with `tag`.
+    pub fn may_contain_tag(&self, num: usize, tag: &str) -> bool {
+        let off = match self.spans.binary_search_by_key(&(num as u64), |s| s.journal) {
+            Ok(off) => off,
+            Err(_) => return true,
+        };
+        match Bloom::from_bytes(&self.spans[off].tags) {
+            Some(bloom) => bloom.contains(tag),
+            None => true,
+        }
+    }
+}
+
+#[cfg(test)]
+#[path = "manifest_test.rs"]
+mod manifest_test;
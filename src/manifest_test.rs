@@ -0,0 +1,106 @@
+use super::*;
+
+#[test]
+fn test_manifest() {
+    let dir = tempfile::tempdir().unwrap();
+    let name = "test-manifest";
+
+    assert_eq!(Manifest::load(dir.path().as_ref(), name).unwrap(), None);
+
+    let mut mf = Manifest::new(name);
+    assert_eq!(mf.to_epoch(), None);
+    assert!(mf.add_epoch(Epoch::new(0, 1, 100)).is_err());
+    mf.add_epoch(Epoch::new(1, 1, 100)).unwrap();
+    assert!(mf.add_epoch(Epoch::new(1, 2, 200)).is_err());
+    mf.add_epoch(Epoch::new(2, 3, 300)).unwrap();
+    assert_eq!(mf.to_epoch(), Some(Epoch::new(2, 3, 300)));
+    assert!(!mf.is_sealed());
+    mf.set_sealed();
+
+    let settings = vec![("fsync".to_string(), "true".to_string())];
+    assert!(mf.add_config(ConfigVersion::new(10, settings.clone())));
+    assert!(!mf.add_config(ConfigVersion::new(20, settings)));
+    let settings = vec![
+        ("journal_limit".to_string(), "1000".to_string()),
+        ("fsync".to_string(), "false".to_string()),
+    ];
+    assert!(mf.add_config(ConfigVersion::new(30, settings)));
+    let configs = mf.to_configs();
+    assert_eq!(configs.len(), 2);
+    assert_eq!(configs[1].get("fsync"), Some("false"));
+    assert_eq!(configs[1].get("codec"), None);
+    let names: Vec<&str> = configs[1].iter().map(|(name, _)| name).collect();
+    assert_eq!(names, vec!["fsync", "journal_limit"]);
+    let ts = configs[0].to_timestamp().duration_since(time::UNIX_EPOCH).unwrap();
+    assert_eq!(ts.as_nanos(), 10);
+
+    mf.set_savepoint(Savepoint::new("a", 2, 10, 40));
+    mf.set_savepoint(Savepoint::new("b", 2, 20, 50));
+    mf.set_savepoint(Savepoint::new("a", 2, 30, 60));
+    mf.set_savepoint(Savepoint::new("c", 1, 40, 70));
+    let names: Vec<String> =
+        mf.to_savepoints().iter().map(|s| s.as_name().to_string()).collect();
+    assert_eq!(names, vec!["b", "a", "c"]);
+    assert_eq!(mf.to_savepoint("a").map(|s| s.to_seqno()), Some(30));
+    assert_eq!(mf.to_savepoint("d"), None);
+    mf.remove_savepoints_after(2, 20);
+    let names: Vec<String> =
+        mf.to_savepoints().iter().map(|s| s.as_name().to_string()).collect();
+    assert_eq!(names, vec!["b", "c"]);
+
+    assert!(mf.set_consumer("y", 2, 10));
+    assert!(mf.set_consumer("x", 1, 40));
+    assert!(!mf.set_consumer("y", 2, 10));
+    assert!(mf.set_consumer("y", 2, 20));
+    assert!(mf.set_consumer("z", 2, 5));
+    assert!(mf.remove_consumer("z"));
+    assert!(!mf.remove_consumer("z"));
+    let consumers = vec![("x".to_string(), 1, 40), ("y".to_string(), 2, 20)];
+    assert_eq!(mf.to_consumers(), consumers);
+
+    mf.save(dir.path().as_ref()).unwrap();
+    let val = Manifest::load(dir.path().as_ref(), name).unwrap().unwrap();
+    assert_eq!(val, mf);
+    assert!(val.is_sealed());
+
+    Manifest::purge(dir.path().as_ref(), name).unwrap();
+    assert_eq!(Manifest::load(dir.path().as_ref(), name).unwrap(), None);
+}
+
+#[test]
+fn test_manifest_spans() {
+    let dir = tempfile::tempdir().unwrap();
+    let name = "test-manifest-spans";
+
+    let mut mf = Manifest::new(name);
+    assert!(!mf.set_spans(vec![]));
+    assert!(mf.set_spans(vec![Span::new(2, 21, 30), Span::new(1, 11, 20)]));
+    assert!(!mf.set_spans(vec![Span::new(1, 11, 20), Span::new(2, 21, 30)]));
+    mf.add_span(Span::new(3, 31, 40));
+    mf.add_span(Span::new(2, 21, 29));
+    assert_eq!(mf.to_span(1), Some(11..=20));
+    assert_eq!(mf.to_span(2), Some(21..=29));
+    assert_eq!(mf.to_span(3), Some(31..=40));
+    mf.remove_span(1);
+    assert_eq!(mf.to_span(1), None);
+    assert_eq!(mf.to_span(4), None);
+
+    mf.add_epoch(Epoch::new(1, 3, 40)).unwrap();
+    mf.shift_journals(2, 2);
+    assert_eq!(mf.to_span(2), Some(21..=29));
+    assert_eq!(mf.to_span(3), None);
+    assert_eq!(mf.to_span(5), Some(31..=40));
+    assert_eq!(mf.to_epoch().unwrap().to_journal_number(), 5);
+
+    let bloom = Bloom::from_tags(vec!["user-1", "user-2"].into_iter());
+    mf.add_span(Span::new(6, 41, 50).set_tags(&bloom));
+    mf.add_span(Span::new(7, 51, 60).set_tags(&Bloom::default()));
+    assert!(mf.may_contain_tag(6, "user-1"));
+    assert!(!mf.may_contain_tag(7, "user-1"));
+    assert!(mf.may_contain_tag(5, "user-1"));
+    assert!(mf.may_contain_tag(8, "user-1"));
+
+    mf.save(dir.path().as_ref()).unwrap();
+    let val = Manifest::load(dir.path().as_ref(), name).unwrap().unwrap();
+    assert_eq!(val, mf);
+}
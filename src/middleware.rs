@@ -0,0 +1,138 @@
+//! Module implement middleware chain over batch payload, refer to
+//! [Config::set_middleware][crate::Config::set_middleware].
+//!
+//! Middleware are registered process-wide, by id, and a batch records the
+//! ids of the chain it was sealed with, so that readers can unseal any
+//! batch without being configured with the writer's chain. Ids below
+//! [MIDDLEWARE_USER] are reserved for built-in middleware.
+
+use std::{
+    collections::BTreeMap,
+    convert::TryFrom,
+    sync::{Arc, OnceLock, RwLock},
+};
+
+use crate::{compress, util, Error, Result};
+
+/// Id of built-in middleware that compress the payload, refer to
+/// [Config::set_entry_compression][crate::Config::set_entry_compression]
+/// for compressing individual ops instead.
+pub const MIDDLEWARE_COMPRESS: u32 = 1;
+/// Id of built-in middleware that append CRC-32 of the payload, and
+/// verify it on read.
+pub const MIDDLEWARE_CHECKSUM: u32 = 2;
+/// Smallest id available for application middleware.
+pub const MIDDLEWARE_USER: u32 = 256;
+
+static MIDDLEWARE: OnceLock<RwLock<BTreeMap<u32, Arc<dyn Middleware>>>> = OnceLock::new();
+
+/// Transformation over the payload of a batch, that is, its encoded
+/// entries. Say, encryption. Transformations must be deterministic in
+/// the sense that `unseal(seal(data)) == data`.
+pub trait Middleware: Send + Sync {
+    /// Transform `data` on its way to the journal.
+    fn seal(&self, data: Vec<u8>) -> Result<Vec<u8>>;
+
+    /// Reverse of [Middleware::seal], on its way back from the journal.
+    fn unseal(&self, data: Vec<u8>) -> Result<Vec<u8>>;
+}
+
+/// Register middleware `mw` under `id`, which can subsequently be used in
+/// a chain. Fail with [Error::AlreadyExists] if `id` is already
+/// registered, and with [Error::Invalid] if `id` is reserved.
+///
+/// Middleware must be registered, under the same id, before loading any
+/// instance with batches sealed by it.
+pub fn register_middleware(id: u32, mw: Arc<dyn Middleware>) -> Result<()> {
+    if id < MIDDLEWARE_USER {
+        err_at!(Invalid, msg: "middleware id {} is reserved", id)?
+    }
+    let mut registry = err_at!(Fatal, to_registry().write())?;
+    if registry.contains_key(&id) {
+        err_at!(AlreadyExists, msg: "middleware id {}", id)?
+    }
+    registry.insert(id, mw);
+    Ok(())
+}
+
+/// Make sure every middleware in the chain `ids` is registered.
+pub fn validate(ids: &[u32]) -> Result<()> {
+    to_chain(ids).map(|_| ())
+}
+
+/// Apply the chain `ids` over `data`, in order.
+pub fn seal(ids: &[u32], mut data: Vec<u8>) -> Result<Vec<u8>> {
+    for mw in to_chain(ids)?.iter() {
+        data = mw.seal(data)?;
+    }
+    Ok(data)
+}
+
+/// Reverse the chain `ids` over `data`, in the reverse order.
+pub fn unseal(ids: &[u32], mut data: Vec<u8>) -> Result<Vec<u8>> {
+    for mw in to_chain(ids)?.iter().rev() {
+        data = mw.unseal(data)?;
+    }
+    Ok(data)
+}
+
+fn to_registry() -> &'static RwLock<BTreeMap<u32, Arc<dyn Middleware>>> {
+    MIDDLEWARE.get_or_init(|| {
+        let mut registry: BTreeMap<u32, Arc<dyn Middleware>> = BTreeMap::default();
+        registry.insert(MIDDLEWARE_COMPRESS, Arc::new(Compress));
+        registry.insert(MIDDLEWARE_CHECKSUM, Arc::new(Checksum));
+        RwLock::new(registry)
+    })
+}
+
+fn to_chain(ids: &[u32]) -> Result<Vec<Arc<dyn Middleware>>> {
+    let registry = err_at!(Fatal, to_registry().read())?;
+    let mut chain = vec![];
+    for id in ids.iter() {
+        match registry.get(id) {
+            Some(mw) => chain.push(Arc::clone(mw)),
+            None => err_at!(NotFound, msg: "middleware id {} not registered", id)?,
+        }
+    }
+    Ok(chain)
+}
+
+struct Compress;
+
+impl Middleware for Compress {
+    fn seal(&self, data: Vec<u8>) -> Result<Vec<u8>> {
+        Ok(compress::compress(&data))
+    }
+
+    fn unseal(&self, data: Vec<u8>) -> Result<Vec<u8>> {
+        compress::decompress(&data)
+    }
+}
+
+struct Checksum;
+
+impl Middleware for Checksum {
+    fn seal(&self, mut data: Vec<u8>) -> Result<Vec<u8>> {
+        let crc = util::crc32(&data);
+        data.extend_from_slice(&crc.to_be_bytes());
+        Ok(data)
+    }
+
+    fn unseal(&self, mut data: Vec<u8>) -> Result<Vec<u8>> {
+        let n = match data.len().checked_sub(4) {
+            Some(n) => n,
+            None => err_at!(Invalid, msg: "checksum payload of {} bytes", data.len())?,
+        };
+        let crc =
+            u32::from_be_bytes(err_at!(FailConvert, <[u8; 4]>::try_from(&data[n..]))?);
+        data.truncate(n);
+        if util::crc32(&data) != crc {
+            err_at!(Invalid, msg: "batch payload checksum mismatch")?
+        }
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+#[path = "middleware_test.rs"]
+mod middleware_test;
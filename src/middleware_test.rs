@@ -0,0 +1,55 @@
+use super::*;
+
+struct Xor(u8);
+
+impl Middleware for Xor {
+    fn seal(&self, data: Vec<u8>) -> Result<Vec<u8>> {
+        Ok(data.into_iter().map(|b| b ^ self.0).collect())
+    }
+
+    fn unseal(&self, data: Vec<u8>) -> Result<Vec<u8>> {
+        self.seal(data)
+    }
+}
+
+#[test]
+fn test_middleware() {
+    let id = MIDDLEWARE_USER + 1;
+    match register_middleware(MIDDLEWARE_CHECKSUM, Arc::new(Xor(0x5a))) {
+        Err(Error::Invalid(_, _)) => (),
+        res => panic!("{:?}", res),
+    }
+    register_middleware(id, Arc::new(Xor(0x5a))).unwrap();
+    match register_middleware(id, Arc::new(Xor(0xa5))) {
+        Err(Error::AlreadyExists(_, _)) => (),
+        res => panic!("{:?}", res),
+    }
+
+    let data: Vec<u8> = (0..1000).map(|i| (i % 7) as u8).collect();
+    let chains = [
+        vec![],
+        vec![MIDDLEWARE_COMPRESS, id, MIDDLEWARE_CHECKSUM],
+        vec![MIDDLEWARE_CHECKSUM, id, MIDDLEWARE_COMPRESS],
+        vec![id, id],
+    ];
+    for ids in chains.iter() {
+        validate(ids).unwrap();
+        let sealed = seal(ids, data.clone()).unwrap();
+        assert_eq!(unseal(ids, sealed).unwrap(), data, "{:?}", ids);
+    }
+    let sealed = seal(&[MIDDLEWARE_COMPRESS], data.clone()).unwrap();
+    assert!(sealed.len() < data.len());
+
+    let mut sealed = seal(&[id, MIDDLEWARE_CHECKSUM], data.clone()).unwrap();
+    sealed[10] ^= 0xff;
+    match unseal(&[id, MIDDLEWARE_CHECKSUM], sealed) {
+        Err(Error::Invalid(_, _)) => (),
+        res => panic!("{:?}", res),
+    }
+    assert!(unseal(&[MIDDLEWARE_CHECKSUM], vec![1, 2]).is_err());
+
+    match validate(&[MIDDLEWARE_COMPRESS, MIDDLEWARE_USER + 1000]) {
+        Err(Error::NotFound(_, _)) => (),
+        res => panic!("{:?}", res),
+    }
+}
@@ -0,0 +1,46 @@
+//! Module implement rewriting of journal files into a different format,
+//! refer to [Wal::migrate_format][crate::Wal::migrate_format].
+
+use std::{
+    ffi, fs,
+    io::{self, Write},
+    path,
+};
+
+use crate::{batch, util, Codec, Error, Result};
+
+/// Return the directory, under `dir`, holding journals rewritten by format
+/// migration, until they replace their source.
+pub fn to_migrate_dir(dir: &ffi::OsStr, name: &str) -> path::PathBuf {
+    path::Path::new(dir).join(format!("{}-migrate", name))
+}
+
+/// Rewrite batches from journal at `src_path` into `dst_path`, encoding
+/// entries with `codec` and compressing them as per `compress`, refer to
+/// [batch::Batch::recode]. The rewritten file is synced. Return the number
+/// of batches rewritten.
+pub fn rewrite_journal(
+    src_path: &ffi::OsStr,
+    dst_path: &ffi::OsStr,
+    codec: Codec,
+    compress: Option<usize>,
+) -> Result<usize> {
+    let file = err_at!(IOError, fs::File::open(src_path))?;
+    let file_size = err_at!(IOError, file.metadata())?.len();
+    let mut reader = io::BufReader::new(file);
+
+    let mut file = err_at!(IOError, fs::File::create(dst_path))?;
+    let (mut fpos, mut n_batches, mut buf) = (0_u64, 0, vec![]);
+    while fpos < file_size {
+        let (val, n) = util::decode_cbor(&mut reader, file_size - fpos)?;
+        let batch = batch::Batch::decode(val)?.recode(codec, compress)?;
+        buf.clear();
+        util::encode_cbor_into(batch, &mut buf)?;
+        err_at!(IOError, file.write_all(&buf))?;
+        fpos += n as u64;
+        n_batches += 1;
+    }
+    err_at!(IOError, file.sync_all())?;
+
+    Ok(n_batches)
+}
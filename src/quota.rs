@@ -0,0 +1,107 @@
+//! Module implement per identity quota over the write path, refer to
+//! [Config::set_quota][crate::Config::set_quota] and
+//! [Wal::labeled_clone][crate::Wal::labeled_clone].
+//!
+//! Quota is evaluated by the writer, for every request adding ops, before
+//! seqnos are assigned. Ops are accounted by their payload size, ops from
+//! handles without an identity, or with an identity without quota, are
+//! not accounted.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    convert::TryFrom,
+    time,
+};
+
+use crate::{Error, Result};
+
+/// Quota for an identity, ZERO for either limit means unlimited.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct Quota {
+    /// Sustained rate, in op bytes per second. Ops can burst upto a
+    /// second's worth of bytes, requests larger than that always fail.
+    pub bytes_per_sec: u64,
+    /// Total op bytes, accounted since the instance was opened.
+    pub total_bytes: u64,
+}
+
+// Accounting of an identity, rate is enforced as a token bucket.
+struct Account {
+    total: u64,
+    tokens: u64,
+    refilled: time::Instant,
+}
+
+impl Account {
+    fn new(quota: &Quota, now: time::Instant) -> Account {
+        Account {
+            total: 0,
+            tokens: quota.bytes_per_sec,
+            refilled: now,
+        }
+    }
+
+    fn refill(&mut self, quota: &Quota, now: time::Instant) {
+        let elapsed = now.saturating_duration_since(self.refilled).as_nanos();
+        let tokens =
+            elapsed.saturating_mul(u128::from(quota.bytes_per_sec)) / 1_000_000_000;
+        let tokens = u64::try_from(tokens).unwrap_or(u64::MAX);
+        self.tokens = self.tokens.saturating_add(tokens).min(quota.bytes_per_sec);
+        self.refilled = now;
+    }
+}
+
+/// Per identity accounting, as per the quota of each identity.
+#[derive(Default)]
+pub struct Accounts {
+    quotas: BTreeMap<String, Quota>,
+    accounts: HashMap<String, Account>,
+}
+
+impl Accounts {
+    pub fn new(quotas: BTreeMap<String, Quota>) -> Accounts {
+        Accounts { quotas, accounts: HashMap::default() }
+    }
+
+    /// Account `bytes` to `identity`, fail with [Error::OverQuota] without
+    /// accounting them, if they exceed its quota.
+    pub fn charge(
+        &mut self,
+        identity: &str,
+        bytes: u64,
+        now: time::Instant,
+    ) -> Result<()> {
+        let quota = match self.quotas.get(identity) {
+            Some(quota) => quota,
+            None => return Ok(()),
+        };
+        let account = self
+            .accounts
+            .entry(identity.to_string())
+            .or_insert_with(|| Account::new(quota, now));
+
+        let total = account.total.saturating_add(bytes);
+        if quota.total_bytes > 0 && total > quota.total_bytes {
+            err_at!(
+                OverQuota,
+                msg: "{} total bytes {} > {}", identity, total, quota.total_bytes
+            )?
+        }
+        if quota.bytes_per_sec > 0 {
+            account.refill(quota, now);
+            if bytes > account.tokens {
+                err_at!(
+                    OverQuota,
+                    msg: "{} rate exceeds {} bytes/sec", identity, quota.bytes_per_sec
+                )?
+            }
+            account.tokens -= bytes;
+        }
+        account.total = total;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+#[path = "quota_test.rs"]
+mod quota_test;
@@ -0,0 +1,43 @@
+use super::*;
+
+#[test]
+fn test_quota_total() {
+    let quota = Quota { bytes_per_sec: 0, total_bytes: 100 };
+    let mut quotas = BTreeMap::default();
+    quotas.insert("tenant-a".to_string(), quota);
+    let mut accounts = Accounts::new(quotas);
+
+    let now = time::Instant::now();
+    accounts.charge("tenant-a", 60, now).unwrap();
+    accounts.charge("tenant-a", 40, now).unwrap();
+    match accounts.charge("tenant-a", 1, now) {
+        Err(Error::OverQuota(_, _)) => (),
+        res => panic!("expected OverQuota, {:?}", res),
+    }
+    // identities without quota are not accounted.
+    accounts.charge("tenant-b", 1000, now).unwrap();
+    accounts.charge("", 1000, now).unwrap();
+}
+
+#[test]
+fn test_quota_rate() {
+    let quota = Quota { bytes_per_sec: 1000, total_bytes: 0 };
+    let mut quotas = BTreeMap::default();
+    quotas.insert("tenant-a".to_string(), quota);
+    let mut accounts = Accounts::new(quotas);
+
+    let now = time::Instant::now();
+    accounts.charge("tenant-a", 1000, now).unwrap();
+    match accounts.charge("tenant-a", 1, now) {
+        Err(Error::OverQuota(_, _)) => (),
+        res => panic!("expected OverQuota, {:?}", res),
+    }
+    // refilled at the sustained rate, upto a second's worth.
+    let later = now + time::Duration::from_millis(500);
+    accounts.charge("tenant-a", 500, later).unwrap();
+    assert!(accounts.charge("tenant-a", 1, later).is_err());
+
+    let later = later + time::Duration::from_secs(10);
+    assert!(accounts.charge("tenant-a", 1001, later).is_err());
+    accounts.charge("tenant-a", 1000, later).unwrap();
+}
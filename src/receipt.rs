@@ -0,0 +1,91 @@
+//! Module implement receipts for ops submitted without waiting on the
+//! writer, refer to [Wal::submit_ops][crate::Wal::submit_ops].
+//!
+//! Requests made via [Wal::add_op][crate::Wal::add_op] and friends are
+//! acknowledged with a channel send per request, which wakes up each
+//! caller separately. Submitted requests are instead acknowledged by
+//! filling in their [Ack] slot, without waking anyone, and their callers
+//! are woken up together by the durable watermark, once per batch.
+
+use std::{
+    ops,
+    sync::{Arc, Mutex},
+    time,
+};
+
+use crate::{writer, Error, Result};
+
+/// Acknowledgement slot for a submitted request, filled in by the writer.
+#[derive(Debug, Default)]
+pub struct Ack {
+    res: Mutex<Option<Result<ops::RangeInclusive<u64>>>>,
+}
+
+impl Ack {
+    /// Fill in the response for the request, callers are not woken up,
+    /// writer shall move the durable watermark after filling in acks.
+    pub fn set(&self, res: writer::Res) {
+        let res = match res {
+            writer::Res::Seqno(seqno) => Ok(seqno..=seqno),
+            writer::Res::Seqnos(seqnos) => Ok(seqnos),
+            writer::Res::Fail(err) => Err(err),
+            res => err_at!(Fatal, msg: "unexpected response {:?}", res),
+        };
+        if let Ok(mut slot) = self.res.lock() {
+            *slot = Some(res);
+        }
+    }
+
+    fn to_result(&self) -> Result<Option<Result<ops::RangeInclusive<u64>>>> {
+        Ok(err_at!(Fatal, self.res.lock())?.clone())
+    }
+}
+
+/// Receipt for ops submitted via [Wal::submit_ops][crate::Wal::submit_ops].
+///
+/// Receipt resolves to the seqnos assigned to the ops once they are
+/// durable, or to the error that failed them.
+pub struct Receipt {
+    ack: Arc<Ack>,
+    durable: Arc<writer::Watermark>,
+}
+
+impl Receipt {
+    pub(crate) fn new(ack: Arc<Ack>, durable: Arc<writer::Watermark>) -> Receipt {
+        Receipt { ack, durable }
+    }
+
+    /// Return the seqnos of the submitted ops if they are durable, the
+    /// error if they failed, or None if they are yet to be acknowledged
+    /// or yet to be fsynced, refer to
+    /// [Config::set_fsync_interval][crate::Config::set_fsync_interval].
+    pub fn try_wait(&self) -> Result<Option<ops::RangeInclusive<u64>>> {
+        let durable = self.durable.to_seqno()?;
+        Self::to_durable(self.ack.to_result()?, durable)
+    }
+
+    /// Block until the submitted ops are acknowledged, or until `timeout`
+    /// elapses, in which case [Error::Timeout] is returned. On success
+    /// return the seqnos of the ops, which are durable.
+    pub fn wait(&self, timeout: time::Duration) -> Result<ops::RangeInclusive<u64>> {
+        self.durable.wait_until(timeout, |durable| match self.ack.to_result() {
+            Ok(res) => !matches!(Self::to_durable(res, durable), Ok(None)),
+            Err(_) => true,
+        })?;
+        match self.try_wait()? {
+            Some(seqnos) => Ok(seqnos),
+            None => err_at!(Timeout, msg: "not acknowledged after {:?}", timeout),
+        }
+    }
+
+    fn to_durable(
+        res: Option<Result<ops::RangeInclusive<u64>>>,
+        durable: u64,
+    ) -> Result<Option<ops::RangeInclusive<u64>>> {
+        match res {
+            Some(Ok(seqnos)) if *seqnos.end() <= durable => Ok(Some(seqnos)),
+            Some(Ok(_)) | None => Ok(None),
+            Some(Err(err)) => Err(err),
+        }
+    }
+}
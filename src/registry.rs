@@ -0,0 +1,121 @@
+//! Module implement a process-wide registry of [Wal] instances.
+//!
+//! Registration is optional, applications register a Wal instance by
+//! name, and subsequently other modules can lookup a handle to the
+//! instance without plumbing clones through every constructor.
+
+use std::{
+    any::Any,
+    collections::BTreeMap,
+    sync::{Mutex, OnceLock},
+};
+
+use crate::{
+    wral::{Stats, Wal},
+    Error, Result,
+};
+
+static REGISTRY: OnceLock<Registry> = OnceLock::new();
+
+/// Return the process-wide registry of [Wal] instances.
+pub fn registry() -> &'static Registry {
+    REGISTRY.get_or_init(|| Registry { handles: Mutex::new(BTreeMap::default()) })
+}
+
+/// Registry of [Wal] instances, indexed by name. Refer to [registry].
+///
+/// Registry holds a clone of each registered instance. Closing the last
+/// application handle to an instance, via [Wal::close], shall unregister
+/// the instance.
+pub struct Registry {
+    handles: Mutex<BTreeMap<String, Box<dyn Handle>>>,
+}
+
+// Type erased Wal handle.
+trait Handle: Send + Sync {
+    fn to_key(&self) -> usize;
+
+    fn to_stats(&self) -> Result<Stats>;
+
+    fn as_any(&self) -> &dyn Any;
+}
+
+impl<S> Handle for Wal<S>
+where
+    S: 'static + Send + Sync,
+{
+    fn to_key(&self) -> usize {
+        Wal::to_key(self)
+    }
+
+    fn to_stats(&self) -> Result<Stats> {
+        self.stats()
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+impl Registry {
+    /// Register `wal` under its current name. Fail if another instance is
+    /// already registered under the same name.
+    pub fn register<S>(&self, wal: &Wal<S>) -> Result<()>
+    where
+        S: 'static + Send + Sync,
+    {
+        let name = wal.stats()?.name;
+        let mut handles = err_at!(Fatal, self.handles.lock())?;
+        if handles.contains_key(&name) {
+            err_at!(Invalid, msg: "wal {} already registered", name)?
+        }
+        handles.insert(name, Box::new(wal.clone()));
+        Ok(())
+    }
+
+    /// Unregister instance registered as `name`, return false if there is
+    /// no such instance.
+    pub fn unregister(&self, name: &str) -> Result<bool> {
+        Ok(err_at!(Fatal, self.handles.lock())?.remove(name).is_some())
+    }
+
+    /// Return a handle to the instance registered as `name`. Return None
+    /// if there is no such instance, or if the instance's state type is
+    /// not `S`.
+    pub fn get<S>(&self, name: &str) -> Result<Option<Wal<S>>>
+    where
+        S: 'static,
+    {
+        let handles = err_at!(Fatal, self.handles.lock())?;
+        let wal =
+            handles.get(name).and_then(|h| h.as_any().downcast_ref::<Wal<S>>()).cloned();
+        Ok(wal)
+    }
+
+    /// Return names of registered instances, in sort order.
+    pub fn names(&self) -> Result<Vec<String>> {
+        Ok(err_at!(Fatal, self.handles.lock())?.keys().cloned().collect())
+    }
+
+    /// Return statistics for every registered instance, in name order.
+    pub fn stats(&self) -> Result<Vec<Stats>> {
+        let handles = err_at!(Fatal, self.handles.lock())?;
+        handles.values().map(|h| h.to_stats()).collect()
+    }
+
+    // Called when a handle, identified by `key`, is closed. If the only
+    // other reference to the instance is held by the registry, then the
+    // instance is unregistered so that it can be closed. Return the number
+    // of references to the instance still held by the registry.
+    pub(crate) fn on_close(&self, key: usize, refs: usize) -> Result<usize> {
+        let mut handles = err_at!(Fatal, self.handles.lock())?;
+        if refs == 2 {
+            handles.retain(|_, h| h.to_key() != key);
+        }
+        Ok(handles.values().filter(|h| h.to_key() == key).count())
+    }
+}
+
+#[cfg(test)]
+#[path = "registry_test.rs"]
+mod registry_test;
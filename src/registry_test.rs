@@ -0,0 +1,36 @@
+use super::*;
+use crate::{
+    state,
+    wral::{CloseOutcome, Config},
+};
+
+#[test]
+fn test_registry() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-registry", dir.path().as_ref());
+    config.set_fsync(false);
+
+    let wal = Wal::create(config, state::NoState).unwrap();
+    registry().register(&wal).unwrap();
+    assert!(registry().register(&wal).is_err());
+    assert!(registry().names().unwrap().contains(&"test-registry".to_string()));
+
+    let other: Wal = registry().get("test-registry").unwrap().unwrap();
+    assert_eq!(other.add_op(&[1, 2, 3]).unwrap(), 1);
+    assert!(registry().get::<u64>("test-registry").unwrap().is_none());
+    assert!(registry().get::<state::NoState>("missing").unwrap().is_none());
+
+    let stats = registry().stats().unwrap();
+    let stats = stats.iter().find(|s| s.name == "test-registry").unwrap();
+    assert_eq!(stats.last_seqno, 1);
+    assert_eq!(stats.durable_seqno, 1);
+    assert_eq!(stats.n_journals, 1);
+    assert_eq!(stats.n_batches, 1);
+
+    // registry does not hold up closing the instance.
+    let outcome = CloseOutcome::Deferred { remaining_clones: 1 };
+    assert_eq!(other.close(false).unwrap(), outcome);
+    assert_eq!(wal.close(true).unwrap(), CloseOutcome::Purged(1));
+    assert!(registry().get::<state::NoState>("test-registry").unwrap().is_none());
+    assert!(!registry().unregister("test-registry").unwrap());
+}
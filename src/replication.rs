@@ -0,0 +1,274 @@
+//! Module implement a minimal leader/follower protocol, to ship entries
+//! from one [Wal] to another over TCP, refer to [Leader] and [Follower].
+//!
+//! Every message is a frame, a 4-byte big-endian length, followed by a
+//! kind byte and the message body encoded in CBOR, length covers the kind
+//! byte and the body.
+//!
+//! * On connect, follower sends a hello with its name, seqno epoch,
+//!   durable seqno and purged seqno, and the leader replies with its own
+//!   hello.
+//!   Both sides fail the session if epochs differ, or if the leader has
+//!   purged entries beyond the follower's durable seqno.
+//! * Leader ships durable entries beyond the follower's durable seqno, as
+//!   batch frames, each carrying a CBOR array of entries.
+//! * Follower ingests each batch, refer to [Wal::ingest_entries], and
+//!   acknowledges with its durable seqno, from where shipping resumes on
+//!   the next session. Leader acknowledges the follower's progress under
+//!   its name, refer to [Wal::ack].
+
+use mkit::{
+    cbor::{Cbor, FromCbor},
+    Cborize,
+};
+
+use std::{io, net};
+
+use crate::{entry, state, util, wral::Wal, Error, Result};
+
+/// Protocol version, exchanged in hello.
+pub const PROTOCOL_VERSION: u64 = 1;
+/// Default number of entries shipped per batch frame, refer to
+/// [Leader::set_batch_entries].
+pub const BATCH_ENTRIES: usize = 1024;
+/// Frames larger than this are rejected as invalid.
+pub const MAX_FRAME: usize = 64 * 1024 * 1024;
+
+// Kind byte of frames.
+const FRAME_HELLO: u8 = 1;
+const FRAME_BATCH: u8 = 2;
+const FRAME_ACK: u8 = 3;
+
+#[derive(Debug, Clone, Default, Eq, PartialEq, Cborize)]
+struct Hello {
+    version: u64,
+    // name of the sender's Wal instance.
+    name: String,
+    epoch: u64,
+    // durable seqno of the sender.
+    seqno: u64,
+    // latest seqno purged by the sender.
+    purged: u64,
+}
+
+impl Hello {
+    const ID: u32 = 0x0;
+
+    fn new<S>(wal: &Wal<S>) -> Result<Hello> {
+        let marks = wal.watermarks()?;
+        let hello = Hello {
+            version: PROTOCOL_VERSION,
+            name: wal.to_config()?.name,
+            epoch: wal.epoch()?,
+            seqno: marks.durable,
+            purged: marks.purged,
+        };
+        Ok(hello)
+    }
+}
+
+// Check whether leader can resume shipping to follower, computed alike on
+// both sides.
+fn check_resume(follower: &Hello, leader: &Hello) -> Result<()> {
+    if follower.version != leader.version {
+        err_at!(
+            Mismatch,
+            msg: "protocol version {} != {}", follower.version, leader.version
+        )?
+    }
+    if follower.epoch != leader.epoch {
+        err_at!(Mismatch, msg: "epoch {} != {}", follower.epoch, leader.epoch)?
+    }
+    if follower.seqno < leader.purged {
+        err_at!(
+            NotFound,
+            msg: "resume from {}, leader purged upto {}", follower.seqno + 1, leader.purged
+        )?
+    }
+    Ok(())
+}
+
+/// Leader side of a replication session, ships entries from a [Wal] to a
+/// [Follower] connected over `stream`.
+///
+/// [Leader::ship] ships entries durable so far, applications can ship
+/// continuously by calling it again, say after [Wal::wait_for] returns.
+/// Read and write timeouts, if any, are to be set on `stream` by the
+/// application, a failed session is resumed by connecting again.
+pub struct Leader<S = state::NoState> {
+    wal: Wal<S>,
+    stream: net::TcpStream,
+    batch_entries: usize,
+    // name of the follower, last seqno shipped, and the follower's durable
+    // seqno.
+    follower: String,
+    shipped: u64,
+    acked: u64,
+}
+
+impl<S> Leader<S> {
+    /// Accept a replication session from a follower connected on `stream`,
+    /// exchange hello and return the leader positioned at the follower's
+    /// durable seqno.
+    pub fn accept(wal: Wal<S>, mut stream: net::TcpStream) -> Result<Leader<S>> {
+        let follower: Hello = match read_frame(&mut stream)? {
+            Some((FRAME_HELLO, body)) => decode(&body)?,
+            Some((kind, _)) => err_at!(Invalid, msg: "expected hello, frame {}", kind)?,
+            None => err_at!(IOError, msg: "follower closed before hello")?,
+        };
+        let hello = Hello::new(&wal)?;
+        write_frame(&mut stream, FRAME_HELLO, &util::encode_cbor(hello.clone())?)?;
+        check_resume(&follower, &hello)?;
+        wal.ack(&follower.name, follower.seqno)?;
+
+        let leader = Leader {
+            wal,
+            stream,
+            batch_entries: BATCH_ENTRIES,
+            follower: follower.name,
+            shipped: follower.seqno,
+            acked: follower.seqno,
+        };
+        Ok(leader)
+    }
+
+    /// Set the maximum number of entries shipped per batch frame, default
+    /// is [BATCH_ENTRIES].
+    pub fn set_batch_entries(&mut self, n: usize) -> &mut Self {
+        self.batch_entries = n.max(1);
+        self
+    }
+
+    /// Return the follower's durable seqno, as last acknowledged.
+    pub fn to_acked(&self) -> u64 {
+        self.acked
+    }
+
+    /// Ship entries durable on this instance, beyond those already shipped,
+    /// waiting for the follower to acknowledge each batch. Return the
+    /// follower's durable seqno.
+    pub fn ship(&mut self) -> Result<u64> {
+        let durable = self.wal.durable_seqno()?;
+        while self.shipped < durable {
+            let entries = self
+                .wal
+                .range((self.shipped + 1)..=durable)?
+                .take(self.batch_entries)
+                .collect::<Result<Vec<entry::Entry>>>()?;
+            // rest of the span is masked or absent, nothing to ship.
+            self.shipped = match entries.len() < self.batch_entries {
+                true => durable,
+                false => entries.last().map(|e| e.to_seqno()).unwrap_or(durable),
+            };
+            if entries.is_empty() {
+                break;
+            }
+
+            write_frame(&mut self.stream, FRAME_BATCH, &util::encode_cbor(entries)?)?;
+            self.acked = match read_frame(&mut self.stream)? {
+                Some((FRAME_ACK, body)) => decode(&body)?,
+                Some((kind, _)) => err_at!(Invalid, msg: "expected ack, frame {}", kind)?,
+                None => err_at!(IOError, msg: "follower closed before ack")?,
+            };
+            self.wal.ack(&self.follower, self.acked)?;
+        }
+        Ok(self.acked)
+    }
+}
+
+/// Follower side of a replication session, ingests entries shipped by a
+/// [Leader] into a [Wal], refer to [Wal::ingest_entries].
+pub struct Follower<S = state::NoState> {
+    wal: Wal<S>,
+    stream: net::TcpStream,
+    // leader's durable seqno, as of hello.
+    leader_seqno: u64,
+}
+
+impl<S> Follower<S> {
+    /// Start a replication session with the leader connected on `stream`,
+    /// exchange hello, shipping resumes from this instance's durable
+    /// seqno.
+    pub fn connect(wal: Wal<S>, mut stream: net::TcpStream) -> Result<Follower<S>> {
+        let hello = Hello::new(&wal)?;
+        write_frame(&mut stream, FRAME_HELLO, &util::encode_cbor(hello.clone())?)?;
+        let leader: Hello = match read_frame(&mut stream)? {
+            Some((FRAME_HELLO, body)) => decode(&body)?,
+            Some((kind, _)) => err_at!(Invalid, msg: "expected hello, frame {}", kind)?,
+            None => err_at!(IOError, msg: "leader closed before hello")?,
+        };
+        check_resume(&hello, &leader)?;
+
+        Ok(Follower { wal, stream, leader_seqno: leader.seqno })
+    }
+
+    /// Return the leader's durable seqno, as of hello.
+    pub fn to_leader_seqno(&self) -> u64 {
+        self.leader_seqno
+    }
+
+    /// Receive the next batch from the leader, ingest it and acknowledge
+    /// with this instance's durable seqno, which is also returned. Return
+    /// None if the leader closed the session.
+    pub fn apply(&mut self) -> Result<Option<u64>> {
+        let entries: Vec<entry::Entry> = match read_frame(&mut self.stream)? {
+            Some((FRAME_BATCH, body)) => decode(&body)?,
+            Some((kind, _)) => err_at!(Invalid, msg: "expected batch, frame {}", kind)?,
+            None => return Ok(None),
+        };
+        self.wal.ingest_entries(entries)?;
+
+        let seqno = self.wal.durable_seqno()?;
+        write_frame(&mut self.stream, FRAME_ACK, &util::encode_cbor(seqno)?)?;
+        Ok(Some(seqno))
+    }
+}
+
+fn decode<T>(body: &[u8]) -> Result<T>
+where
+    T: FromCbor,
+{
+    let (val, _) = Cbor::decode(&mut &body[..])?;
+    Ok(T::from_cbor(val)?)
+}
+
+fn write_frame<W>(w: &mut W, kind: u8, body: &[u8]) -> Result<()>
+where
+    W: io::Write,
+{
+    let n = body.len() + 1;
+    if n > MAX_FRAME {
+        err_at!(Invalid, msg: "frame of {} bytes > {}", n, MAX_FRAME)?
+    }
+    let mut buf = Vec::with_capacity(n + 4);
+    buf.extend_from_slice(&(n as u32).to_be_bytes());
+    buf.push(kind);
+    buf.extend_from_slice(body);
+    err_at!(IOError, w.write_all(&buf))?;
+    err_at!(IOError, w.flush())
+}
+
+// Return None if the stream is closed at a frame boundary.
+fn read_frame<R>(r: &mut R) -> Result<Option<(u8, Vec<u8>)>>
+where
+    R: io::Read,
+{
+    let mut hdr = [0_u8; 4];
+    match r.read_exact(&mut hdr) {
+        Ok(()) => (),
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => err_at!(IOError, Err(err))?,
+    }
+    let n = u32::from_be_bytes(hdr) as usize;
+    if n == 0 || n > MAX_FRAME {
+        err_at!(Invalid, msg: "frame of {} bytes", n)?
+    }
+    let mut buf = vec![0; n];
+    err_at!(IOError, r.read_exact(&mut buf))?;
+    let body = buf.split_off(1);
+    Ok(Some((buf[0], body)))
+}
+
+#[cfg(test)]
+#[path = "replication_test.rs"]
+mod replication_test;
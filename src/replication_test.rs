@@ -0,0 +1,113 @@
+use super::*;
+
+use std::thread;
+
+use crate::wral::Config;
+
+fn session(leader: &Wal, follower: &Wal, batch_entries: usize) -> Result<(u64, usize)> {
+    let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let wal = leader.clone();
+    let handle = thread::spawn(move || -> Result<u64> {
+        let (stream, _) = listener.accept().unwrap();
+        let mut leader = Leader::accept(wal, stream)?;
+        leader.set_batch_entries(batch_entries);
+        leader.ship()
+    });
+
+    let stream = net::TcpStream::connect(addr).unwrap();
+    let res = Follower::connect(follower.clone(), stream).and_then(|mut f| {
+        let mut n = 0;
+        while f.apply()?.is_some() {
+            n += 1;
+        }
+        Ok(n)
+    });
+    let acked = handle.join().unwrap()?;
+    Ok((acked, res?))
+}
+
+#[test]
+fn test_replication() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-replication-leader", dir.path().as_ref());
+    config.set_journal_limit(1000).set_fsync(false);
+    let leader = Wal::create(config, state::NoState).unwrap();
+    let config = Config::new("test-replication-follower", dir.path().as_ref());
+    let follower = Wal::create(config, state::NoState).unwrap();
+
+    for i in 0..100_u64 {
+        leader.add_op(&i.to_be_bytes()).unwrap();
+    }
+    assert_eq!(session(&leader, &follower, 30).unwrap(), (100, 4));
+
+    // next session resumes from the follower's durable seqno.
+    for i in 100..150_u64 {
+        leader.add_op(&i.to_be_bytes()).unwrap();
+    }
+    assert_eq!(session(&leader, &follower, 30).unwrap(), (150, 2));
+    assert_eq!(session(&leader, &follower, 30).unwrap(), (150, 0));
+    let consumers = leader.consumers().unwrap();
+    assert_eq!(consumers.len(), 1);
+    assert_eq!(consumers[0].name, "test-replication-follower");
+    assert_eq!((consumers[0].seqno, consumers[0].entries), (150, 0));
+
+    let entries: Vec<entry::Entry> = leader.iter().unwrap().map(|e| e.unwrap()).collect();
+    let replica: Vec<entry::Entry> =
+        follower.iter().unwrap().map(|e| e.unwrap()).collect();
+    assert_eq!(replica.len(), 150);
+    for (a, b) in entries.iter().zip(replica.iter()) {
+        assert_eq!(a.to_seqno(), b.to_seqno());
+        assert_eq!(a.as_op(), b.as_op());
+    }
+
+    // follower in a different epoch can't resume.
+    leader.rebase(1).unwrap();
+    leader.add_op(&[1]).unwrap();
+    match session(&leader, &follower, 30) {
+        Err(Error::Mismatch(_, _)) => (),
+        res => panic!("expected mismatch {:?}", res),
+    }
+
+    leader.close(true).unwrap();
+    follower.close(true).unwrap();
+}
+
+#[test]
+fn test_replication_purged() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-replication-purged", dir.path().as_ref());
+    config.set_journal_limit(1000).set_fsync(false);
+    let leader = Wal::create(config, state::NoState).unwrap();
+    let config = Config::new("test-replication-empty", dir.path().as_ref());
+    let follower = Wal::create(config, state::NoState).unwrap();
+
+    for _i in 0..100 {
+        leader.add_op(&[0; 32]).unwrap();
+    }
+    leader.purge_till(50, "retention").unwrap().unwrap();
+    match session(&leader, &follower, 30) {
+        Err(Error::NotFound(_, _)) => (),
+        res => panic!("expected not-found {:?}", res),
+    }
+
+    leader.close(true).unwrap();
+    follower.close(true).unwrap();
+}
+
+#[test]
+fn test_replication_frame() {
+    let mut buf = vec![];
+    write_frame(&mut buf, FRAME_ACK, &util::encode_cbor(10_u64).unwrap()).unwrap();
+    let mut r = buf.as_slice();
+    let (kind, body) = read_frame(&mut r).unwrap().unwrap();
+    assert_eq!(kind, FRAME_ACK);
+    assert_eq!(decode::<u64>(&body).unwrap(), 10);
+    assert!(read_frame(&mut r).unwrap().is_none());
+
+    let mut r: &[u8] = &[0, 0, 0, 0];
+    assert!(read_frame(&mut r).is_err());
+    let mut r: &[u8] = &[0xff, 0xff, 0xff, 0xff];
+    assert!(read_frame(&mut r).is_err());
+}
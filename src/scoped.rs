@@ -0,0 +1,96 @@
+//! Module implement a client handle that makes its ops durable at the end
+//! of its scope, refer to [Wal::scoped_writer].
+
+use log::error;
+
+use std::ops;
+
+use crate::{wral::Wal, Result};
+
+/// Scoped handle to a [Wal] instance, created by [Wal::scoped_writer].
+///
+/// Ops are added to the Wal as they are, and the handle tracks the latest
+/// seqno handed out to them. Once the handle goes out of scope, every op
+/// added through it is durable, even under
+/// [Config::set_fsync_interval][crate::Config::set_fsync_interval], without
+/// manual calls to [Wal::sync]. Dropping the handle shall log errors, if
+/// any, applications that care about failures should call
+/// [ScopedWriter::finish] instead.
+pub struct ScopedWriter<'a, S> {
+    wal: &'a Wal<S>,
+    // latest seqno handed out to ops added through this handle.
+    seqno: Option<u64>,
+}
+
+impl<'a, S> ScopedWriter<'a, S> {
+    pub(crate) fn new(wal: &'a Wal<S>) -> ScopedWriter<'a, S> {
+        ScopedWriter { wal, seqno: None }
+    }
+
+    /// Add an operation, refer to [Wal::add_op].
+    pub fn add_op(&mut self, op: &[u8]) -> Result<u64> {
+        let seqno = self.wal.add_op(op)?;
+        Ok(self.track(seqno))
+    }
+
+    /// Add an operation for `topic`, refer to [Wal::add_op_to].
+    pub fn add_op_to(&mut self, topic: &str, op: &[u8]) -> Result<u64> {
+        let seqno = self.wal.add_op_to(topic, op)?;
+        Ok(self.track(seqno))
+    }
+
+    /// Add a tagged operation, refer to [Wal::add_op_tagged].
+    pub fn add_op_tagged(&mut self, tag: &str, op: &[u8]) -> Result<u64> {
+        let seqno = self.wal.add_op_tagged(tag, op)?;
+        Ok(self.track(seqno))
+    }
+
+    /// Add operations as contiguous entries, return their seqnos.
+    pub fn add_ops(&mut self, ops: Vec<Vec<u8>>) -> Result<ops::RangeInclusive<u64>> {
+        let ops = ops.into_iter().map(|op| (String::default(), op)).collect();
+        let seqnos = self.wal.do_add_ops(ops)?;
+        self.track(*seqnos.end());
+        Ok(seqnos)
+    }
+
+    /// Return the latest seqno handed out to ops added through this
+    /// handle, None if no op was added.
+    pub fn to_seqno(&self) -> Option<u64> {
+        self.seqno
+    }
+
+    /// Wait for ops added through this handle to be durable, fsyncing
+    /// them if required, and return the latest seqno. Return None if no
+    /// op was added.
+    pub fn finish(mut self) -> Result<Option<u64>> {
+        self.do_finish()
+    }
+
+    fn track(&mut self, seqno: u64) -> u64 {
+        self.seqno = Some(self.seqno.map_or(seqno, |s| s.max(seqno)));
+        seqno
+    }
+
+    fn do_finish(&mut self) -> Result<Option<u64>> {
+        let seqno = match self.seqno.take() {
+            Some(seqno) => seqno,
+            None => return Ok(None),
+        };
+        if self.wal.durable_seqno()? < seqno {
+            self.wal.sync()?;
+        }
+        Ok(Some(seqno))
+    }
+}
+
+impl<'a, S> Drop for ScopedWriter<'a, S> {
+    fn drop(&mut self) {
+        if let Err(err) = self.do_finish() {
+            error!(target: "wral", "scoped writer, sync on drop failed: {}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+#[path = "scoped_test.rs"]
+mod scoped_test;
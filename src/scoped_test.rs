@@ -0,0 +1,43 @@
+use std::time;
+
+use super::*;
+
+use crate::{state, wral::Config, FsyncInterval};
+
+#[test]
+fn test_scoped_writer() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-scoped-writer", dir.path().as_ref());
+    let hour = time::Duration::from_secs(3600);
+    config.set_fsync_interval(Some(FsyncInterval::Elapsed(hour)));
+
+    let wal = Wal::create(config.clone(), state::NoState).unwrap();
+    assert_eq!(wal.scoped_writer().finish().unwrap(), None);
+    {
+        let mut sw = wal.scoped_writer();
+        assert_eq!(sw.add_op(&[1]).unwrap(), 1);
+        assert_eq!(sw.add_op_to("topic", &[2]).unwrap(), 2);
+        assert_eq!(wal.add_op(&[100]).unwrap(), 3);
+        assert_eq!(sw.add_ops(vec![vec![3], vec![4]]).unwrap(), 4..=5);
+        assert_eq!(sw.to_seqno(), Some(5));
+        assert_eq!(wal.durable_seqno().unwrap(), 0);
+    }
+    assert_eq!(wal.durable_seqno().unwrap(), 5);
+
+    let mut sw = wal.scoped_writer();
+    assert_eq!(sw.add_op_tagged("tag", &[6]).unwrap(), 6);
+    assert_eq!(wal.durable_seqno().unwrap(), 5);
+    assert_eq!(sw.finish().unwrap(), Some(6));
+    assert_eq!(wal.durable_seqno().unwrap(), 6);
+
+    wal.close(false).unwrap();
+
+    let wal: Wal = Wal::open(config, crate::OpenMode::ReadOnly).unwrap();
+    let mut sw = wal.scoped_writer();
+    match sw.add_op(&[0]) {
+        Err(crate::Error::ReadOnly(_, _)) => (),
+        res => panic!("expected ReadOnly {:?}", res),
+    }
+    assert_eq!(sw.finish().unwrap(), None);
+    wal.close(false).unwrap();
+}
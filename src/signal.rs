@@ -0,0 +1,31 @@
+//! Module implement a cooperative shutdown signal, refer to
+//! [Wal::shutdown_signal][crate::Wal::shutdown_signal].
+
+use std::sync::{
+    atomic::{AtomicBool, Ordering::SeqCst},
+    Arc,
+};
+
+/// Cancellation token shared by a [Wal][crate::Wal] instance and all its
+/// clones.
+///
+/// Once cancelled, long running operations on the instance, like
+/// iterating over entries, stop at the next entry and return
+/// [Error::Cancelled][crate::Error::Cancelled]. Cancellation cannot be
+/// undone, load the instance afresh to continue with it.
+#[derive(Debug, Clone, Default)]
+pub struct ShutdownSignal {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl ShutdownSignal {
+    /// Cancel in-flight and subsequent long running operations.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, SeqCst)
+    }
+
+    /// Return whether the signal is cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(SeqCst)
+    }
+}
@@ -0,0 +1,154 @@
+//! Module implement the thread factory for the writer thread, refer to
+//! [Config::set_spawner][crate::Config::set_spawner].
+
+use std::{
+    fmt, io,
+    sync::{mpsc, Arc, Mutex},
+    thread,
+};
+
+use crate::{Error, Result};
+
+/// Work to be run by a [Spawner], once.
+pub type Job = Box<dyn FnOnce() + Send + 'static>;
+
+type SpawnFn = dyn Fn(&str, Job) -> io::Result<()> + Send + Sync;
+
+/// Thread factory for the writer thread.
+///
+/// Default spawner spawns a named OS thread. Embedders can supply their
+/// own, to control thread naming, stack size and panic handling, or to run
+/// the writer under their supervision, say on a thread from their pool.
+/// The job runs till the instance is closed, it shall be run exactly once
+/// and on a thread of its own, not on the calling thread.
+///
+/// ```
+/// let spawner = wral::Spawner::new(|name, job| {
+///     std::thread::Builder::new()
+///         .name(format!("app-{}", name))
+///         .stack_size(1024 * 1024)
+///         .spawn(job)
+///         .map(|_| ())
+/// });
+/// ```
+///
+/// With the `inline-writer` feature, [Spawner::inline] does away with the
+/// writer thread.
+#[derive(Clone, Default)]
+pub struct Spawner {
+    spawn: Option<Arc<SpawnFn>>,
+    // run the writer on the caller's thread, refer to Spawner::inline.
+    #[cfg(feature = "inline-writer")]
+    inline: bool,
+}
+
+impl fmt::Debug for Spawner {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        #[cfg(feature = "inline-writer")]
+        if self.inline {
+            return write!(f, "Spawner::inline");
+        }
+        match &self.spawn {
+            Some(_) => write!(f, "Spawner::custom"),
+            None => write!(f, "Spawner::thread"),
+        }
+    }
+}
+
+impl Spawner {
+    /// Spawner calling `spawn` with the thread name and the job to run.
+    pub fn new<F>(spawn: F) -> Spawner
+    where
+        F: 'static + Fn(&str, Job) -> io::Result<()> + Send + Sync,
+    {
+        Spawner {
+            spawn: Some(Arc::new(spawn)),
+            #[cfg(feature = "inline-writer")]
+            inline: false,
+        }
+    }
+
+    /// Spawner that doesn't spawn the writer. Requests are batched and
+    /// flushed on the caller's thread, one request at a time, guarded by
+    /// a mutex, so that IO happens only within calls on the instance. For
+    /// single threaded embedders, like command line tools, tests and wasm.
+    ///
+    /// Without a thread, there are no heartbeats and every request is a
+    /// batch of its own, irrespective of [Config::set_adaptive_commit].
+    /// Batches left unsynced under [FsyncInterval::Elapsed] are synced by
+    /// a later request, on close or on drop.
+    ///
+    /// [FsyncInterval::Elapsed]: crate::FsyncInterval::Elapsed
+    /// [Config::set_adaptive_commit]: crate::Config::set_adaptive_commit
+    #[cfg(feature = "inline-writer")]
+    pub fn inline() -> Spawner {
+        Spawner { spawn: None, inline: true }
+    }
+
+    #[cfg(feature = "inline-writer")]
+    pub(crate) fn is_inline(&self) -> bool {
+        self.inline
+    }
+
+    // Run `f` on a thread named `name`, return the handle to wait for its
+    // result.
+    pub(crate) fn spawn<F, T>(&self, name: &str, f: F) -> Result<Handle<T>>
+    where
+        F: 'static + FnOnce() -> T + Send,
+        T: 'static + Send,
+    {
+        let (tx, rx) = mpsc::sync_channel(1);
+        let job: Job = Box::new(move || {
+            tx.send(f()).ok();
+        });
+        let handle = match &self.spawn {
+            Some(spawn) => {
+                err_at!(ThreadFail, spawn(name, job), "spawn {}", name)?;
+                None
+            }
+            None => {
+                let builder = thread::Builder::new().name(name.to_string());
+                Some(err_at!(ThreadFail, builder.spawn(job), "spawn {}", name)?)
+            }
+        };
+        Ok(Handle { name: name.to_string(), rx: Mutex::new(rx), handle })
+    }
+}
+
+/// Handle to a job started by [Spawner].
+pub(crate) struct Handle<T> {
+    name: String,
+    rx: Mutex<mpsc::Receiver<T>>,
+    // join handle, for threads spawned by the default spawner.
+    handle: Option<thread::JoinHandle<()>>,
+}
+
+impl<T> Handle<T> {
+    /// Handle to a job run by the caller, not by the spawner, which shall
+    /// send its result on the returned sender.
+    #[cfg(feature = "inline-writer")]
+    pub fn inline(name: &str) -> (mpsc::SyncSender<T>, Handle<T>) {
+        let (tx, rx) = mpsc::sync_channel(1);
+        let handle = Handle {
+            name: name.to_string(),
+            rx: Mutex::new(rx),
+            handle: None,
+        };
+        (tx, handle)
+    }
+
+    /// Wait for the job to complete and return its result. Fail if the job
+    /// panicked, or was dropped by the spawner without running it.
+    pub fn join(self) -> Result<T> {
+        let res = err_at!(Fatal, self.rx.into_inner())?.recv();
+        if let Some(handle) = self.handle {
+            if let Err(err) = handle.join() {
+                err_at!(ThreadFail, msg: "{} {:?}", self.name, err)?
+            }
+        }
+        match res {
+            Ok(val) => Ok(val),
+            Err(_) => err_at!(ThreadFail, msg: "{} exited without result", self.name),
+        }
+    }
+}
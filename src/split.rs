@@ -0,0 +1,169 @@
+//! Module implement splitting of journal files, refer to
+//! [Wal::split_journal][crate::Wal::split_journal].
+
+use log::debug;
+
+use std::{
+    ffi, fs,
+    io::{self, Read, Seek},
+    path,
+};
+
+use crate::{batch, files, manifest::Manifest, manifest::Span, util, wral::Config};
+use crate::{Error, Result};
+
+// A chunk of consecutive batches from the journal being split.
+#[derive(Default)]
+struct Chunk {
+    // (fpos, length) of each batch in the source journal.
+    batches: Vec<(u64, u64)>,
+    size: u64,
+    // seqno span across batches carrying entries.
+    span: Option<(u64, u64)>,
+}
+
+pub fn split_journal(config: &Config, num: usize, target_size: usize) -> Result<usize> {
+    if target_size == 0 {
+        err_at!(Invalid, msg: "split {} target_size is ZERO", num)?
+    }
+
+    // journals are located by number, their file names can be of any width.
+    let mut nums = files::find_journals(&config.to_journal_dirs(), &config.name)?;
+    nums.sort_unstable();
+    let src_path = match nums.iter().find(|(n, _)| *n == num) {
+        Some((_, file_path)) => file_path.clone(),
+        None => err_at!(NotFound, msg: "journal {} for {:?}", num, config.name)?,
+    };
+    let chunks = scan_chunks(&src_path, target_size as u64)?;
+    if chunks.len() < 2 {
+        return Ok(chunks.len());
+    }
+    let by = chunks.len() - 1;
+
+    // write chunks aside, before touching existing journals.
+    let mut src = err_at!(IOError, fs::File::open(&src_path))?;
+    let mut tmp_paths = vec![];
+    for (i, chunk) in chunks.iter().enumerate() {
+        let mut tmp_path = src_path.clone().into_os_string();
+        tmp_path.push(format!(".split-{}", i));
+        let mut file = err_at!(IOError, fs::File::create(&tmp_path))?;
+        for (fpos, length) in chunk.batches.iter() {
+            err_at!(IOError, src.seek(io::SeekFrom::Start(*fpos)))?;
+            let mut batch = (&mut src).take(*length);
+            err_at!(IOError, io::copy(&mut batch, &mut file))?;
+        }
+        err_at!(IOError, file.sync_all())?;
+        // ops spilled from any batch can be referred by a chunk, each
+        // chunk gets its own copy of the blob file.
+        let blob_path = files::make_blob_filename(src_path.as_os_str());
+        if path::Path::new(&blob_path).exists() {
+            let tmp_blob = files::make_blob_filename(&tmp_path);
+            err_at!(IOError, fs::copy(&blob_path, &tmp_blob))?;
+            err_at!(IOError, fs::File::open(&tmp_blob).and_then(|f| f.sync_all()))?;
+        }
+        tmp_paths.push(tmp_path);
+    }
+
+    // make room for the new journals, starting from the latest. Journals
+    // striped across directories are renamed in place, chunks are placed
+    // along with the source journal.
+    for (n, from) in nums.iter().rev().take_while(|(n, _)| *n > num) {
+        let to = to_file_path(config, from, *n + by);
+        err_at!(IOError, fs::rename(from, &to))?;
+        rename_blob(from.as_os_str(), to.as_os_str())?;
+    }
+    for (i, tmp_path) in tmp_paths.iter().enumerate() {
+        let to = to_file_path(config, &src_path, num + i);
+        err_at!(IOError, fs::rename(tmp_path, &to))?;
+        rename_blob(tmp_path, to.as_os_str())?;
+    }
+    // source journal, named with a different width, is replaced by chunks.
+    if src_path != to_file_path(config, &src_path, num) {
+        err_at!(IOError, fs::remove_file(&src_path))?;
+        fs::remove_file(files::make_blob_filename(src_path.as_os_str())).ok();
+    }
+    for dir in config.to_journal_dirs().iter() {
+        if path::Path::new(dir).is_dir() {
+            util::sync_dir(path::Path::new(dir))?;
+        }
+    }
+
+    if let Some(mut manifest) = Manifest::load(&config.dir, &config.name)? {
+        manifest.shift_journals(num, by);
+        manifest.remove_span(num);
+        for (i, chunk) in chunks.iter().enumerate() {
+            if let Some((first, last)) = chunk.span {
+                manifest.add_span(Span::new(num + i, first, last));
+            }
+        }
+        manifest.save(&config.dir)?;
+    }
+
+    debug!(
+        target: "wral",
+        "split journal {:?} into {} journals", src_path, chunks.len()
+    );
+    Ok(chunks.len())
+}
+
+// Group batches in journal file into chunks of `target_size` bytes, a
+// chunk can exceed the target size, if it holds a single batch.
+fn scan_chunks(file_path: &path::Path, target_size: u64) -> Result<Vec<Chunk>> {
+    let file = err_at!(IOError, fs::File::open(file_path))?;
+    let file_size = err_at!(IOError, file.metadata())?.len();
+    let mut reader = io::BufReader::new(file);
+
+    let mut chunks = vec![Chunk::default()];
+    let mut fpos = 0_u64;
+    while fpos < file_size {
+        let (val, n) = util::decode_cbor(&mut reader, file_size - fpos)?;
+        let batch = batch::Batch::decode(val)?;
+        let n = n as u64;
+        // trailer covers the entire journal, it is dropped from chunks.
+        if batch.to_trailer().is_some() {
+            fpos += n;
+            continue;
+        }
+
+        let chunk = match chunks.last_mut() {
+            Some(chunk) if chunk.size + n > target_size && chunk.size > 0 => {
+                chunks.push(Chunk::default());
+                chunks.last_mut().unwrap()
+            }
+            Some(chunk) => chunk,
+            None => unreachable!(),
+        };
+        chunk.batches.push((fpos, n));
+        chunk.size += n;
+        // batches recording metadata don't carry entries.
+        if batch.len_entries()? > 0 {
+            let (first, last) = (batch.to_first_seqno(), batch.to_last_seqno());
+            chunk.span = match chunk.span {
+                Some((f, _)) => Some((f, last)),
+                None => Some((first, last)),
+            };
+        }
+        fpos += n;
+    }
+
+    chunks.retain(|chunk| !chunk.batches.is_empty());
+    Ok(chunks)
+}
+
+// File path for journal `num`, in the same directory as `file_path`.
+fn to_file_path(config: &Config, file_path: &path::Path, num: usize) -> path::PathBuf {
+    let file = files::make_filename(config.name.clone(), num, config.journal_width);
+    match file_path.parent() {
+        Some(dir) => dir.join(file),
+        None => path::PathBuf::from(file),
+    }
+}
+
+// Rename blob file, if any, along with its journal.
+fn rename_blob(from: &ffi::OsStr, to: &ffi::OsStr) -> Result<()> {
+    let from = files::make_blob_filename(from);
+    match path::Path::new(&from).exists() {
+        true => err_at!(IOError, fs::rename(&from, files::make_blob_filename(to))),
+        false => Ok(()),
+    }
+}
@@ -0,0 +1,157 @@
+//! Module implement memory bounded buffering of pending entries, refer to
+//! [Config::set_buffer_limit][crate::Config::set_buffer_limit].
+//!
+//! Once ops pending in a batch cross the limit, subsequent entries are
+//! encoded into a spool file, kept next to the journal file, and read
+//! back when the batch is flushed. Spool file is neither synced nor
+//! recovered, entries in it are not yet acknowledged.
+
+use log::debug;
+use mkit::cbor::{Cbor, FromCbor};
+
+use std::{
+    ffi, fs,
+    io::{self, Write},
+    path,
+};
+
+use crate::{entry, files, util, Error, Result};
+
+/// Position in spool file, to undo entries spooled after it.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Mark {
+    n_entries: usize,
+    size: u64,
+    last_seqno: Option<u64>,
+}
+
+pub struct Spool {
+    limit: usize,
+    file_path: ffi::OsString,
+    file: Option<fs::File>,
+    // entries spooled, their size in file and the last seqno spooled.
+    mark: Mark,
+    first_seqno: Option<u64>,
+}
+
+impl Drop for Spool {
+    fn drop(&mut self) {
+        if self.file.take().is_some() {
+            fs::remove_file(&self.file_path).ok();
+        }
+    }
+}
+
+impl Spool {
+    pub fn new(limit: usize, journal_path: &ffi::OsStr) -> Spool {
+        Spool {
+            limit,
+            file_path: files::make_spool_filename(journal_path),
+            file: None,
+            mark: Mark::default(),
+            first_seqno: None,
+        }
+    }
+
+    /// Spool file is moved along with its journal, to `journal_path`.
+    pub fn relocate(&mut self, journal_path: &ffi::OsStr) -> Result<()> {
+        let file_path = files::make_spool_filename(journal_path);
+        if self.file.is_some() {
+            err_at!(IOError, fs::rename(&self.file_path, &file_path), "{:?}", file_path)?;
+        }
+        self.file_path = file_path;
+        Ok(())
+    }
+
+    /// Return whether an op of `size` bytes shall be spooled, when ops
+    /// pending in memory add upto `buffered` bytes. Once an entry is
+    /// spooled, every entry after it is spooled as well.
+    pub fn is_spooled(&self, buffered: usize, size: usize) -> bool {
+        self.mark.n_entries > 0 || buffered.saturating_add(size) > self.limit
+    }
+
+    pub fn len(&self) -> usize {
+        self.mark.n_entries
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.mark.n_entries == 0
+    }
+
+    pub fn to_first_seqno(&self) -> Option<u64> {
+        self.first_seqno
+    }
+
+    pub fn to_last_seqno(&self) -> Option<u64> {
+        self.mark.last_seqno
+    }
+
+    pub fn to_mark(&self) -> Mark {
+        self.mark
+    }
+
+    /// Append `entry` to spool file.
+    pub fn push(&mut self, entry: entry::Entry) -> Result<()> {
+        let file = match &mut self.file {
+            Some(file) => file,
+            file @ None => {
+                let mut opts = fs::OpenOptions::new();
+                let fd = opts.read(true).write(true).create(true).truncate(true);
+                let fd = fd.open(&self.file_path);
+                debug!(target: "wral", "spooling entries to {:?}", self.file_path);
+                file.get_or_insert(err_at!(IOError, fd, "{:?}", self.file_path)?)
+            }
+        };
+
+        let seqno = entry.to_seqno();
+        let data = util::encode_cbor(entry)?;
+        if let Err(err) = err_at!(IOError, file.write_all(&data)) {
+            file.set_len(self.mark.size).ok();
+            return Err(err);
+        }
+        self.first_seqno.get_or_insert(seqno);
+        self.mark.n_entries += 1;
+        self.mark.size += data.len() as u64;
+        self.mark.last_seqno = Some(seqno);
+        Ok(())
+    }
+
+    /// Discard entries spooled after `mark`.
+    pub fn rewind(&mut self, mark: Mark) -> Result<()> {
+        if let Some(file) = &mut self.file {
+            err_at!(IOError, file.set_len(mark.size))?;
+            err_at!(IOError, io::Seek::seek(file, io::SeekFrom::Start(mark.size)))?;
+        }
+        if mark.n_entries == 0 {
+            self.first_seqno = None;
+        }
+        self.mark = mark;
+        Ok(())
+    }
+
+    /// Read back spooled entries, in the order they were spooled.
+    pub fn read(&self) -> Result<Vec<entry::Entry>> {
+        if self.mark.n_entries == 0 {
+            return Ok(vec![]);
+        }
+
+        let file = {
+            let file_path = path::Path::new(&self.file_path);
+            err_at!(IOError, fs::File::open(file_path), "{:?}", self.file_path)?
+        };
+        let mut reader = io::BufReader::new(file);
+        let mut entries = Vec::with_capacity(self.mark.n_entries);
+        for _ in 0..self.mark.n_entries {
+            let (val, _) = Cbor::decode(&mut reader)?;
+            entries.push(entry::Entry::from_cbor(val)?);
+        }
+        Ok(entries)
+    }
+
+    /// Read back spooled entries and empty the spool file.
+    pub fn drain(&mut self) -> Result<Vec<entry::Entry>> {
+        let entries = self.read()?;
+        self.rewind(Mark::default())?;
+        Ok(entries)
+    }
+}
@@ -8,8 +8,41 @@ use crate::wral::Wal;
 use crate::{entry::Entry, Result};
 
 /// Callback trait for updating application state in relation to [Wal] type.
+///
+/// State is serialized along with every batch, refer to [cbor][crate::cbor]
+/// for the serialization traits.
 pub trait State: 'static + Clone + Sync + Send + IntoCbor + FromCbor + Default {
-    fn on_add_entry(&mut self, new_entry: &Entry) -> Result<()>;
+    /// Update state for `new_entry`, called from the writer thread.
+    fn on_add_entry(&mut self, _new_entry: &Entry) -> Result<()> {
+        Ok(())
+    }
+
+    /// Update state for `new_entry` and decide whether the entry shall be
+    /// logged, called from the writer thread. Default implementation calls
+    /// [State::on_add_entry] and accepts the entry.
+    ///
+    /// State shall not be updated for rejected entries. Accepted entries
+    /// can be replayed, with the transformed op, when pending entries are
+    /// carried over to a new journal, in which case the returned action is
+    /// ignored.
+    fn on_entry(&mut self, new_entry: &Entry) -> Result<Action> {
+        self.on_add_entry(new_entry)?;
+        Ok(Action::Accept)
+    }
+}
+
+/// Action returned by [State::on_entry].
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Action {
+    /// Log the entry as is.
+    Accept,
+    /// Don't log the entry, the request fails with [Error::Rejected]
+    /// carrying the reason.
+    ///
+    /// [Error::Rejected]: crate::Error::Rejected
+    Reject(String),
+    /// Log the entry with its op replaced.
+    Transform(Vec<u8>),
 }
 
 /// Default parameter, implementing [State] trait, for [Wal] type.
@@ -25,3 +58,17 @@ impl State for NoState {
         Ok(())
     }
 }
+
+/// Policy when the application state, persisted along with an archived
+/// journal, cannot be decoded while loading a [Wal] instance. Refer to
+/// [Config::set_state_policy][crate::Config::set_state_policy].
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub enum StatePolicy {
+    /// Ignore the journal along with its entries.
+    #[default]
+    Discard,
+    /// Load the journal's entries and use `S::default()` as its state,
+    /// [WalEvent::StateReset][crate::WalEvent::StateReset] is emitted for
+    /// the journal.
+    UseDefault,
+}
@@ -0,0 +1,378 @@
+//! Module implement storage backends for the active journal.
+//!
+//! Only the write path, appending batches to the active journal, goes
+//! through [Storage]. Archived journals are read back using std::fs.
+
+use log::error;
+
+use std::{ffi, fs, path, time};
+
+use crate::{batch, clock::Clock, util, Error, Result};
+
+/// Storage backend for journal files, refer to
+/// [Config::set_backend][crate::Config::set_backend].
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub enum Backend {
+    /// Blocking file IO using std::fs, default backend.
+    #[default]
+    Std,
+    /// File IO driven by io-uring, on a dedicated tokio-uring runtime.
+    #[cfg(feature = "uring")]
+    Uring,
+}
+
+/// Policy for mirrored journals, when writing to the mirror fails. Refer
+/// to [Config::set_mirror][crate::Config::set_mirror].
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub enum MirrorPolicy {
+    /// Fail the batch, entries are acknowledged only after they are
+    /// durable on both devices.
+    #[default]
+    FailStop,
+    /// Log the failure and continue writing to primary alone, until the
+    /// journal is rotated, after which the mirror is retried.
+    Degrade,
+}
+
+/// Policy when the file system, on which the next journal is created, is
+/// low on space. Refer to [Config::set_space_policy][crate::Config::set_space_policy].
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub enum SpacePolicy {
+    /// Don't check for space before rotation.
+    Ignore,
+    /// Emit [WalEvent::LowSpace][crate::WalEvent::LowSpace] and rotate.
+    #[default]
+    Warn,
+    /// Emit [WalEvent::LowSpace][crate::WalEvent::LowSpace] and refuse
+    /// rotation with [Error::NoSpace][crate::Error::NoSpace]. Entries
+    /// continue to be appended to the active journal, upto the journal
+    /// limit plus tolerance, beyond which they fail with the same error.
+    /// Rotation is retried after every batch.
+    Refuse,
+}
+
+/// Interval between fsyncs of the active journal, when batches are not
+/// synced as they are written. Refer to
+/// [Config::set_fsync_interval][crate::Config::set_fsync_interval].
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum FsyncInterval {
+    /// Fsync once this duration has elapsed since the first batch written
+    /// after the last fsync, also when the writer is idle.
+    Elapsed(time::Duration),
+    /// Fsync after every these many batches.
+    Batches(usize),
+}
+
+/// Placement of journals across directories, refer to
+/// [Config::set_dirs][crate::Config::set_dirs].
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub enum Placement {
+    /// Journals are placed in each directory in turn, by journal number.
+    #[default]
+    RoundRobin,
+    /// Capacity, in bytes, of each directory, in the same order as the
+    /// directories. Journal is placed in the directory with most capacity
+    /// left, after accounting for journals of this instance already in it.
+    Capacity(Vec<u64>),
+}
+
+/// Options for opening journal files, derived from [Config][crate::Config].
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    pub backend: Backend,
+    // encoding of entries in batches written to the journal.
+    pub codec: batch::Codec,
+    // persist tag index along with each batch.
+    pub tag_index: bool,
+    // secondary codec to verify batches against.
+    pub shadow: Option<batch::Codec>,
+    // spill ops larger than this many bytes into blob file.
+    pub blob_threshold: Option<usize>,
+    // compress ops larger than this many bytes.
+    pub compress_threshold: Option<usize>,
+    // hold pending entries in memory upto these many bytes of ops.
+    pub buffer_limit: Option<usize>,
+    // skip fsync for batches carrying entries, writer syncs periodically.
+    pub defer_sync: bool,
+    // write trailer, with checksum over entries, on rotation.
+    pub trailer: bool,
+    // chain of middleware ids, to seal batch payload.
+    pub middleware: Vec<u32>,
+    // minimum number of digits in journal file names.
+    pub journal_width: usize,
+    // directory to mirror journal files.
+    pub mirror: Option<ffi::OsString>,
+    pub policy: MirrorPolicy,
+    // source of batch timestamps.
+    pub clock: Clock,
+}
+
+/// Write side of a journal file. Data is always appended at the end of
+/// the file.
+pub trait Storage {
+    /// Append `data` to the end of file, return the number of bytes written.
+    fn append(&mut self, data: &[u8]) -> Result<usize>;
+
+    /// Flush file data and metadata to the underlying device.
+    fn sync(&mut self) -> Result<()>;
+
+    /// Return the current size of file.
+    fn to_size(&self) -> Result<u64>;
+
+    /// Truncate file to `size` bytes, used to discard partially written
+    /// batches.
+    fn truncate(&mut self, size: u64) -> Result<()>;
+}
+
+impl Storage for fs::File {
+    fn append(&mut self, data: &[u8]) -> Result<usize> {
+        util::write_all(self, data)
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        err_at!(IOError, self.sync_all())
+    }
+
+    fn to_size(&self) -> Result<u64> {
+        Ok(err_at!(IOError, self.metadata())?.len())
+    }
+
+    fn truncate(&mut self, size: u64) -> Result<()> {
+        err_at!(IOError, self.set_len(size))
+    }
+}
+
+/// Storage handle for the active journal, shared with the writer thread.
+pub type File = Box<dyn Storage + Send + Sync>;
+
+/// Create a new journal file at `file_path`, and its mirror if configured.
+pub fn create(options: &Options, file_path: &ffi::OsStr) -> Result<File> {
+    let create_file = |file_path: &ffi::OsStr| -> Result<File> {
+        fs::remove_file(file_path).ok(); // cleanup a single journal file
+        let file = {
+            let mut opts = fs::OpenOptions::new();
+            err_at!(IOError, opts.append(true).create_new(true).open(file_path))?
+        };
+        from_std(options.backend, file)
+    };
+    with_mirror(options, file_path, create_file)
+}
+
+/// Open an existing journal file at `file_path`, and its mirror if
+/// configured, for appending.
+pub fn open(options: &Options, file_path: &ffi::OsStr) -> Result<File> {
+    let open_file = |file_path: &ffi::OsStr| -> Result<File> {
+        let file = {
+            let mut opts = fs::OpenOptions::new();
+            err_at!(IOError, opts.append(true).open(file_path))?
+        };
+        from_std(options.backend, file)
+    };
+    with_mirror(options, file_path, open_file)
+}
+
+/// Return the location of mirror copy for journal file at `file_path`,
+/// under `dir`.
+pub fn mirror_path(dir: &ffi::OsStr, file_path: &ffi::OsStr) -> Option<ffi::OsString> {
+    let file_name = path::Path::new(file_path).file_name()?;
+    let mirror: path::PathBuf = [dir, file_name].iter().collect();
+    Some(mirror.into_os_string())
+}
+
+fn with_mirror<F>(options: &Options, file_path: &ffi::OsStr, open: F) -> Result<File>
+where
+    F: Fn(&ffi::OsStr) -> Result<File>,
+{
+    let primary = open(file_path)?;
+    let mirror_path = match &options.mirror {
+        Some(dir) => match mirror_path(dir, file_path) {
+            Some(mirror_path) => mirror_path,
+            None => err_at!(Invalid, msg: "bad journal file {:?}", file_path)?,
+        },
+        None => return Ok(primary),
+    };
+
+    let mirror = match (open(&mirror_path), options.policy) {
+        (Ok(mirror), _) => Some(mirror),
+        (Err(err), MirrorPolicy::FailStop) => return Err(err),
+        (Err(err), MirrorPolicy::Degrade) => {
+            error!(target: "wral", "mirror {:?} degraded: {}", mirror_path, err);
+            None
+        }
+    };
+
+    let file = Mirror {
+        primary,
+        mirror,
+        mirror_path,
+        policy: options.policy,
+    };
+    Ok(Box::new(file))
+}
+
+fn from_std(backend: Backend, file: fs::File) -> Result<File> {
+    match backend {
+        Backend::Std => Ok(Box::new(file)),
+        #[cfg(feature = "uring")]
+        Backend::Uring => Ok(Box::new(uring::UringFile::new(file)?)),
+    }
+}
+
+/// Journal file mirrored to another directory, ideally on a different
+/// device. Primary file is the source of truth, readers only read the
+/// primary file.
+struct Mirror {
+    primary: File,
+    // None when the mirror has degraded.
+    mirror: Option<File>,
+    mirror_path: ffi::OsString,
+    policy: MirrorPolicy,
+}
+
+impl Mirror {
+    fn do_mirror<T, F>(&mut self, op: F) -> Result<()>
+    where
+        F: FnOnce(&mut File) -> Result<T>,
+    {
+        let res = match self.mirror.as_mut() {
+            Some(mirror) => op(mirror),
+            None => return Ok(()),
+        };
+        match (res, self.policy) {
+            (Ok(_), _) => Ok(()),
+            (Err(err), MirrorPolicy::FailStop) => Err(err),
+            (Err(err), MirrorPolicy::Degrade) => {
+                error!(target: "wral", "mirror {:?} degraded: {}", self.mirror_path, err);
+                self.mirror = None;
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Storage for Mirror {
+    fn append(&mut self, data: &[u8]) -> Result<usize> {
+        let n = self.primary.append(data)?;
+        self.do_mirror(|mirror| mirror.append(data))?;
+        Ok(n)
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        self.primary.sync()?;
+        self.do_mirror(|mirror| mirror.sync())
+    }
+
+    fn to_size(&self) -> Result<u64> {
+        self.primary.to_size()
+    }
+
+    fn truncate(&mut self, size: u64) -> Result<()> {
+        self.primary.truncate(size)?;
+        self.do_mirror(|mirror| mirror.truncate(size))
+    }
+}
+
+#[cfg(feature = "uring")]
+mod uring {
+    use tokio::sync::mpsc;
+
+    use std::{fs, sync, thread};
+
+    use super::Storage;
+    use crate::{Error, Result};
+
+    enum Cmd {
+        Write { data: Vec<u8>, fpos: u64 },
+        Sync,
+    }
+
+    type Reply = sync::mpsc::Sender<Result<usize>>;
+
+    /// Journal file driven by io-uring. tokio-uring files are bound to
+    /// the runtime that created them, hence the file is owned by a driver
+    /// thread running its own runtime, and this handle forward commands to
+    /// it. Size and truncate are served from a duplicate std handle.
+    pub struct UringFile {
+        tx: mpsc::Sender<(Cmd, Reply)>,
+        file: fs::File,
+        fpos: u64,
+    }
+
+    impl UringFile {
+        pub fn new(file: fs::File) -> Result<UringFile> {
+            let fpos = err_at!(IOError, file.metadata())?.len();
+            let dup = err_at!(IOError, file.try_clone())?;
+            let (tx, rx) = mpsc::channel(1);
+            err_at!(
+                ThreadFail,
+                thread::Builder::new()
+                    .name("wral-uring".to_string())
+                    .spawn(move || tokio_uring::start(drive(dup, rx)))
+            )?;
+            Ok(UringFile { tx, file, fpos })
+        }
+
+        fn request(&self, cmd: Cmd) -> Result<usize> {
+            let (tx, rx) = sync::mpsc::channel();
+            err_at!(IPCFail, self.tx.blocking_send((cmd, tx)))?;
+            err_at!(IPCFail, rx.recv())?
+        }
+    }
+
+    impl Storage for UringFile {
+        fn append(&mut self, data: &[u8]) -> Result<usize> {
+            let cmd = Cmd::Write { data: data.to_vec(), fpos: self.fpos };
+            let n = self.request(cmd)?;
+            self.fpos += n as u64;
+            Ok(n)
+        }
+
+        fn sync(&mut self) -> Result<()> {
+            self.request(Cmd::Sync)?;
+            Ok(())
+        }
+
+        fn to_size(&self) -> Result<u64> {
+            Ok(err_at!(IOError, self.file.metadata())?.len())
+        }
+
+        fn truncate(&mut self, size: u64) -> Result<()> {
+            err_at!(IOError, self.file.set_len(size))?;
+            self.fpos = size;
+            Ok(())
+        }
+    }
+
+    async fn drive(file: fs::File, mut rx: mpsc::Receiver<(Cmd, Reply)>) {
+        let file = tokio_uring::fs::File::from_std(file);
+        while let Some((cmd, tx)) = rx.recv().await {
+            let res = match cmd {
+                Cmd::Write { data, fpos } => write_all(&file, data, fpos).await,
+                Cmd::Sync => err_at!(IOError, file.sync_all().await).map(|_| 0),
+            };
+            tx.send(res).ok();
+        }
+        file.close().await.ok();
+    }
+
+    async fn write_all(
+        file: &tokio_uring::fs::File,
+        mut data: Vec<u8>,
+        fpos: u64,
+    ) -> Result<usize> {
+        let n = data.len();
+        let mut m = 0;
+        while !data.is_empty() {
+            let (res, mut buf) = file.write_at(data, fpos + (m as u64)).await;
+            match err_at!(IOError, res)? {
+                0 => err_at!(IOError, msg: "partial write to file {} {}", m, n)?,
+                k => {
+                    buf.drain(..k);
+                    m += k;
+                }
+            }
+            data = buf;
+        }
+        Ok(n)
+    }
+}
@@ -0,0 +1,88 @@
+//! Module implement async stream of entries, refer to [Wal::stream].
+
+use futures::stream::Stream;
+use tokio::task;
+
+use std::{
+    future::Future,
+    ops,
+    pin::Pin,
+    task::{Context, Poll},
+    vec,
+};
+
+use crate::{entry::Entry, wral::Iter, wral::Wal, Error, Result};
+
+// Number of entries read from disk, by a single blocking task.
+const STREAM_CHUNK: usize = 256;
+
+/// Stream of entries, created by [Wal::stream].
+///
+/// Entries are read from journal files in chunks, on tokio's blocking
+/// pool, and the next chunk is read only after the current chunk is
+/// consumed. Must be polled from within a tokio runtime.
+pub struct EntryStream {
+    iter: Option<Iter>,
+    entries: vec::IntoIter<Result<Entry>>,
+    task: Option<task::JoinHandle<(Iter, Vec<Result<Entry>>)>>,
+}
+
+impl Stream for EntryStream {
+    type Item = Result<Entry>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        loop {
+            if let Some(entry) = self.entries.next() {
+                break Poll::Ready(Some(entry));
+            }
+            if let Some(t) = self.task.as_mut() {
+                match Pin::new(t).poll(cx) {
+                    Poll::Pending => break Poll::Pending,
+                    Poll::Ready(Ok((_, entries))) if entries.is_empty() => {
+                        self.task = None;
+                        break Poll::Ready(None);
+                    }
+                    Poll::Ready(Ok((iter, entries))) => {
+                        self.task = None;
+                        self.iter = Some(iter);
+                        self.entries = entries.into_iter();
+                        continue;
+                    }
+                    Poll::Ready(Err(err)) => {
+                        self.task = None;
+                        break Poll::Ready(Some(err_at!(ThreadFail, Err(err))));
+                    }
+                }
+            }
+            match self.iter.take() {
+                Some(mut iter) => {
+                    self.task = Some(task::spawn_blocking(move || {
+                        let entries = iter.by_ref().take(STREAM_CHUNK).collect();
+                        (iter, entries)
+                    }))
+                }
+                None => break Poll::Ready(None),
+            }
+        }
+    }
+}
+
+impl<S> Wal<S> {
+    /// Same as [Wal::range], but return entries as an async stream, refer
+    /// to [EntryStream].
+    pub fn stream<R>(&self, range: R) -> Result<EntryStream>
+    where
+        R: ops::RangeBounds<u64>,
+    {
+        let iter = self.do_range(range, None)?;
+        Ok(EntryStream {
+            iter: Some(iter),
+            entries: vec![].into_iter(),
+            task: None,
+        })
+    }
+}
+
+#[cfg(test)]
+#[path = "stream_test.rs"]
+mod stream_test;
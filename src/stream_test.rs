@@ -0,0 +1,33 @@
+use futures::stream::StreamExt;
+
+use super::*;
+
+use crate::{state, wral::Config};
+
+#[test]
+fn test_stream() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-stream", dir.path().as_ref());
+    config.set_journal_limit(1000).set_fsync(false);
+
+    let wal = Wal::create(config, state::NoState).unwrap();
+    for i in 0..1000_u64 {
+        wal.add_op(&i.to_be_bytes()).unwrap();
+    }
+
+    let rt = tokio::runtime::Builder::new_current_thread().build().unwrap();
+    let seqnos: Vec<u64> = rt.block_on(async {
+        let mut stream = wal.stream(10..=900).unwrap();
+        let mut seqnos = vec![];
+        while let Some(entry) = stream.next().await {
+            seqnos.push(entry.unwrap().to_seqno());
+        }
+        seqnos
+    });
+    assert_eq!(seqnos, (10..=900).collect::<Vec<u64>>());
+
+    let n = rt.block_on(wal.stream(2000..).unwrap().count());
+    assert_eq!(n, 0);
+
+    wal.close(true).unwrap();
+}
@@ -0,0 +1,287 @@
+//! Module implement crash-recovery property tests as a reusable harness,
+//! enabled with the `testing` feature.
+//!
+//! Storage engines built on [Wal] can run the property against their own
+//! [Config] and [State] combinations, refer to [crash_recovery]:
+//!
+//! ```ignore
+//! let config = wral::Config::new("engine", dir.path().as_ref());
+//! let opts = wral::testkit::Options::new(seed);
+//! let report = wral::testkit::crash_recovery::<EngineState>(config, &opts).unwrap();
+//! ```
+//!
+//! Each round, concurrent writers add random ops to a live instance, which
+//! is killed at a random point by taking a crash image of its files, with
+//! a torn tail that loses a random number of bytes after the durable
+//! seqno. The image is repaired with [FsckLevel::TruncateTorn], reloaded,
+//! and shall hold a contiguous prefix of the acknowledged ops, covering
+//! at least the durable seqno. The recovered instance is the live instance
+//! for the next round.
+
+use log::debug;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering::SeqCst},
+        Arc,
+    },
+    thread, time,
+};
+
+use crate::{fsck, state::State, wral::Config, Error, FsckLevel, Result, Wal};
+
+/// Topics and tags that random ops are added with.
+const TOPICS: [&str; 3] = ["", "alpha", "beta"];
+
+/// Options for [crash_recovery].
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// Seed for random ops, kill points and torn tails. Batching across
+    /// concurrent writers is not repeatable, failures shall report the seed
+    /// along with the round.
+    pub seed: u64,
+    /// Number of crash and recovery rounds.
+    pub rounds: usize,
+    /// Number of concurrent writers adding ops.
+    pub writers: usize,
+    /// Maximum number of ops acknowledged before the crash, in each round.
+    pub max_ops: usize,
+    /// Maximum size of an op, in bytes.
+    pub max_op_size: usize,
+}
+
+impl Options {
+    /// Default options for `seed`.
+    pub fn new(seed: u64) -> Options {
+        Options {
+            seed,
+            rounds: 8,
+            writers: 4,
+            max_ops: 1000,
+            max_op_size: 256,
+        }
+    }
+}
+
+/// Report generated by [crash_recovery].
+#[derive(Debug, Clone, Default)]
+pub struct Report {
+    /// Number of rounds run.
+    pub rounds: usize,
+    /// Number of ops acknowledged across all rounds.
+    pub n_ops: usize,
+    /// Number of acknowledged ops lost to crashes, none of them durable.
+    pub n_lost: usize,
+    /// Number of journals repaired by fsck before reload.
+    pub n_repaired: usize,
+    /// Last seqno after the final recovery.
+    pub seqno: u64,
+}
+
+// topic, tag and op, as added by a writer.
+type Op = (String, String, Vec<u8>);
+
+/// Run crash-recovery rounds for a Wal instance with state `S`, created
+/// under `config`, refer to [module level][crate::testkit] documentation.
+/// Files under `config.dir` are purged after the first crash, recovered
+/// instances live in temporary directories.
+///
+/// Ops are compared as they were added, hence `S` shall not transform ops.
+/// Ops rejected by `S` are not counted. Journals striped across
+/// [Config::dirs] and mirrors are not supported.
+pub fn crash_recovery<S>(config: Config, opts: &Options) -> Result<Report>
+where
+    S: State,
+{
+    if !config.dirs.is_empty() || config.mirror_dir.is_some() {
+        err_at!(Invalid, msg: "crash recovery with dirs or mirror")?
+    }
+    if opts.writers == 0 || opts.max_ops == 0 {
+        err_at!(Invalid, msg: "crash recovery without writers or ops")?
+    }
+
+    let mut rng = StdRng::seed_from_u64(opts.seed);
+    let mut report = Report::default();
+    let mut expected: BTreeMap<u64, Op> = BTreeMap::new();
+    let mut wal: Wal<S> = Wal::create(config.clone(), S::default())?;
+    // holds the image that the live instance was recovered from.
+    let mut _image: Option<tempfile::TempDir> = None;
+
+    for round in 0..opts.rounds {
+        let kill_at = rng.gen_range(1..=opts.max_ops);
+        let (ops, durable, image) = crash_round(&wal, opts, round, kill_at, &mut rng)?;
+        report.n_ops += ops.len();
+        expected.extend(ops);
+        wal.close(true)?;
+
+        let config = Config {
+            dir: image.path().as_os_str().to_os_string(),
+            ..config.clone()
+        };
+        let fr = fsck::fsck(&config, FsckLevel::TruncateTorn)?;
+        report.n_repaired += fr.journals.iter().filter(|jr| jr.repaired).count();
+        wal = Wal::load(config)?;
+
+        let seqno = verify(&wal, &expected, durable).map_err(|err| {
+            let prefix = format!("{}:{}", file!(), line!());
+            let msg = format!("seed:{} round:{} {}", opts.seed, round, err);
+            Error::Fatal(prefix, msg)
+        })?;
+        debug!(
+            target: "wral",
+            "crash recovery round {}, durable {} recovered {}", round, durable, seqno
+        );
+        let lost = expected.split_off(&(seqno + 1));
+        report.n_lost += lost.len();
+        report.seqno = seqno;
+        report.rounds += 1;
+        _image = Some(image);
+    }
+    wal.close(true)?;
+
+    Ok(report)
+}
+
+// Add random ops from concurrent writers until `kill_at` ops are
+// acknowledged, and crash. Return acknowledged ops, the durable seqno at
+// the crash, and the crash image.
+fn crash_round<S>(
+    wal: &Wal<S>,
+    opts: &Options,
+    round: usize,
+    kill_at: usize,
+    rng: &mut StdRng,
+) -> Result<(BTreeMap<u64, Op>, u64, tempfile::TempDir)>
+where
+    S: State,
+{
+    let acked = Arc::new(AtomicUsize::new(0));
+    let stop = Arc::new(AtomicBool::new(false));
+
+    let mut writers = vec![];
+    for id in 0..opts.writers {
+        let (wal, opts) = (wal.clone(), opts.clone());
+        let (acked, stop) = (Arc::clone(&acked), Arc::clone(&stop));
+        let seed = opts.seed ^ ((round as u64) << 32) ^ (id as u64);
+        writers.push(thread::spawn(move || {
+            let res = add_ops(&wal, &opts, seed, &acked, &stop);
+            // let other writers and the killer bail out.
+            if res.is_err() {
+                stop.store(true, SeqCst);
+            }
+            wal.close(false).ok();
+            res
+        }));
+    }
+
+    while acked.load(SeqCst) < kill_at && !stop.load(SeqCst) {
+        thread::sleep(time::Duration::from_millis(1));
+    }
+    let image = err_at!(IOError, tempfile::tempdir())?;
+    let res = wal.crash_image(image.path().as_os_str(), |offs| rng.gen_range(offs));
+    stop.store(true, SeqCst);
+
+    let mut ops = BTreeMap::new();
+    for writer in writers.into_iter() {
+        match writer.join() {
+            Ok(res) => ops.extend(res?),
+            Err(err) => err_at!(ThreadFail, msg: "writer {:?}", err)?,
+        }
+    }
+    let durable = res?;
+
+    Ok((ops, durable, image))
+}
+
+fn add_ops<S>(
+    wal: &Wal<S>,
+    opts: &Options,
+    seed: u64,
+    acked: &AtomicUsize,
+    stop: &AtomicBool,
+) -> Result<Vec<(u64, Op)>> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut ops = vec![];
+    while !stop.load(SeqCst) {
+        let topic = TOPICS[rng.gen_range(0..TOPICS.len())].to_string();
+        let res = match rng.gen_range(0..4) {
+            0 => {
+                let op = random_op(&mut rng, opts.max_op_size);
+                let res = wal.add_op(&op);
+                res.map(|seqno| vec![(seqno, (String::default(), String::default(), op))])
+            }
+            1 => {
+                let op = random_op(&mut rng, opts.max_op_size);
+                let res = wal.add_op_to(&topic, &op);
+                res.map(|seqno| vec![(seqno, (topic, String::default(), op))])
+            }
+            2 => {
+                let op = random_op(&mut rng, opts.max_op_size);
+                let res = wal.add_op_tagged(&topic, &op);
+                res.map(|seqno| vec![(seqno, (String::default(), topic, op))])
+            }
+            _ => {
+                let n = rng.gen_range(1..=8);
+                let items: Vec<(String, Vec<u8>)> = (0..n)
+                    .map(|_| (topic.clone(), random_op(&mut rng, opts.max_op_size)))
+                    .collect();
+                wal.do_add_ops(items.clone()).map(|seqnos| {
+                    let iter = seqnos.zip(items);
+                    iter.map(|(seqno, (tp, op))| (seqno, (tp, String::default(), op)))
+                        .collect()
+                })
+            }
+        };
+        match res {
+            Ok(items) => {
+                acked.fetch_add(items.len(), SeqCst);
+                ops.extend(items);
+            }
+            Err(Error::Rejected(_, _)) => (),
+            Err(err) => return Err(err),
+        }
+    }
+
+    Ok(ops)
+}
+
+fn random_op(rng: &mut StdRng, max_op_size: usize) -> Vec<u8> {
+    let n = rng.gen_range(0..=max_op_size);
+    (0..n).map(|_| rng.gen()).collect()
+}
+
+// Verify that the recovered instance holds a contiguous prefix of the
+// acknowledged ops, covering `durable`, return its last seqno.
+fn verify<S>(wal: &Wal<S>, expected: &BTreeMap<u64, Op>, durable: u64) -> Result<u64> {
+    let mut seqno = 0;
+    for entry in wal.iter()? {
+        let entry = entry?;
+        if entry.to_seqno() != seqno + 1 {
+            err_at!(Mismatch, msg: "seqno {} after {}", entry.to_seqno(), seqno)?
+        }
+        seqno = entry.to_seqno();
+        match expected.get(&seqno) {
+            Some((topic, tag, op))
+                if entry.as_topic() == topic
+                    && entry.as_tag() == tag
+                    && entry.as_op() == op.as_slice() => {}
+            Some(_) => err_at!(Mismatch, msg: "entry {} differs from op", seqno)?,
+            None => err_at!(Mismatch, msg: "entry {} was never acknowledged", seqno)?,
+        }
+    }
+    if seqno < durable {
+        err_at!(Mismatch, msg: "recovered upto {}, durable {}", seqno, durable)?
+    }
+    if wal.durable_seqno()? != seqno {
+        let dseqno = wal.durable_seqno()?;
+        err_at!(Mismatch, msg: "durable seqno {} after reload upto {}", dseqno, seqno)?
+    }
+
+    Ok(seqno)
+}
+
+#[cfg(test)]
+#[path = "testkit_test.rs"]
+mod testkit_test;
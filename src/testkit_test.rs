@@ -0,0 +1,48 @@
+use rand::prelude::random;
+
+use std::time;
+
+use super::*;
+
+use crate::{state::NoState, FsyncInterval};
+
+#[test]
+fn test_testkit_crash_recovery() {
+    let seed: u64 = random();
+    println!("test_testkit_crash_recovery {}", seed);
+
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-testkit", dir.path().as_ref());
+    config.set_journal_limit(4096).set_fsync(false);
+
+    let opts = Options { rounds: 4, max_ops: 200, ..Options::new(seed) };
+    let report = crash_recovery::<NoState>(config, &opts).unwrap();
+    println!("test_testkit_crash_recovery {:?}", report);
+    assert_eq!(report.rounds, 4);
+    assert!(report.n_ops >= 4, "{:?}", report);
+    assert!(report.n_lost <= report.n_ops, "{:?}", report);
+}
+
+#[test]
+fn test_testkit_fsync_interval() {
+    let seed: u64 = random();
+    println!("test_testkit_fsync_interval {}", seed);
+
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-testkit-fsync", dir.path().as_ref());
+    let hour = time::Duration::from_secs(3600);
+    config.set_fsync_interval(Some(FsyncInterval::Elapsed(hour)));
+
+    let opts = Options { rounds: 4, max_ops: 200, ..Options::new(seed) };
+    let report = crash_recovery::<NoState>(config, &opts).unwrap();
+    println!("test_testkit_fsync_interval {:?}", report);
+    assert_eq!(report.rounds, 4);
+    assert_eq!(report.seqno as usize, report.n_ops - report.n_lost, "{:?}", report);
+
+    let mut config = Config::new("test-testkit-fsync", dir.path().as_ref());
+    config.set_mirror(dir.path().as_ref(), crate::MirrorPolicy::default());
+    match crash_recovery::<NoState>(config, &opts) {
+        Err(Error::Invalid(_, _)) => (),
+        res => panic!("expected Invalid {:?}", res),
+    }
+}
@@ -0,0 +1,145 @@
+use mkit::Cborize;
+
+use std::{
+    cmp,
+    fmt::{self, Display},
+    ops, result, time,
+};
+
+/// Record of a purge operation, refer to [Wal::purge_till][crate::Wal::purge_till].
+///
+/// Tombstones are persisted in the active journal, before the purged
+/// journals are removed from disk, and explain why entries are no longer
+/// present.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Cborize)]
+pub struct Tombstone {
+    // seqno epoch of the purged entries.
+    epoch: u64,
+    // entries upto and including this seqno were purged.
+    seqno: u64,
+    // time of purge, in nanoseconds since UNIX_EPOCH.
+    timestamp: u64,
+    // application supplied reason for purge.
+    reason: String,
+}
+
+impl Display for Tombstone {
+    fn fmt(&self, f: &mut fmt::Formatter) -> result::Result<(), fmt::Error> {
+        write!(f, "tombstone<epoch:{},seqno:{}>", self.epoch, self.seqno)
+    }
+}
+
+impl Tombstone {
+    const ID: u32 = 0x0;
+
+    pub fn new(epoch: u64, seqno: u64, reason: &str, timestamp: u64) -> Tombstone {
+        Tombstone {
+            epoch,
+            seqno,
+            timestamp,
+            reason: reason.to_string(),
+        }
+    }
+
+    /// Return the seqno epoch of purged entries.
+    #[inline]
+    pub fn to_epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    /// Return the seqno upto which entries were purged.
+    #[inline]
+    pub fn to_seqno(&self) -> u64 {
+        self.seqno
+    }
+
+    /// Return the time of purge.
+    #[inline]
+    pub fn to_timestamp(&self) -> time::SystemTime {
+        time::UNIX_EPOCH + time::Duration::from_nanos(self.timestamp)
+    }
+
+    /// Return the reason for purge.
+    #[inline]
+    pub fn as_reason(&self) -> &str {
+        &self.reason
+    }
+}
+
+/// Masked seqno range, refer to [Wal::mask_range][crate::Wal::mask_range].
+#[derive(Debug, Clone, Default, Eq, PartialEq, Cborize)]
+pub struct Mask {
+    // seqno epoch of the masked range.
+    epoch: u64,
+    // first seqno in the masked range.
+    start: u64,
+    // last seqno in the masked range, inclusive.
+    end: u64,
+}
+
+impl Mask {
+    const ID: u32 = 0x0;
+
+    /// Return the masked seqno range.
+    #[inline]
+    pub fn to_range(&self) -> ops::RangeInclusive<u64> {
+        self.start..=self.end
+    }
+}
+
+/// Wal metadata, persisted in batches without entries. Every such batch
+/// carry the entire metadata, and the latest one supersedes the others.
+#[derive(Debug, Clone, Default)]
+pub struct Metadata {
+    // purge history, oldest first.
+    pub tombstones: Vec<Tombstone>,
+    // masked seqno ranges, sorted and non-overlapping within an epoch.
+    pub masks: Vec<Mask>,
+}
+
+impl Metadata {
+    /// Mask `range` in `epoch`, merging with overlapping and adjacent
+    /// masks.
+    pub fn mask(&mut self, epoch: u64, range: ops::RangeInclusive<u64>) {
+        let (mut start, mut end) = (*range.start(), *range.end());
+        let mut masks = vec![];
+        for m in self.masks.drain(..) {
+            let adjacent = m.epoch == epoch
+                && m.start <= end.saturating_add(1)
+                && start <= m.end.saturating_add(1);
+            if adjacent {
+                start = cmp::min(start, m.start);
+                end = cmp::max(end, m.end);
+            } else {
+                masks.push(m)
+            }
+        }
+        masks.push(Mask { epoch, start, end });
+        masks.sort_by_key(|m| (m.epoch, m.start));
+        self.masks = masks;
+    }
+
+    /// Unmask `range` in `epoch`, splitting masks that partially overlap.
+    pub fn unmask(&mut self, epoch: u64, range: ops::RangeInclusive<u64>) {
+        let (start, end) = (*range.start(), *range.end());
+        let mut masks = vec![];
+        for m in self.masks.drain(..) {
+            if m.epoch != epoch || m.end < start || end < m.start {
+                masks.push(m);
+                continue;
+            }
+            if m.start < start {
+                masks.push(Mask { epoch, start: m.start, end: start - 1 });
+            }
+            if end < m.end {
+                masks.push(Mask { epoch, start: end + 1, end: m.end });
+            }
+        }
+        self.masks = masks;
+    }
+
+    /// Return masked ranges in `epoch`.
+    pub fn to_masks(&self, epoch: u64) -> Vec<ops::RangeInclusive<u64>> {
+        self.masks.iter().filter(|m| m.epoch == epoch).map(Mask::to_range).collect()
+    }
+}
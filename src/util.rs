@@ -1,6 +1,10 @@
-use mkit::cbor::IntoCbor;
+use mkit::cbor::{Cbor, IntoCbor};
 
-use std::{fs, io::Write};
+use std::{
+    ffi, fs,
+    io::{self, Read, Write},
+    path, time,
+};
 
 use crate::{Error, Result};
 
@@ -17,11 +21,293 @@ where
     }
 }
 
+/// Same as [encode_cbor], but append the encoded bytes to `buf`, so that
+/// callers can reuse the buffer across calls. Return the number of bytes
+/// appended.
+pub fn encode_cbor_into<T>(val: T, buf: &mut Vec<u8>) -> Result<usize>
+where
+    T: IntoCbor,
+{
+    let m = buf.len();
+    let n = val.into_cbor()?.encode(buf)?;
+    if n != buf.len() - m {
+        err_at!(Fatal, msg: "cbor encoding len mistmatch {} {}", n, buf.len() - m)
+    } else {
+        Ok(n)
+    }
+}
+
 pub fn sync_write(file: &mut fs::File, data: &[u8]) -> Result<usize> {
+    let n = write_all(file, data)?;
+    err_at!(IOError, file.sync_all())?;
+    Ok(n)
+}
+
+pub fn write_all(file: &mut fs::File, data: &[u8]) -> Result<usize> {
     let n = err_at!(IOError, file.write(data))?;
     if n != data.len() {
         err_at!(IOError, msg: "partial write to file {} {}", n, data.len())?
     }
-    err_at!(IOError, file.sync_all())?;
     Ok(n)
 }
+
+/// Write `data` into a temporary file and rename it to `file_path`, so that
+/// readers either see the old content or the new content.
+pub fn atomic_write(file_path: &path::Path, data: &[u8]) -> Result<()> {
+    let tmp_path = {
+        let mut tmp: ffi::OsString = file_path.as_os_str().to_os_string();
+        tmp.push(".tmp");
+        path::PathBuf::from(tmp)
+    };
+
+    let mut file = err_at!(IOError, fs::File::create(&tmp_path))?;
+    sync_write(&mut file, data)?;
+    err_at!(IOError, fs::rename(&tmp_path, file_path))?;
+
+    match file_path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => sync_dir(dir),
+        _ => Ok(()),
+    }
+}
+
+/// Sync directory entries, so that file creation, rename and removal under
+/// `dir` are durable.
+pub fn sync_dir(dir: &path::Path) -> Result<()> {
+    err_at!(IOError, fs::File::open(dir).and_then(|d| d.sync_all()))
+}
+
+/// Append `val` to `buf` as LEB128 varint.
+pub fn encode_varint(mut val: u64, buf: &mut Vec<u8>) {
+    while val >= 0x80 {
+        buf.push((val as u8) | 0x80);
+        val >>= 7;
+    }
+    buf.push(val as u8);
+}
+
+/// Decode LEB128 varint from `data` starting at `off`, and move `off` past
+/// the decoded bytes.
+pub fn decode_varint(data: &[u8], off: &mut usize) -> Result<u64> {
+    let mut val = 0_u64;
+    for shift in (0..64).step_by(7) {
+        let byte = match data.get(*off) {
+            Some(byte) => *byte,
+            None => err_at!(FailConvert, msg: "truncated varint at {}", off)?,
+        };
+        *off += 1;
+        val |= u64::from(byte & 0x7f) << shift;
+        if byte < 0x80 {
+            return Ok(val);
+        }
+    }
+    err_at!(FailConvert, msg: "varint overflow at {}", off)
+}
+
+// Table for CRC-32 (IEEE 802.3), reflected polynomial 0xEDB88320.
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0_u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut k = 0;
+        while k < 8 {
+            crc = match crc & 1 {
+                1 => (crc >> 1) ^ 0xEDB8_8320,
+                _ => crc >> 1,
+            };
+            k += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// Return the CRC-32 (IEEE) checksum of `data`.
+pub fn crc32(data: &[u8]) -> u32 {
+    crc32_update(0, data)
+}
+
+/// Extend CRC-32 (IEEE) checksum `crc`, of the bytes seen so far, with
+/// `data`. Start with a `crc` of ZERO.
+pub fn crc32_update(crc: u32, data: &[u8]) -> u32 {
+    let crc = data.iter().fold(!crc, |crc, byte| {
+        CRC32_TABLE[((crc ^ u32::from(*byte)) & 0xff) as usize] ^ (crc >> 8)
+    });
+    !crc
+}
+
+/// Return the current time in nanoseconds since UNIX_EPOCH, ZERO if the
+/// clock is set before UNIX_EPOCH.
+pub fn unix_nanos() -> u64 {
+    time::SystemTime::now()
+        .duration_since(time::UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or(0)
+}
+
+/// Return the space available to unprivileged users, in bytes, on the
+/// file system holding `dir`, None if it can't be determined.
+#[cfg(unix)]
+pub fn available_space(dir: &path::Path) -> Option<u64> {
+    let stat = rustix::fs::statvfs(dir).ok()?;
+    Some(stat.f_bavail.saturating_mul(stat.f_frsize))
+}
+
+#[cfg(not(unix))]
+pub fn available_space(_dir: &path::Path) -> Option<u64> {
+    None
+}
+
+/// Return a random, version 4, UUID in its hyphenated text form. Random
+/// bits are drawn from the process' randomly keyed hasher, mixed with the
+/// current time and a process wide counter.
+pub fn new_uuid() -> String {
+    use std::collections::hash_map::RandomState;
+    use std::hash::{BuildHasher, Hasher};
+    use std::sync::atomic::{AtomicU64, Ordering::SeqCst};
+
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    let mut bits = [0_u64; 2];
+    for (i, item) in bits.iter_mut().enumerate() {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u64(unix_nanos());
+        hasher.write_u64(COUNTER.fetch_add(1, SeqCst));
+        hasher.write_u32(std::process::id());
+        hasher.write_usize(i);
+        *item = hasher.finish();
+    }
+    format_uuid(bits[0], bits[1])
+}
+
+/// Format 128 bits, as `hi` and `lo` words, as version 4 UUID in its
+/// hyphenated text form, version and variant bits are overwritten.
+pub fn format_uuid(hi: u64, lo: u64) -> String {
+    let (hi, lo) = ((hi & !0xF000) | 0x4000, (lo & !(0xC << 60)) | (0x8 << 60));
+    format!(
+        "{:08x}-{:04x}-{:04x}-{:04x}-{:012x}",
+        hi >> 32,
+        (hi >> 16) & 0xFFFF,
+        hi & 0xFFFF,
+        lo >> 48,
+        lo & 0xFFFF_FFFF_FFFF
+    )
+}
+
+/// Return the number of bytes taken by cbor array header, for an array of
+/// `n` items.
+pub fn array_hdr_len(n: u64) -> u64 {
+    match n {
+        0..=23 => 1,
+        24..=0xff => 2,
+        0x100..=0xffff => 3,
+        0x10000..=0xffff_ffff => 5,
+        _ => 9,
+    }
+}
+
+/// Decode a definite-length cbor array header from `r`, return the number
+/// of items in the array. Items themselves are left in the reader.
+pub fn decode_array_hdr<R>(r: &mut R) -> Result<u64>
+where
+    R: io::Read,
+{
+    let mut byte = [0_u8; 1];
+    err_at!(IOError, r.read_exact(&mut byte))?;
+
+    let (major, info) = (byte[0] >> 5, byte[0] & 0x1f);
+    if major != 4 {
+        err_at!(FailCbor, msg: "expected cbor array, found major {}", major)?
+    }
+
+    let n = match info {
+        0..=23 => return Ok(u64::from(info)),
+        24 => 1,
+        25 => 2,
+        26 => 4,
+        27 => 8,
+        info => err_at!(FailCbor, msg: "unsupported array info {}", info)?,
+    };
+    let mut buf = [0_u8; 8];
+    err_at!(IOError, r.read_exact(&mut buf[8 - n..]))?;
+    Ok(u64::from_be_bytes(buf))
+}
+
+/// Read a single cbor item from `r`, without decoding it. Length fields
+/// are validated against `limit`, the number of bytes left in the
+/// source, failing with [Error::Corrupted] if the item claims more bytes
+/// than available, so that a damaged or crafted length does not lead to
+/// huge allocations.
+pub fn read_cbor<R>(r: &mut R, limit: u64) -> Result<Vec<u8>>
+where
+    R: io::Read,
+{
+    let mut data: Vec<u8> = vec![];
+    // number of items yet to be read, each item takes atleast a byte.
+    let mut pending = 1_u64;
+    while pending > 0 {
+        pending -= 1;
+        let (major, n) = read_cbor_hdr(r, &mut data)?;
+        let avail = limit.saturating_sub(data.len() as u64);
+        let claim = match major {
+            2 | 3 => n,
+            4 => pending.saturating_add(n),
+            5 => pending.saturating_add(n.saturating_mul(2)),
+            6 => pending.saturating_add(1),
+            _ => pending,
+        };
+        if claim > avail {
+            err_at!(Corrupted, msg: "cbor major {} claims {} > {} bytes", major, claim, avail)?
+        }
+        match major {
+            2 | 3 => {
+                let m = err_at!(IOError, r.take(n).read_to_end(&mut data))?;
+                if (m as u64) < n {
+                    err_at!(Corrupted, msg: "cbor truncated {} < {} bytes", m, n)?
+                }
+            }
+            4..=6 => pending = claim,
+            _ => (),
+        }
+    }
+    Ok(data)
+}
+
+/// Same as [read_cbor], and decode the item. Return the item and the
+/// number of bytes read from `r`.
+pub fn decode_cbor<R>(r: &mut R, limit: u64) -> Result<(Cbor, usize)>
+where
+    R: io::Read,
+{
+    let data = read_cbor(r, limit)?;
+    Ok(Cbor::decode(&mut data.as_slice())?)
+}
+
+// Read cbor header into `data`, return major type and its argument.
+fn read_cbor_hdr<R>(r: &mut R, data: &mut Vec<u8>) -> Result<(u8, u64)>
+where
+    R: io::Read,
+{
+    let mut byte = [0_u8; 1];
+    err_at!(IOError, r.read_exact(&mut byte))?;
+    data.push(byte[0]);
+
+    let (major, info) = (byte[0] >> 5, byte[0] & 0x1f);
+    let n = match info {
+        0..=23 => return Ok((major, u64::from(info))),
+        24 => 1,
+        25 => 2,
+        26 => 4,
+        27 => 8,
+        info => err_at!(Corrupted, msg: "unsupported cbor info {}", info)?,
+    };
+    let mut buf = [0_u8; 8];
+    err_at!(IOError, r.read_exact(&mut buf[8 - n..]))?;
+    data.extend_from_slice(&buf[8 - n..]);
+    match major {
+        // simple values and floats, argument is not a length.
+        7 => Ok((major, 0)),
+        _ => Ok((major, u64::from_be_bytes(buf))),
+    }
+}
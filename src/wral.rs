@@ -3,20 +3,60 @@
 //! Entries are added to `Wal` journal. Journals automatically rotate
 //! and are numbered from ZERO.
 
+#[cfg(any(test, feature = "testing"))]
 use arbitrary::{Arbitrary, Unstructured};
-use log::debug;
+use log::{debug, warn};
 use mkit::{self, thread};
 
 use std::{
-    ffi, fs, mem, ops, path,
-    sync::{Arc, RwLock},
-    vec,
+    collections::BTreeMap,
+    ffi, fs, io, mem, ops, path,
+    sync::{
+        atomic::{AtomicU64, Ordering::SeqCst},
+        mpsc, Arc, Mutex, RwLock,
+    },
+    time, vec,
 };
 
-use crate::{entry, journal, journal::Journal, state, writer, Error, Result};
+use crate::{
+    batch::Codec,
+    buffered::BufferedWriter,
+    checkpoint::Checkpoint,
+    clock::Clock,
+    cursor, diff, entry,
+    entry::EntryBuilder,
+    event::{Events, WalEvent},
+    export,
+    export::OpEncoding,
+    files, fsck, journal,
+    journal::{EmptyPolicy, Journal, ThawPolicy},
+    lsn::Lsn,
+    maintenance,
+    manifest::{ConfigVersion, Epoch, Manifest, Savepoint},
+    middleware, migrate,
+    quota::Quota,
+    receipt,
+    receipt::Receipt,
+    registry,
+    scoped::ScopedWriter,
+    signal::ShutdownSignal,
+    spawner::{self, Spawner},
+    split, state,
+    state::StatePolicy,
+    storage,
+    storage::{Backend, FsyncInterval, MirrorPolicy, Placement, SpacePolicy},
+    tombstone::Tombstone,
+    util, writer, Error, Result,
+};
 
 /// Default journal file limit is set at 1GB.
 pub const JOURNAL_LIMIT: usize = 1024 * 1024 * 1024;
+/// Default slack allowed beyond journal limit, refer to
+/// [Config::set_journal_tolerance].
+pub const JOURNAL_TOLERANCE: usize = 0;
+/// Default number of digits in journal file names, refer to
+/// [Config::set_journal_width].
+pub const JOURNAL_WIDTH: usize = 3;
 /// Default channel buffer for writer thread.
 pub const SYNC_BUFFER: usize = 1024;
 
@@ -27,13 +67,112 @@ pub struct Config {
     pub name: String,
     /// Directory in which wral journals are stored.
     pub dir: ffi::OsString,
+    /// Directories to place journals across, default is empty, in which
+    /// case journals are placed in `dir`.
+    pub dirs: Vec<ffi::OsString>,
+    /// Placement of journals across `dirs`.
+    pub placement: Placement,
     /// Define file-size limit for a single journal file, beyond with
     /// journal files are rotated.
     pub journal_limit: usize,
+    /// Slack, in bytes, allowed beyond `journal_limit` before a batch is
+    /// split across journal files.
+    pub journal_tolerance: usize,
     /// Enable fsync for every flush.
     pub fsync: bool,
+    /// Fsync periodically, instead of for every batch, default is None.
+    pub fsync_interval: Option<FsyncInterval>,
+    /// Maximum number of ops, from a single [Wal] handle, that can be
+    /// group-committed in a single batch. Remaining ops are deferred to
+    /// subsequent batches, so that a chatty handle does not starve the
+    /// other handles. Default is unlimited.
+    pub client_batch_limit: usize,
+    /// Acknowledge requests from a [Wal] handle strictly in the order they
+    /// were received, default is false.
+    pub ordered_acks: bool,
+    /// Maximum number of requests drained into a single batch, default is
+    /// unlimited.
+    pub max_batch_requests: usize,
+    /// Maximum number of entries in a batch, loaded from journal files,
+    /// beyond which the batch is treated as corrupted. Default is
+    /// unlimited.
+    pub max_batch_entries: usize,
+    /// Storage backend for journal files, default is [Backend::Std].
+    pub backend: Backend,
+    /// Directory to mirror journal files, ideally on a different device.
+    pub mirror_dir: Option<ffi::OsString>,
+    /// Policy when writing to the mirror fails.
+    pub mirror_policy: MirrorPolicy,
+    /// Policy when space is low for the next journal, default is
+    /// [SpacePolicy::Warn].
+    pub space_policy: SpacePolicy,
+    /// Bytes to keep free on the file system, beyond the journal limit,
+    /// when checking for space, default is ZERO.
+    pub space_reserve: u64,
+    /// Encoding of entries in batches, default is [Codec::Cbor].
+    pub codec: Codec,
+    /// Policy when application state in archived journal cannot be
+    /// decoded, default is [StatePolicy::Discard].
+    pub state_policy: StatePolicy,
+    /// Policy for reads touching a cold journal, default is
+    /// [ThawPolicy::Manual].
+    pub thaw_policy: ThawPolicy,
+    /// Policy for journal files without batches, found on load, default is
+    /// [EmptyPolicy::Keep].
+    pub empty_policy: EmptyPolicy,
+    /// Persist tag index along with each batch, default is false.
+    pub tag_index: bool,
+    /// Secondary codec to verify batches against, default is None.
+    pub shadow_codec: Option<Codec>,
+    /// Number of batches, from the tail of the log, to decode and cache
+    /// on load, default is ZERO.
+    pub preload_batches: usize,
+    /// Latency target for adaptive group commit, default is None.
+    pub commit_latency: Option<time::Duration>,
+    /// Ops larger than this many bytes are spilled into blob file,
+    /// default is None.
+    pub blob_threshold: Option<usize>,
+    /// Ops larger than this many bytes are compressed, default is None.
+    pub compress_threshold: Option<usize>,
+    /// Bytes of ops, pending in a batch, held in memory beyond which
+    /// entries are spooled to file, default is None.
+    pub buffer_limit: Option<usize>,
+    /// Chain of middleware ids, applied over the payload of each batch,
+    /// default is empty.
+    pub middleware: Vec<u32>,
+    /// Write a trailer, with checksum over entries, to journals on
+    /// rotation, default is false.
+    pub journal_trailer: bool,
+    /// Minimum number of digits in journal file names, default is
+    /// [JOURNAL_WIDTH].
+    pub journal_width: usize,
+    /// Interval for heartbeat batches while the writer is idle, default
+    /// is None.
+    pub heartbeat: Option<time::Duration>,
+    /// Interval after which state is checkpointed, default is None.
+    pub checkpoint_interval: Option<time::Duration>,
+    /// Number of batches after which state is checkpointed, default is
+    /// None.
+    pub checkpoint_batches: Option<usize>,
+    /// Quota for each identity, default is empty.
+    pub quotas: BTreeMap<String, Quota>,
+    /// Duration the writer shall be idle before running background
+    /// maintenance, default is None.
+    pub maintenance: Option<time::Duration>,
+    /// Warn when serialized state exceeds this many bytes, default is None.
+    pub state_size_limit: Option<usize>,
+    /// Warn when serialized state exceeds this percentage of the batch it
+    /// is written in, default is None.
+    pub state_size_percent: Option<u8>,
+    /// Source of timestamps and instance ids, default is the system clock.
+    pub clock: Clock,
+    /// Thread factory for the writer thread, default spawns an OS thread.
+    pub spawner: Spawner,
+    /// Purge journals acknowledged by all consumers, default is false.
+    pub consumer_retention: bool,
 }
 
+#[cfg(any(test, feature = "testing"))]
 impl Arbitrary for Config {
     fn arbitrary(u: &mut Unstructured) -> arbitrary::Result<Self> {
         let name: String = u.arbitrary()?;
@@ -41,8 +180,56 @@ impl Arbitrary for Config {
 
         let journal_limit = *u.choose(&[100, 1000, 10_000, 1_000_000])?;
         let fsync: bool = u.arbitrary()?;
+        let client_batch_limit = *u.choose(&[1, 10, 100, usize::MAX])?;
 
-        let config = Config { name, dir, journal_limit, fsync };
+        let config = Config {
+            name,
+            dir,
+            dirs: Vec::default(),
+            placement: Placement::default(),
+            journal_limit,
+            journal_tolerance: JOURNAL_TOLERANCE,
+            fsync,
+            fsync_interval: *u.choose(&[None, Some(FsyncInterval::Batches(10))])?,
+            client_batch_limit,
+            ordered_acks: u.arbitrary()?,
+            max_batch_requests: *u.choose(&[1, 10, usize::MAX])?,
+            max_batch_entries: usize::MAX,
+            backend: Backend::default(),
+            mirror_dir: None,
+            mirror_policy: MirrorPolicy::default(),
+            space_policy: SpacePolicy::default(),
+            space_reserve: 0,
+            codec: *u.choose(&[Codec::Cbor, Codec::Compact])?,
+            state_policy: StatePolicy::default(),
+            thaw_policy: *u.choose(&[ThawPolicy::Manual, ThawPolicy::Auto])?,
+            empty_policy: *u.choose(&[EmptyPolicy::Keep, EmptyPolicy::Remove])?,
+            tag_index: u.arbitrary()?,
+            shadow_codec: None,
+            preload_batches: *u.choose(&[0, 1, 100])?,
+            commit_latency: None,
+            blob_threshold: *u.choose(&[None, Some(0), Some(100)])?,
+            compress_threshold: *u.choose(&[None, Some(0), Some(100)])?,
+            buffer_limit: *u.choose(&[None, Some(0), Some(1000)])?,
+            middleware: match u.arbitrary()? {
+                true => {
+                    vec![middleware::MIDDLEWARE_COMPRESS, middleware::MIDDLEWARE_CHECKSUM]
+                }
+                false => Vec::default(),
+            },
+            journal_trailer: u.arbitrary()?,
+            journal_width: *u.choose(&[0, JOURNAL_WIDTH, 20])?,
+            heartbeat: None,
+            checkpoint_interval: None,
+            checkpoint_batches: *u.choose(&[None, Some(1), Some(10)])?,
+            quotas: BTreeMap::default(),
+            maintenance: None,
+            state_size_limit: *u.choose(&[None, Some(1000)])?,
+            state_size_percent: *u.choose(&[None, Some(50)])?,
+            clock: Clock::default(),
+            spawner: Spawner::default(),
+            consumer_retention: u.arbitrary()?,
+        };
         Ok(config)
     }
 }
@@ -52,86 +239,976 @@ impl Config {
         Config {
             name: name.to_string(),
             dir: dir.to_os_string(),
+            dirs: Vec::default(),
+            placement: Placement::default(),
             journal_limit: JOURNAL_LIMIT,
+            journal_tolerance: JOURNAL_TOLERANCE,
             fsync: true,
+            fsync_interval: None,
+            client_batch_limit: usize::MAX,
+            ordered_acks: false,
+            max_batch_requests: usize::MAX,
+            max_batch_entries: usize::MAX,
+            backend: Backend::default(),
+            mirror_dir: None,
+            mirror_policy: MirrorPolicy::default(),
+            space_policy: SpacePolicy::default(),
+            space_reserve: 0,
+            codec: Codec::default(),
+            state_policy: StatePolicy::default(),
+            thaw_policy: ThawPolicy::default(),
+            empty_policy: EmptyPolicy::default(),
+            tag_index: false,
+            shadow_codec: None,
+            preload_batches: 0,
+            commit_latency: None,
+            blob_threshold: None,
+            compress_threshold: None,
+            buffer_limit: None,
+            middleware: Vec::default(),
+            journal_trailer: false,
+            journal_width: JOURNAL_WIDTH,
+            heartbeat: None,
+            checkpoint_interval: None,
+            checkpoint_batches: None,
+            quotas: BTreeMap::default(),
+            maintenance: None,
+            state_size_limit: None,
+            state_size_percent: None,
+            clock: Clock::default(),
+            spawner: Spawner::default(),
+            consumer_retention: false,
         }
     }
 
+    /// Stripe journals across `dirs`, say on different mount points, for
+    /// aggregate throughput and for logs larger than a single volume.
+    /// `placement` picks the directory for each new journal. Manifest and
+    /// other files stay under [Config::dir], journals already under it
+    /// continue to be read and purged as usual.
+    pub fn set_dirs(&mut self, dirs: &[&ffi::OsStr], placement: Placement) -> &mut Self {
+        self.dirs = dirs.iter().map(|dir| dir.to_os_string()).collect();
+        self.placement = placement;
+        self
+    }
+
     pub fn set_journal_limit(&mut self, journal_limit: usize) -> &mut Self {
         self.journal_limit = journal_limit;
         self
     }
 
+    /// Entries group-committed together are split into multiple batches,
+    /// and rotated into a new journal mid-group, when writing them as a
+    /// single batch would grow the journal beyond `journal_limit` plus
+    /// `tolerance` bytes. A journal can still grow beyond, if it holds a
+    /// single entry larger than the limit.
+    pub fn set_journal_tolerance(&mut self, tolerance: usize) -> &mut Self {
+        self.journal_tolerance = tolerance;
+        self
+    }
+
     pub fn set_fsync(&mut self, fsync: bool) -> &mut Self {
         self.fsync = fsync;
         self
     }
+
+    /// Fsync the active journal periodically, as per `interval`, rather
+    /// than for every batch, when full per-batch durability is not
+    /// required. Ops are acknowledged once their batch is written, and
+    /// the durable watermark moves only when batches are fsynced, use
+    /// [Wal::wait_for] or [Wal::sync] to wait for durability. Journals
+    /// are fsynced before rotation and on close, default is None.
+    pub fn set_fsync_interval(&mut self, interval: Option<FsyncInterval>) -> &mut Self {
+        self.fsync_interval = interval;
+        self
+    }
+
+    /// Deferring ops beyond the limit never reorders a handle's requests,
+    /// requests from the same handle are assigned seqnos in the order they
+    /// were received, even when they span multiple batches.
+    pub fn set_client_batch_limit(&mut self, limit: usize) -> &mut Self {
+        self.client_batch_limit = limit;
+        self
+    }
+
+    /// Acknowledge requests in the order the writer received them. By
+    /// default, submitted requests, refer to [Wal::submit_ops], are
+    /// acknowledged ahead of the other requests in their batch, so that
+    /// they are woken up together by the durable watermark. With `ordered`
+    /// set, they are acknowledged along with the other requests, in order,
+    /// at the cost of an extra wake-up per batch. Default is false.
+    pub fn set_ordered_acks(&mut self, ordered: bool) -> &mut Self {
+        self.ordered_acks = ordered;
+        self
+    }
+
+    /// Cap the number of requests, from all handles, drained into a single
+    /// batch, ZERO is treated as 1. Requests beyond the cap stay queued, in
+    /// order, for subsequent batches. Without the cap, requests piled up
+    /// during a slow flush are all committed in one large batch, delaying
+    /// every waiter till it is flushed. Refer to [Stats::queue_time] to
+    /// observe how long requests wait.
+    pub fn set_max_batch_requests(&mut self, max: usize) -> &mut Self {
+        self.max_batch_requests = max;
+        self
+    }
+
+    /// Cap the number of entries in a batch, as loaded from journal files.
+    /// Journals carrying a batch with more entries are treated as
+    /// corrupted, failing the load, so that a damaged or crafted journal
+    /// doesn't exhaust memory. Length fields in journal files are always
+    /// validated against the file size. The writer does not split batches
+    /// on this cap, it shall be larger than the ops committed in a single
+    /// batch.
+    pub fn set_max_batch_entries(&mut self, max: usize) -> &mut Self {
+        self.max_batch_entries = max;
+        self
+    }
+
+    /// Set the storage backend for journal files. Only the active journal
+    /// is written through the backend, archived journals are always read
+    /// using std::fs.
+    pub fn set_backend(&mut self, backend: Backend) -> &mut Self {
+        self.backend = backend;
+        self
+    }
+
+    /// Mirror journal files under `dir`. Every batch is written and synced
+    /// to both directories before it is acknowledged. When writing to the
+    /// mirror fails, `policy` decides whether to fail the batch or to
+    /// continue with the primary alone. Readers only read from primary.
+    pub fn set_mirror(&mut self, dir: &ffi::OsStr, policy: MirrorPolicy) -> &mut Self {
+        self.mirror_dir = Some(dir.to_os_string());
+        self.mirror_policy = policy;
+        self
+    }
+
+    /// Check the space available on the file system before creating the
+    /// next journal, on rotation. Space is low when less than
+    /// `journal_limit + reserve` bytes are available, `policy` decides
+    /// whether to warn and rotate, or to refuse rotation, rather than
+    /// running out of space halfway through a batch.
+    pub fn set_space_policy(&mut self, policy: SpacePolicy, reserve: u64) -> &mut Self {
+        self.space_policy = policy;
+        self.space_reserve = reserve;
+        self
+    }
+
+    /// Set the encoding for entries in batches. Only affects batches
+    /// written subsequently, journals can carry batches in either encoding.
+    pub fn set_codec(&mut self, codec: Codec) -> &mut Self {
+        self.codec = codec;
+        self
+    }
+
+    /// Set the policy for archived journals whose application state can't
+    /// be decoded, say after a change to the state's schema.
+    pub fn set_state_policy(&mut self, policy: StatePolicy) -> &mut Self {
+        self.state_policy = policy;
+        self
+    }
+
+    /// Set the policy for reads touching journals frozen by [Wal::freeze],
+    /// with [ThawPolicy::Auto] they are thawed before reading.
+    pub fn set_thaw_policy(&mut self, policy: ThawPolicy) -> &mut Self {
+        self.thaw_policy = policy;
+        self
+    }
+
+    /// Set the policy for journal files without batches, found while
+    /// loading the instance. Such files are never treated as corrupted.
+    pub fn set_empty_policy(&mut self, policy: EmptyPolicy) -> &mut Self {
+        self.empty_policy = policy;
+        self
+    }
+
+    /// Persist, along with each batch, the offset of tagged entries within
+    /// the batch, so that [Wal::find_by_tag] can read them without decoding
+    /// the entire batch. Tagged entries are located using an in-memory
+    /// index in either case. Only applies to [Codec::Cbor].
+    pub fn set_tag_index(&mut self, tag_index: bool) -> &mut Self {
+        self.tag_index = tag_index;
+        self
+    }
+
+    /// Shadow mode, for migrating to a new codec. Every batch is written
+    /// with [Config::codec] as usual, and is also encoded with `codec` and
+    /// verified to decode to identical entries. Mismatches are logged and
+    /// reported as [WalEvent::ShadowMismatch], batches are written all the
+    /// same.
+    pub fn set_shadow_codec(&mut self, codec: Option<Codec>) -> &mut Self {
+        self.shadow_codec = codec;
+        self
+    }
+
+    /// Decode and cache `n` batches from the tail of the log while loading
+    /// the instance. Recovery reads mostly target the tail, preloading them
+    /// cuts the latency of the first range queries after restart. Older
+    /// batches are read from disk as usual.
+    pub fn set_preload_batches(&mut self, n: usize) -> &mut Self {
+        self.preload_batches = n;
+        self
+    }
+
+    /// Adaptive group commit. Writer measures the time taken to flush
+    /// batches, and waits upto that long to accumulate requests before
+    /// forming the next batch, such that an op is acknowledged within
+    /// `max_latency`, as long as flushes are faster than half of it. Without
+    /// this, batches are formed as soon as the writer is free.
+    pub fn set_adaptive_commit(
+        &mut self,
+        max_latency: Option<time::Duration>,
+    ) -> &mut Self {
+        self.commit_latency = max_latency;
+        self
+    }
+
+    /// Spill ops larger than `threshold` bytes into a blob file, kept next
+    /// to the journal file as `{journal-file}.blob`. Journal logs a
+    /// checksummed reference in place of the op, keeping batches small,
+    /// and readers transparently replace it with the op. Blob files are
+    /// not mirrored.
+    pub fn set_blob_spill(&mut self, threshold: Option<usize>) -> &mut Self {
+        self.blob_threshold = threshold;
+        self
+    }
+
+    /// Compress ops larger than `threshold` bytes, individually, flagging
+    /// them in their entry. Smaller ops, and ops that don't shrink, are
+    /// written as is, keeping their fast path. Large ops are compressed
+    /// before they are spilled, refer to [Config::set_blob_spill].
+    /// Readers transparently decompress them.
+    pub fn set_entry_compression(&mut self, threshold: Option<usize>) -> &mut Self {
+        self.compress_threshold = threshold;
+        self
+    }
+
+    /// Hold upto `limit` bytes of ops, pending in a batch, in memory. Say
+    /// a flood of ops arrives while the writer is stalled on fsync, ops
+    /// beyond the limit are spooled to a file next to the journal file,
+    /// as `{journal-file}.spool`, and stitched back into the same batch,
+    /// in order, when it is flushed. Batches stay atomic irrespective of
+    /// spooling. Default is None, all pending ops are held in memory.
+    pub fn set_buffer_limit(&mut self, limit: Option<usize>) -> &mut Self {
+        self.buffer_limit = limit;
+        self
+    }
+
+    /// Seal the payload of each batch, that is, its encoded entries, with
+    /// the chain of middleware `ids`, applied in order on write and in the
+    /// reverse order on read. Say, compress and then encrypt, or encrypt
+    /// and then checksum, as the compliance regime requires. Middleware
+    /// are registered using [register_middleware][crate::register_middleware],
+    /// and ids of the chain are recorded in each batch, hence the chain
+    /// can be changed across restarts. Sealed batches are decoded whole,
+    /// tag offsets are not persisted for them, refer to
+    /// [Config::set_tag_index]. Default is empty, batches are not sealed.
+    pub fn set_middleware(&mut self, ids: Vec<u32>) -> &mut Self {
+        self.middleware = ids;
+        self
+    }
+
+    /// Write a trailer to each journal when it is rotated, carrying the
+    /// number of entries in the journal and a rolling CRC-32 over their
+    /// seqnos and ops, computed as entries are flushed. Verification, refer
+    /// to [Wal::verify_journal], checks entries end-to-end against the
+    /// trailer, even when batches are not checksummed, refer to
+    /// [Config::set_middleware]. Journal active at close, or crash, is
+    /// left without a trailer. Default is false.
+    pub fn set_journal_trailer(&mut self, trailer: bool) -> &mut Self {
+        self.journal_trailer = trailer;
+        self
+    }
+
+    /// Zero pad journal numbers in file names to `width` digits, ZERO
+    /// for no padding. Journal numbers beyond `width` digits are still
+    /// named and ordered correctly, a wider width keeps the file names in
+    /// lexical order for external tools. Existing journals, of any
+    /// width, are renamed when the instance is loaded for writing.
+    pub fn set_journal_width(&mut self, width: usize) -> &mut Self {
+        self.journal_width = width;
+        self
+    }
+
+    /// Write a batch without entries, carrying the application state and
+    /// the time of writing, every `interval` the writer is idle. Followers
+    /// tailing the log can tell an idle writer from a dead one using
+    /// [Wal::last_batch_time], and the latest journal always carries a
+    /// recent state checkpoint. Without this, nothing is written while
+    /// there are no ops.
+    pub fn set_heartbeat(&mut self, interval: Option<time::Duration>) -> &mut Self {
+        self.heartbeat = interval;
+        self
+    }
+
+    /// Run background maintenance, on a thread owned by the instance, once
+    /// no ops are appended for `idle` duration. Maintenance pauses as soon
+    /// as ops are appended, so that it never competes with commits, and
+    /// resumes on the next idle period. Archived journals are scrubbed,
+    /// refer to [WalEvent::Scrubbed]. Default is None, no maintenance.
+    pub fn set_maintenance(&mut self, idle: Option<time::Duration>) -> &mut Self {
+        self.maintenance = idle;
+        self
+    }
+
+    /// Application state is serialized into every batch, warn when state
+    /// written in a batch exceeds `limit` bytes, or `percent` of the batch
+    /// size, refer to [WalEvent::StateOversize]. Either can be None.
+    /// Default is None for both, no warning.
+    pub fn set_state_size_warning(
+        &mut self,
+        limit: Option<usize>,
+        percent: Option<u8>,
+    ) -> &mut Self {
+        self.state_size_limit = limit;
+        self.state_size_percent = percent;
+        self
+    }
+
+    /// Set the source of timestamps and instance ids. With the `testing`
+    /// feature, use a fixed clock, refer to [Clock], for journals to be
+    /// byte-identical across runs, given the same sequence of operations.
+    pub fn set_clock(&mut self, clock: Clock) -> &mut Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Set the thread factory for the writer thread, so that embedders can
+    /// control its name, stack size and panic handling, or run it under
+    /// their supervision. Refer to [Spawner].
+    pub fn set_spawner(&mut self, spawner: Spawner) -> &mut Self {
+        self.spawner = spawner;
+        self
+    }
+
+    /// Checkpoint the application state to `dir/{name}.state`, once
+    /// `interval` has elapsed or `batches` are written since the last
+    /// checkpoint, whichever is earlier, and when the instance is closed.
+    /// Checkpoints are written by the writer, after the batch is durable,
+    /// and atomically replace the older one. On load, the checkpoint is
+    /// preferred over the state carried by the last batch when it is as
+    /// new or newer, within the same epoch, say when the state in the last
+    /// batch fails to decode. Default is None for both, no checkpoints.
+    pub fn set_state_checkpoint(
+        &mut self,
+        interval: Option<time::Duration>,
+        batches: Option<usize>,
+    ) -> &mut Self {
+        self.checkpoint_interval = interval;
+        self.checkpoint_batches = batches;
+        self
+    }
+
+    /// Enforce `quota` on ops added by handles labeled as `identity`,
+    /// refer to [Wal::labeled_clone]. Requests exceeding the quota fail
+    /// with [Error::OverQuota], without being assigned seqnos. Quota is
+    /// accounted per instance, in memory, and starts afresh every time the
+    /// instance is opened.
+    pub fn set_quota(&mut self, identity: &str, quota: Quota) -> &mut Self {
+        self.quotas.insert(identity.to_string(), quota);
+        self
+    }
+
+    /// Tie retention to consumer progress. Every time a consumer
+    /// acknowledges, journals whose entries are acknowledged by all
+    /// consumers are purged, refer to [Wal::ack]. Nothing is purged while
+    /// there are no consumers, or while any of them is behind the current
+    /// epoch. Default is false, journals are purged only by
+    /// [Wal::purge_till].
+    pub fn set_consumer_retention(&mut self, enable: bool) -> &mut Self {
+        self.consumer_retention = enable;
+        self
+    }
+
+    // Return directories that can hold journals, `dir` first.
+    pub(crate) fn to_journal_dirs(&self) -> Vec<ffi::OsString> {
+        let mut dirs = vec![self.dir.clone()];
+        for dir in self.dirs.iter() {
+            if !dirs.contains(dir) {
+                dirs.push(dir.clone())
+            }
+        }
+        dirs
+    }
+
+    // Return the directory for a new journal `num`, as per placement.
+    pub(crate) fn to_journal_dir(&self, num: usize) -> Result<ffi::OsString> {
+        let n = self.dirs.len();
+        let dir = match &self.placement {
+            _ if n == 0 => self.dir.clone(),
+            Placement::RoundRobin => self.dirs[num % n].clone(),
+            Placement::Capacity(caps) if caps.len() != n => {
+                err_at!(Invalid, msg: "{} capacities for {} dirs", caps.len(), n)?
+            }
+            Placement::Capacity(caps) => {
+                let mut used = vec![0_u64; n];
+                for (i, dir) in self.dirs.iter().enumerate() {
+                    let dirs = [dir.clone()];
+                    for (_, file_path) in files::find_journals(&dirs, &self.name)? {
+                        used[i] += err_at!(IOError, fs::metadata(&file_path))?.len();
+                    }
+                }
+                let mut off = 0;
+                for i in 1..n {
+                    if caps[i].saturating_sub(used[i])
+                        > caps[off].saturating_sub(used[off])
+                    {
+                        off = i;
+                    }
+                }
+                self.dirs[off].clone()
+            }
+        };
+        Ok(dir)
+    }
+
+    pub(crate) fn to_storage_options(&self) -> storage::Options {
+        storage::Options {
+            backend: self.backend,
+            codec: self.codec,
+            tag_index: self.tag_index,
+            shadow: self.shadow_codec,
+            blob_threshold: self.blob_threshold,
+            compress_threshold: self.compress_threshold,
+            buffer_limit: self.buffer_limit,
+            defer_sync: self.fsync_interval.is_some(),
+            trailer: self.journal_trailer,
+            middleware: self.middleware.clone(),
+            journal_width: self.journal_width,
+            mirror: self.mirror_dir.clone(),
+            policy: self.mirror_policy,
+            clock: self.clock.clone(),
+        }
+    }
+
+    // Version of this configuration, to record in manifest. Location of the
+    // manifest, `dir`, and clock are not recorded.
+    pub(crate) fn to_config_version(&self) -> ConfigVersion {
+        let settings = vec![
+            ("name", format!("{:?}", self.name)),
+            ("dirs", format!("{:?}", self.dirs)),
+            ("placement", format!("{:?}", self.placement)),
+            ("journal_limit", format!("{:?}", self.journal_limit)),
+            ("journal_tolerance", format!("{:?}", self.journal_tolerance)),
+            ("fsync", format!("{:?}", self.fsync)),
+            ("fsync_interval", format!("{:?}", self.fsync_interval)),
+            ("client_batch_limit", format!("{:?}", self.client_batch_limit)),
+            ("ordered_acks", format!("{:?}", self.ordered_acks)),
+            ("max_batch_requests", format!("{:?}", self.max_batch_requests)),
+            ("max_batch_entries", format!("{:?}", self.max_batch_entries)),
+            ("backend", format!("{:?}", self.backend)),
+            ("mirror_dir", format!("{:?}", self.mirror_dir)),
+            ("mirror_policy", format!("{:?}", self.mirror_policy)),
+            ("space_policy", format!("{:?}", self.space_policy)),
+            ("space_reserve", format!("{:?}", self.space_reserve)),
+            ("codec", format!("{:?}", self.codec)),
+            ("state_policy", format!("{:?}", self.state_policy)),
+            ("thaw_policy", format!("{:?}", self.thaw_policy)),
+            ("empty_policy", format!("{:?}", self.empty_policy)),
+            ("tag_index", format!("{:?}", self.tag_index)),
+            ("shadow_codec", format!("{:?}", self.shadow_codec)),
+            ("preload_batches", format!("{:?}", self.preload_batches)),
+            ("commit_latency", format!("{:?}", self.commit_latency)),
+            ("blob_threshold", format!("{:?}", self.blob_threshold)),
+            ("compress_threshold", format!("{:?}", self.compress_threshold)),
+            ("buffer_limit", format!("{:?}", self.buffer_limit)),
+            ("middleware", format!("{:?}", self.middleware)),
+            ("journal_trailer", format!("{:?}", self.journal_trailer)),
+            ("journal_width", format!("{:?}", self.journal_width)),
+            ("heartbeat", format!("{:?}", self.heartbeat)),
+            ("checkpoint_interval", format!("{:?}", self.checkpoint_interval)),
+            ("checkpoint_batches", format!("{:?}", self.checkpoint_batches)),
+            ("quotas", format!("{:?}", self.quotas)),
+            ("maintenance", format!("{:?}", self.maintenance)),
+            ("state_size_limit", format!("{:?}", self.state_size_limit)),
+            ("state_size_percent", format!("{:?}", self.state_size_percent)),
+            ("consumer_retention", format!("{:?}", self.consumer_retention)),
+        ];
+        let settings = settings.into_iter().map(|(k, v)| (k.to_string(), v)).collect();
+        ConfigVersion::new(self.clock.to_nanos(), settings)
+    }
+}
+
+/// Mode for opening a [Wal] instance, refer to [Wal::open].
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum OpenMode {
+    /// Create a new instance, fail with [Error::AlreadyExists] if an
+    /// instance with the same name exists under `dir`.
+    CreateNew,
+    /// Load the instance if it exists, else create a new one.
+    CreateOrLoad,
+    /// Load an existing instance, fail with [Error::NotFound] if there is
+    /// none. Loading always starts a new journal.
+    LoadOnly,
+    /// Open an existing instance for reading alone, nothing is written to
+    /// disk. Fail with [Error::NotFound] if there is none. Write operations
+    /// on the instance fail with [Error::ReadOnly].
+    ReadOnly,
+    /// Same as [OpenMode::ReadOnly], while a writer from another process
+    /// is live on the same instance. Only batches that are completely
+    /// written by the writer are visible, and [Wal::refresh] can be used to
+    /// observe batches written since open. Meant for sidecar processes,
+    /// like backup agents and indexers, to read the log without IPC to the
+    /// writer process.
+    Attach,
+}
+
+/// Outcome of closing a [Wal] handle, refer to [Wal::close].
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum CloseOutcome {
+    /// Instance is closed, with the final seqno.
+    Closed(u64),
+    /// Instance is closed and its journals and manifest are removed, with
+    /// the final seqno.
+    Purged(u64),
+    /// Only this handle is dropped, the instance is kept open for other
+    /// handles, and is closed along with the last of them.
+    Deferred { remaining_clones: usize },
+}
+
+/// Statistics for a [Wal] instance, refer to [Wal::stats].
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Stats {
+    /// Name of the instance.
+    pub name: String,
+    /// Directory in which journals are stored.
+    pub dir: ffi::OsString,
+    /// Current seqno epoch.
+    pub epoch: u64,
+    /// Last seqno handed out in the current epoch.
+    pub last_seqno: u64,
+    /// Seqno upto which entries are flushed to disk.
+    pub durable_seqno: u64,
+    /// Number of journals, including the active journal.
+    pub n_journals: usize,
+    /// Number of batches flushed across all journals.
+    pub n_batches: usize,
+    /// Number of purges recorded.
+    pub n_purges: usize,
+    /// Number of requests pending, when the writer formed the last batch.
+    pub queue_depth: usize,
+    /// Moving average of time requests, adding entries, wait before they
+    /// are formed into a batch.
+    pub queue_time: time::Duration,
+    /// Longest time a request waited, among requests in the last batch.
+    pub max_queue_time: time::Duration,
+    /// Average number of entries committed per flush.
+    pub avg_batch_size: usize,
+    /// Average time between flushes that committed entries. Every flush
+    /// is synced to disk.
+    pub avg_sync_interval: time::Duration,
+    /// Moving average of time taken to flush a batch.
+    pub fsync_latency: time::Duration,
+    /// Window to accumulate requests for the next batch, refer to
+    /// [Config::set_adaptive_commit].
+    pub commit_window: time::Duration,
+    /// Histogram of op sizes committed since the instance was opened.
+    /// Bucket ZERO counts empty ops, and bucket `i` counts ops of
+    /// `2^(i-1)..2^i` bytes. Trailing empty buckets are omitted.
+    pub op_sizes: Vec<u64>,
+    /// Largest ops committed since the instance was opened, as
+    /// (seqno, size), largest first, upto 8 ops.
+    pub largest_ops: Vec<(u64, usize)>,
+    /// Serialized size of application state, as written in the last batch
+    /// since the instance was opened, ZERO if none.
+    pub state_size: usize,
+    /// Largest batch flushed since the instance was opened, in number of
+    /// entries.
+    pub batch_high_water: usize,
+    /// Capacity held for entries pending in the active journal, in number
+    /// of entries. Capacity grows to the largest batch and is shrunk back
+    /// once batches stay well below it.
+    pub pending_capacity: usize,
+    /// Journal rotations since the instance was opened.
+    pub rotations: Rotations,
+    /// Lag of consumers, furthest behind first, refer to [Wal::consumers].
+    pub consumers: Vec<ConsumerLag>,
+}
+
+/// Progress of a consumer, as acknowledged with [Wal::ack], and its lag
+/// behind the durable seqno.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct ConsumerLag {
+    /// Name of the consumer.
+    pub name: String,
+    /// Epoch and seqno upto which the consumer has acknowledged entries.
+    pub epoch: u64,
+    pub seqno: u64,
+    /// Durable seqnos, in the current epoch, beyond the acknowledged
+    /// seqno. Masked seqnos and gaps are counted as well.
+    pub entries: u64,
+    /// Bytes of batches, in the current epoch, holding entries beyond the
+    /// acknowledged seqno.
+    pub bytes: u64,
+}
+
+/// Cause of a journal rotation, refer to [WalEvent::JournalRotated].
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum RotateCause {
+    /// Journal reached the journal limit, either after a batch, or when
+    /// the next batch wouldn't fit within the limit plus tolerance, refer
+    /// to [Config::set_journal_tolerance].
+    SizeLimit,
+    /// Journal is rotated on demand, irrespective of its size, like on
+    /// [Wal::rebase].
+    Forced,
+}
+
+/// Journal rotations, by cause, and the fill of rotated journals, refer
+/// to [Stats::rotations]. Journals rotated on size limit, with fill well
+/// below 100%, suggest batches are large for the journal limit, or that
+/// tolerance is too small. Mostly [RotateCause::Forced] rotations
+/// suggest the journal limit is seldom reached.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Rotations {
+    /// Number of rotations due to [RotateCause::SizeLimit].
+    pub size_limit: usize,
+    /// Number of rotations due to [RotateCause::Forced].
+    pub forced: usize,
+    /// Smallest, average and largest size of rotated journals, as a
+    /// percentage of the journal limit, ZERO if there was no rotation.
+    pub min_fill: usize,
+    pub avg_fill: usize,
+    pub max_fill: usize,
+}
+
+impl Rotations {
+    /// Return the total number of rotations.
+    pub fn len(&self) -> usize {
+        self.size_limit + self.forced
+    }
+
+    /// Return whether there was no rotation.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// State of the background writer, refer to [Health].
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub enum HealthState {
+    /// Writer is processing requests, and its last flush succeeded.
+    #[default]
+    Running,
+    /// Writer is processing requests, but its last flush failed and the
+    /// affected requests were failed.
+    Degraded,
+    /// Writer has exited with a fatal error, or has panicked, all
+    /// subsequent requests shall fail.
+    Poisoned,
+    /// Next seqno is not beyond the seqnos retained on disk, say after an
+    /// older manifest or journal is restored, writes shall fail with
+    /// [Error::Fenced] until the instance is rebased, refer to
+    /// [Wal::rebase].
+    Fenced,
+    /// Instance is permanently read-only, writes shall fail with
+    /// [Error::Sealed], refer to [Wal::seal].
+    Sealed,
+}
+
+/// Health of a [Wal] instance, refer to [Wal::health].
+#[derive(Debug, Clone, Default)]
+pub struct Health {
+    /// State of the background writer.
+    pub state: HealthState,
+    /// Last error seen by the background writer, if any. Retained after
+    /// the writer recovers from [HealthState::Degraded].
+    pub last_error: Option<Error>,
+    /// Number of requests pending, when the writer formed the last batch.
+    pub queue_depth: usize,
+    /// Seqno upto which entries are flushed to disk.
+    pub durable_seqno: u64,
+}
+
+/// Seqno frontiers of a [Wal] instance, in the current epoch, refer to
+/// [Wal::watermarks]. At any point, `durable <= flushed <= appended`.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct Watermarks {
+    /// Latest seqno handed out to an entry. Entries upto this seqno are
+    /// accepted by the writer, and may not be on disk yet.
+    pub appended: u64,
+    /// Latest seqno written to a journal file, batches are synced as they
+    /// are written.
+    pub flushed: u64,
+    /// Latest seqno acknowledged to writers, entries upto this seqno
+    /// survive a crash and are visible to readers.
+    pub durable: u64,
+    /// Latest seqno purged by [Wal::purge_till], ZERO if none. Entries
+    /// upto this seqno are no longer on disk.
+    pub purged: u64,
+}
+
+/// Reclaimable space for a purge upto a seqno, refer to [Wal::reclaimable].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ReclaimReport {
+    /// Seqno upto which the purge is evaluated.
+    pub seqno: u64,
+    /// Journals that would be removed by the purge, oldest first.
+    pub journals: Vec<journal::JournalIndex>,
+    /// Bytes freed by removing `journals`.
+    pub bytes: u64,
+    /// First journal retained after the purge, the active journal if all
+    /// archived journals are removed.
+    pub boundary: usize,
+    /// Fraction of bytes in the `boundary` journal, holding entries upto
+    /// `seqno`, which can't be reclaimed by purge.
+    pub wasted: f64,
+}
+
+/// Outcome of recovering journals while loading an instance, refer to
+/// [Wal::recovery_report].
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct RecoveryReport {
+    /// Latest journal ended with a partially written batch, which was
+    /// truncated. Entries in the torn batch were not yet synced, hence
+    /// not acknowledged as durable.
+    pub torn_tail: Option<TornTail>,
+    /// Journals ignored because of corruption before their tail, or
+    /// because they couldn't be decoded at all. Entries in them are lost.
+    pub corrupted: Vec<ffi::OsString>,
+}
+
+impl RecoveryReport {
+    /// Return whether durable entries were lost while loading.
+    pub fn is_data_lost(&self) -> bool {
+        !self.corrupted.is_empty()
+    }
+}
+
+/// Partially written batch at the tail of a journal, refer to
+/// [RecoveryReport].
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct TornTail {
+    /// Journal number.
+    pub num: usize,
+    /// Journal file, truncated at `offset`.
+    pub file: ffi::OsString,
+    /// File position of the torn batch, batches before it are valid.
+    pub offset: u64,
+    /// Number of bytes truncated.
+    pub bytes: u64,
+}
+
+/// Cost of reading a range of entries, refer to [Wal::estimate].
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Estimate {
+    /// Bytes read from disk, batches are read as a whole.
+    pub bytes: u64,
+    /// Number of batches overlapping the range.
+    pub batches: usize,
+    /// Number of entries within the range, an upper bound if there are
+    /// gaps in seqnos, refer to [Wal::add_op_at].
+    pub entries: u64,
+}
+
+// Journals and manifest loaded from disk, refer to Wal::scan.
+struct Loaded<S> {
+    manifest: Manifest,
+    journals: Vec<Journal<S>>,
+    // next seqno, and next journal number.
+    seqno: u64,
+    num: usize,
+    state: S,
+    // whether journal spans in manifest have changed.
+    changed: bool,
+    report: RecoveryReport,
 }
 
 /// Write ahead logging.
+///
+/// Every clone of a Wal is a separate handle to the same instance.
+/// Requests from a handle, including those made concurrently from
+/// multiple threads, are assigned seqnos in the order the writer received
+/// them, irrespective of batch boundaries and of
+/// [Config::set_client_batch_limit]. Requests are acknowledged batch by
+/// batch, submitted requests ahead of the others in their batch, unless
+/// [Config::set_ordered_acks] is set, in which case every request,
+/// including a failed one, is acknowledged in the order it was received.
 pub struct Wal<S = state::NoState> {
     config: Config,
+    // identify this handle, every clone is a new client.
+    client: u64,
+    // identity of this handle for quota, empty if not labeled.
+    label: String,
+    read_only: bool,
+    // attached to a live writer from another process.
+    attach: bool,
+    clients: Arc<AtomicU64>,
 
-    tx: thread::Tx<writer::Req, writer::Res>,
-    t: Arc<RwLock<mkit::thread::Thread<writer::Req, writer::Res, Result<u64>>>>,
+    durable: Arc<writer::Watermark>,
+    frontiers: Arc<writer::Frontiers>,
+    health: Arc<Mutex<Health>>,
+    events: Arc<Events>,
+    signal: ShutdownSignal,
+    tx: writer::Tx,
+    t: Arc<RwLock<spawner::Handle<Result<u64>>>>,
     w: Arc<RwLock<writer::Writer<S>>>,
+    maintenance: Arc<Mutex<Option<maintenance::Scheduler>>>,
+    recovery: Arc<RecoveryReport>,
 }
 
 impl<S> Clone for Wal<S> {
     fn clone(&self) -> Wal<S> {
         Wal {
             config: self.config.clone(),
+            client: self.clients.fetch_add(1, SeqCst),
+            label: self.label.clone(),
+            read_only: self.read_only,
+            attach: self.attach,
+            clients: Arc::clone(&self.clients),
+            durable: Arc::clone(&self.durable),
+            frontiers: Arc::clone(&self.frontiers),
+            health: Arc::clone(&self.health),
+            events: Arc::clone(&self.events),
+            signal: self.signal.clone(),
 
             tx: self.tx.clone(),
             t: Arc::clone(&self.t),
             w: Arc::clone(&self.w),
+            maintenance: Arc::clone(&self.maintenance),
+            recovery: Arc::clone(&self.recovery),
         }
     }
 }
 
 impl<S> Wal<S> {
+    /// Open Write-Ahead-Log instance for `config`, as per `mode`.
+    pub fn open(config: Config, mode: OpenMode) -> Result<Wal<S>>
+    where
+        S: state::State,
+    {
+        let exists = Self::exists(&config)?;
+        let (dir, name) = (&config.dir, &config.name);
+        match mode {
+            OpenMode::CreateNew if exists => {
+                err_at!(AlreadyExists, msg: "wal {:?}/{}", dir, name)
+            }
+            OpenMode::LoadOnly | OpenMode::ReadOnly | OpenMode::Attach if !exists => {
+                err_at!(NotFound, msg: "wal {:?}/{}", dir, name)
+            }
+            OpenMode::CreateNew => Self::create(config, S::default()),
+            OpenMode::CreateOrLoad if !exists => Self::create(config, S::default()),
+            OpenMode::CreateOrLoad | OpenMode::LoadOnly => Self::do_load(config, mode),
+            OpenMode::ReadOnly | OpenMode::Attach => Self::do_load(config, mode),
+        }
+    }
+
+    // Instance exists if there is a manifest or a journal for its name.
+    fn exists(config: &Config) -> Result<bool> {
+        let manifest: path::PathBuf = {
+            let file = files::make_manifest_filename(&config.name);
+            [config.dir.clone(), file].iter().collect()
+        };
+        if manifest.exists() {
+            return Ok(true);
+        }
+        let journals = files::find_journals(&config.to_journal_dirs(), &config.name)?;
+        Ok(!journals.is_empty())
+    }
+
     /// Create a new Write-Ahead-Log instance, while create a new journal,
     /// older journals matching the `name` shall be purged.
     pub fn create(config: Config, state: S) -> Result<Wal<S>>
     where
         S: state::State,
     {
-        // try creating the directory, if it does not exist.
-        fs::create_dir_all(&config.dir).ok();
-
-        // purge existing journals for this shard.
-        for item in err_at!(IOError, fs::read_dir(&config.dir))? {
-            let file_path: path::PathBuf = {
-                let file_name = err_at!(IOError, item)?.file_name();
-                [config.dir.clone(), file_name.clone()].iter().collect()
-            };
-            match Journal::<S>::load_cold(&config.name, file_path.as_ref()) {
-                Some(journal) => match journal.purge() {
-                    Ok(_) => (),
-                    Err(err) => {
-                        debug!(target: "wral", "failed to purge {:?}, {}", file_path, err)
-                    }
-                },
-                None => continue,
-            };
+        middleware::validate(&config.middleware)?;
+        // try creating the directories, if they do not exist.
+        for dir in config.to_journal_dirs().iter() {
+            fs::create_dir_all(dir).ok();
+        }
+
+        if let Some(dir) = &config.mirror_dir {
+            fs::create_dir_all(dir).ok();
+        }
+
+        // purge existing journals for this shard, and their mirror copies.
+        let mut dirs = config.to_journal_dirs();
+        dirs.extend(config.mirror_dir.clone());
+        for dir in dirs.into_iter() {
+            for item in err_at!(IOError, fs::read_dir(&dir))? {
+                let file_path: path::PathBuf = {
+                    let file_name = err_at!(IOError, item)?.file_name();
+                    [dir.clone(), file_name.clone()].iter().collect()
+                };
+                match Journal::<S>::load_cold(&config.name, file_path.as_ref()) {
+                    Some(journal) => match journal.purge() {
+                        Ok(_) => (),
+                        Err(err) => {
+                            debug!(target: "wral", "failed to purge {:?}, {}", file_path, err)
+                        }
+                    },
+                    None => continue,
+                };
+            }
         }
+        Manifest::purge(&config.dir, &config.name)?;
+        Checkpoint::purge(&config.dir, &config.name)?;
+
+        let mut manifest = Manifest::new(&config.name);
+        manifest.set_instance(&config.clock.new_uuid());
+        manifest.add_config(config.to_config_version());
+        manifest.save(&config.dir)?;
 
         let num = 0;
-        let journal = Journal::start(&config.name, &config.dir, num, state)?;
+        let options = config.to_storage_options();
+        let dir = config.to_journal_dir(num)?;
+        let mut journal = Journal::start(&config.name, &dir, num, &options, state)?;
+        journal.set_instance(manifest.as_instance());
 
         debug!(target: "wral", "{:?}/{} created", &config.dir, &config.name);
 
+        let events = Arc::new(Events::new());
+        let file = journal.to_file_path();
+        events.emit(WalEvent::JournalCreated { num, file });
+
         let seqno = 1;
-        let (w, t, tx) = writer::Writer::start(config.clone(), vec![], journal, seqno);
+        let (w, t, tx) = {
+            let events = Arc::clone(&events);
+            writer::Writer::start(
+                config.clone(),
+                manifest,
+                vec![],
+                journal,
+                seqno,
+                events,
+            )?
+        };
 
-        let val = Wal { config, tx, t: Arc::new(RwLock::new(t)), w };
+        let (durable, frontiers, health) = {
+            let rd = err_at!(Fatal, w.read())?;
+            let frontiers = Arc::clone(&rd.frontiers);
+            (Arc::clone(&rd.durable), frontiers, Arc::clone(&rd.health))
+        };
+        let maintenance = Self::start_maintenance(&config, &w, &frontiers, &events)?;
+        let val = Wal {
+            config,
+            client: 0,
+            label: String::default(),
+            read_only: false,
+            attach: false,
+            clients: Arc::new(AtomicU64::new(1)),
+            durable,
+            frontiers,
+            health,
+            events,
+            signal: ShutdownSignal::default(),
+            tx,
+            t: Arc::new(RwLock::new(t)),
+            w,
+            maintenance,
+            recovery: Arc::new(RecoveryReport::default()),
+        };
 
         Ok(val)
     }
 
     /// Load an existing journal under `dir`, matching `name`. Files that
     /// don't match the journal file-name structure or journals with
-    /// corrupted batch or corrupted state shall be ignored.
+    /// corrupted batch shall be ignored. Journals with corrupted state are
+    /// handled as per [Config::set_state_policy]. Partially written batch
+    /// at the tail of the latest journal is truncated, refer to
+    /// [Wal::recovery_report].
     ///
     /// Application state shall be loaded from the last batch of the
     /// last journal.
@@ -139,104 +1216,2057 @@ impl<S> Wal<S> {
     where
         S: state::State,
     {
-        let mut journals: Vec<(Journal<S>, u64, S)> = vec![];
-        for item in err_at!(IOError, fs::read_dir(&config.dir))? {
-            let file_path: path::PathBuf = {
-                let file_name = err_at!(IOError, item)?.file_name();
-                [config.dir.clone(), file_name.clone()].iter().collect()
-            };
-            match Journal::load(&config.name, file_path.as_ref()) {
-                Some((journal, state)) => {
-                    let seqno = journal.to_last_seqno().unwrap();
-                    journals.push((journal, seqno, state));
-                }
-                None => debug!(target: "wral", "failed to load {:?}", file_path),
-            };
-        }
-
-        journals.sort_by(|(_, a, _), (_, b, _)| a.cmp(b));
+        Self::do_load(config, OpenMode::LoadOnly)
+    }
 
-        let (mut seqno, num, state) = match journals.last() {
-            Some((j, seqno, state)) => (*seqno, j.to_journal_number(), state.clone()),
-            None => (0, 0, S::default()),
-        };
-        seqno += 1;
-        let num = num.saturating_add(1);
-        let journal = Journal::start(&config.name, &config.dir, num, state)?;
+    fn do_load(config: Config, mode: OpenMode) -> Result<Wal<S>>
+    where
+        S: state::State,
+    {
+        let read_only = matches!(mode, OpenMode::ReadOnly | OpenMode::Attach);
+        if !read_only {
+            middleware::validate(&config.middleware)?;
+        }
+        let events = Arc::new(Events::new());
+        let loaded = Self::scan(&config, &events, mode)?;
+        let Loaded {
+            mut manifest,
+            mut journals,
+            seqno,
+            num,
+            state,
+            changed,
+            report,
+        } = loaded;
 
-        let n_batches: usize = journals.iter().map(|(j, _, _)| j.len_batches()).sum();
+        let n_batches: usize = journals.iter().map(|j| j.len_batches()).sum();
         debug!(
             target: "wral",
             "{:?}/{} loaded with {} journals, {} batches",
             config.dir, config.name, journals.len(), n_batches
         );
+        events.emit(WalEvent::Loaded {
+            journals: journals.len(),
+            batches: n_batches,
+            seqno: seqno - 1,
+        });
 
-        let journals: Vec<Journal<S>> = journals.into_iter().map(|(j, _, _)| j).collect();
-        let (w, t, tx) = writer::Writer::start(config.clone(), journals, journal, seqno);
+        // read-only and sealed instances don't start a new journal, the
+        // latest journal is held as the active journal.
+        let frozen = read_only || manifest.is_sealed();
+        let changed =
+            (!frozen && manifest.add_config(config.to_config_version())) || changed;
+        if changed && !read_only {
+            manifest.save(&config.dir)?;
+        }
+        let journal = match frozen {
+            false => {
+                for dir in config.dirs.iter() {
+                    fs::create_dir_all(dir).ok();
+                }
+                let options = config.to_storage_options();
+                let dir = config.to_journal_dir(num)?;
+                let mut journal =
+                    Journal::start(&config.name, &dir, num, &options, state)?;
+                journal.set_instance(manifest.as_instance());
+                let file = journal.to_file_path();
+                events.emit(WalEvent::JournalCreated { num, file });
+                journal
+            }
+            true => Self::pop_active(&config, &mut journals, num, state),
+        };
+        let (w, t, tx) = {
+            let events = Arc::clone(&events);
+            // nor do they write heartbeat batches.
+            let config = match frozen {
+                true => Config { heartbeat: None, ..config.clone() },
+                false => config.clone(),
+            };
+            writer::Writer::start(config, manifest, journals, journal, seqno, events)?
+        };
 
-        let val = Wal { config, tx, t: Arc::new(RwLock::new(t)), w };
+        let (durable, frontiers, health) = {
+            let rd = err_at!(Fatal, w.read())?;
+            let frontiers = Arc::clone(&rd.frontiers);
+            (Arc::clone(&rd.durable), frontiers, Arc::clone(&rd.health))
+        };
+        let maintenance = Self::start_maintenance(&config, &w, &frontiers, &events)?;
+        let val = Wal {
+            config,
+            client: 0,
+            label: String::default(),
+            read_only,
+            attach: mode == OpenMode::Attach,
+            clients: Arc::new(AtomicU64::new(1)),
+            durable,
+            frontiers,
+            health,
+            events,
+            signal: ShutdownSignal::default(),
+            tx,
+            t: Arc::new(RwLock::new(t)),
+            w,
+            maintenance,
+            recovery: Arc::new(report),
+        };
 
         Ok(val)
     }
 
-    /// Close the [Wal] instance. To purge the instance pass `purge` as true.
-    pub fn close(self, purge: bool) -> Result<Option<u64>> {
-        match Arc::try_unwrap(self.t) {
-            Ok(t) => {
-                mem::drop(self.tx);
-                (err_at!(IPCFail, t.into_inner())?.join()?)?;
+    // Scan `dir` for journals, along with the manifest. While attached to
+    // a live writer, the latest journal may end with a partial batch.
+    fn scan(config: &Config, events: &Events, mode: OpenMode) -> Result<Loaded<S>>
+    where
+        S: state::State,
+    {
+        let attach = mode == OpenMode::Attach;
+        let mut manifest = match Manifest::load(&config.dir, &config.name)? {
+            Some(manifest) => manifest,
+            None => Manifest::new(&config.name),
+        };
 
-                match Arc::try_unwrap(self.w) {
-                    Ok(w) => {
-                        let w = err_at!(IPCFail, w.into_inner())?;
-                        Ok(Some(if purge { w.purge()? } else { w.close()? }))
+        let read_only = matches!(mode, OpenMode::ReadOnly | OpenMode::Attach);
+        let files = files::find_journals(&config.to_journal_dirs(), &config.name)?;
+        // only the latest journal can end with a batch torn by a crash.
+        let tail = files
+            .iter()
+            .filter(|(_, file_path)| fs::metadata(file_path).is_ok_and(|m| m.len() > 0))
+            .map(|(num, _)| *num)
+            .max();
+
+        let mut report = RecoveryReport::default();
+        let mut journals: Vec<(Journal<S>, u64, S, bool)> = vec![];
+        let mut failed: Vec<(usize, path::PathBuf)> = vec![];
+        for (num, file_path) in files.into_iter() {
+            let (policy, max) = (config.state_policy, config.max_batch_entries);
+            let (journal, partial) =
+                match Journal::load(&config.name, file_path.as_ref(), policy, max) {
+                    Some(journal) => (Some(journal), false),
+                    None if attach => {
+                        let journal = Journal::load_durable(
+                            &config.name,
+                            file_path.as_ref(),
+                            policy,
+                            max,
+                        );
+                        (journal, true)
                     }
-                    Err(_) => Ok(None), // there are active clones
+                    None if !read_only && Some(num) == tail => {
+                        let journal = Self::recover_tail(
+                            config,
+                            events,
+                            &mut report,
+                            num,
+                            &file_path,
+                        )?;
+                        (journal, false)
+                    }
+                    None => (None, false),
+                };
+            let journal = journal.and_then(|(journal, state, reset)| {
+                let seqno = journal.to_last_seqno()?;
+                Some((journal, seqno, state, reset))
+            });
+            match journal {
+                Some((mut journal, seqno, state, reset)) => {
+                    if reset {
+                        let file = file_path.into_os_string();
+                        events.emit(WalEvent::StateReset { file });
+                    }
+                    journal.set_mirror(config.mirror_dir.as_deref());
+                    journals.push((journal, seqno, state, partial));
                 }
-            }
-            Err(_) => Ok(None), // there are active clones
+                // empty journals are left behind when nothing was flushed.
+                None if fs::metadata(&file_path).is_ok_and(|m| m.len() == 0) => {
+                    Self::load_empty(config, events, read_only, num, &file_path)?;
+                }
+                None => {
+                    debug!(target: "wral", "failed to load {:?}", file_path);
+                    failed.push((num, file_path));
+                }
+            };
         }
-    }
-}
 
-impl<S> Wal<S> {
-    /// Add a operation to WAL, operations are pre-serialized and opaque to
-    /// Wal instances. Return the sequence-number for this operation.
-    pub fn add_op(&self, op: &[u8]) -> Result<u64> {
-        let req = writer::Req::AddEntry { op: op.to_vec() };
-        let writer::Res::Seqno(seqno) = self.tx.request(req)?;
-        Ok(seqno)
-    }
-}
+        // seqnos restart after every rebase, journal numbers don't.
+        journals.sort_by_key(|(j, _, _, _)| j.to_journal_number());
+        let journals = Self::dedup(config, events, &manifest, mode, journals)?;
+        let stamped = Self::check_instance(config, &mut manifest, &journals)?;
 
-impl<S> Wal<S> {
-    /// Iterate over all entries in this Wal instance, entries can span
-    /// across multiple journal files. Iteration will start from lowest
-    /// sequence-number to highest.
-    pub fn iter(&self) -> Result<impl Iterator<Item = Result<entry::Entry>>> {
-        self.range(..)
-    }
+        // only the latest journal can be partially written by a live
+        // writer, partial batches in older journals are corruption.
+        let latest = journals.iter().map(|(j, _, _, _)| j.to_journal_number());
+        let latest = latest.chain(failed.iter().map(|(num, _)| *num)).max();
+        let (journals, partials): (Vec<_>, Vec<_>) =
+            journals.into_iter().partition(|(j, _, _, partial)| {
+                !partial || Some(j.to_journal_number()) == latest
+            });
+        for (journal, _, _, _) in partials.into_iter() {
+            let file = journal.to_file_path();
+            report.corrupted.push(file.clone());
+            events.emit(WalEvent::Corruption { file });
+        }
+        for (num, file_path) in failed.into_iter() {
+            if !attach || Some(num) != latest {
+                let file = file_path.into_os_string();
+                report.corrupted.push(file.clone());
+                events.emit(WalEvent::Corruption { file });
+            }
+        }
 
-    /// Iterate over entries whose sequence number fall within the
-    /// specified `range`.
-    pub fn range<R>(&self, range: R) -> Result<impl Iterator<Item = Result<entry::Entry>>>
-    where
-        R: ops::RangeBounds<u64>,
-    {
-        let journals = match Self::range_bound_to_range_inclusive(range) {
-            Some(range) => {
-                let rd = err_at!(Fatal, self.w.read())?;
-                let mut journals = vec![];
-                for jn in rd.journals.iter() {
-                    journals.push(journal::RdJournal::from_journal(jn, range.clone())?);
+        let (mut seqno, num, mut state) = match journals.last() {
+            Some((j, seqno, state, _)) => (*seqno, j.to_journal_number(), state.clone()),
+            None => (0, 0, S::default()),
+        };
+        // checkpoint is an optimization, failing to read it is not fatal.
+        let epoch = manifest.to_epoch().as_ref().map(Epoch::to_epoch).unwrap_or(0);
+        match Checkpoint::load(&config.dir, &config.name) {
+            Ok(Some(checkpoint))
+                if !journals.is_empty()
+                    && checkpoint.to_epoch() == epoch
+                    && checkpoint.to_seqno() >= seqno =>
+            {
+                match checkpoint.to_state() {
+                    Ok(val) => {
+                        debug!(target: "wral", "state from checkpoint at {}", checkpoint.to_seqno());
+                        state = val;
+                    }
+                    Err(err) => warn!(target: "wral", "skipped checkpoint, {}", err),
                 }
-                journals.push(journal::RdJournal::from_journal(&rd.journal, range)?);
-                journals
             }
-            None => vec![],
+            Ok(_) => (),
+            Err(err) => warn!(target: "wral", "skipped checkpoint, {}", err),
+        }
+        let num = match manifest.to_epoch() {
+            Some(epoch) if num < epoch.to_journal_number() => {
+                seqno = 0;
+                epoch.to_journal_number()
+            }
+            _ => num.saturating_add(1),
         };
+        seqno += 1;
+
+        let mut journals: Vec<Journal<S>> =
+            journals.into_iter().map(|(j, _, _, _)| j).collect();
+
+        // migrate journals named with a different width, say from an
+        // older release, unless the instance is opened for reading alone.
+        if !matches!(mode, OpenMode::ReadOnly | OpenMode::Attach) {
+            for journal in journals.iter_mut() {
+                let dir = journal.to_dir();
+                journal.relocate(&dir, &config.name, config.journal_width)?;
+            }
+        }
+
+        // preload batches from the tail, older batches are read lazily.
+        let mut n = config.preload_batches;
+        for journal in journals.iter_mut().rev() {
+            if n == 0 {
+                break;
+            }
+            n -= journal.preload(n)?;
+        }
+
+        // persist seqno span of archived journals, so that range queries
+        // can skip journals without consulting their index.
+        let spans = journals.iter().filter_map(Journal::to_span);
+        let changed = manifest.set_spans(spans.collect()) || stamped;
+
+        Ok(Loaded {
+            manifest,
+            journals,
+            seqno,
+            num,
+            state,
+            changed,
+            report,
+        })
+    }
+
+    // Latest journal that fails to load may end with a batch torn by a
+    // crash, while batches before it are valid. Truncate the torn tail and
+    // load the journal. Corruption, a batch that is written in full but
+    // doesn't decode, is left to the caller. A batch whose middleware is
+    // not registered is neither, fail with its error.
+    fn recover_tail(
+        config: &Config,
+        events: &Events,
+        report: &mut RecoveryReport,
+        num: usize,
+        file_path: &path::Path,
+    ) -> Result<Option<(Journal<S>, S, bool)>>
+    where
+        S: state::State,
+    {
+        let (policy, max) = (config.state_policy, config.max_batch_entries);
+        let offset = match journal::find_damage(file_path.as_os_str(), max) {
+            // without a valid batch, the file can't be told from garbage.
+            Ok(Some(journal::Damage::TornTail { offset })) if offset > 0 => offset,
+            Ok(Some(journal::Damage::Corrupted { offset, resume })) => {
+                warn!(
+                    target: "wral",
+                    "corrupted {:?} from {} to {}", file_path, offset, resume
+                );
+                return Ok(None);
+            }
+            Ok(Some(journal::Damage::Undecodable { offset })) => {
+                warn!(target: "wral", "corrupted {:?} at {}", file_path, offset);
+                return Ok(None);
+            }
+            Ok(_) => return Ok(None),
+            Err(err) => return Err(err),
+        };
+
+        let file = err_at!(IOError, fs::OpenOptions::new().write(true).open(file_path))?;
+        let bytes = err_at!(IOError, file.metadata())?.len() - offset;
+        err_at!(IOError, file.set_len(offset))?;
+        err_at!(IOError, file.sync_all())?;
+        warn!(
+            target: "wral",
+            "truncated {:?} at {}, {} torn bytes", file_path, offset, bytes
+        );
+
+        let file = file_path.as_os_str().to_os_string();
+        events.emit(WalEvent::TornTail { file: file.clone(), offset, bytes });
+        report.torn_tail = Some(TornTail { num, file, offset, bytes });
+
+        let journal = Journal::load(&config.name, file_path.as_os_str(), policy, max);
+        Ok(journal)
+    }
+
+    // Journals stamped with an instance id other than the manifest's are
+    // from another instance, say a wrong backup restored into `dir`, fail
+    // with Error::Mismatch before their entries interleave. Manifests
+    // without instance id, from an older release or rebuilt by fsck, adopt
+    // the id of their journals. Return whether the manifest is updated.
+    #[allow(clippy::type_complexity)]
+    fn check_instance(
+        config: &Config,
+        manifest: &mut Manifest,
+        journals: &[(Journal<S>, u64, S, bool)],
+    ) -> Result<bool> {
+        let mut stamped = journals
+            .iter()
+            .filter_map(|(j, _, _, _)| Some((j.to_instance()?, j.to_file_path())));
+        let (instance, adopt) = match manifest.as_instance() {
+            "" => match stamped.next() {
+                Some((instance, _)) => (instance, true),
+                None => (config.clock.new_uuid(), true),
+            },
+            instance => (instance.to_string(), false),
+        };
+        for (other, file_path) in stamped {
+            if other != instance {
+                err_at!(
+                    Mismatch,
+                    msg: "journal {:?} from instance {}, expected {}", file_path, other, instance
+                )?
+            }
+        }
+        if adopt {
+            manifest.set_instance(&instance);
+        }
+        Ok(adopt)
+    }
+
+    // Handle journal file without batches as per Config::empty_policy.
+    fn load_empty(
+        config: &Config,
+        events: &Events,
+        read_only: bool,
+        num: usize,
+        file_path: &path::Path,
+    ) -> Result<()> {
+        match config.empty_policy {
+            EmptyPolicy::Remove if !read_only => {
+                err_at!(IOError, fs::remove_file(file_path), "{:?}", file_path)?;
+                debug!(target: "wral", "removed empty journal {:?}", file_path);
+                let file = file_path.as_os_str().to_os_string();
+                events.emit(WalEvent::JournalPurged { num, file });
+            }
+            _ => debug!(target: "wral", "skipped empty journal {:?}", file_path),
+        }
+        Ok(())
+    }
+
+    // Files that parse to the same journal number, say a restored backup
+    // along with the live file, are resolved by preferring the one
+    // consistent with the manifest, then the one continuing the seqno
+    // chain, then the one with the canonical file name. Others are
+    // quarantined, and left in place for read-only instances.
+    #[allow(clippy::type_complexity)]
+    fn dedup(
+        config: &Config,
+        events: &Events,
+        manifest: &Manifest,
+        mode: OpenMode,
+        journals: Vec<(Journal<S>, u64, S, bool)>,
+    ) -> Result<Vec<(Journal<S>, u64, S, bool)>> {
+        let mut items: Vec<(Journal<S>, u64, S, bool)> = vec![];
+        let mut iter = journals.into_iter().peekable();
+        while let Some(item) = iter.next() {
+            let num = item.0.to_journal_number();
+            let mut dups = vec![item];
+            while let Some(item) =
+                iter.next_if(|(j, _, _, _)| j.to_journal_number() == num)
+            {
+                dups.push(item);
+            }
+            if dups.len() == 1 {
+                items.extend(dups);
+                continue;
+            }
+
+            let prev = items.last().map(|(_, seqno, _, _)| *seqno);
+            let span = manifest.to_span(num);
+            let canonical =
+                files::make_filename(config.name.clone(), num, config.journal_width);
+            let rank = |j: &Journal<S>| {
+                let first = j.to_first_seqno();
+                let in_span = match (&span, first, j.to_last_seqno()) {
+                    (Some(span), Some(first), Some(last)) => {
+                        *span.start() == first && *span.end() == last
+                    }
+                    _ => false,
+                };
+                let chained = matches!((prev, first), (Some(p), Some(f)) if f == p + 1);
+                let file_path = j.to_file_path();
+                let file_name = path::Path::new(&file_path).file_name();
+                let canonical = file_name == Some(canonical.as_os_str());
+                (in_span, chained, canonical, j.len_batches())
+            };
+            let keep = match (0..dups.len()).max_by_key(|off| rank(&dups[*off].0)) {
+                Some(off) => dups.remove(off),
+                None => err_at!(Fatal, msg: "no journal to keep for {}", num)?,
+            };
+
+            for (journal, _, _, _) in dups.into_iter() {
+                let file = journal.to_file_path();
+                let to = match mode {
+                    OpenMode::ReadOnly | OpenMode::Attach => None,
+                    _ => {
+                        let to = files::make_quarantine_filename(&file);
+                        err_at!(IOError, fs::rename(&file, &to), "{:?}", file)?;
+                        Some(to)
+                    }
+                };
+                warn!(
+                    target: "wral",
+                    "duplicate journal {} {:?}, kept {:?}", num, file, keep.0.to_file_path()
+                );
+                events.emit(WalEvent::Quarantined { num, file, to });
+            }
+            items.push(keep);
+        }
+        Ok(items)
+    }
+
+    // Latest journal is held as the active journal by read-only instances.
+    fn pop_active(
+        config: &Config,
+        journals: &mut Vec<Journal<S>>,
+        num: usize,
+        state: S,
+    ) -> Journal<S> {
+        match journals.pop() {
+            Some(journal) => journal,
+            None => {
+                let width = config.journal_width;
+                Journal::empty_archive(&config.name, &config.dir, num, width, state)
+            }
+        }
+    }
+
+    /// Re-scan journals for an instance opened with [OpenMode::Attach],
+    /// picking up batches and journals made durable by the writer process
+    /// since the last scan, as well as journals purged by it. Return the
+    /// latest seqno visible to this instance.
+    pub fn refresh(&self) -> Result<u64>
+    where
+        S: state::State,
+    {
+        if !self.attach {
+            err_at!(Invalid, msg: "wal {} is not attached", self.config.name)?
+        }
+        let loaded = Self::scan(&self.config, &self.events, OpenMode::Attach)?;
+        let Loaded { manifest, mut journals, seqno, num, state, .. } = loaded;
+        let journal = Self::pop_active(&self.config, &mut journals, num, state);
+
+        let mut w = err_at!(Fatal, self.w.write())?;
+        w.refresh(manifest, journals, journal, seqno)?;
+        Ok(seqno - 1)
+    }
+
+    /// Close the [Wal] instance. To purge the instance pass `purge` as true.
+    ///
+    /// If this is the last handle, requests queued ahead of close are
+    /// processed and flushed to disk, and anything after is rejected. Return
+    /// [CloseOutcome::Closed] or [CloseOutcome::Purged] with the final
+    /// seqno, or [CloseOutcome::Deferred] if there are other active
+    /// handles, in which case `purge` is ignored.
+    pub fn close(self, purge: bool) -> Result<CloseOutcome> {
+        if purge {
+            self.check_writable()?;
+        }
+        let held =
+            registry::registry().on_close(self.to_key(), Arc::strong_count(&self.t))?;
+
+        match Arc::try_unwrap(self.t) {
+            Ok(t) => {
+                // requests queued ahead of shutdown are flushed, the rest
+                // are rejected.
+                match self.tx.request(writer::Req::Shutdown)? {
+                    writer::Res::Seqno(_) => (),
+                    writer::Res::Fail(err) => return Err(err),
+                    res => err_at!(Fatal, msg: "unexpected response {:?}", res)?,
+                }
+                mem::drop(self.tx);
+                (err_at!(IPCFail, t.into_inner())?.join()?)?;
+                let scheduler = err_at!(Fatal, self.maintenance.lock())?.take();
+                if let Some(scheduler) = scheduler {
+                    scheduler.close()?;
+                }
+
+                match Arc::try_unwrap(self.w) {
+                    Ok(w) => {
+                        let w = err_at!(IPCFail, w.into_inner())?;
+                        match purge {
+                            true => Ok(CloseOutcome::Purged(w.purge()?)),
+                            false => Ok(CloseOutcome::Closed(w.close()?)),
+                        }
+                    }
+                    Err(w) => {
+                        let remaining_clones =
+                            (Arc::strong_count(&w) - 1).saturating_sub(held);
+                        Ok(CloseOutcome::Deferred { remaining_clones })
+                    }
+                }
+            }
+            Err(t) => {
+                let remaining_clones = (Arc::strong_count(&t) - 1).saturating_sub(held);
+                Ok(CloseOutcome::Deferred { remaining_clones })
+            }
+        }
+    }
+
+    fn start_maintenance(
+        config: &Config,
+        w: &Arc<RwLock<writer::Writer<S>>>,
+        frontiers: &Arc<writer::Frontiers>,
+        events: &Arc<Events>,
+    ) -> Result<Arc<Mutex<Option<maintenance::Scheduler>>>>
+    where
+        S: state::State,
+    {
+        let scheduler = match config.maintenance {
+            Some(idle) => Some(maintenance::Scheduler::start(
+                &config.name,
+                idle,
+                Arc::downgrade(w),
+                Arc::clone(frontiers),
+                Arc::clone(events),
+            )?),
+            None => None,
+        };
+        Ok(Arc::new(Mutex::new(scheduler)))
+    }
+
+    /// Same as [Wal::close] with `purge` as true. Return
+    /// [CloseOutcome::Purged] if this is the last handle, else
+    /// [CloseOutcome::Deferred] and nothing is purged.
+    pub fn try_purge(self) -> Result<CloseOutcome> {
+        self.close(true)
+    }
+}
+
+impl<S> Wal<S> {
+    /// Same as [Wal::clone], and label the new handle as `identity`, say a
+    /// tenant logging into a shared instance. Ops added by the handle, and
+    /// by its clones, are accounted to `identity`, refer to
+    /// [Config::set_quota].
+    pub fn labeled_clone(&self, identity: &str) -> Wal<S> {
+        let mut wal = self.clone();
+        wal.label = identity.to_string();
+        wal
+    }
+
+    /// Add a operation to WAL, operations are pre-serialized and opaque to
+    /// Wal instances. Return the sequence-number for this operation.
+    pub fn add_op(&self, op: &[u8]) -> Result<u64> {
+        self.add_op_to("", op)
+    }
+
+    /// Same as [Wal::add_op], but stop waiting for the writer at `deadline`
+    /// and fail with [Error::Timeout], so that callers can bound their
+    /// latency when the writer stalls, say on a slow fsync. On timeout the
+    /// entry may still be committed later, its seqno is not reported back.
+    pub fn add_op_deadline(&self, op: &[u8], deadline: time::Instant) -> Result<u64> {
+        self.check_writable()?;
+        let req = writer::Req::AddEntry {
+            client: self.client,
+            label: self.label.clone(),
+            topic: String::default(),
+            tag: String::default(),
+            op: op.to_vec(),
+            queued: time::Instant::now(),
+        };
+        match self.request_deadline(req, deadline)? {
+            writer::Res::Seqno(seqno) => Ok(seqno),
+            writer::Res::Fail(err) => Err(err),
+            res => err_at!(Fatal, msg: "unexpected response {:?}", res),
+        }
+    }
+
+    /// Submit `ops` to be added as contiguous entries, without waiting for
+    /// them to be durable, and return a [Receipt] to wait on. Refer to
+    /// [Wal::buffered_writer] for adding ops as a single request.
+    ///
+    /// Ops added via [Wal::add_op] are acknowledged with a response per
+    /// request, waking up each caller separately. Submitted ops are
+    /// acknowledged via the durable watermark instead, that wakes up all
+    /// receipts with a single notification per batch, which cuts down
+    /// wakeups in the writer when thousands of callers are waiting.
+    pub fn submit_ops(&self, ops: Vec<Vec<u8>>) -> Result<Receipt> {
+        self.check_writable()?;
+        if ops.is_empty() {
+            err_at!(Invalid, msg: "empty list of ops")?
+        }
+        let ack = Arc::new(receipt::Ack::default());
+        let req = writer::Req::AddEntries {
+            client: self.client,
+            label: self.label.clone(),
+            ops: ops.into_iter().map(|op| (String::default(), op)).collect(),
+            queued: time::Instant::now(),
+        };
+        let req = writer::Req::Submit { ack: Arc::clone(&ack), req: Box::new(req) };
+        self.tx.post(req)?;
+        Ok(Receipt::new(ack, Arc::clone(&self.durable)))
+    }
+
+    /// Add a operation to `topic`. Topics are multiplexed into the same
+    /// journals and share the same sequence-number space, while they are
+    /// indexed separately so that [Wal::range_topic] can skip batches that
+    /// don't carry the topic. Empty string is the default topic.
+    pub fn add_op_to(&self, topic: &str, op: &[u8]) -> Result<u64> {
+        self.do_add_op(topic.to_string(), String::default(), op.to_vec())
+    }
+
+    /// Same as [Wal::add_op], and tag the entry with `tag`, refer to
+    /// [Wal::find_by_tag].
+    pub fn add_op_tagged(&self, tag: &str, op: &[u8]) -> Result<u64> {
+        self.do_add_op(String::default(), tag.to_string(), op.to_vec())
+    }
+
+    /// Same as [Wal::add_op], but take ownership of `op`. Payload is moved
+    /// all the way into the batch, without copying, which avoids an
+    /// allocation per op for callers that already own the payload, like
+    /// `Vec<u8>`, `Box<[u8]>` or `String`.
+    pub fn add_op_owned<T>(&self, op: T) -> Result<u64>
+    where
+        T: Into<Vec<u8>>,
+    {
+        self.do_add_op(String::default(), String::default(), op.into())
+    }
+
+    /// Add operation at a pre-assigned `seqno`, say an upstream log position
+    /// while ingesting a change-data-capture stream, and return the same.
+    /// Seqnos must be strictly increasing, and gaps are allowed, else fail
+    /// with [Error::Invalid]. Subsequent [Wal::add_op] continue from
+    /// `seqno + 1`.
+    pub fn add_op_at(&self, seqno: u64, op: &[u8]) -> Result<u64> {
+        self.check_writable()?;
+        let req = writer::Req::AddEntryAt {
+            client: self.client,
+            label: self.label.clone(),
+            seqno,
+            op: op.to_vec(),
+            queued: time::Instant::now(),
+        };
+        match self.tx.request(req)? {
+            writer::Res::Seqno(seqno) => Ok(seqno),
+            writer::Res::Fail(err) => Err(err),
+            res => err_at!(Fatal, msg: "unexpected response {:?}", res),
+        }
+    }
+
+    /// Idempotent version of [Wal::add_op_at], for batch of `ops` delivered
+    /// atleast once by the source. Ops whose seqno is already in the log
+    /// are acknowledged but skipped, and the rest are added as a single
+    /// unit. Seqnos must be strictly increasing within `ops`. Return the
+    /// seqno span of ops added, None if all of them were skipped.
+    pub fn ingest(
+        &self,
+        ops: Vec<(u64, Vec<u8>)>,
+    ) -> Result<Option<ops::RangeInclusive<u64>>> {
+        let entries = ops
+            .into_iter()
+            .map(|(seqno, op)| EntryBuilder::new(seqno).payload(op).build())
+            .collect();
+        self.ingest_entries(entries)
+    }
+
+    /// Same as [Wal::ingest], for entries built with [EntryBuilder], that
+    /// can carry topic, tag and timestamp along with the op.
+    pub fn ingest_entries(
+        &self,
+        entries: Vec<entry::Entry>,
+    ) -> Result<Option<ops::RangeInclusive<u64>>> {
+        self.check_writable()?;
+        if entries.is_empty() {
+            return Ok(None);
+        }
+        let req = writer::Req::Ingest {
+            client: self.client,
+            label: self.label.clone(),
+            entries,
+            queued: time::Instant::now(),
+        };
+        match self.tx.request(req)? {
+            writer::Res::Ingested { seqnos, .. } => Ok(seqnos),
+            writer::Res::Fail(err) => Err(err),
+            res => err_at!(Fatal, msg: "unexpected response {:?}", res),
+        }
+    }
+
+    fn do_add_op(&self, topic: String, tag: String, op: Vec<u8>) -> Result<u64> {
+        self.check_writable()?;
+        let req = writer::Req::AddEntry {
+            client: self.client,
+            label: self.label.clone(),
+            topic,
+            tag,
+            op,
+            queued: time::Instant::now(),
+        };
+        match self.tx.request(req)? {
+            writer::Res::Seqno(seqno) => Ok(seqno),
+            writer::Res::Fail(err) => Err(err),
+            res => err_at!(Fatal, msg: "unexpected response {:?}", res),
+        }
+    }
+
+    // Same as tx.request(), but give up waiting at `deadline`. Writer
+    // ignores responses to requests whose caller has stopped waiting.
+    fn request_deadline(
+        &self,
+        req: writer::Req,
+        deadline: time::Instant,
+    ) -> Result<writer::Res> {
+        use std::sync::mpsc::{RecvTimeoutError, TrySendError};
+
+        let (stx, srx) = mpsc::channel();
+        match &self.tx {
+            writer::Tx::Thread(thread::Tx::N(tx)) => {
+                err_at!(IPCFail, tx.send((req, Some(stx))))?
+            }
+            writer::Tx::Thread(thread::Tx::S(tx)) => {
+                // request queue is bounded, wait for room till deadline.
+                let mut item = (req, Some(stx));
+                loop {
+                    item = match tx.try_send(item) {
+                        Ok(()) => break,
+                        Err(TrySendError::Full(val))
+                            if time::Instant::now() < deadline =>
+                        {
+                            std::thread::sleep(time::Duration::from_millis(1));
+                            val
+                        }
+                        Err(TrySendError::Full(_)) => {
+                            err_at!(Timeout, msg: "request queue full till deadline")?
+                        }
+                        Err(err) => err_at!(IPCFail, Err(err))?,
+                    };
+                }
+            }
+            // processed on this thread, before the deadline can be checked.
+            #[cfg(feature = "inline-writer")]
+            writer::Tx::Inline(_) => return self.tx.request(req),
+        }
+
+        let timeout = deadline.saturating_duration_since(time::Instant::now());
+        match srx.recv_timeout(timeout) {
+            Ok(res) => Ok(res),
+            Err(RecvTimeoutError::Timeout) => {
+                err_at!(Timeout, msg: "no response after {:?}", timeout)
+            }
+            Err(err) => err_at!(IPCFail, Err(err)),
+        }
+    }
+
+    /// Return a handle that accumulates ops locally, and add them as a
+    /// single request once `capacity` ops are buffered, on flush or on
+    /// drop. Refer to [BufferedWriter].
+    pub fn buffered_writer(&self, capacity: usize) -> BufferedWriter<'_, S> {
+        BufferedWriter::new(self, capacity)
+    }
+
+    /// Return a handle that adds ops as they are, and makes every op
+    /// added through it durable by the end of its scope. Refer to
+    /// [ScopedWriter].
+    pub fn scoped_writer(&self) -> ScopedWriter<'_, S> {
+        ScopedWriter::new(self)
+    }
+
+    // Add ops as contiguous entries, return their seqnos.
+    pub(crate) fn do_add_ops(
+        &self,
+        ops: Vec<(String, Vec<u8>)>,
+    ) -> Result<ops::RangeInclusive<u64>> {
+        self.check_writable()?;
+        if ops.is_empty() {
+            err_at!(Invalid, msg: "empty list of ops")?
+        }
+        let req = writer::Req::AddEntries {
+            client: self.client,
+            label: self.label.clone(),
+            ops,
+            queued: time::Instant::now(),
+        };
+        match self.tx.request(req)? {
+            writer::Res::Seqnos(seqnos) => Ok(seqnos),
+            writer::Res::Fail(err) => Err(err),
+            res => err_at!(Fatal, msg: "unexpected response {:?}", res),
+        }
+    }
+
+    /// Return the active configuration for this instance, refer to
+    /// [Config::fsync] for whether fsync is enabled, and to [Wal::stats]
+    /// for the effective sync cadence.
+    pub fn to_config(&self) -> Result<Config> {
+        Ok(err_at!(Fatal, self.w.read())?.to_config())
+    }
+
+    /// Return statistics for this instance.
+    pub fn stats(&self) -> Result<Stats> {
+        let rd = err_at!(Fatal, self.w.read())?;
+        let n_batches: usize = rd.journals.iter().map(|j| j.len_batches()).sum();
+        let (batch_high_water, pending_capacity) = rd.to_high_water();
+        let durable_seqno = self.durable.to_seqno()?;
+        let stats = Stats {
+            name: rd.to_name(),
+            dir: rd.to_dir(),
+            epoch: rd.to_epoch(),
+            last_seqno: rd.to_next_seqno().saturating_sub(1),
+            durable_seqno,
+            n_journals: rd.journals.len() + 1,
+            n_batches: n_batches + rd.journal.len_batches(),
+            n_purges: rd.to_tombstones().len(),
+            queue_depth: rd.cadence.to_queue_depth(),
+            queue_time: rd.cadence.to_queue_time(),
+            max_queue_time: rd.cadence.to_max_queue_time(),
+            avg_batch_size: rd.cadence.to_avg_batch_size(),
+            avg_sync_interval: rd.cadence.to_avg_sync_interval(),
+            fsync_latency: rd.cadence.to_fsync_latency(),
+            commit_window: rd.cadence.to_commit_window(),
+            op_sizes: rd.cadence.to_op_sizes(),
+            largest_ops: rd.cadence.to_largest_ops(),
+            state_size: rd.to_state_size(),
+            batch_high_water,
+            pending_capacity,
+            rotations: rd.to_rotations(),
+            consumers: rd.to_consumers(durable_seqno),
+        };
+        Ok(stats)
+    }
+
+    /// Report the journals, and the bytes, that shall be reclaimed by
+    /// [Wal::purge_till] with `seqno`, without purging them. Retention
+    /// controllers can use this to decide whether a purge is worth it.
+    pub fn reclaimable(&self, seqno: u64) -> Result<ReclaimReport> {
+        let rd = err_at!(Fatal, self.w.read())?;
+
+        let n = rd.journals.iter().take_while(|j| rd.is_purgeable(j, seqno)).count();
+        let journals: Vec<journal::JournalIndex> =
+            rd.journals[..n].iter().map(Journal::to_journal_index).collect();
+        let bytes = journals.iter().map(journal::JournalIndex::to_size).sum();
+
+        let boundary = match rd.journals.get(n) {
+            Some(journal) => journal.to_journal_index(),
+            None => rd.journal.to_journal_index(),
+        };
+        let wasted = match boundary.to_size() {
+            0 => 0.0,
+            size => {
+                let iter = boundary.iter().filter(|i| i.to_last_seqno() <= seqno);
+                let n: u64 = iter.map(|i| i.to_length() as u64).sum();
+                (n as f64) / (size as f64)
+            }
+        };
+
+        let report = ReclaimReport {
+            seqno,
+            journals,
+            bytes,
+            boundary: boundary.to_journal_number(),
+            wasted,
+        };
+        Ok(report)
+    }
+
+    /// Record that `consumer` has applied entries upto and including
+    /// `seqno`, in the current epoch, so that its lag is reported by
+    /// [Wal::consumers] and [Stats::consumers]. Acknowledging registers
+    /// the consumer, a new consumer can acknowledge ZERO to hold back
+    /// retention from the start. Progress is persisted in the manifest and
+    /// survives reloads, like a consumer group's committed offset.
+    ///
+    /// Consumers replaying with [Wal::replay_from_cursor], and followers
+    /// shipped to by a replication leader, are acknowledged implicitly.
+    /// Under [Config::set_consumer_retention], journals acknowledged by
+    /// all consumers are purged, else the consumer furthest behind is the
+    /// one holding back [Wal::purge_till] for retention tied to consumer
+    /// progress.
+    pub fn ack(&self, consumer: &str, seqno: u64) -> Result<()> {
+        self.check_writable()?;
+        if consumer.is_empty() {
+            err_at!(Invalid, msg: "empty consumer name")?
+        }
+        let retain = {
+            let mut w = err_at!(Fatal, self.w.write())?;
+            w.ack(consumer, seqno)?;
+            w.to_retention_seqno()
+        };
+        match retain {
+            Some(seqno) if self.config.consumer_retention => {
+                match self.purge_till(seqno, "consumers") {
+                    Ok(_) | Err(Error::Sealed(_, _)) => Ok(()),
+                    Err(err) => Err(err),
+                }
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Stop tracking `consumer`, say after it is retired, so that it no
+    /// longer holds back retention. Return whether the consumer was
+    /// tracked.
+    pub fn forget(&self, consumer: &str) -> Result<bool> {
+        self.check_writable()?;
+        err_at!(Fatal, self.w.write())?.forget(consumer)
+    }
+
+    /// Return the lag of consumers acknowledged with [Wal::ack], furthest
+    /// behind first.
+    pub fn consumers(&self) -> Result<Vec<ConsumerLag>> {
+        let durable = self.durable.to_seqno()?;
+        Ok(err_at!(Fatal, self.w.read())?.to_consumers(durable))
+    }
+
+    /// Return the number of bytes on disk, across all journal files
+    /// including the active journal.
+    pub fn disk_footprint(&self) -> Result<u64> {
+        let rd = err_at!(Fatal, self.w.read())?;
+        let mut bytes = 0;
+        for journal in rd.journals.iter().chain(std::iter::once(&rd.journal)) {
+            let file_path = journal.to_file_path();
+            bytes += err_at!(IOError, fs::metadata(&file_path), "{:?}", file_path)?.len();
+        }
+        Ok(bytes)
+    }
+
+    /// Estimate the cost of reading entries whose seqno fall within `range`,
+    /// say to forecast recovery time or to display progress while
+    /// replaying. Estimate is computed from batch index, without reading
+    /// journal files, and excludes entries that are not yet flushed.
+    pub fn estimate<R>(&self, range: R) -> Result<Estimate>
+    where
+        R: ops::RangeBounds<u64>,
+    {
+        let range = match Self::range_bound_to_range_inclusive(range) {
+            Some(range) if !range.is_empty() => range,
+            _ => return Ok(Estimate::default()),
+        };
+
+        let rd = err_at!(Fatal, self.w.read())?;
+        let journals = rd.journals.iter().filter(|j| rd.is_current_epoch(j));
+        let mut estimate = Estimate::default();
+        // metadata batches repeat the last seqno, count entries only once.
+        let mut seqno = 0;
+        for jn in journals.chain(std::iter::once(&rd.journal)) {
+            for index in jn.to_journal_index().iter() {
+                let (first, last) = (index.to_first_seqno(), index.to_last_seqno());
+                if last < *range.start() || *range.end() < first {
+                    continue;
+                }
+                estimate.bytes += index.to_length() as u64;
+                estimate.batches += 1;
+
+                let first = first.max(seqno + 1).max(*range.start());
+                let last = last.min(*range.end());
+                if first <= last {
+                    estimate.entries += last - first + 1;
+                }
+                seqno = seqno.max(index.to_last_seqno());
+            }
+        }
+        Ok(estimate)
+    }
+
+    // Identify the instance, same for all its clones.
+    pub(crate) fn to_key(&self) -> usize {
+        Arc::as_ptr(&self.t) as *const u8 as usize
+    }
+
+    fn check_writable(&self) -> Result<()> {
+        match self.read_only {
+            true => err_at!(ReadOnly, msg: "wal {} opened read-only", self.config.name),
+            false => Ok(()),
+        }
+    }
+
+    /// Return the shutdown signal shared by this instance and its clones.
+    /// Services can cancel the signal during fast shutdown, so that long
+    /// running operations, like iterating over entries, stop early instead
+    /// of running to completion. Refer to [ShutdownSignal].
+    pub fn shutdown_signal(&self) -> ShutdownSignal {
+        self.signal.clone()
+    }
+
+    /// Subscribe to events emitted by this instance, like journal rotation
+    /// and purge, refer to [WalEvent]. Events emitted while there are no
+    /// subscribers, including the ones emitted while creating or loading
+    /// this instance, are held back for the next subscriber.
+    pub fn events(&self) -> Result<mpsc::Receiver<WalEvent>> {
+        self.events.subscribe()
+    }
+
+    /// Return how journals were recovered when this instance was loaded.
+    /// Torn tail, a partially written batch at the end of the latest
+    /// journal, is truncated without losing durable entries, while
+    /// journals corrupted elsewhere are ignored along with their entries.
+    /// Instances opened read-only don't repair journals, a torn tail is
+    /// reported as corruption.
+    pub fn recovery_report(&self) -> RecoveryReport {
+        self.recovery.as_ref().clone()
+    }
+
+    /// Return the health of the background writer, without waiting on it,
+    /// so that health checks can detect a stuck or failed instance.
+    pub fn health(&self) -> Result<Health> {
+        let mut health = err_at!(Fatal, self.health.lock())?.clone();
+        if self.w.is_poisoned() {
+            health.state = HealthState::Poisoned;
+        }
+        health.durable_seqno = self.durable.to_seqno()?;
+        Ok(health)
+    }
+
+    /// Return the appended, flushed, durable and purged seqnos, without
+    /// waiting on the writer, so that they can be polled while writes are
+    /// in progress.
+    pub fn watermarks(&self) -> Result<Watermarks> {
+        // frontiers move independently, read them trailing one first.
+        let durable = self.durable.to_seqno()?;
+        let flushed = self.frontiers.to_flushed().max(durable);
+        let appended = self.frontiers.to_appended().max(flushed);
+        let purged = self.frontiers.to_purged();
+        Ok(Watermarks { appended, flushed, durable, purged })
+    }
+
+    /// Return the time the latest batch, including heartbeat batches, was
+    /// written to the log, None if no batch is written yet. Refer to
+    /// [Config::set_heartbeat].
+    pub fn last_batch_time(&self) -> Result<Option<time::SystemTime>> {
+        let rd = err_at!(Fatal, self.w.read())?;
+        let timestamp = rd
+            .journal
+            .to_last_timestamp()
+            .or_else(|| rd.journals.last().and_then(Journal::to_last_timestamp));
+        Ok(timestamp.map(|ts| time::UNIX_EPOCH + time::Duration::from_nanos(ts)))
+    }
+
+    /// Return the seqno upto which entries are flushed to disk.
+    pub fn durable_seqno(&self) -> Result<u64> {
+        self.durable.to_seqno()
+    }
+
+    /// Return the oldest seqno, in the current epoch, retained on disk, that
+    /// is, not yet purged. Return the durable seqno plus one if no entry is
+    /// retained. Refer to [Wal::coverage].
+    pub fn oldest_seqno(&self) -> Result<u64> {
+        Ok(*self.coverage()?.start())
+    }
+
+    /// Return the span of seqnos, in the current epoch, retained on disk,
+    /// from [Wal::oldest_seqno] upto the durable seqno. Range is empty if
+    /// no entry is retained. Use this to check whether a replay range is
+    /// available, iterating over purged seqnos silently yields nothing.
+    ///
+    /// Cold journals are part of the coverage, though reads touching them
+    /// are handled as per [Config::set_thaw_policy], and so are masked
+    /// entries, which are skipped by iterators, refer to [Wal::mask_range].
+    pub fn coverage(&self) -> Result<ops::RangeInclusive<u64>> {
+        let durable = self.durable.to_seqno()?;
+        let purged = self.frontiers.to_purged();
+        let next = durable.saturating_add(1);
+        let oldest = err_at!(Fatal, self.w.read())?.to_oldest_seqno().unwrap_or(next);
+        Ok(oldest.max(purged.saturating_add(1)).min(next)..=durable)
+    }
+
+    /// Block until all entries upto `seqno` are flushed to disk, or until
+    /// `timeout` elapses, in which case [Error::Timeout] is returned. On
+    /// success return the durable seqno, which is `>=` `seqno`.
+    ///
+    /// Useful for read-your-writes semantics, when `seqno` was learnt from
+    /// another node replicating this log. Seqnos are local to an epoch, if
+    /// the instance is rebased while waiting, [Error::Invalid] is returned.
+    pub fn wait_for(&self, seqno: u64, timeout: time::Duration) -> Result<u64> {
+        self.durable.wait_for(seqno, timeout)
+    }
+
+    /// Return the number of sequence-numbers that can still be generated
+    /// in the current epoch. Once exhausted, [Wal::add_op] fails with
+    /// [Error::Overflow] until the Wal is rebased to a new epoch.
+    pub fn remaining_seqnos(&self) -> Result<u64> {
+        let rd = err_at!(Fatal, self.w.read())?;
+        Ok(u64::MAX - rd.to_next_seqno())
+    }
+
+    /// Return the instance id, a UUID generated when the instance was
+    /// created and stamped on each of its journals.
+    pub fn instance_id(&self) -> Result<String> {
+        Ok(err_at!(Fatal, self.w.read())?.manifest.as_instance().to_string())
+    }
+
+    /// Return the current seqno epoch, ZERO if this instance was never
+    /// rebased.
+    pub fn epoch(&self) -> Result<u64> {
+        Ok(err_at!(Fatal, self.w.read())?.to_epoch())
+    }
+
+    /// Return versions of configuration this instance was created, loaded
+    /// or relocated with, oldest first, say to learn whether fsync was
+    /// disabled or journal limit was changed while analysing a data-loss
+    /// incident. A version is recorded only when the configuration differs
+    /// from the previous version, read-only and sealed instances don't
+    /// record their configuration.
+    pub fn config_history(&self) -> Result<Vec<ConfigVersion>> {
+        Ok(err_at!(Fatal, self.w.read())?.manifest.to_configs())
+    }
+
+    /// Rebase sequence-numbers to a new `epoch`, which must be greater than
+    /// the current epoch. Pending entries are flushed, journal is rotated,
+    /// and sequence-numbering restarts from 1. The epoch marker is recorded
+    /// in the manifest. Return the last seqno of the previous epoch.
+    ///
+    /// Entries from older epochs are no longer visible to [Wal::iter] and
+    /// [Wal::range], applications are expected to consume them before
+    /// rebasing.
+    pub fn rebase(&self, epoch: u64) -> Result<u64> {
+        self.check_writable()?;
+        match self.tx.request(writer::Req::Rebase { epoch })? {
+            writer::Res::Seqno(seqno) => Ok(seqno),
+            writer::Res::Fail(err) => Err(err),
+            res => err_at!(Fatal, msg: "unexpected response {:?}", res),
+        }
+    }
+
+    /// Flush entries added so far and fsync them, irrespective of
+    /// [Config::set_fsync_interval]. Return the durable seqno.
+    pub fn sync(&self) -> Result<u64> {
+        self.check_writable()?;
+        match self.tx.request(writer::Req::Sync)? {
+            writer::Res::Seqno(seqno) => Ok(seqno),
+            writer::Res::Fail(err) => Err(err),
+            res => err_at!(Fatal, msg: "unexpected response {:?}", res),
+        }
+    }
+
+    /// Seal the instance permanently as read-only, say when a shard is
+    /// decommissioned but its log must remain queryable. Requests queued
+    /// ahead of seal are flushed, a terminal batch is written, the active
+    /// journal is archived, and the instance is recorded as sealed in the
+    /// manifest. Return the final durable seqno.
+    ///
+    /// Subsequent writes, including purge, mask and rebase, fail with
+    /// [Error::Sealed], also after the instance is re-opened. Readers are
+    /// not affected.
+    pub fn seal(&self) -> Result<u64> {
+        self.check_writable()?;
+        match self.tx.request(writer::Req::Seal)? {
+            writer::Res::Seqno(seqno) => Ok(seqno),
+            writer::Res::Fail(err) => Err(err),
+            res => err_at!(Fatal, msg: "unexpected response {:?}", res),
+        }
+    }
+}
+
+impl<S> Wal<S> {
+    /// Purge archived journals whose entries are upto and including
+    /// `seqno`, along with journals from older epochs. Only whole journals
+    /// are purged, oldest first, and the active journal is never purged.
+    ///
+    /// Purge is recorded as a [Tombstone] in the active journal, with
+    /// `reason`, before the journal files are removed. Return None if there
+    /// was nothing to purge. If a journal file fails to be removed, journals
+    /// from it onwards are retained and the error is returned, purge can
+    /// be retried.
+    pub fn purge_till(&self, seqno: u64, reason: &str) -> Result<Option<Tombstone>> {
+        self.check_writable()?;
+        let req = writer::Req::Purge { seqno, reason: reason.to_string() };
+        match self.tx.request(req)? {
+            writer::Res::Purged(tombstone) => Ok(tombstone),
+            writer::Res::Fail(err) => Err(err),
+            res => err_at!(Fatal, msg: "unexpected response {:?}", res),
+        }
+    }
+
+    /// Mask entries whose seqno fall within `range`, in the current epoch.
+    /// Masked entries are skipped by iteration, while they remain in the
+    /// journals until they are purged. Mask is persisted in the active
+    /// journal, and survives reloads.
+    pub fn mask_range<R>(&self, range: R) -> Result<()>
+    where
+        R: ops::RangeBounds<u64>,
+    {
+        self.do_mask(range, false)
+    }
+
+    /// Undo [Wal::mask_range] for entries whose seqno fall within `range`.
+    pub fn unmask_range<R>(&self, range: R) -> Result<()>
+    where
+        R: ops::RangeBounds<u64>,
+    {
+        self.do_mask(range, true)
+    }
 
-        Ok(Iter { journal: None, journals: journals.into_iter() })
+    /// Return masked seqno ranges in the current epoch, in sort order.
+    pub fn masks(&self) -> Result<Vec<ops::RangeInclusive<u64>>> {
+        Ok(err_at!(Fatal, self.w.read())?.to_masks())
+    }
+
+    fn do_mask<R>(&self, range: R, unmask: bool) -> Result<()>
+    where
+        R: ops::RangeBounds<u64>,
+    {
+        self.check_writable()?;
+        let range = match Self::range_bound_to_range_inclusive(range) {
+            Some(range) if !range.is_empty() => range,
+            _ => return Ok(()),
+        };
+        match self.tx.request(writer::Req::Mask { range, unmask })? {
+            writer::Res::Ok => Ok(()),
+            writer::Res::Fail(err) => Err(err),
+            res => err_at!(Fatal, msg: "unexpected response {:?}", res),
+        }
+    }
+
+    /// Return the purge history, oldest first. Purge history survives
+    /// reloads, and applications can use it to explain why entries are no
+    /// longer present.
+    pub fn purge_history(&self) -> Result<Vec<Tombstone>> {
+        Ok(err_at!(Fatal, self.w.read())?.to_tombstones())
+    }
+
+    /// Set savepoint `name` at the last seqno handed out, replacing the
+    /// older savepoint with the same name. Savepoints are persisted in the
+    /// manifest and survive reloads. Return the seqno of the savepoint,
+    /// refer to [Wal::rollback_to].
+    pub fn savepoint(&self, name: &str) -> Result<u64> {
+        self.check_writable()?;
+        let name = name.to_string();
+        match self.tx.request(writer::Req::Savepoint { name })? {
+            writer::Res::Seqno(seqno) => Ok(seqno),
+            writer::Res::Fail(err) => Err(err),
+            res => err_at!(Fatal, msg: "unexpected response {:?}", res),
+        }
+    }
+
+    /// Roll back entries added after savepoint `name` by masking them,
+    /// refer to [Wal::mask_range], and remove savepoints set after it.
+    /// Application state is not rolled back, and new entries continue from
+    /// the next seqno. Entries queued by other handles, before the
+    /// rollback, are masked as well. Return the seqno of the savepoint.
+    ///
+    /// Fail with [Error::NotFound] if there is no such savepoint, and with
+    /// [Error::Invalid] if the savepoint is from an older epoch.
+    pub fn rollback_to(&self, name: &str) -> Result<u64> {
+        self.check_writable()?;
+        let name = name.to_string();
+        match self.tx.request(writer::Req::Rollback { name })? {
+            writer::Res::Seqno(seqno) => Ok(seqno),
+            writer::Res::Fail(err) => Err(err),
+            res => err_at!(Fatal, msg: "unexpected response {:?}", res),
+        }
+    }
+
+    /// Return savepoints in the order they were set, refer to
+    /// [Wal::savepoint].
+    pub fn savepoints(&self) -> Result<Vec<Savepoint>> {
+        Ok(err_at!(Fatal, self.w.read())?.manifest.to_savepoints())
+    }
+}
+
+impl Wal {
+    /// Check journals and manifest for `config`, repairing them as per
+    /// `level`. Shall be called before opening the Wal instance, via
+    /// [Wal::create] or [Wal::load], and never while it is open.
+    pub fn fsck(config: &Config, level: fsck::FsckLevel) -> Result<fsck::FsckReport> {
+        fsck::fsck(config, level)
+    }
+
+    /// Verify an archived journal file, say a frozen backup, without
+    /// loading it into an instance and without holding its batch index in
+    /// memory. `file_path` must name a journal file of `config`. Unlike
+    /// [Wal::fsck], entries are decoded and checked for seqno order, and
+    /// spilled or compressed ops are checked as well. Entries are checked
+    /// against the journal's trailer, if it was written with one, refer to
+    /// [Config::set_journal_trailer]. Verification is
+    /// read-only and can be run while the instance is open.
+    pub fn verify_journal(
+        config: &Config,
+        file_path: &ffi::OsStr,
+    ) -> Result<fsck::JournalReport> {
+        match Journal::<state::NoState>::load_cold(&config.name, file_path) {
+            Some(journal) => journal.verify(),
+            None => {
+                err_at!(Invalid, msg: "{:?} not a journal of {}", file_path, config.name)
+            }
+        }
+    }
+
+    /// Split journal `num` into journals of `target_size` bytes, at batch
+    /// boundaries, say after lowering `journal_limit` for an existing
+    /// instance. Subsequent journals are renumbered and the manifest is
+    /// updated. Return the number of journals `num` is split into.
+    ///
+    /// Like [Wal::fsck], shall be called only while the instance is not
+    /// open. Journals are renamed one by one, so take a backup of `dir` in
+    /// case the process is interrupted midway.
+    pub fn split_journal(
+        config: &Config,
+        num: usize,
+        target_size: usize,
+    ) -> Result<usize> {
+        split::split_journal(config, num, target_size)
+    }
+}
+
+impl<S> Wal<S> {
+    /// Move journal files and manifest to `dir`, without interrupting
+    /// concurrent writers. Files are renamed when `dir` is on the same
+    /// file-system, else they are copied, synced to disk and removed from
+    /// the older location. On failure the operation can be retried.
+    /// Journals striped across [Config::dirs] stay where they are.
+    pub fn relocate(&self, dir: &ffi::OsStr) -> Result<()> {
+        self.do_relocate(Some(dir.to_os_string()), None)
+    }
+
+    /// Rename this Wal instance to `name`, renaming journal files and
+    /// manifest in place. Subsequent [Wal::load] must use the new name.
+    pub fn rename(&self, name: &str) -> Result<()> {
+        self.do_relocate(None, Some(name.to_string()))
+    }
+
+    // current dir, or name, is resolved by the writer, along with other
+    // relocations in flight.
+    fn do_relocate(
+        &self,
+        dir: Option<ffi::OsString>,
+        name: Option<String>,
+    ) -> Result<()> {
+        self.check_writable()?;
+        match self.tx.request(writer::Req::Relocate { dir, name })? {
+            writer::Res::Ok => Ok(()),
+            writer::Res::Fail(err) => Err(err),
+            res => err_at!(Fatal, msg: "unexpected response {:?}", res),
+        }
+    }
+}
+
+#[cfg(feature = "testing")]
+impl<S> Wal<S> {
+    /// Copy files of this instance into `dir`, as they would be found on
+    /// disk after a crash at this point. Batches upto the durable seqno are
+    /// retained, the active journal is truncated at an offset picked by
+    /// `tail` from the range of offsets that can survive the crash. Return
+    /// the durable seqno.
+    pub(crate) fn crash_image<F>(&self, dir: &ffi::OsStr, tail: F) -> Result<u64>
+    where
+        F: FnOnce(ops::RangeInclusive<u64>) -> u64,
+    {
+        // writer holds the lock for a whole batch, files are at rest.
+        let w = err_at!(Fatal, self.w.read())?;
+        let durable = w.durable.to_seqno()?;
+
+        for item in err_at!(IOError, fs::read_dir(w.to_dir()))? {
+            let item = err_at!(IOError, item)?;
+            if err_at!(IOError, item.file_type())?.is_file() {
+                let dst: path::PathBuf = [dir, &item.file_name()].iter().collect();
+                err_at!(IOError, fs::copy(item.path(), dst))?;
+            }
+        }
+
+        let index = w.journal.to_journal_index();
+        let file_path: path::PathBuf =
+            match path::Path::new(&index.to_file_path()).file_name() {
+                Some(file) => [dir, file].iter().collect(),
+                None => return Ok(durable),
+            };
+        let size = match fs::metadata(&file_path) {
+            Ok(m) => m.len(),
+            Err(_) => return Ok(durable),
+        };
+        let synced = index
+            .iter()
+            .filter(|b| b.to_last_seqno() <= durable)
+            .map(|b| b.to_fpos() + b.to_length() as u64)
+            .max()
+            .unwrap_or(0);
+        let len = tail(synced.min(size)..=size);
+        let file = err_at!(IOError, fs::OpenOptions::new().write(true).open(&file_path))?;
+        err_at!(IOError, file.set_len(len))?;
+
+        Ok(durable)
+    }
+}
+
+impl<S> Wal<S> {
+    /// Iterate over all entries in this Wal instance, entries can span
+    /// across multiple journal files. Iteration will start from lowest
+    /// sequence-number to highest. Seqnos are unique, an entry whose seqno
+    /// is not greater than the previous entry's, say from journals
+    /// restored over one another, fails the iteration with
+    /// [Error::Invalid] and stops it.
+    pub fn iter(&self) -> Result<impl Iterator<Item = Result<entry::Entry>>> {
+        self.range(..)
+    }
+
+    /// Iterate over entries whose sequence number fall within the
+    /// specified `range`.
+    pub fn range<R>(&self, range: R) -> Result<impl Iterator<Item = Result<entry::Entry>>>
+    where
+        R: ops::RangeBounds<u64>,
+    {
+        self.do_range(range, None)
+    }
+
+    /// Same as [Wal::range], with the range specified as [Lsn] values,
+    /// only the seqno of each bound is considered.
+    pub fn range_lsn<R>(
+        &self,
+        range: R,
+    ) -> Result<impl Iterator<Item = Result<entry::Entry>>>
+    where
+        R: ops::RangeBounds<Lsn>,
+    {
+        let start = range.start_bound().map(|lsn| lsn.to_seqno());
+        let end = range.end_bound().map(|lsn| lsn.to_seqno());
+        self.range((start, end))
+    }
+
+    /// Return the [Lsn] for `seqno`, along with the journal number and the
+    /// file position of the batch holding it. Fail with [Error::NotFound]
+    /// if `seqno` is not yet flushed, or already purged.
+    pub fn lsn(&self, seqno: u64) -> Result<Lsn> {
+        for index in self.indexes()?.into_iter() {
+            let num = index.to_journal_number();
+            let batch = index
+                .iter()
+                .find(|b| (b.to_first_seqno()..=b.to_last_seqno()).contains(&seqno));
+            if let Some(batch) = batch {
+                return Ok(Lsn::with_position(seqno, num, batch.to_fpos()));
+            }
+        }
+        err_at!(NotFound, msg: "seqno {} in {:?}", seqno, self.config.name)
+    }
+
+    /// Iterate over entries added to `topic`, whose sequence number fall
+    /// within the specified `range`.
+    pub fn range_topic<R>(
+        &self,
+        topic: &str,
+        range: R,
+    ) -> Result<impl Iterator<Item = Result<entry::Entry>>>
+    where
+        R: ops::RangeBounds<u64>,
+    {
+        self.do_range(range, Some(topic))
+    }
+
+    /// Iterate over entries tagged with `tag`, refer to [Wal::add_op_tagged],
+    /// whose sequence number fall within the specified `range`. Batches
+    /// without tagged entries are skipped, refer to [Config::set_tag_index]
+    /// for reading tagged entries without decoding whole batches.
+    pub fn find_by_tag<R>(
+        &self,
+        tag: &str,
+        range: R,
+    ) -> Result<impl Iterator<Item = Result<entry::Entry>>>
+    where
+        R: ops::RangeBounds<u64>,
+    {
+        let mut iter = self.do_range_filter(range, None, Some(tag))?;
+        iter.journals = iter
+            .journals
+            .map(|j| j.filter_tag(tag))
+            .collect::<Vec<journal::RdJournal>>()
+            .into_iter();
+        Ok(iter)
+    }
+
+    /// Replay durable entries after `cursor`, calling `f` for each entry,
+    /// and commit the cursor to disk after `f` returns. Return the number
+    /// of entries applied. Replay stops at the first error from `f`, with
+    /// the cursor pointing to the last applied entry.
+    ///
+    /// A consumer that crashes mid-replay resumes from the entry it was
+    /// applying, so that it is never skipped and only that entry can be
+    /// applied twice. Consumers needing strict exactly-once can persist
+    /// the entry's seqno along with its effect, and skip entries upto it.
+    ///
+    /// Cursor from an older epoch resumes from the start of the current
+    /// epoch. Fail with [Error::NotFound] if entries after the cursor are
+    /// already purged. Cursor's progress is acknowledged under its consumer
+    /// name, refer to [Wal::ack].
+    pub fn replay_from_cursor<F>(
+        &self,
+        cursor: &mut cursor::ReplayCursor,
+        mut f: F,
+    ) -> Result<usize>
+    where
+        F: FnMut(&entry::Entry) -> Result<()>,
+    {
+        let epoch = self.epoch()?;
+        let seqno = match cursor.to_epoch() {
+            cepoch if cepoch == epoch => cursor.to_seqno(),
+            cepoch if cepoch < epoch => 0,
+            cepoch => err_at!(Invalid, msg: "cursor epoch {} > {}", cepoch, epoch)?,
+        };
+
+        let Watermarks { durable, purged, .. } = self.watermarks()?;
+        if seqno < purged {
+            err_at!(NotFound, msg: "cursor at {}, purged upto {}", seqno, purged)?
+        }
+
+        let mut n = 0;
+        let res = || -> Result<()> {
+            for entry in self.range((seqno + 1)..=durable)? {
+                let entry = entry?;
+                f(&entry)?;
+                cursor.commit(epoch, entry.to_seqno())?;
+                n += 1;
+            }
+            Ok(())
+        }();
+        if !self.read_only {
+            let acked = if cursor.to_epoch() == epoch { cursor.to_seqno() } else { 0 };
+            self.ack(cursor.as_consumer(), acked)?;
+        }
+        res.map(|_| n)
+    }
+
+    pub(crate) fn do_range<R>(&self, range: R, topic: Option<&str>) -> Result<Iter>
+    where
+        R: ops::RangeBounds<u64>,
+    {
+        self.do_range_filter(range, topic, None)
+    }
+
+    // archived journals whose bloom filter rule out `tag` are skipped.
+    fn do_range_filter<R>(
+        &self,
+        range: R,
+        topic: Option<&str>,
+        tag: Option<&str>,
+    ) -> Result<Iter>
+    where
+        R: ops::RangeBounds<u64>,
+    {
+        let mut masks = vec![];
+        let journals = match Self::range_bound_to_range_inclusive(range) {
+            Some(range) => {
+                self.thaw_range(&range)?;
+                let rd = err_at!(Fatal, self.w.read())?;
+                masks = rd.to_masks();
+                let mut journals = vec![];
+                for jn in rd.journals.iter().filter(|jn| rd.is_current_epoch(jn)) {
+                    let num = jn.to_journal_number();
+                    let overlap = match rd.manifest.to_span(num) {
+                        Some(span) => {
+                            span.start() <= range.end() && range.start() <= span.end()
+                        }
+                        None => true,
+                    };
+                    let overlap = overlap
+                        && tag
+                            .map(|tag| rd.manifest.may_contain_tag(num, tag))
+                            .unwrap_or(true);
+                    let jn = match rd.pinned.get(&num) {
+                        _ if !overlap => continue,
+                        _ if jn.is_cold() => {
+                            err_at!(Invalid, msg: "journal {} is cold, thaw it", num)?
+                        }
+                        Some(entries) => {
+                            journal::RdJournal::from_entries(entries, range.clone())
+                        }
+                        None => journal::RdJournal::from_journal(jn, range.clone())?,
+                    };
+                    journals.push(jn);
+                }
+                journals.push(journal::RdJournal::from_journal(&rd.journal, range)?);
+                match topic {
+                    Some(topic) => {
+                        journals.into_iter().map(|j| j.filter_topic(topic)).collect()
+                    }
+                    None => journals,
+                }
+            }
+            None => vec![],
+        };
+
+        Ok(Iter {
+            journal: None,
+            journals: journals.into_iter(),
+            masks,
+            signal: self.signal.clone(),
+            last: None,
+        })
+    }
+
+    /// Pin decoded entries of archived journals, in the current epoch, whose
+    /// seqnos overlap with `range`, so that subsequent reads from them are
+    /// served from memory without reading journal files. The active journal
+    /// is not pinned. Return the number of journals newly pinned. Pinned
+    /// entries are released by [Wal::unpin_range], or when their journal
+    /// is purged.
+    pub fn pin_range<R>(&self, range: R) -> Result<usize>
+    where
+        R: ops::RangeBounds<u64>,
+    {
+        let range = match Self::range_bound_to_range_inclusive(range) {
+            Some(range) if !range.is_empty() => range,
+            _ => return Ok(0),
+        };
+        // decode journals without holding the lock.
+        let mut readers = vec![];
+        {
+            let rd = err_at!(Fatal, self.w.read())?;
+            for jn in rd.journals.iter().filter(|jn| rd.is_current_epoch(jn)) {
+                let num = jn.to_journal_number();
+                if jn.is_cold() {
+                    continue;
+                }
+                if Self::overlaps(jn, &range) && !rd.pinned.contains_key(&num) {
+                    let reader = journal::RdJournal::from_journal(jn, 0..=u64::MAX)?;
+                    readers.push((num, reader));
+                }
+            }
+        }
+        let mut pinned = vec![];
+        for (num, reader) in readers.into_iter() {
+            let entries = reader.collect::<Result<Vec<entry::Entry>>>()?;
+            pinned.push((num, Arc::new(entries)));
+        }
+
+        let mut w = err_at!(Fatal, self.w.write())?;
+        let mut n = 0;
+        for (num, entries) in pinned.into_iter() {
+            // journal might have been purged in the mean time.
+            if w.journals.iter().any(|jn| jn.to_journal_number() == num) {
+                w.pinned.insert(num, entries);
+                n += 1;
+            }
+        }
+        Ok(n)
+    }
+
+    /// Unpin journals whose seqnos overlap with `range`, refer to
+    /// [Wal::pin_range]. Return the number of journals unpinned.
+    pub fn unpin_range<R>(&self, range: R) -> Result<usize>
+    where
+        R: ops::RangeBounds<u64>,
+    {
+        let range = match Self::range_bound_to_range_inclusive(range) {
+            Some(range) if !range.is_empty() => range,
+            _ => return Ok(0),
+        };
+        let mut w = err_at!(Fatal, self.w.write())?;
+        let nums: Vec<usize> = w
+            .journals
+            .iter()
+            .filter(|jn| w.is_current_epoch(jn) && Self::overlaps(jn, &range))
+            .map(|jn| jn.to_journal_number())
+            .collect();
+        Ok(nums.into_iter().filter(|num| w.pinned.remove(num).is_some()).count())
+    }
+
+    /// Freeze archived journal `num` deep, dropping its batch index and
+    /// state from memory while retaining its seqno span and file size, say
+    /// for historical journals that are rarely read. Reads touching a cold
+    /// journal are handled as per [Config::set_thaw_policy]. Pinned entries
+    /// of the journal are released.
+    pub fn freeze(&self, num: usize) -> Result<()> {
+        let mut w = err_at!(Fatal, self.w.write())?;
+        let off = w.journals.iter().position(|jn| jn.to_journal_number() == num);
+        let off = match off {
+            Some(off) => off,
+            None => {
+                err_at!(NotFound, msg: "archived journal {} for {:?}", num, w.to_name())?
+            }
+        };
+        if !w.journals[off].is_cold() {
+            let journal = w.journals.remove(off).into_cold();
+            w.journals.insert(off, journal);
+        }
+        w.pinned.remove(&num);
+        debug!(target: "wral", "{:?}/{} froze journal {}", w.to_dir(), w.to_name(), num);
+        Ok(())
+    }
+
+    /// Thaw cold journal `num`, frozen by [Wal::freeze], reloading its batch
+    /// index from file so that it can be read again without restarting the
+    /// instance. Thawing an archived journal is a no-op. Return the index.
+    pub fn thaw(&self, num: usize) -> Result<journal::JournalIndex> {
+        match self.tx.request(writer::Req::Thaw { num })? {
+            writer::Res::Ok => (),
+            writer::Res::Fail(err) => return Err(err),
+            res => err_at!(Fatal, msg: "unexpected response {:?}", res)?,
+        }
+        let rd = err_at!(Fatal, self.w.read())?;
+        match rd.journals.iter().find(|jn| jn.to_journal_number() == num) {
+            Some(journal) => Ok(journal.to_journal_index()),
+            None => err_at!(NotFound, msg: "journal {} for {:?}", num, rd.to_name()),
+        }
+    }
+
+    // Thaw cold journals, in the current epoch, overlapping `range` if
+    // thaw policy is Auto.
+    fn thaw_range(&self, range: &ops::RangeInclusive<u64>) -> Result<()> {
+        if self.config.thaw_policy != ThawPolicy::Auto {
+            return Ok(());
+        }
+        let nums: Vec<usize> = {
+            let rd = err_at!(Fatal, self.w.read())?;
+            let iter = rd.journals.iter().filter(|jn| rd.is_current_epoch(jn));
+            iter.filter(|jn| jn.is_cold() && Self::overlaps(jn, range))
+                .map(Journal::to_journal_number)
+                .collect()
+        };
+        for num in nums.into_iter() {
+            self.thaw(num)?;
+        }
+        Ok(())
+    }
+
+    /// Rebuild the index of archived journal `num` by rescanning its file,
+    /// and reinstate it into the set of journals served to readers, say
+    /// after it was ignored on load as [WalEvent::Corruption]. Journals
+    /// don't carry footers, batch boundaries are recovered by decoding
+    /// batches one after the other, damaged bytes are skipped by scanning
+    /// forward for the next batch that decodes and continues the seqno
+    /// order. Journal's seqno span is updated in the manifest. Return the
+    /// rebuilt index.
+    pub fn rebuild_index(&self, num: usize) -> Result<journal::JournalIndex>
+    where
+        S: state::State,
+    {
+        self.check_writable()?;
+
+        // instance might have been relocated, or renamed.
+        let config = err_at!(Fatal, self.w.read())?.to_config();
+        let name = config.name.clone();
+        let file_path = {
+            let journals = files::find_journals(&config.to_journal_dirs(), &name)?;
+            match journals.into_iter().find(|(n, _)| *n == num) {
+                Some((_, file_path)) => file_path.into_os_string(),
+                None => err_at!(NotFound, msg: "journal {} for {:?}", num, name)?,
+            }
+        };
+
+        // rescan without holding the lock.
+        let (policy, max) = (self.config.state_policy, self.config.max_batch_entries);
+        let (mut journal, _, _) = match Journal::rebuild(&name, &file_path, policy, max) {
+            Some(item) => item,
+            None => err_at!(Invalid, msg: "no valid batch in {:?}", file_path)?,
+        };
+        journal.set_mirror(self.config.mirror_dir.as_deref());
+
+        let mut w = err_at!(Fatal, self.w.write())?;
+        let active = w.journal.to_journal_number();
+        if num >= active {
+            err_at!(Invalid, msg: "journal {} is not archived, active {}", num, active)?
+        }
+        if let Some(span) = journal.to_span() {
+            w.manifest.add_span(span);
+        }
+        w.manifest.save(&config.dir)?;
+
+        let index = journal.to_journal_index();
+        w.journals.retain(|jn| jn.to_journal_number() != num);
+        let off = w.journals.iter().take_while(|jn| jn.to_journal_number() < num).count();
+        w.journals.insert(off, journal);
+        w.pinned.remove(&num);
+
+        debug!(
+            target: "wral",
+            "{:?}/{} rebuilt journal {} with {} batches", config.dir, name, num, index.len()
+        );
+        Ok(index)
+    }
+
+    /// Migrate journals to a new format, encoding entries with `codec` and
+    /// compressing ops larger than `compress` bytes, refer to
+    /// [Config::set_codec] and [Config::set_entry_compression]. Journals
+    /// started hereafter are written in the new format, which is recorded
+    /// in the manifest, and archived journals are rewritten one after the
+    /// other while appends continue to the active journal.
+    ///
+    /// Each journal is rewritten aside without holding the lock, and then
+    /// renamed over the original, iterators already reading the original
+    /// continue with it. Cold journals, and journals purged meanwhile, are
+    /// skipped. The active journal is migrated by calling this again after
+    /// it is rotated. Mirror copies are left in the original format. Can
+    /// take a while for large logs, applications can call this from a
+    /// background thread. Return the number of journals rewritten.
+    pub fn migrate_format(&self, codec: Codec, compress: Option<usize>) -> Result<usize>
+    where
+        S: state::State,
+    {
+        self.check_writable()?;
+
+        let (config, journals) = {
+            let mut w = err_at!(Fatal, self.w.write())?;
+            if w.migrating {
+                err_at!(Invalid, msg: "format migration in progress {:?}", w.to_name())?
+            }
+            w.set_format(codec, compress)?;
+            w.migrating = true;
+            let journals: Vec<(usize, ffi::OsString, ffi::OsString)> = w
+                .journals
+                .iter()
+                .filter(|jn| !jn.is_cold())
+                .map(|jn| (jn.to_journal_number(), jn.to_file_path(), jn.to_dir()))
+                .collect();
+            (w.to_config(), journals)
+        };
+
+        let mut n = 0;
+        let mut res = Ok(());
+        for (num, file_path, dir) in journals.into_iter() {
+            match self.migrate_journal(&config, num, &file_path, &dir, codec, compress) {
+                Ok(true) => n += 1,
+                Ok(false) => (),
+                Err(err) => {
+                    res = Err(err);
+                    break;
+                }
+            }
+        }
+        err_at!(Fatal, self.w.write())?.migrating = false;
+        res?;
+
+        debug!(
+            target: "wral",
+            "{:?}/{} migrated {} journals to {:?}", config.dir, config.name, n, codec
+        );
+        Ok(n)
+    }
+
+    // Rewrite archived journal `num` under a migrate directory in `dir`, and
+    // rename it over `file_path`. Return false if the journal was purged,
+    // frozen or moved while it was being rewritten.
+    fn migrate_journal(
+        &self,
+        config: &Config,
+        num: usize,
+        file_path: &ffi::OsStr,
+        dir: &ffi::OsStr,
+        codec: Codec,
+        compress: Option<usize>,
+    ) -> Result<bool>
+    where
+        S: state::State,
+    {
+        // rewritten journal is renamed within the same file system.
+        let migrate_dir = migrate::to_migrate_dir(dir, &config.name);
+        err_at!(IOError, fs::create_dir_all(&migrate_dir))?;
+        let tmp_path = match path::Path::new(file_path).file_name() {
+            Some(file) => migrate_dir.join(file).into_os_string(),
+            None => err_at!(Fatal, msg: "invalid journal file {:?}", file_path)?,
+        };
+
+        migrate::rewrite_journal(file_path, &tmp_path, codec, compress)?;
+        let (policy, max) = (config.state_policy, config.max_batch_entries);
+        let mut journal = match Journal::load(&config.name, &tmp_path, policy, max) {
+            Some((journal, _, _)) => journal,
+            None => err_at!(Corrupted, msg: "rewritten journal {:?}", tmp_path)?,
+        };
+
+        let mut w = err_at!(Fatal, self.w.write())?;
+        let off = w.journals.iter().position(|jn| {
+            jn.to_journal_number() == num
+                && jn.to_file_path() == file_path
+                && !jn.is_cold()
+        });
+        let ok = match off {
+            Some(off) => {
+                journal.replace(file_path)?;
+                util::sync_dir(path::Path::new(dir))?;
+                journal.set_mirror(config.mirror_dir.as_deref());
+                w.journals[off] = journal;
+                true
+            }
+            None => {
+                fs::remove_file(&tmp_path).ok();
+                false
+            }
+        };
+        fs::remove_dir(&migrate_dir).ok();
+
+        Ok(ok)
+    }
+
+    fn overlaps(journal: &Journal<S>, range: &ops::RangeInclusive<u64>) -> bool {
+        match (journal.to_first_seqno(), journal.to_last_seqno()) {
+            (Some(first), Some(last)) => first <= *range.end() && *range.start() <= last,
+            _ => false,
+        }
+    }
+
+    /// Export entries within `range` into `w` as JSON Lines, one object
+    /// per entry with fields `seqno`, `timestamp`, `topic`, `tag` and `op`,
+    /// op encoded as per `encoding`. Tools like jq, or analysis notebooks,
+    /// can consume the output without linking this package. Return the
+    /// number of entries exported.
+    pub fn export_jsonl<R, W>(
+        &self,
+        range: R,
+        encoding: OpEncoding,
+        w: &mut W,
+    ) -> Result<usize>
+    where
+        R: ops::RangeBounds<u64>,
+        W: io::Write,
+    {
+        let mut n = 0;
+        for entry in self.range(range)? {
+            export::write_jsonl(&entry?, encoding, w)?;
+            n += 1;
+        }
+        err_at!(IOError, w.flush())?;
+        Ok(n)
+    }
+
+    /// Same as [Wal::export_jsonl], but entries are exported as a CBOR
+    /// sequence, RFC 8742, one map per entry with op as byte string.
+    pub fn export_cbor_seq<R, W>(&self, range: R, w: &mut W) -> Result<usize>
+    where
+        R: ops::RangeBounds<u64>,
+        W: io::Write,
+    {
+        let mut n = 0;
+        for entry in self.range(range)? {
+            export::write_cbor(&entry?, w)?;
+            n += 1;
+        }
+        err_at!(IOError, w.flush())?;
+        Ok(n)
+    }
+
+    /// Return seqno span and hash of entries, for durable batches in the
+    /// current epoch overlapping `range`. Spans are clipped to `range`, and
+    /// to entries that are not purged. Remote replicas can send this list
+    /// to [Wal::diff] to learn the entries they are missing.
+    pub fn batch_meta<R>(&self, range: R) -> Result<Vec<diff::BatchMeta>>
+    where
+        R: ops::RangeBounds<u64>,
+    {
+        let window = match self.to_ship_window(range)? {
+            Some(window) => window,
+            None => return Ok(vec![]),
+        };
+        let spans = self.to_batch_spans(&window)?;
+        let hashes = diff::hash_ranges(&spans, self.range(window)?)?;
+        let metas = spans.into_iter().zip(hashes).map(|(span, hash)| {
+            let (first_seqno, last_seqno) = span.into_inner();
+            diff::BatchMeta { first_seqno, last_seqno, hash }
+        });
+        Ok(metas.collect())
+    }
+
+    /// Compare `remote` batch metadata, from [Wal::batch_meta] of another
+    /// replica, with local entries and return the plan to ship local
+    /// entries that the remote is missing or holds differently.
+    ///
+    /// Batch boundaries need not match between replicas, each remote span
+    /// is compared with the hash of local entries within the same span.
+    /// Only remote spans within the local durable and un-purged seqnos
+    /// are compared, remote can be ahead of this replica. Fail with
+    /// [Error::Invalid] if remote spans overlap.
+    pub fn diff(&self, remote: &[diff::BatchMeta]) -> Result<diff::ShipPlan> {
+        let remote = diff::sort_remote(remote)?;
+        let window = match self.to_ship_window(..)? {
+            Some(window) => window,
+            None => return Ok(diff::ShipPlan::default()),
+        };
+
+        let compared: Vec<&diff::BatchMeta> = remote
+            .iter()
+            .filter(|m| window.contains(&m.first_seqno) && window.contains(&m.last_seqno))
+            .collect();
+        let ranges: Vec<ops::RangeInclusive<u64>> =
+            compared.iter().map(|m| m.to_range()).collect();
+        let hashes = diff::hash_ranges(&ranges, self.range(window.clone())?)?;
+        let mismatched = compared
+            .into_iter()
+            .zip(hashes)
+            .filter_map(
+                |(m, hash)| if m.hash == hash { None } else { Some(m.to_range()) },
+            )
+            .collect();
+
+        let covered: Vec<ops::RangeInclusive<u64>> =
+            remote.iter().map(|m| m.to_range()).collect();
+        let missing = diff::subtract(&self.to_batch_spans(&window)?, &covered);
+
+        Ok(diff::ShipPlan { missing, mismatched })
+    }
+
+    // Clip `range` to durable seqnos that are not purged.
+    fn to_ship_window<R>(&self, range: R) -> Result<Option<ops::RangeInclusive<u64>>>
+    where
+        R: ops::RangeBounds<u64>,
+    {
+        let wm = self.watermarks()?;
+        let window = Self::range_bound_to_range_inclusive(range).map(|range| {
+            let start = (*range.start()).max(wm.purged + 1).max(1);
+            start..=(*range.end()).min(wm.durable)
+        });
+        Ok(window.filter(|window| !window.is_empty()))
+    }
+
+    // Seqno spans of batches in the current epoch, clipped to `window`.
+    fn to_batch_spans(
+        &self,
+        window: &ops::RangeInclusive<u64>,
+    ) -> Result<Vec<ops::RangeInclusive<u64>>> {
+        let rd = err_at!(Fatal, self.w.read())?;
+        let journals = rd.journals.iter().filter(|j| rd.is_current_epoch(j));
+        // metadata batches repeat the last seqno, count entries only once.
+        let (mut seqno, mut spans) = (0, vec![]);
+        for jn in journals.chain(std::iter::once(&rd.journal)) {
+            for index in jn.to_journal_index().iter() {
+                let first = index.to_first_seqno().max(seqno + 1).max(*window.start());
+                let last = index.to_last_seqno().min(*window.end());
+                if first <= last {
+                    spans.push(first..=last);
+                }
+                seqno = seqno.max(index.to_last_seqno());
+            }
+        }
+        Ok(spans)
+    }
+
+    /// Return a snapshot of batch index for each journal, in journal order,
+    /// including the active journal. Only flushed batches are indexed.
+    /// External tools can use the index to ship byte-ranges of journal
+    /// files without decoding them.
+    pub fn indexes(&self) -> Result<Vec<journal::JournalIndex>> {
+        let rd = err_at!(Fatal, self.w.read())?;
+        let mut indexes: Vec<journal::JournalIndex> =
+            rd.journals.iter().map(|j| j.to_journal_index()).collect();
+        indexes.push(rd.journal.to_journal_index());
+        Ok(indexes)
     }
 
     fn range_bound_to_range_inclusive<R>(range: R) -> Option<ops::RangeInclusive<u64>>
@@ -259,15 +3289,54 @@ impl<S> Wal<S> {
     }
 }
 
-struct Iter {
+pub(crate) struct Iter {
     journal: Option<journal::RdJournal>,
     journals: vec::IntoIter<journal::RdJournal>,
+    // masked seqno ranges, entries within them are skipped.
+    masks: Vec<ops::RangeInclusive<u64>>,
+    signal: ShutdownSignal,
+    // seqno of the last entry, entries are iterated in strictly increasing
+    // seqno order.
+    last: Option<u64>,
 }
 
 impl Iterator for Iter {
     type Item = Result<entry::Entry>;
 
     fn next(&mut self) -> Option<Self::Item> {
+        if self.signal.is_cancelled() {
+            // fail once, and stop.
+            let pending = self.journal.take().is_some() || self.journals.len() > 0;
+            self.journals = vec![].into_iter();
+            return match pending {
+                true => Some(err_at!(Cancelled, msg: "iteration cancelled")),
+                false => None,
+            };
+        }
+        loop {
+            match self.next_entry()? {
+                // duplicate seqno fails once, and stops, instead of either
+                // entry silently shadowing the other.
+                Ok(e) if self.last.is_some_and(|last| e.to_seqno() <= last) => {
+                    let (seqno, last) = (e.to_seqno(), self.last.unwrap());
+                    self.journal = None;
+                    self.journals = vec![].into_iter();
+                    break Some(err_at!(Invalid, msg: "seqno {} after {}", seqno, last));
+                }
+                Ok(e) => {
+                    self.last = Some(e.to_seqno());
+                    if !self.masks.iter().any(|m| m.contains(&e.to_seqno())) {
+                        break Some(Ok(e));
+                    }
+                }
+                item => break Some(item),
+            }
+        }
+    }
+}
+
+impl Iter {
+    fn next_entry(&mut self) -> Option<Result<entry::Entry>> {
         let mut journal = match self.journal.take() {
             Some(journal) => journal,
             None => self.journals.next()?,
@@ -9,8 +9,9 @@ fn test_wal() {
     println!("test_wal {}", seed);
     let mut rng = StdRng::seed_from_u64(seed);
 
+    // config draws a byte or more per field, and its name can take many.
     let mut config: Config = {
-        let bytes = rng.gen::<[u8; 32]>();
+        let bytes: Vec<u8> = (0..1024).map(|_| rng.gen::<u8>()).collect();
         let mut uns = Unstructured::new(&bytes);
         uns.arbitrary().unwrap()
     };
@@ -95,3 +96,3811 @@ fn reader(_id: u128, wal: Wal, ops: usize, seed: u64, entries: Vec<entry::Entry>
         }
     }
 }
+
+#[test]
+fn test_wal_rebase() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-wal-rebase", dir.path().as_ref());
+    config.set_fsync(false);
+
+    let wal = Wal::create(config.clone(), state::NoState).unwrap();
+    assert_eq!(wal.epoch().unwrap(), 0);
+    assert_eq!(wal.remaining_seqnos().unwrap(), u64::MAX - 1);
+
+    for seqno in 1..=10 {
+        assert_eq!(wal.add_op(&[1, 2, 3]).unwrap(), seqno);
+    }
+    assert!(wal.rebase(0).is_err());
+    assert_eq!(wal.rebase(1).unwrap(), 10);
+    assert_eq!(wal.epoch().unwrap(), 1);
+    assert!(wal.rebase(1).is_err());
+    assert_eq!(wal.iter().unwrap().count(), 0);
+
+    assert_eq!(wal.add_op(&[4, 5, 6]).unwrap(), 1);
+    assert_eq!(wal.iter().unwrap().count(), 1);
+    assert_eq!(wal.close(false).unwrap(), CloseOutcome::Closed(1));
+
+    let wal: Wal = Wal::load(config).unwrap();
+    assert_eq!(wal.epoch().unwrap(), 1);
+    assert_eq!(wal.add_op(&[7, 8, 9]).unwrap(), 2);
+    let seqnos: Vec<u64> = wal.iter().unwrap().map(|e| e.unwrap().to_seqno()).collect();
+    assert_eq!(seqnos, vec![1, 2]);
+
+    wal.close(true).unwrap();
+}
+
+#[test]
+fn test_wal_fenced() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-wal-fenced", dir.path().as_ref());
+    config.set_journal_limit(1000).set_fsync(false);
+
+    let wal = Wal::create(config.clone(), state::NoState).unwrap();
+    for _i in 0..100 {
+        wal.add_op(&[0; 32]).unwrap();
+    }
+    assert_eq!(wal.close(false).unwrap(), CloseOutcome::Closed(100));
+
+    // restore a stale copy of the oldest journal as the latest journal.
+    let journals = files::find_journals(&config.to_journal_dirs(), &config.name).unwrap();
+    let (_, file_path) = journals.iter().min().cloned().unwrap();
+    let last = journals.iter().map(|(num, _)| *num).max().unwrap();
+    let file = files::make_filename(config.name.clone(), last + 1, config.journal_width);
+    let mut stale = path::PathBuf::from(&config.dir);
+    stale.push(file);
+    std::fs::copy(&file_path, &stale).unwrap();
+
+    let wal: Wal = Wal::load(config.clone()).unwrap();
+    assert_eq!(wal.health().unwrap().state, HealthState::Fenced);
+    match wal.add_op(&[1]) {
+        Err(Error::Fenced(_, _)) => (),
+        res => panic!("expected Fenced, {:?}", res),
+    }
+    match wal.purge_till(10, "test") {
+        Err(Error::Fenced(_, _)) => (),
+        res => panic!("expected Fenced, {:?}", res),
+    }
+    assert!(wal.iter().unwrap().count() > 0);
+
+    assert_eq!(wal.rebase(1).unwrap(), 100);
+    assert_eq!(wal.add_op(&[1]).unwrap(), 1);
+    assert_eq!(wal.health().unwrap().state, HealthState::Running);
+    wal.close(true).unwrap();
+}
+
+#[test]
+fn test_wal_close_outcome() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-wal-close-outcome", dir.path().as_ref());
+    config.set_fsync(false);
+
+    let wal = Wal::create(config.clone(), state::NoState).unwrap();
+    let (a, b) = (wal.clone(), wal.clone());
+    assert_eq!(wal.add_op(&[1, 2, 3]).unwrap(), 1);
+    assert_eq!(a.close(true).unwrap(), CloseOutcome::Deferred { remaining_clones: 2 });
+    assert_eq!(b.try_purge().unwrap(), CloseOutcome::Deferred { remaining_clones: 1 });
+    assert_eq!(wal.add_op(&[4, 5, 6]).unwrap(), 2);
+    assert_eq!(wal.close(false).unwrap(), CloseOutcome::Closed(2));
+
+    let wal: Wal = Wal::load(config.clone()).unwrap();
+    assert_eq!(wal.iter().unwrap().count(), 2);
+    assert_eq!(wal.try_purge().unwrap(), CloseOutcome::Purged(2));
+    assert!(!Wal::<state::NoState>::exists(&config).unwrap());
+}
+
+#[test]
+fn test_wal_relocate() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-wal-relocate", dir.path().as_ref());
+    config.set_journal_limit(1000).set_fsync(false);
+
+    let wal = Wal::create(config.clone(), state::NoState).unwrap();
+    for _i in 0..100 {
+        wal.add_op(&[0; 32]).unwrap();
+    }
+
+    let new_dir = tempfile::tempdir().unwrap();
+    wal.relocate(new_dir.path().as_ref()).unwrap();
+    for _i in 0..100 {
+        wal.add_op(&[0; 32]).unwrap();
+    }
+    wal.rename("test-wal-renamed").unwrap();
+    assert_eq!(wal.iter().unwrap().count(), 200);
+    assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+    assert_eq!(wal.close(false).unwrap(), CloseOutcome::Closed(200));
+
+    let mut config = Config::new("test-wal-renamed", new_dir.path().as_ref());
+    config.set_fsync(false);
+    let wal: Wal = Wal::load(config).unwrap();
+    let seqnos: Vec<u64> = wal.iter().unwrap().map(|e| e.unwrap().to_seqno()).collect();
+    assert_eq!(seqnos, (1..=200).collect::<Vec<u64>>());
+
+    wal.close(true).unwrap();
+}
+
+#[test]
+fn test_wal_quota() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-wal-quota", dir.path().as_ref());
+    config
+        .set_fsync(false)
+        .set_quota("tenant-a", Quota { bytes_per_sec: 0, total_bytes: 100 });
+
+    let wal = Wal::create(config, state::NoState).unwrap();
+    let (a, b) = (wal.labeled_clone("tenant-a"), wal.labeled_clone("tenant-b"));
+    assert_eq!(a.add_op(&[0; 60]).unwrap(), 1);
+    match a.add_op(&[0; 50]) {
+        Err(Error::OverQuota(_, _)) => (),
+        res => panic!("expected OverQuota, {:?}", res),
+    }
+    // clones are accounted to the same identity.
+    let c = a.clone();
+    let mut bw = c.buffered_writer(2);
+    assert_eq!(bw.add_op(&[0; 20]).unwrap(), None);
+    match bw.add_op(&[0; 30]) {
+        Err(Error::OverQuota(_, _)) => (),
+        res => panic!("expected OverQuota, {:?}", res),
+    }
+    drop(bw);
+    // over-quota requests are not assigned seqnos.
+    assert_eq!(b.add_op(&[0; 1000]).unwrap(), 2);
+    assert_eq!(wal.add_op(&[0; 1000]).unwrap(), 3);
+    assert_eq!(a.add_op(&[0; 40]).unwrap(), 4);
+    assert!(a.add_op(&[0]).is_err());
+
+    for w in vec![a, b, c].into_iter() {
+        w.close(false).unwrap();
+    }
+    wal.close(true).unwrap();
+}
+
+#[test]
+fn test_wal_client_batch_limit() {
+    let seed: u64 = random();
+    println!("test_wal_client_batch_limit {}", seed);
+
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-wal-client-limit", dir.path().as_ref());
+    config.set_fsync(false).set_client_batch_limit(1);
+
+    let val = Wal::create(config, state::NoState).unwrap();
+
+    let mut writers = vec![];
+    for id in 0..4 {
+        let wal = val.clone();
+        writers
+            .push(std::thread::spawn(move || writer(id, wal, 500, seed + (id as u64))));
+    }
+    let mut entries: Vec<entry::Entry> =
+        writers.into_iter().flat_map(|h| h.join().unwrap()).collect();
+    entries.sort();
+
+    let items: Vec<entry::Entry> = val.iter().unwrap().map(|x| x.unwrap()).collect();
+    assert_eq!(items, entries);
+
+    val.close(true).unwrap();
+}
+
+#[test]
+fn test_wal_max_batch_requests() {
+    let seed: u64 = random();
+    println!("test_wal_max_batch_requests {}", seed);
+
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-wal-max-batch-requests", dir.path().as_ref());
+    config.set_fsync(false).set_max_batch_requests(2);
+
+    let val = Wal::create(config, state::NoState).unwrap();
+
+    let mut writers = vec![];
+    for id in 0..4 {
+        let wal = val.clone();
+        writers
+            .push(std::thread::spawn(move || writer(id, wal, 500, seed + (id as u64))));
+    }
+    let mut entries: Vec<entry::Entry> =
+        writers.into_iter().flat_map(|h| h.join().unwrap()).collect();
+    entries.sort();
+
+    let items: Vec<entry::Entry> = val.iter().unwrap().map(|x| x.unwrap()).collect();
+    assert_eq!(items, entries);
+
+    // every batch carry atmost two single-op requests.
+    for index in val.indexes().unwrap().iter().flat_map(|jn| jn.iter()) {
+        assert!(index.to_last_seqno() - index.to_first_seqno() < 2, "{:?}", index);
+    }
+    let stats = val.stats().unwrap();
+    assert!(stats.queue_time > time::Duration::default(), "{:?}", stats);
+    assert!(stats.max_queue_time > time::Duration::default(), "{:?}", stats);
+
+    val.close(true).unwrap();
+}
+
+#[test]
+fn test_wal_wait_for() {
+    use std::time::Duration;
+
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-wal-wait-for", dir.path().as_ref());
+    config.set_fsync(false);
+
+    let wal = Wal::create(config, state::NoState).unwrap();
+    assert_eq!(wal.durable_seqno().unwrap(), 0);
+
+    let waiter = {
+        let wal = wal.clone();
+        std::thread::spawn(move || wal.wait_for(10, Duration::from_secs(10)).unwrap())
+    };
+    for _i in 0..10 {
+        wal.add_op(&[1, 2, 3]).unwrap();
+    }
+    assert!(waiter.join().unwrap() >= 10);
+    assert_eq!(wal.durable_seqno().unwrap(), 10);
+
+    match wal.wait_for(11, Duration::from_millis(10)) {
+        Err(Error::Timeout(_, _)) => (),
+        res => panic!("unexpected {:?}", res),
+    }
+
+    wal.close(true).unwrap();
+}
+
+#[test]
+fn test_wal_wait_for_rebase() {
+    use std::time::Duration;
+
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-wal-wait-for-rebase", dir.path().as_ref());
+    config.set_fsync(false);
+
+    let wal = Wal::create(config, state::NoState).unwrap();
+    for _i in 0..10 {
+        wal.add_op(&[1, 2, 3]).unwrap();
+    }
+    let waiter = {
+        let wal = wal.clone();
+        std::thread::spawn(move || wal.wait_for(20, Duration::from_secs(10)))
+    };
+    std::thread::sleep(Duration::from_millis(100));
+    assert_eq!(wal.rebase(1).unwrap(), 10);
+    for _i in 0..20 {
+        wal.add_op(&[1, 2, 3]).unwrap();
+    }
+    // seqno 20 of the new epoch is not the seqno waited upon.
+    match waiter.join().unwrap() {
+        Err(Error::Invalid(_, _)) => (),
+        res => panic!("unexpected {:?}", res),
+    }
+    assert_eq!(wal.wait_for(20, Duration::from_secs(10)).unwrap(), 20);
+
+    wal.close(true).unwrap();
+}
+
+#[test]
+fn test_wal_add_op_deadline() {
+    use std::time::{Duration, Instant};
+
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-wal-add-op-deadline", dir.path().as_ref());
+    config.set_fsync(false);
+
+    let wal = Wal::create(config, state::NoState).unwrap();
+    let deadline = Instant::now() + Duration::from_secs(10);
+    assert_eq!(wal.add_op_deadline(&[1], deadline).unwrap(), 1);
+
+    // stall the writer, by holding on to the journals.
+    {
+        let _guard = wal.w.write().unwrap();
+        let deadline = Instant::now() + Duration::from_millis(50);
+        match wal.add_op_deadline(&[2], deadline) {
+            Err(Error::Timeout(_, _)) => (),
+            res => panic!("unexpected {:?}", res),
+        }
+        assert!(Instant::now() >= deadline);
+    }
+
+    // writer survives the dropped response, and commits the entry.
+    assert_eq!(wal.add_op(&[3]).unwrap(), 3);
+    let ops: Vec<Vec<u8>> = wal.iter().unwrap().map(|e| e.unwrap().unwrap().1).collect();
+    assert_eq!(ops, vec![vec![1], vec![2], vec![3]]);
+
+    wal.close(true).unwrap();
+}
+
+#[test]
+fn test_wal_instance_id() {
+    let dir_a = tempfile::tempdir().unwrap();
+    let dir_b = tempfile::tempdir().unwrap();
+    let name = "test-wal-instance-id";
+
+    let mut configs = vec![];
+    let mut ids = vec![];
+    for dir in [&dir_a, &dir_b].iter() {
+        let mut config = Config::new(name, dir.path().as_ref());
+        config.set_journal_limit(1000).set_fsync(false);
+        let wal = Wal::create(config.clone(), state::NoState).unwrap();
+        for _i in 0..100 {
+            wal.add_op(&[0; 32]).unwrap();
+        }
+        let id = wal.instance_id().unwrap();
+        assert_eq!(id.len(), 36, "{}", id);
+        assert_eq!(&id[14..15], "4", "{}", id);
+        wal.close(false).unwrap();
+        configs.push(config);
+        ids.push(id);
+    }
+    assert_ne!(ids[0], ids[1]);
+
+    let wal: Wal = Wal::load(configs[0].clone()).unwrap();
+    assert_eq!(wal.instance_id().unwrap(), ids[0]);
+    wal.close(false).unwrap();
+
+    // manifest rebuilt without instance id adopts the journals' id.
+    let manifest = files::make_manifest_filename(name);
+    std::fs::remove_file(dir_a.path().join(&manifest)).unwrap();
+    let wal: Wal = Wal::load(configs[0].clone()).unwrap();
+    assert_eq!(wal.instance_id().unwrap(), ids[0]);
+    assert_eq!(wal.iter().unwrap().count(), 100);
+    wal.close(false).unwrap();
+
+    // restore a journal from the other instance into this directory.
+    let index = Wal::<state::NoState>::open(configs[1].clone(), OpenMode::ReadOnly)
+        .map(|wal| {
+            let indexes = wal.indexes().unwrap();
+            wal.close(false).unwrap();
+            indexes
+        })
+        .unwrap();
+    let file_path = path::Path::new(&index[1].to_file_path()).to_path_buf();
+    let to = dir_a.path().join(file_path.file_name().unwrap());
+    std::fs::copy(&file_path, &to).unwrap();
+    match Wal::<state::NoState>::load(configs[0].clone()) {
+        Err(Error::Mismatch(_, msg)) => assert!(msg.contains(&ids[1]), "{}", msg),
+        Err(err) => panic!("unexpected {}", err),
+        Ok(_) => panic!("expected Mismatch"),
+    }
+}
+
+#[test]
+fn test_wal_indexes() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-wal-indexes", dir.path().as_ref());
+    config.set_journal_limit(1000).set_fsync(false);
+
+    let wal = Wal::create(config, state::NoState).unwrap();
+    for _i in 0..100 {
+        wal.add_op(&[0; 32]).unwrap();
+    }
+
+    let indexes = wal.indexes().unwrap();
+    assert!(indexes.len() > 1);
+    let mut seqno = 0;
+    for (i, jindex) in indexes.into_iter().enumerate() {
+        assert_eq!(jindex.to_journal_number(), i);
+        let len = std::fs::metadata(jindex.to_file_path()).unwrap().len();
+        let mut fpos = 0;
+        for index in jindex.into_iter() {
+            assert_eq!(index.to_fpos(), fpos);
+            assert_eq!(index.to_first_seqno(), seqno + 1);
+            fpos += index.to_length() as u64;
+            seqno = index.to_last_seqno();
+        }
+        assert_eq!(fpos, len);
+    }
+    assert_eq!(seqno, 100);
+
+    wal.close(true).unwrap();
+}
+
+#[test]
+fn test_wal_topics() {
+    let seed: u64 = random();
+    println!("test_wal_topics {}", seed);
+    let mut rng = StdRng::seed_from_u64(seed);
+
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-wal-topics", dir.path().as_ref());
+    config.set_journal_limit(10_000).set_fsync(false);
+
+    let wal = Wal::create(config.clone(), state::NoState).unwrap();
+    let topics = ["", "alpha", "beta", "gamma"];
+    let mut entries: Vec<(&str, u64)> = vec![];
+    for _i in 0..1000 {
+        let topic = topics[rng.gen::<usize>() % topics.len()];
+        entries.push((topic, wal.add_op_to(topic, &[1, 2, 3]).unwrap()));
+    }
+    wal.close(false).unwrap();
+
+    let wal: Wal = Wal::load(config).unwrap();
+    for topic in topics.iter() {
+        let (x, y) = (rng.gen::<u64>() % 1000, rng.gen::<u64>() % 1000);
+        let refs: Vec<u64> = entries
+            .iter()
+            .filter(|(t, seqno)| t == topic && x <= *seqno && *seqno < y)
+            .map(|(_, seqno)| *seqno)
+            .collect();
+        let items: Vec<u64> = wal
+            .range_topic(topic, x..y)
+            .unwrap()
+            .map(|e| {
+                let e = e.unwrap();
+                assert_eq!(e.as_topic(), *topic);
+                e.to_seqno()
+            })
+            .collect();
+        assert_eq!(items, refs);
+    }
+    assert_eq!(wal.iter().unwrap().count(), 1000);
+
+    wal.close(true).unwrap();
+}
+
+#[test]
+fn test_wal_find_by_tag() {
+    let seed: u64 = random();
+    println!("test_wal_find_by_tag {}", seed);
+    let mut rng = StdRng::seed_from_u64(seed);
+
+    let codecs = [(Codec::Cbor, true), (Codec::Cbor, false), (Codec::Compact, true)];
+    for (codec, tag_index) in codecs.iter() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = Config::new("test-wal-find-by-tag", dir.path().as_ref());
+        config
+            .set_journal_limit(10_000)
+            .set_fsync(false)
+            .set_codec(*codec)
+            .set_tag_index(*tag_index);
+
+        let wal = Wal::create(config.clone(), state::NoState).unwrap();
+        let tags = ["", "user-1", "user-2", "user-3"];
+        let mut entries: Vec<(&str, u64)> = vec![];
+        for _i in 0..1000 {
+            let tag = tags[rng.gen::<usize>() % tags.len()];
+            entries.push((tag, wal.add_op_tagged(tag, &[1, 2, 3]).unwrap()));
+        }
+        wal.close(false).unwrap();
+
+        let wal: Wal = Wal::load(config).unwrap();
+        for tag in tags[1..].iter() {
+            let (x, y) = (rng.gen::<u64>() % 1000, rng.gen::<u64>() % 1000);
+            let refs: Vec<u64> = entries
+                .iter()
+                .filter(|(t, seqno)| t == tag && x <= *seqno && *seqno < y)
+                .map(|(_, seqno)| *seqno)
+                .collect();
+            let items: Vec<u64> = wal
+                .find_by_tag(tag, x..y)
+                .unwrap()
+                .map(|e| {
+                    let e = e.unwrap();
+                    assert_eq!(e.as_tag(), *tag);
+                    e.to_seqno()
+                })
+                .collect();
+            assert_eq!(items, refs, "{:?} {}", codec, tag_index);
+        }
+        assert_eq!(wal.find_by_tag("user-4", ..).unwrap().count(), 0);
+
+        wal.close(true).unwrap();
+    }
+}
+
+#[test]
+fn test_wal_find_by_tag_bloom() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-wal-find-by-tag-bloom", dir.path().as_ref());
+    config.set_journal_limit(1000).set_fsync(false);
+
+    let wal = Wal::create(config.clone(), state::NoState).unwrap();
+    let mut refs = vec![];
+    for i in 0..500 {
+        match i % 97 {
+            0 => refs.push(wal.add_op_tagged("rare", &[1, 2, 3]).unwrap()),
+            _ => {
+                wal.add_op_tagged("common", &[1, 2, 3]).unwrap();
+            }
+        }
+    }
+    wal.close(false).unwrap();
+
+    let wal: Wal = Wal::load(config).unwrap();
+    let (n, skipped) = {
+        let rd = wal.w.read().unwrap();
+        let nums = rd.journals.iter().map(|jn| jn.to_journal_number());
+        let skipped =
+            nums.filter(|num| !rd.manifest.may_contain_tag(*num, "rare")).count();
+        (rd.journals.len(), skipped)
+    };
+    assert!(n > refs.len(), "{} journals", n);
+    assert!(skipped >= n - refs.len(), "{}/{} journals skipped", skipped, n);
+
+    let items: Vec<u64> =
+        wal.find_by_tag("rare", ..).unwrap().map(|e| e.unwrap().to_seqno()).collect();
+    assert_eq!(items, refs);
+    assert_eq!(wal.find_by_tag("none", ..).unwrap().count(), 0);
+
+    wal.close(true).unwrap();
+}
+
+#[test]
+fn test_wal_add_op_owned() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-wal-add-op-owned", dir.path().as_ref());
+    config.set_fsync(false);
+
+    let wal = Wal::create(config, state::NoState).unwrap();
+    assert_eq!(wal.add_op_owned(vec![1, 2, 3]).unwrap(), 1);
+    assert_eq!(wal.add_op_owned(vec![4, 5].into_boxed_slice()).unwrap(), 2);
+    assert_eq!(wal.add_op_owned("hello".to_string()).unwrap(), 3);
+
+    let ops: Vec<Vec<u8>> = wal.iter().unwrap().map(|e| e.unwrap().unwrap().1).collect();
+    assert_eq!(ops, vec![vec![1, 2, 3], vec![4, 5], b"hello".to_vec()]);
+
+    wal.close(true).unwrap();
+}
+
+#[cfg(feature = "uring")]
+#[test]
+fn test_wal_uring() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-wal-uring", dir.path().as_ref());
+    config.set_journal_limit(100).set_backend(crate::Backend::Uring);
+
+    let wal = Wal::create(config.clone(), state::NoState).unwrap();
+    for i in 0..100_u64 {
+        assert_eq!(wal.add_op(&i.to_be_bytes()).unwrap(), i + 1);
+    }
+    wal.close(false).unwrap();
+
+    let wal: Wal = Wal::load(config).unwrap();
+    let seqnos: Vec<u64> = wal.iter().unwrap().map(|e| e.unwrap().to_seqno()).collect();
+    assert_eq!(seqnos, (1..=100).collect::<Vec<u64>>());
+    assert_eq!(wal.add_op(b"hello").unwrap(), 101);
+
+    wal.close(true).unwrap();
+}
+
+#[test]
+fn test_wal_purge_till() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-wal-purge-till", dir.path().as_ref());
+    config.set_journal_limit(1000).set_fsync(false);
+
+    let wal = Wal::create(config.clone(), state::NoState).unwrap();
+    for _i in 0..100 {
+        wal.add_op(&[0; 32]).unwrap();
+    }
+    assert_eq!(wal.purge_till(0, "nothing").unwrap(), None);
+    assert!(wal.purge_history().unwrap().is_empty());
+
+    let tombstone = wal.purge_till(50, "retention").unwrap().unwrap();
+    assert!(tombstone.to_seqno() <= 50);
+    assert_eq!(tombstone.to_epoch(), 0);
+    assert_eq!(tombstone.as_reason(), "retention");
+    assert!(tombstone.to_timestamp() <= std::time::SystemTime::now());
+
+    let seqnos: Vec<u64> = wal.iter().unwrap().map(|e| e.unwrap().to_seqno()).collect();
+    let want: Vec<u64> = ((tombstone.to_seqno() + 1)..=100).collect();
+    assert_eq!(seqnos, want);
+
+    wal.rebase(1).unwrap();
+    wal.add_op(&[1, 2, 3]).unwrap();
+    let tombstone = wal.purge_till(0, "older epoch").unwrap().unwrap();
+    assert_eq!(tombstone.to_seqno(), 100);
+    assert_eq!(wal.purge_history().unwrap().len(), 2);
+    assert_eq!(wal.close(false).unwrap(), CloseOutcome::Closed(1));
+
+    let report = Wal::fsck(&config, fsck::FsckLevel::ReportOnly).unwrap();
+    assert!(report.is_clean(), "{:?}", report);
+
+    let wal: Wal = Wal::load(config).unwrap();
+    let history = wal.purge_history().unwrap();
+    let reasons: Vec<&str> = history.iter().map(|t| t.as_reason()).collect();
+    assert_eq!(reasons, vec!["retention", "older epoch"]);
+    assert_eq!(wal.add_op(&[4, 5, 6]).unwrap(), 2);
+    let seqnos: Vec<u64> = wal.iter().unwrap().map(|e| e.unwrap().to_seqno()).collect();
+    assert_eq!(seqnos, vec![1, 2]);
+
+    wal.close(true).unwrap();
+}
+
+#[test]
+fn test_wal_mirror() {
+    let (dir, mirror_dir) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+    let mut config = Config::new("test-wal-mirror", dir.path().as_ref());
+    config
+        .set_journal_limit(1000)
+        .set_fsync(false)
+        .set_mirror(mirror_dir.path().as_ref(), MirrorPolicy::FailStop);
+
+    let wal = Wal::create(config.clone(), state::NoState).unwrap();
+    for _i in 0..100 {
+        wal.add_op(&[0; 32]).unwrap();
+    }
+    wal.rename("test-wal-mirror-renamed").unwrap();
+    assert_eq!(wal.close(false).unwrap(), CloseOutcome::Closed(100));
+
+    // mirror has a byte-for-byte copy of every journal.
+    let mut names = vec![];
+    for item in std::fs::read_dir(mirror_dir.path()).unwrap() {
+        let item = item.unwrap();
+        let primary = std::fs::read(dir.path().join(item.file_name())).unwrap();
+        assert_eq!(std::fs::read(item.path()).unwrap(), primary);
+        names.push(item.file_name());
+    }
+    assert!(names.len() > 1);
+
+    // load from mirror alone.
+    let mut mconfig = Config::new("test-wal-mirror-renamed", mirror_dir.path().as_ref());
+    mconfig.set_fsync(false);
+    let wal: Wal = Wal::load(mconfig).unwrap();
+    assert_eq!(wal.iter().unwrap().count(), 100);
+    wal.close(false).unwrap();
+
+    // degrade when mirror is not available.
+    let mut config = Config::new("test-wal-mirror-renamed", dir.path().as_ref());
+    let bad_dir = dir.path().join("missing-file").join("mirror");
+    config.set_fsync(false).set_mirror(bad_dir.as_ref(), MirrorPolicy::FailStop);
+    assert!(Wal::<state::NoState>::load(config.clone()).is_err());
+    config.set_mirror(bad_dir.as_ref(), MirrorPolicy::Degrade);
+    let wal: Wal = Wal::load(config).unwrap();
+    assert_eq!(wal.add_op(&[1]).unwrap(), 101);
+    wal.close(true).unwrap();
+}
+
+#[test]
+fn test_wal_mask_range() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-wal-mask-range", dir.path().as_ref());
+    config.set_journal_limit(1000).set_fsync(false);
+
+    let wal = Wal::create(config.clone(), state::NoState).unwrap();
+    for _i in 0..100 {
+        wal.add_op(&[0; 32]).unwrap();
+    }
+    wal.mask_range(10..20).unwrap();
+    wal.mask_range(15..=30).unwrap();
+    wal.mask_range(90..).unwrap();
+    wal.mask_range(5..5).unwrap();
+    assert_eq!(wal.masks().unwrap(), vec![10..=30, 90..=u64::MAX]);
+
+    wal.unmask_range(20..=25).unwrap();
+    assert_eq!(wal.masks().unwrap(), vec![10..=19, 26..=30, 90..=u64::MAX]);
+
+    let visible = |wal: &Wal| -> Vec<u64> {
+        wal.iter().unwrap().map(|e| e.unwrap().to_seqno()).collect()
+    };
+    let mut want: Vec<u64> = (1..10).collect();
+    want.extend(20..=25);
+    want.extend(31..90);
+    assert_eq!(visible(&wal), want);
+    assert_eq!(wal.range(8..12).unwrap().count(), 2);
+
+    // new entries beyond an open-ended mask are hidden as well.
+    assert_eq!(wal.add_op(&[1]).unwrap(), 101);
+    assert_eq!(visible(&wal), want);
+    assert_eq!(wal.close(false).unwrap(), CloseOutcome::Closed(101));
+
+    let wal: Wal = Wal::load(config).unwrap();
+    assert_eq!(wal.masks().unwrap(), vec![10..=19, 26..=30, 90..=u64::MAX]);
+    wal.unmask_range(..).unwrap();
+    assert!(wal.masks().unwrap().is_empty());
+    assert_eq!(visible(&wal), (1..=101).collect::<Vec<u64>>());
+
+    wal.close(true).unwrap();
+}
+
+#[test]
+fn test_wal_close_drains() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-wal-close-drains", dir.path().as_ref());
+    config.set_fsync(false).set_client_batch_limit(1);
+
+    let wal = Wal::create(config.clone(), state::NoState).unwrap();
+    for i in 0..100_u8 {
+        let req = writer::Req::AddEntry {
+            client: 0,
+            label: String::default(),
+            topic: String::default(),
+            tag: String::default(),
+            op: vec![i],
+            queued: time::Instant::now(),
+        };
+        wal.tx.post(req).unwrap();
+    }
+    // posted requests are queued ahead of shutdown, and flushed.
+    assert_eq!(wal.close(false).unwrap(), CloseOutcome::Closed(100));
+
+    let wal: Wal = Wal::load(config).unwrap();
+    let ops: Vec<Vec<u8>> = wal.iter().unwrap().map(|e| e.unwrap().unwrap().1).collect();
+    assert_eq!(ops, (0..100_u8).map(|i| vec![i]).collect::<Vec<Vec<u8>>>());
+    wal.close(true).unwrap();
+}
+
+#[test]
+fn test_wal_open() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-wal-open", dir.path().as_ref());
+    config.set_fsync(false);
+
+    for mode in [OpenMode::LoadOnly, OpenMode::ReadOnly, OpenMode::Attach].iter() {
+        match Wal::<state::NoState>::open(config.clone(), *mode) {
+            Err(Error::NotFound(_, _)) => (),
+            _ => panic!("expected NotFound for {:?}", mode),
+        }
+    }
+
+    let wal: Wal = Wal::open(config.clone(), OpenMode::CreateOrLoad).unwrap();
+    for i in 0..10_u8 {
+        wal.add_op(&[i]).unwrap();
+    }
+    wal.close(false).unwrap();
+
+    match Wal::<state::NoState>::open(config.clone(), OpenMode::CreateNew) {
+        Err(Error::AlreadyExists(_, _)) => (),
+        _ => panic!("expected AlreadyExists"),
+    }
+
+    let n_files = fs::read_dir(dir.path()).unwrap().count();
+    let wal: Wal = Wal::open(config.clone(), OpenMode::ReadOnly).unwrap();
+    let ops: Vec<Vec<u8>> = wal.iter().unwrap().map(|e| e.unwrap().unwrap().1).collect();
+    assert_eq!(ops, (0..10_u8).map(|i| vec![i]).collect::<Vec<Vec<u8>>>());
+    match wal.add_op(&[10]) {
+        Err(Error::ReadOnly(_, _)) => (),
+        res => panic!("expected ReadOnly, {:?}", res),
+    }
+    wal.close(false).unwrap();
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), n_files);
+
+    let wal: Wal = Wal::open(config, OpenMode::CreateOrLoad).unwrap();
+    assert_eq!(wal.iter().unwrap().count(), 10);
+    wal.close(true).unwrap();
+}
+
+#[test]
+fn test_wal_attach() {
+    use std::io::Write;
+
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-wal-attach", dir.path().as_ref());
+    config.set_journal_limit(1000).set_fsync(false);
+
+    let writer: Wal = Wal::create(config.clone(), state::NoState).unwrap();
+    for i in 0..50_u8 {
+        writer.add_op(&[i; 32]).unwrap();
+    }
+
+    let reader: Wal = Wal::open(config.clone(), OpenMode::Attach).unwrap();
+    assert_eq!(reader.durable_seqno().unwrap(), 50);
+    assert_eq!(reader.iter().unwrap().count(), 50);
+    match reader.add_op(&[1]) {
+        Err(Error::ReadOnly(_, _)) => (),
+        res => panic!("expected ReadOnly, {:?}", res),
+    }
+
+    // observe journals rotated and purged by the writer.
+    for i in 50..100_u8 {
+        writer.add_op(&[i; 32]).unwrap();
+    }
+    writer.purge_till(20, "test").unwrap().unwrap();
+    assert_eq!(reader.durable_seqno().unwrap(), 50);
+    assert_eq!(reader.refresh().unwrap(), 100);
+    assert_eq!(reader.durable_seqno().unwrap(), 100);
+    let first = writer.iter().unwrap().next().unwrap().unwrap().to_seqno();
+    let seqnos: Vec<u64> =
+        reader.iter().unwrap().map(|e| e.unwrap().to_seqno()).collect();
+    assert_eq!(seqnos, (first..=100).collect::<Vec<u64>>());
+    assert_eq!(reader.purge_history().unwrap().len(), 1);
+    reader.close(false).unwrap();
+
+    let readonly: Wal = Wal::open(config.clone(), OpenMode::ReadOnly).unwrap();
+    match readonly.refresh() {
+        Err(Error::Invalid(_, _)) => (),
+        res => panic!("expected Invalid, {:?}", res),
+    }
+    readonly.close(false).unwrap();
+
+    // partial batch at the tail of the latest journal, being written.
+    writer.add_op(&[100; 32]).unwrap();
+    let indexes = writer.indexes().unwrap();
+    let latest = indexes.last().unwrap();
+    let batch = latest.iter().last().unwrap();
+    let data = fs::read(latest.to_file_path()).unwrap();
+    let (fpos, length) = (batch.to_fpos() as usize, batch.to_length());
+    let partial = data[fpos..fpos + (length / 2)].to_vec();
+    let mut file =
+        fs::OpenOptions::new().append(true).open(latest.to_file_path()).unwrap();
+    file.write_all(&partial).unwrap();
+
+    let reader: Wal = Wal::open(config.clone(), OpenMode::Attach).unwrap();
+    assert_eq!(reader.durable_seqno().unwrap(), 101);
+    let seqnos: Vec<u64> =
+        reader.iter().unwrap().map(|e| e.unwrap().to_seqno()).collect();
+    assert_eq!(seqnos, (first..=101).collect::<Vec<u64>>());
+    reader.close(false).unwrap();
+
+    // without attach, the latest journal is treated as corrupted.
+    let readonly: Wal = Wal::open(config, OpenMode::ReadOnly).unwrap();
+    let events: Vec<WalEvent> = readonly.events().unwrap().try_iter().collect();
+    assert!(events.iter().any(|e| matches!(e, WalEvent::Corruption { .. })));
+    assert!(readonly.durable_seqno().unwrap() < 101);
+    readonly.close(false).unwrap();
+
+    writer.close(true).unwrap();
+}
+
+#[test]
+fn test_wal_events() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-wal-events", dir.path().as_ref());
+    config.set_journal_limit(1000).set_fsync(false);
+
+    let wal = Wal::create(config.clone(), state::NoState).unwrap();
+    for _i in 0..100 {
+        wal.add_op(&[0; 32]).unwrap();
+    }
+    let events: Vec<WalEvent> = wal.events().unwrap().try_iter().collect();
+    match &events[0] {
+        WalEvent::JournalCreated { num: 0, .. } => (),
+        event => panic!("unexpected {:?}", event),
+    }
+    let n =
+        events.iter().filter(|e| matches!(e, WalEvent::JournalRotated { .. })).count();
+    assert!(n > 0, "{:?}", events);
+
+    let rx = wal.events().unwrap();
+    wal.purge_till(50, "retention").unwrap().unwrap();
+    let purged: Vec<usize> = rx
+        .try_iter()
+        .filter_map(|e| match e {
+            WalEvent::JournalPurged { num, .. } => Some(num),
+            _ => None,
+        })
+        .collect();
+    assert!(!purged.is_empty() && purged.iter().all(|num| *num < n), "{:?}", purged);
+    wal.close(false).unwrap();
+
+    let file: path::PathBuf = {
+        let file = files::make_filename(config.name.clone(), 1000, config.journal_width);
+        [dir.path().as_os_str().to_os_string(), file].iter().collect()
+    };
+    fs::write(&file, b"corrupted").unwrap();
+
+    let wal: Wal = Wal::load(config).unwrap();
+    let events: Vec<WalEvent> = wal.events().unwrap().try_iter().collect();
+    assert_eq!(events[0], WalEvent::Corruption { file: file.into_os_string() });
+    match &events[1] {
+        WalEvent::Loaded { seqno: 100, .. } => (),
+        event => panic!("unexpected {:?}", event),
+    }
+    assert!(matches!(&events[2], WalEvent::JournalCreated { .. }), "{:?}", events);
+    wal.close(true).unwrap();
+}
+
+#[test]
+fn test_wal_rotations() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-wal-rotations", dir.path().as_ref());
+    config.set_journal_limit(1000).set_fsync(false);
+
+    let wal = Wal::create(config, state::NoState).unwrap();
+    assert_eq!(wal.stats().unwrap().rotations, Rotations::default());
+
+    let rx = wal.events().unwrap();
+    for _i in 0..100 {
+        wal.add_op(&[0; 32]).unwrap();
+    }
+    let rotations = wal.stats().unwrap().rotations;
+    assert!(rotations.size_limit > 0, "{:?}", rotations);
+    assert_eq!(rotations.len(), rotations.size_limit);
+    assert!(rotations.min_fill > 50, "{:?}", rotations);
+    assert!(rotations.min_fill <= rotations.avg_fill, "{:?}", rotations);
+    assert!(rotations.avg_fill <= rotations.max_fill, "{:?}", rotations);
+    let causes: Vec<RotateCause> = rx
+        .try_iter()
+        .filter_map(|e| match e {
+            WalEvent::JournalRotated { cause, .. } => Some(cause),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(causes.len(), rotations.size_limit);
+    assert!(causes.iter().all(|c| *c == RotateCause::SizeLimit), "{:?}", causes);
+
+    // rebase rotates the active journal irrespective of its size, the
+    // active journal still holds ops left over from above, so rebase once
+    // more with a single op in it.
+    wal.add_op(&[0; 32]).unwrap();
+    wal.rebase(1).unwrap();
+    wal.add_op(&[0; 32]).unwrap();
+    wal.rebase(2).unwrap();
+    let after = wal.stats().unwrap().rotations;
+    assert_eq!(after.forced, 2);
+    assert_eq!(after.size_limit, rotations.size_limit);
+    assert!(after.min_fill < rotations.min_fill, "{:?}", after);
+    let event = rx.try_iter().find(|e| matches!(e, WalEvent::JournalRotated { .. }));
+    match event {
+        Some(WalEvent::JournalRotated { cause: RotateCause::Forced, .. }) => (),
+        event => panic!("unexpected {:?}", event),
+    }
+
+    wal.close(true).unwrap();
+}
+
+#[test]
+fn test_wal_torn_tail() {
+    use std::io::Write;
+
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-wal-torn-tail", dir.path().as_ref());
+    config.set_journal_limit(1_000_000).set_fsync(false);
+
+    let wal = Wal::create(config.clone(), state::NoState).unwrap();
+    for _i in 0..10 {
+        wal.add_op(&[1; 32]).unwrap();
+    }
+    assert_eq!(wal.recovery_report(), RecoveryReport::default());
+    let latest = wal.indexes().unwrap().last().unwrap().clone();
+    wal.close(false).unwrap();
+
+    // crash while writing the next batch, leaves a partial batch behind.
+    let data = fs::read(latest.to_file_path()).unwrap();
+    let batch = latest.iter().last().unwrap();
+    let (fpos, length) = (batch.to_fpos() as usize, batch.to_length());
+    let partial = data[fpos..fpos + (length / 2)].to_vec();
+    let mut file =
+        fs::OpenOptions::new().append(true).open(latest.to_file_path()).unwrap();
+    file.write_all(&partial).unwrap();
+
+    let wal: Wal = Wal::load(config.clone()).unwrap();
+    let report = wal.recovery_report();
+    let torn = report.torn_tail.clone().unwrap();
+    assert_eq!(torn.file, latest.to_file_path());
+    assert_eq!(torn.offset, data.len() as u64);
+    assert_eq!(torn.bytes, partial.len() as u64);
+    assert!(!report.is_data_lost(), "{:?}", report);
+    let events: Vec<WalEvent> = wal.events().unwrap().try_iter().collect();
+    assert!(
+        events.iter().any(|e| matches!(e, WalEvent::TornTail { .. })),
+        "{:?}",
+        events
+    );
+    assert!(!events.iter().any(|e| matches!(e, WalEvent::Corruption { .. })));
+    assert_eq!(fs::read(latest.to_file_path()).unwrap(), data);
+    let seqnos: Vec<u64> = wal.iter().unwrap().map(|e| e.unwrap().to_seqno()).collect();
+    assert_eq!(seqnos, (1..=10).collect::<Vec<u64>>());
+    for _i in 0..10 {
+        wal.add_op(&[2; 32]).unwrap();
+    }
+    let latest = wal.indexes().unwrap().last().unwrap().clone();
+    wal.close(false).unwrap();
+
+    // damaged batch followed by a valid batch, is corruption.
+    let mut data = fs::read(latest.to_file_path()).unwrap();
+    let batch = latest.iter().next().unwrap();
+    assert!(latest.iter().count() > 1);
+    data[batch.to_fpos() as usize] = 0xff;
+    fs::write(latest.to_file_path(), &data).unwrap();
+
+    let wal: Wal = Wal::load(config).unwrap();
+    let report = wal.recovery_report();
+    assert_eq!(report.torn_tail, None);
+    assert_eq!(report.corrupted, vec![latest.to_file_path()]);
+    assert!(report.is_data_lost());
+    wal.close(false).unwrap();
+}
+
+#[test]
+fn test_wal_tail_unregistered_middleware() {
+    use crate::middleware::{register_middleware, Middleware, MIDDLEWARE_USER};
+
+    struct Xor;
+
+    impl Middleware for Xor {
+        fn seal(&self, data: Vec<u8>) -> Result<Vec<u8>> {
+            Ok(data.into_iter().map(|b| b ^ 0xa5).collect())
+        }
+
+        fn unseal(&self, data: Vec<u8>) -> Result<Vec<u8>> {
+            self.seal(data)
+        }
+    }
+
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-wal-tail-unregistered", dir.path().as_ref());
+    config.set_journal_limit(1_000_000).set_fsync(false);
+
+    let (id, unknown) = (MIDDLEWARE_USER + 100, MIDDLEWARE_USER + 101);
+    register_middleware(id, Arc::new(Xor)).unwrap();
+    config.set_middleware(vec![id]);
+
+    let wal = Wal::create(config.clone(), state::NoState).unwrap();
+    for _i in 0..10 {
+        wal.add_op(&[1; 32]).unwrap();
+    }
+    let latest = wal.indexes().unwrap().last().unwrap().to_file_path();
+    wal.close(false).unwrap();
+
+    // batches in the latest journal are sealed by a middleware that is not
+    // registered, they are not torn.
+    let from = util::encode_cbor(vec![id]).unwrap();
+    let to = util::encode_cbor(vec![unknown]).unwrap();
+    assert_eq!(from.len(), to.len());
+    let mut data = fs::read(&latest).unwrap();
+    let mut n = 0;
+    for i in 0..=(data.len() - from.len()) {
+        if data[i..i + from.len()] == from[..] {
+            data[i..i + to.len()].copy_from_slice(&to);
+            n += 1;
+        }
+    }
+    assert!(n > 0);
+    fs::write(&latest, &data).unwrap();
+
+    let res: Result<Wal> = Wal::load(config);
+    match res {
+        Err(Error::NotFound(_, _)) => (),
+        Err(err) => panic!("{}", err),
+        Ok(_) => panic!("middleware {} is not registered", unknown),
+    }
+    assert_eq!(fs::read(&latest).unwrap(), data);
+}
+
+#[test]
+fn test_wal_tail_undecodable() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-wal-tail-undecodable", dir.path().as_ref());
+    config.set_journal_limit(1000).set_fsync(false);
+
+    let wal = Wal::create(config.clone(), state::NoState).unwrap();
+    for _i in 0..100 {
+        wal.add_op(&[1; 32]).unwrap();
+    }
+    let latest = wal.indexes().unwrap().last().unwrap().to_file_path();
+    wal.close(false).unwrap();
+
+    // an item written in full, after valid batches, that is not a batch,
+    // is reported as corruption and not as a torn tail.
+    let mut data = fs::read(&latest).unwrap();
+    data.extend_from_slice(&util::encode_cbor(vec![1_u64, 2, 3]).unwrap());
+    fs::write(&latest, &data).unwrap();
+
+    let wal: Wal = Wal::load(config).unwrap();
+    let events: Vec<WalEvent> = wal.events().unwrap().try_iter().collect();
+    let file = latest.clone();
+    assert!(events.contains(&WalEvent::Corruption { file }), "{:?}", events);
+    let report = wal.recovery_report();
+    assert!(report.torn_tail.is_none());
+    assert_eq!(report.corrupted, vec![latest]);
+    wal.close(true).unwrap();
+}
+
+#[test]
+fn test_wal_empty_journals() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-wal-empty-journals", dir.path().as_ref());
+    config.set_fsync(false);
+
+    let wal = Wal::create(config.clone(), state::NoState).unwrap();
+    for _i in 0..10 {
+        wal.add_op(&[0; 32]).unwrap();
+    }
+    wal.close(false).unwrap();
+
+    let file: path::PathBuf = {
+        let file = files::make_filename(config.name.clone(), 7, config.journal_width);
+        [dir.path().as_os_str().to_os_string(), file].iter().collect()
+    };
+    fs::write(&file, b"").unwrap();
+    let is_removed = |events: &[WalEvent]| {
+        events.iter().any(|e| matches!(e, WalEvent::JournalPurged { num: 7, .. }))
+    };
+
+    // empty journal is skipped, and not reported as corrupted.
+    let wal: Wal = Wal::load(config.clone()).unwrap();
+    let events: Vec<WalEvent> = wal.events().unwrap().try_iter().collect();
+    assert!(!events.iter().any(|e| matches!(e, WalEvent::Corruption { .. })));
+    assert!(!is_removed(&events), "{:?}", events);
+    assert!(file.exists());
+    assert_eq!(wal.iter().unwrap().count(), 10);
+    assert!(wal.recovery_report().corrupted.is_empty());
+    wal.close(false).unwrap();
+
+    config.set_empty_policy(EmptyPolicy::Remove);
+    let wal: Wal = Wal::open(config.clone(), OpenMode::ReadOnly).unwrap();
+    assert!(file.exists());
+    wal.close(false).unwrap();
+
+    let wal: Wal = Wal::load(config).unwrap();
+    let events: Vec<WalEvent> = wal.events().unwrap().try_iter().collect();
+    assert!(is_removed(&events), "{:?}", events);
+    assert!(!file.exists());
+    assert_eq!(wal.iter().unwrap().count(), 10);
+    wal.close(true).unwrap();
+}
+
+#[test]
+fn test_wal_duplicate_seqnos() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-wal-duplicate-seqnos", dir.path().as_ref());
+    config.set_journal_limit(1000).set_fsync(false);
+
+    let wal = Wal::create(config.clone(), state::NoState).unwrap();
+    for _i in 0..100 {
+        wal.add_op(&[0; 32]).unwrap();
+    }
+    let first = wal.indexes().unwrap().first().unwrap().clone();
+    wal.close(false).unwrap();
+
+    // older journal restored over a newer journal number.
+    let file: path::PathBuf = {
+        let file = files::make_filename(config.name.clone(), 1000, config.journal_width);
+        [dir.path().as_os_str().to_os_string(), file].iter().collect()
+    };
+    fs::copy(first.to_file_path(), &file).unwrap();
+
+    let wal: Wal = Wal::load(config).unwrap();
+    let items: Vec<Result<entry::Entry>> = wal.iter().unwrap().collect();
+    let (last, items) = items.split_last().unwrap();
+    match last {
+        Err(Error::Invalid(_, msg)) => assert!(msg.contains("after 100"), "{}", msg),
+        res => panic!("expected Invalid {:?}", res),
+    }
+    let seqnos: Vec<u64> = items.iter().map(|e| e.as_ref().unwrap().to_seqno()).collect();
+    assert_eq!(seqnos, (1..=100).collect::<Vec<u64>>());
+    wal.close(false).unwrap();
+}
+
+#[test]
+fn test_wal_savepoint() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-wal-savepoint", dir.path().as_ref());
+    config.set_journal_limit(1000).set_fsync(false);
+
+    let seqnos = |wal: &Wal| -> Vec<u64> {
+        wal.iter().unwrap().map(|e| e.unwrap().to_seqno()).collect()
+    };
+
+    let wal = Wal::create(config.clone(), state::NoState).unwrap();
+    assert_eq!(wal.savepoint("empty").unwrap(), 0);
+    for _i in 0..10 {
+        wal.add_op(&[1; 32]).unwrap();
+    }
+    assert_eq!(wal.savepoint("a").unwrap(), 10);
+    for _i in 0..5 {
+        wal.add_op(&[2; 32]).unwrap();
+    }
+    assert_eq!(wal.savepoint("b").unwrap(), 15);
+    for _i in 0..5 {
+        wal.add_op(&[3; 32]).unwrap();
+    }
+
+    assert_eq!(wal.rollback_to("a").unwrap(), 10);
+    assert_eq!(seqnos(&wal), (1..=10).collect::<Vec<u64>>());
+    let names: Vec<String> =
+        wal.savepoints().unwrap().iter().map(|s| s.as_name().to_string()).collect();
+    assert_eq!(names, vec!["empty", "a"]);
+    assert_eq!(wal.add_op(&[4; 32]).unwrap(), 21);
+    match wal.rollback_to("b") {
+        Err(Error::NotFound(_, _)) => (),
+        res => panic!("expected NotFound {:?}", res),
+    }
+    wal.close(false).unwrap();
+
+    let wal: Wal = Wal::load(config.clone()).unwrap();
+    assert_eq!(wal.savepoints().unwrap().len(), 2);
+    let mut refs: Vec<u64> = (1..=10).collect();
+    refs.push(21);
+    assert_eq!(seqnos(&wal), refs);
+    assert_eq!(wal.rollback_to("a").unwrap(), 10);
+    assert_eq!(seqnos(&wal), (1..=10).collect::<Vec<u64>>());
+    assert_eq!(wal.rollback_to("empty").unwrap(), 0);
+    assert_eq!(seqnos(&wal), Vec::<u64>::default());
+
+    // savepoints from older epochs can't be rolled back to.
+    wal.rebase(1).unwrap();
+    match wal.rollback_to("empty") {
+        Err(Error::Invalid(_, _)) => (),
+        res => panic!("expected Invalid {:?}", res),
+    }
+    wal.close(false).unwrap();
+
+    let wal: Wal = Wal::open(config, OpenMode::ReadOnly).unwrap();
+    assert_eq!(wal.savepoints().unwrap().len(), 1);
+    match wal.savepoint("c") {
+        Err(Error::ReadOnly(_, _)) => (),
+        res => panic!("expected ReadOnly {:?}", res),
+    }
+    wal.close(false).unwrap();
+}
+
+#[test]
+fn test_wal_savepoint_relocate() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-wal-savepoint-relocate", dir.path().as_ref());
+    config.set_journal_limit(1000).set_fsync(false);
+
+    let wal = Wal::create(config, state::NoState).unwrap();
+    for _i in 0..10 {
+        wal.add_op(&[1; 32]).unwrap();
+    }
+    assert_eq!(wal.savepoint("a").unwrap(), 10);
+
+    // savepoints are saved along with the relocated manifest.
+    let new_dir = tempfile::tempdir().unwrap();
+    wal.relocate(new_dir.path().as_ref()).unwrap();
+    wal.rename("test-wal-savepoint-renamed").unwrap();
+    for _i in 0..5 {
+        wal.add_op(&[2; 32]).unwrap();
+    }
+    assert_eq!(wal.savepoint("b").unwrap(), 15);
+    for _i in 0..5 {
+        wal.add_op(&[3; 32]).unwrap();
+    }
+    assert_eq!(wal.rollback_to("a").unwrap(), 10);
+    assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+    wal.close(false).unwrap();
+
+    let mut config = Config::new("test-wal-savepoint-renamed", new_dir.path().as_ref());
+    config.set_fsync(false);
+    let wal: Wal = Wal::load(config).unwrap();
+    let names: Vec<String> =
+        wal.savepoints().unwrap().iter().map(|s| s.as_name().to_string()).collect();
+    assert_eq!(names, vec!["a"]);
+    let seqnos: Vec<u64> = wal.iter().unwrap().map(|e| e.unwrap().to_seqno()).collect();
+    assert_eq!(seqnos, (1..=10).collect::<Vec<u64>>());
+    wal.close(true).unwrap();
+}
+
+#[test]
+fn test_wal_spans() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-wal-spans", dir.path().as_ref());
+    config.set_journal_limit(1000).set_fsync(false);
+
+    let wal = Wal::create(config.clone(), state::NoState).unwrap();
+    for _i in 0..100 {
+        wal.add_op(&[0; 32]).unwrap();
+    }
+    let check_spans = |wal: &Wal| {
+        // journals may rotate after replying, indexes() waits for it.
+        let indexes = wal.indexes().unwrap();
+        let manifest = Manifest::load(&config.dir, &config.name).unwrap().unwrap();
+        for index in indexes[..indexes.len() - 1].iter() {
+            let first = index.iter().next().unwrap().to_first_seqno();
+            let last = index.iter().last().unwrap().to_last_seqno();
+            let span = manifest.to_span(index.to_journal_number());
+            assert_eq!(span, Some(first..=last));
+        }
+    };
+    check_spans(&wal);
+
+    let seqnos: Vec<u64> =
+        wal.range(40..60).unwrap().map(|e| e.unwrap().to_seqno()).collect();
+    assert_eq!(seqnos, (40..60).collect::<Vec<u64>>());
+
+    wal.purge_till(50, "retention").unwrap().unwrap();
+    check_spans(&wal);
+    wal.close(false).unwrap();
+
+    let wal: Wal = Wal::load(config.clone()).unwrap();
+    check_spans(&wal);
+    let seqnos: Vec<u64> =
+        wal.range(90..).unwrap().map(|e| e.unwrap().to_seqno()).collect();
+    assert_eq!(seqnos, (90..=100).collect::<Vec<u64>>());
+    wal.close(true).unwrap();
+}
+
+#[test]
+fn test_wal_journal_tolerance() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-wal-journal-tolerance", dir.path().as_ref());
+    config.set_journal_limit(1000).set_journal_tolerance(100).set_fsync(false);
+
+    let wal = Wal::create(config.clone(), state::NoState).unwrap();
+    {
+        let mut bw = wal.buffered_writer(1000);
+        for i in 0..200_u8 {
+            bw.add_op(&[i; 32]).unwrap();
+        }
+        assert_eq!(bw.flush().unwrap(), Some(1..=200));
+    }
+    let indexes = wal.indexes().unwrap();
+    assert!(indexes.len() > 5, "{}", indexes.len());
+    for index in indexes.iter() {
+        let len = fs::metadata(index.to_file_path()).unwrap().len();
+        assert!(len <= 1100, "{:?} {}", index.to_file_path(), len);
+    }
+    wal.close(false).unwrap();
+
+    let wal: Wal = Wal::load(config).unwrap();
+    let ops: Vec<Vec<u8>> = wal.iter().unwrap().map(|e| e.unwrap().unwrap().1).collect();
+    assert_eq!(ops, (0..200_u8).map(|i| vec![i; 32]).collect::<Vec<Vec<u8>>>());
+    wal.close(true).unwrap();
+}
+
+#[test]
+fn test_wal_cadence() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-wal-cadence", dir.path().as_ref());
+    config.set_fsync(false);
+
+    let wal = Wal::create(config.clone(), state::NoState).unwrap();
+    let val = wal.to_config().unwrap();
+    assert!(!val.fsync);
+    assert_eq!(val.name, config.name);
+
+    let stats = wal.stats().unwrap();
+    assert_eq!(stats.avg_batch_size, 0);
+    assert_eq!(stats.avg_sync_interval, time::Duration::default());
+
+    for _i in 0..2 {
+        let mut bw = wal.buffered_writer(10);
+        for i in 0..10_u8 {
+            bw.add_op(&[i]).unwrap();
+        }
+    }
+    let stats = wal.stats().unwrap();
+    assert_eq!(stats.avg_batch_size, 10);
+    assert_eq!(stats.queue_depth, 1);
+    assert_eq!(stats.batch_high_water, 10);
+    assert!(stats.pending_capacity >= 10, "{}", stats.pending_capacity);
+
+    let new_dir = tempfile::tempdir().unwrap();
+    wal.relocate(new_dir.path().as_ref()).unwrap();
+    assert_eq!(wal.to_config().unwrap().dir, new_dir.path().as_os_str());
+    wal.close(true).unwrap();
+}
+
+#[test]
+fn test_wal_op_sizes() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-wal-op-sizes", dir.path().as_ref());
+    config.set_fsync(false);
+
+    let wal = Wal::create(config, state::NoState).unwrap();
+    let stats = wal.stats().unwrap();
+    assert!(stats.op_sizes.is_empty());
+    assert!(stats.largest_ops.is_empty());
+
+    // sizes 0, 1, 3, 4, 1000 land in buckets 0, 1, 2, 3, 10.
+    for size in [0, 1, 3, 4, 1000].iter() {
+        wal.add_op(&vec![0; *size]).unwrap();
+    }
+    {
+        let mut bw = wal.buffered_writer(10);
+        for size in 1..=10 {
+            bw.add_op(&vec![0; size * 100]).unwrap();
+        }
+    }
+    let ops = vec![(100, vec![0; 5000]), (101, vec![0; 2])];
+    assert_eq!(wal.ingest(ops).unwrap(), Some(100..=101));
+
+    let stats = wal.stats().unwrap();
+    assert_eq!(stats.op_sizes, vec![1, 1, 2, 1, 0, 0, 0, 1, 1, 3, 6, 0, 0, 1]);
+    // ties keep the older op first.
+    let largest = vec![
+        (100, 5000),
+        (5, 1000),
+        (15, 1000),
+        (14, 900),
+        (13, 800),
+        (12, 700),
+        (11, 600),
+        (10, 500),
+    ];
+    assert_eq!(stats.largest_ops, largest);
+    wal.close(true).unwrap();
+}
+
+#[test]
+fn test_wal_adaptive_commit() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-wal-adaptive-commit", dir.path().as_ref());
+    let target = time::Duration::from_millis(50);
+    config.set_adaptive_commit(Some(target));
+
+    let wal = Wal::create(config, state::NoState).unwrap();
+    let mut handles = vec![];
+    for id in 0..8_u8 {
+        let wal = wal.clone();
+        handles.push(std::thread::spawn(move || {
+            for i in 0..20_u8 {
+                wal.add_op(&[id, i]).unwrap();
+            }
+        }));
+    }
+    for handle in handles.into_iter() {
+        handle.join().unwrap();
+    }
+    assert_eq!(wal.iter().unwrap().count(), 160);
+
+    let stats = wal.stats().unwrap();
+    assert!(stats.fsync_latency > time::Duration::default());
+    assert!(stats.commit_window <= stats.fsync_latency, "{:?}", stats);
+    assert!(stats.commit_window <= target.saturating_sub(stats.fsync_latency));
+    assert!(stats.avg_batch_size >= 1);
+    wal.close(true).unwrap();
+
+    // without adaptive commit, batches are formed without waiting.
+    let dir = tempfile::tempdir().unwrap();
+    let config = Config::new("test-wal-adaptive-commit", dir.path().as_ref());
+    let wal = Wal::create(config, state::NoState).unwrap();
+    wal.add_op(&[1]).unwrap();
+    let stats = wal.stats().unwrap();
+    assert!(stats.fsync_latency > time::Duration::default());
+    assert_eq!(stats.commit_window, time::Duration::default());
+    wal.close(true).unwrap();
+}
+
+#[test]
+fn test_wal_shutdown_signal() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-wal-shutdown-signal", dir.path().as_ref());
+    config.set_journal_limit(1000).set_fsync(false);
+
+    let wal = Wal::create(config, state::NoState).unwrap();
+    for i in 0..100_u8 {
+        wal.add_op(&[i]).unwrap();
+    }
+    let signal = wal.clone().shutdown_signal();
+    assert!(!signal.is_cancelled());
+
+    let mut iter = wal.iter().unwrap();
+    assert_eq!(iter.next().unwrap().unwrap().to_seqno(), 1);
+    signal.cancel();
+    match iter.next() {
+        Some(Err(Error::Cancelled(_, _))) => (),
+        item => panic!("expected Cancelled {:?}", item),
+    }
+    assert!(iter.next().is_none());
+    assert!(wal.shutdown_signal().is_cancelled());
+    assert!(wal.iter().unwrap().next().unwrap().is_err());
+
+    // writes are not affected.
+    assert_eq!(wal.add_op(&[100]).unwrap(), 101);
+    wal.close(true).unwrap();
+}
+
+#[test]
+fn test_wal_pin_range() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-wal-pin-range", dir.path().as_ref());
+    config.set_journal_limit(1000).set_fsync(false);
+
+    let wal = Wal::create(config, state::NoState).unwrap();
+    for i in 0..100_u8 {
+        wal.add_op(&[i; 32]).unwrap();
+    }
+    assert_eq!(wal.pin_range(..0).unwrap(), 0);
+    let n = wal.pin_range(10..=50).unwrap();
+    assert!(n > 1, "{}", n);
+    assert_eq!(wal.pin_range(10..=50).unwrap(), 0);
+
+    // pinned journals are served from memory, move their files away.
+    let indexes = wal.indexes().unwrap();
+    let files: Vec<ffi::OsString> = indexes[..indexes.len() - 1]
+        .iter()
+        .filter(|index| {
+            let first = index.iter().next().unwrap().to_first_seqno();
+            let last = index.iter().last().unwrap().to_last_seqno();
+            first <= 50 && 10 <= last
+        })
+        .map(|index| index.to_file_path())
+        .collect();
+    assert_eq!(files.len(), n);
+    for file in files.iter() {
+        let mut moved = file.clone();
+        moved.push(".moved");
+        fs::rename(file, moved).unwrap();
+    }
+
+    let ops: Vec<Vec<u8>> =
+        wal.range(10..=50).unwrap().map(|e| e.unwrap().unwrap().1).collect();
+    assert_eq!(ops, (9..50_u8).map(|i| vec![i; 32]).collect::<Vec<Vec<u8>>>());
+    assert_eq!(wal.unpin_range(30..=30).unwrap(), 1);
+    assert!(wal.range(10..=50).is_err());
+    assert_eq!(wal.unpin_range(..).unwrap(), n - 1);
+
+    for file in files.iter() {
+        let mut moved = file.clone();
+        moved.push(".moved");
+        fs::rename(moved, file).unwrap();
+    }
+    assert_eq!(wal.range(10..=50).unwrap().count(), 41);
+    wal.close(true).unwrap();
+}
+
+#[test]
+fn test_wal_freeze_thaw() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-wal-freeze-thaw", dir.path().as_ref());
+    config.set_journal_limit(1000).set_fsync(false);
+
+    let wal = Wal::create(config.clone(), state::NoState).unwrap();
+    for i in 0..100_u8 {
+        wal.add_op(&[i; 32]).unwrap();
+    }
+    let indexes = wal.indexes().unwrap();
+    assert!(indexes.len() > 2, "{}", indexes.len());
+    let (first, active) = (&indexes[0], indexes.last().unwrap());
+    let (num, seqnos) = (first.to_journal_number(), first.to_seqnos().unwrap());
+    match wal.freeze(active.to_journal_number()) {
+        Err(Error::NotFound(_, _)) => (),
+        res => panic!("expected NotFound {:?}", res),
+    }
+
+    assert_eq!(wal.pin_range(..).unwrap(), indexes.len() - 1);
+    wal.freeze(num).unwrap();
+    wal.freeze(num).unwrap();
+    let cold = &wal.indexes().unwrap()[0];
+    assert!(cold.is_cold() && cold.is_empty());
+    assert_eq!(cold.to_seqnos(), Some(seqnos.clone()));
+    assert!(cold.contains(*seqnos.end()) && !cold.contains(*seqnos.end() + 1));
+    assert!(cold.to_size() >= first.to_size());
+
+    // pinned entries are released, reads touching the cold journal fail.
+    assert_eq!(wal.unpin_range(..).unwrap(), indexes.len() - 2);
+    assert_eq!(wal.pin_range(..).unwrap(), indexes.len() - 2);
+    let after = (*seqnos.end() + 1)..;
+    assert_eq!(wal.range(after).unwrap().count() as u64, 100 - *seqnos.end());
+    match wal.range(..=*seqnos.end()) {
+        Err(Error::Invalid(_, _)) => (),
+        Ok(_) => panic!("expected Invalid"),
+        Err(err) => panic!("expected Invalid {}", err),
+    }
+
+    let index = wal.thaw(num).unwrap();
+    assert!(!index.is_cold());
+    assert_eq!(index.len(), first.len());
+    assert_eq!(index.to_seqnos(), Some(seqnos.clone()));
+    assert_eq!(wal.thaw(num).unwrap().len(), first.len());
+    assert_eq!(wal.iter().unwrap().count(), 100);
+    wal.close(false).unwrap();
+
+    // with auto thaw, reads thaw cold journals on demand.
+    config.set_thaw_policy(ThawPolicy::Auto);
+    let wal: Wal = Wal::load(config).unwrap();
+    wal.freeze(num).unwrap();
+    assert!(wal.indexes().unwrap()[0].is_cold());
+    let ops: Vec<Vec<u8>> = wal.iter().unwrap().map(|e| e.unwrap().unwrap().1).collect();
+    assert_eq!(ops, (0..100_u8).map(|i| vec![i; 32]).collect::<Vec<Vec<u8>>>());
+    assert!(!wal.indexes().unwrap()[0].is_cold());
+    match wal.thaw(1_000) {
+        Err(Error::NotFound(_, _)) => (),
+        res => panic!("expected NotFound {:?}", res.map(|index| index.len())),
+    }
+    wal.close(true).unwrap();
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, mkit::Cborize)]
+struct Count {
+    n: u64,
+}
+
+impl Count {
+    const ID: u32 = 0x0;
+}
+
+impl state::State for Count {
+    fn on_add_entry(&mut self, _: &entry::Entry) -> Result<()> {
+        self.n += 1;
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, mkit::Cborize)]
+struct Label {
+    label: String,
+}
+
+impl Label {
+    const ID: u32 = 0x0;
+}
+
+impl state::State for Label {
+    fn on_add_entry(&mut self, _: &entry::Entry) -> Result<()> {
+        Ok(())
+    }
+}
+
+// state that grows with every entry.
+#[derive(Clone, Debug, Default, Eq, PartialEq, mkit::Cborize)]
+struct Trail {
+    ops: Vec<u8>,
+}
+
+impl Trail {
+    const ID: u32 = 0x0;
+}
+
+impl state::State for Trail {
+    fn on_add_entry(&mut self, entry: &entry::Entry) -> Result<()> {
+        self.ops.extend_from_slice(entry.as_op());
+        Ok(())
+    }
+}
+
+#[test]
+fn test_wal_state_policy() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-wal-state-policy", dir.path().as_ref());
+    config.set_journal_limit(1000).set_fsync(false);
+
+    let wal = Wal::create(config.clone(), Count::default()).unwrap();
+    for i in 0..100_u8 {
+        wal.add_op(&[i; 32]).unwrap();
+    }
+    wal.close(false).unwrap();
+
+    // loading would start a new journal over the discarded ones.
+    let wal = Wal::<Label>::open(config.clone(), OpenMode::ReadOnly).unwrap();
+    assert_eq!(wal.iter().unwrap().count(), 0);
+    let events: Vec<WalEvent> = wal.events().unwrap().try_iter().collect();
+    assert!(events.iter().all(|e| !matches!(e, WalEvent::StateReset { .. })));
+    wal.close(false).unwrap();
+
+    config.set_state_policy(StatePolicy::UseDefault);
+    let wal = Wal::<Label>::load(config.clone()).unwrap();
+    let ops: Vec<Vec<u8>> = wal.iter().unwrap().map(|e| e.unwrap().unwrap().1).collect();
+    assert_eq!(ops, (0..100_u8).map(|i| vec![i; 32]).collect::<Vec<Vec<u8>>>());
+    let events: Vec<WalEvent> = wal.events().unwrap().try_iter().collect();
+    let n = events.iter().filter(|e| matches!(e, WalEvent::StateReset { .. })).count();
+    assert!(n > 1, "{}", n);
+    wal.close(false).unwrap();
+
+    let wal = Wal::<Count>::load(config).unwrap();
+    assert_eq!(wal.iter().unwrap().count(), 100);
+    wal.close(true).unwrap();
+}
+
+#[test]
+fn test_wal_state_checkpoint() {
+    let dir = tempfile::tempdir().unwrap();
+    let name = "test-wal-state-checkpoint";
+    let mut config = Config::new(name, dir.path().as_ref());
+    config.set_fsync(false).set_state_checkpoint(None, Some(5));
+
+    let wal = Wal::create(config.clone(), Count::default()).unwrap();
+    for i in 0..12_u8 {
+        wal.add_op(&[i; 32]).unwrap();
+    }
+    let cp = Checkpoint::load(&config.dir, name).unwrap().unwrap();
+    assert_eq!(cp.to_seqno() % 5, 0, "{}", cp.to_seqno());
+    assert_eq!(cp.to_state::<Count>().unwrap().n, cp.to_seqno());
+    wal.close(false).unwrap();
+
+    // closing checkpoints the latest state.
+    let cp = Checkpoint::load(&config.dir, name).unwrap().unwrap();
+    assert_eq!((cp.to_epoch(), cp.to_seqno()), (0, 12));
+    assert_eq!(cp.to_state::<Count>().unwrap(), Count { n: 12 });
+
+    // checkpoint as new as the last batch is preferred, older ones and
+    // ones from other epochs are not.
+    let load = |epoch: u64, seqno: u64| -> Count {
+        let cp = Checkpoint::new(name, epoch, seqno, Count { n: 1000 }).unwrap();
+        cp.save(&config.dir).unwrap();
+        let wal: Wal<Count> = Wal::load(config.clone()).unwrap();
+        let state = wal.w.read().unwrap().journal.to_state();
+        wal.close(false).unwrap();
+        state
+    };
+    assert_eq!(load(0, 12), Count { n: 1000 });
+    assert_eq!(load(0, 11), Count { n: 12 });
+    assert_eq!(load(1, 12), Count { n: 12 });
+
+    let path = dir.path().join(files::make_state_filename(name));
+    fs::write(&path, [0xff, 0xff]).unwrap();
+    let wal: Wal<Count> = Wal::load(config.clone()).unwrap();
+    assert_eq!(wal.w.read().unwrap().journal.to_state(), Count { n: 12 });
+    wal.close(true).unwrap();
+    assert!(!path.exists());
+}
+
+#[test]
+fn test_wal_split_journal() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-wal-split-journal", dir.path().as_ref());
+    config.set_fsync(false);
+
+    let wal = Wal::create(config.clone(), state::NoState).unwrap();
+    for i in 0..100_u8 {
+        wal.add_op(&[i; 32]).unwrap();
+    }
+    wal.close(false).unwrap();
+
+    let wal = Wal::<state::NoState>::load(config.clone()).unwrap();
+    for i in 100..150_u8 {
+        wal.add_op(&[i; 32]).unwrap();
+    }
+    let indexes = wal.indexes().unwrap();
+    wal.close(false).unwrap();
+    assert_eq!(indexes.len(), 2);
+    let num = indexes[0].to_journal_number();
+
+    assert!(Wal::split_journal(&config, num + 100, 1000).is_err());
+    assert_eq!(Wal::split_journal(&config, num, 1_000_000).unwrap(), 1);
+    let n = Wal::split_journal(&config, num, 1000).unwrap();
+    assert!(n > 2, "{}", n);
+
+    let report = Wal::fsck(&config, fsck::FsckLevel::ReportOnly).unwrap();
+    assert_eq!(report.journals.len(), n + 1);
+    assert!(report.seqno_gaps.is_empty(), "{:?}", report.seqno_gaps);
+    let nums: Vec<usize> = report.journals.iter().map(|j| j.num).collect();
+    assert_eq!(nums, (num..=num + n).collect::<Vec<usize>>());
+
+    let wal = Wal::<state::NoState>::load(config).unwrap();
+    let ops: Vec<Vec<u8>> = wal.iter().unwrap().map(|e| e.unwrap().unwrap().1).collect();
+    assert_eq!(ops, (0..150_u8).map(|i| vec![i; 32]).collect::<Vec<Vec<u8>>>());
+    let ops: Vec<Vec<u8>> =
+        wal.range(40..=120).unwrap().map(|e| e.unwrap().unwrap().1).collect();
+    assert_eq!(ops, (39..120_u8).map(|i| vec![i; 32]).collect::<Vec<Vec<u8>>>());
+    wal.close(true).unwrap();
+}
+
+// Reject ops starting with 0, transform ops starting with 1.
+#[derive(Clone, Debug, Default, Eq, PartialEq, mkit::Cborize)]
+struct Gate {
+    n: u64,
+}
+
+impl Gate {
+    const ID: u32 = 0x0;
+}
+
+impl state::State for Gate {
+    fn on_entry(&mut self, entry: &entry::Entry) -> Result<state::Action> {
+        match entry.as_op().first() {
+            Some(0) => Ok(state::Action::Reject("zero".to_string())),
+            Some(1) => {
+                self.n += 1;
+                Ok(state::Action::Transform(vec![2; entry.as_op().len()]))
+            }
+            _ => {
+                self.n += 1;
+                Ok(state::Action::Accept)
+            }
+        }
+    }
+}
+
+#[test]
+fn test_wal_state_action() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-wal-state-action", dir.path().as_ref());
+    config.set_journal_limit(1000).set_fsync(false);
+
+    let wal = Wal::create(config, Gate::default()).unwrap();
+    assert_eq!(wal.add_op(&[3; 32]).unwrap(), 1);
+    match wal.add_op(&[0; 32]) {
+        Err(Error::Rejected(_, _)) => (),
+        res => panic!("expected Rejected {:?}", res),
+    }
+    assert_eq!(wal.add_op(&[1; 32]).unwrap(), 2);
+    {
+        let mut bw = wal.buffered_writer(3);
+        bw.add_op(&[4; 32]).unwrap();
+        bw.add_op(&[0; 32]).unwrap();
+        assert!(bw.add_op(&[5; 32]).is_err());
+        bw.add_op(&[4; 32]).unwrap();
+        bw.add_op(&[1; 32]).unwrap();
+        assert_eq!(bw.add_op(&[5; 32]).unwrap(), Some(3..=5));
+    }
+    for _ in 0..100 {
+        wal.add_op(&[1; 32]).unwrap();
+    }
+    assert_eq!(wal.add_op(&[6; 32]).unwrap(), 106);
+
+    let ops: Vec<u8> = wal.iter().unwrap().map(|e| e.unwrap().unwrap().1[0]).collect();
+    let mut want = vec![3, 2, 4, 2, 5];
+    want.extend(vec![2; 100]);
+    want.push(6);
+    assert_eq!(ops, want);
+    wal.close(true).unwrap();
+}
+
+#[test]
+fn test_wal_reclaimable() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-wal-reclaimable", dir.path().as_ref());
+    config.set_journal_limit(1000).set_fsync(false);
+
+    let wal = Wal::create(config, state::NoState).unwrap();
+    let report = wal.reclaimable(u64::MAX).unwrap();
+    assert!(report.journals.is_empty());
+    assert_eq!((report.bytes, report.wasted), (0, 0.0));
+
+    for i in 0..100_u8 {
+        wal.add_op(&[i; 32]).unwrap();
+    }
+    let indexes = wal.indexes().unwrap();
+
+    let report = wal.reclaimable(0).unwrap();
+    assert!(report.journals.is_empty());
+    assert_eq!(report.boundary, indexes[0].to_journal_number());
+
+    let report = wal.reclaimable(50).unwrap();
+    assert!(!report.journals.is_empty());
+    let bytes: u64 = report
+        .journals
+        .iter()
+        .map(|j| fs::metadata(j.to_file_path()).unwrap().len())
+        .sum();
+    assert_eq!(report.bytes, bytes);
+    let boundary = &indexes[report.journals.len()];
+    assert_eq!(report.boundary, boundary.to_journal_number());
+    let first = boundary.iter().next().unwrap().to_first_seqno();
+    match first <= 50 {
+        true => assert!(report.wasted > 0.0 && report.wasted < 1.0, "{}", report.wasted),
+        false => assert_eq!(report.wasted, 0.0),
+    }
+
+    wal.purge_till(50, "test").unwrap().unwrap();
+    let nums: Vec<usize> =
+        wal.indexes().unwrap().iter().map(|j| j.to_journal_number()).collect();
+    assert_eq!(nums[0], report.boundary);
+    assert_eq!(nums.len(), indexes.len() - report.journals.len());
+    for journal in report.journals.iter() {
+        assert!(!path::Path::new(&journal.to_file_path()).exists());
+    }
+    wal.close(true).unwrap();
+}
+
+#[test]
+fn test_wal_estimate() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-wal-estimate", dir.path().as_ref());
+    config.set_journal_limit(1000).set_fsync(false);
+
+    let wal = Wal::create(config, state::NoState).unwrap();
+    assert_eq!(wal.estimate(..).unwrap(), Estimate::default());
+
+    for i in 0..100_u8 {
+        wal.add_op(&[i; 32]).unwrap();
+    }
+    wal.purge_till(10, "test").unwrap();
+
+    let indexes = wal.indexes().unwrap();
+    let bytes: u64 =
+        indexes.iter().map(|j| fs::metadata(j.to_file_path()).unwrap().len()).sum();
+    assert_eq!(wal.disk_footprint().unwrap(), bytes);
+
+    let first = indexes[0].iter().next().unwrap().to_first_seqno();
+    let estimate = wal.estimate(..).unwrap();
+    assert_eq!(estimate.entries, 100 - first + 1);
+    assert_eq!(estimate.bytes, indexes.iter().map(|j| j.to_size()).sum::<u64>());
+    assert_eq!(estimate.batches, indexes.iter().map(|j| j.len()).sum::<usize>());
+
+    let estimate = wal.estimate(50..60).unwrap();
+    assert_eq!(estimate.entries, 10);
+    assert!(estimate.batches >= 1 && estimate.batches <= 10);
+    assert!(estimate.bytes < wal.estimate(..).unwrap().bytes);
+    assert_eq!(wal.estimate(200..).unwrap(), Estimate::default());
+
+    wal.close(true).unwrap();
+}
+
+#[test]
+fn test_wal_add_op_at() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-wal-add-op-at", dir.path().as_ref());
+    config.set_journal_limit(1000).set_fsync(false);
+
+    let wal: Wal = Wal::create(config.clone(), state::NoState).unwrap();
+    let mut seqnos = vec![];
+    for lsn in (100..10_000_u64).step_by(100) {
+        seqnos.push(wal.add_op_at(lsn, &lsn.to_be_bytes()).unwrap());
+    }
+    for lsn in [9900, 9800, 0].iter() {
+        match wal.add_op_at(*lsn, &[]) {
+            Err(Error::Invalid(_, _)) => (),
+            res => panic!("expected Invalid for {}, {:?}", lsn, res),
+        }
+    }
+    assert_eq!(wal.durable_seqno().unwrap(), 9900);
+    seqnos.push(wal.add_op(&[1]).unwrap());
+    assert_eq!(seqnos.last(), Some(&9901));
+    wal.close(false).unwrap();
+
+    let wal: Wal = Wal::load(config).unwrap();
+    let items: Vec<u64> = wal.iter().unwrap().map(|e| e.unwrap().to_seqno()).collect();
+    assert_eq!(items, seqnos);
+    let items: Vec<u64> =
+        wal.range(450..=1000).unwrap().map(|e| e.unwrap().to_seqno()).collect();
+    assert_eq!(items, vec![500, 600, 700, 800, 900, 1000]);
+    let entry = wal.range(5000..=5000).unwrap().next().unwrap().unwrap();
+    assert_eq!(entry.as_op(), 5000_u64.to_be_bytes());
+    assert_eq!(wal.add_op_at(9950, &[2]).unwrap(), 9950);
+
+    wal.close(true).unwrap();
+}
+
+#[test]
+fn test_wal_ingest() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-wal-ingest", dir.path().as_ref());
+    config.set_journal_limit(1000).set_fsync(false);
+
+    let batch = |seqnos: ops::RangeInclusive<u64>| -> Vec<(u64, Vec<u8>)> {
+        seqnos.map(|seqno| (seqno, seqno.to_be_bytes().to_vec())).collect()
+    };
+
+    let wal: Wal = Wal::create(config.clone(), state::NoState).unwrap();
+    assert_eq!(wal.ingest(vec![]).unwrap(), None);
+    assert_eq!(wal.ingest(batch(10..=20)).unwrap(), Some(10..=20));
+    // redelivered, fully and partially.
+    assert_eq!(wal.ingest(batch(10..=20)).unwrap(), None);
+    assert_eq!(wal.ingest(batch(15..=18)).unwrap(), None);
+    assert_eq!(wal.ingest(batch(15..=30)).unwrap(), Some(21..=30));
+    match wal.ingest(vec![(40, vec![]), (35, vec![])]) {
+        Err(Error::Invalid(_, _)) => (),
+        res => panic!("expected Invalid, {:?}", res),
+    }
+    match wal.ingest(vec![(40, vec![]), (40, vec![])]) {
+        Err(Error::Invalid(_, _)) => (),
+        res => panic!("expected Invalid, {:?}", res),
+    }
+    assert_eq!(wal.durable_seqno().unwrap(), 30);
+    wal.close(false).unwrap();
+
+    let wal: Wal = Wal::load(config).unwrap();
+    assert_eq!(wal.ingest(batch(25..=35)).unwrap(), Some(31..=35));
+    let items: Vec<(u64, Vec<u8>)> = wal
+        .iter()
+        .unwrap()
+        .map(|e| e.unwrap())
+        .map(|e| (e.to_seqno(), e.as_op().to_vec()))
+        .collect();
+    assert_eq!(items, batch(10..=35));
+
+    wal.close(true).unwrap();
+}
+
+#[test]
+fn test_wal_ingest_entries() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-wal-ingest-entries", dir.path().as_ref());
+    config.set_journal_limit(1000).set_fsync(false).set_codec(Codec::Compact);
+
+    let entries: Vec<entry::Entry> = (1..=100_u64)
+        .map(|seqno| {
+            EntryBuilder::new(seqno)
+                .topic(["", "topic"][(seqno % 2) as usize])
+                .tag(format!("tag-{}", seqno % 3))
+                .timestamp(seqno * 10)
+                .payload_parts(&[b"op-", &seqno.to_be_bytes()])
+                .build()
+        })
+        .collect();
+
+    let wal: Wal = Wal::create(config.clone(), state::NoState).unwrap();
+    assert_eq!(wal.ingest_entries(entries[..60].to_vec()).unwrap(), Some(1..=60));
+    assert_eq!(wal.ingest_entries(entries.clone()).unwrap(), Some(61..=100));
+    wal.close(false).unwrap();
+
+    let wal: Wal = Wal::load(config).unwrap();
+    let items: Vec<entry::Entry> = wal.iter().unwrap().map(|e| e.unwrap()).collect();
+    assert_eq!(items.len(), entries.len());
+    for (item, entry) in items.iter().zip(entries.iter()) {
+        assert_eq!(item.to_seqno(), entry.to_seqno());
+        assert_eq!(item.as_topic(), entry.as_topic());
+        assert_eq!(item.as_tag(), entry.as_tag());
+        assert_eq!(item.to_timestamp(), entry.to_timestamp());
+        assert_eq!(item.as_op(), entry.as_op());
+    }
+
+    wal.close(true).unwrap();
+}
+
+#[test]
+fn test_wal_shadow_codec() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-wal-shadow-codec", dir.path().as_ref());
+    config
+        .set_journal_limit(1000)
+        .set_fsync(false)
+        .set_codec(Codec::Cbor)
+        .set_shadow_codec(Some(Codec::Compact));
+
+    let wal: Wal = Wal::create(config.clone(), state::NoState).unwrap();
+    let events = wal.events().unwrap();
+    for i in 0..100_u8 {
+        wal.add_op_to(["", "topic"][(i % 2) as usize], &[i; 16]).unwrap();
+    }
+    wal.close(false).unwrap();
+    let mismatches = events
+        .try_iter()
+        .filter(|e| matches!(e, WalEvent::ShadowMismatch { .. }))
+        .count();
+    assert_eq!(mismatches, 0);
+
+    let wal: Wal = Wal::load(config).unwrap();
+    assert_eq!(wal.iter().unwrap().count(), 100);
+    wal.close(true).unwrap();
+}
+
+#[test]
+fn test_wal_preload_batches() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-wal-preload-batches", dir.path().as_ref());
+    config.set_journal_limit(1000).set_fsync(false);
+
+    let wal: Wal = Wal::create(config.clone(), state::NoState).unwrap();
+    for i in 0..100_u8 {
+        wal.add_op(&[i; 32]).unwrap();
+    }
+    wal.close(false).unwrap();
+
+    let mut preload = config.clone();
+    preload.set_preload_batches(3);
+    let wal_a: Wal = Wal::open(preload, OpenMode::ReadOnly).unwrap();
+    let wal_b: Wal = Wal::open(config, OpenMode::ReadOnly).unwrap();
+    let refs: Vec<entry::Entry> = wal_b.iter().unwrap().map(|e| e.unwrap()).collect();
+    assert_eq!(
+        wal_a.iter().unwrap().map(|e| e.unwrap()).collect::<Vec<entry::Entry>>(),
+        refs
+    );
+
+    // zero out the last batch on disk, preloaded batch is served from cache.
+    let indexes = wal_a.indexes().unwrap();
+    let latest = indexes.last().unwrap();
+    let batch = latest.iter().last().unwrap();
+    let mut data = fs::read(latest.to_file_path()).unwrap();
+    let fpos = batch.to_fpos() as usize;
+    data[fpos..fpos + batch.to_length()].iter_mut().for_each(|b| *b = 0);
+    fs::write(latest.to_file_path(), data).unwrap();
+
+    let range = batch.to_first_seqno()..=batch.to_last_seqno();
+    let items: Vec<entry::Entry> =
+        wal_a.range(range.clone()).unwrap().map(|e| e.unwrap()).collect();
+    let n = (range.end() - range.start() + 1) as usize;
+    assert_eq!(items, refs[(refs.len() - n)..].to_vec());
+    assert!(wal_b.range(range).unwrap().any(|e| e.is_err()));
+
+    wal_a.close(false).unwrap();
+    wal_b.close(false).unwrap();
+}
+
+#[test]
+fn test_wal_blob_spill() {
+    for codec in [Codec::Cbor, Codec::Compact].iter() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = Config::new("test-wal-blob-spill", dir.path().as_ref());
+        config.set_journal_limit(10_000).set_fsync(false).set_codec(*codec);
+        config.set_blob_spill(Some(64));
+
+        let wal: Wal = Wal::create(config.clone(), state::NoState).unwrap();
+        let mut ops = vec![];
+        for i in 0..100_u8 {
+            let op = match i % 3 {
+                0 => vec![i; 4096],
+                _ => vec![i; 16],
+            };
+            let seqno = wal.add_op(&op).unwrap();
+            ops.push(entry::Entry::new(seqno, op));
+        }
+        let items: Vec<entry::Entry> = wal.iter().unwrap().map(|e| e.unwrap()).collect();
+        assert_eq!(items, ops);
+        for (item, op) in items.iter().zip(ops.iter()) {
+            assert_eq!(item.as_op(), op.as_op());
+        }
+
+        // large ops are kept out of journals.
+        let indexes = wal.indexes().unwrap();
+        let size: u64 = indexes.iter().map(|jn| jn.to_size()).sum();
+        assert!(size < 34 * 4096, "{}", size);
+        for jn in indexes.iter() {
+            let blob_path = files::make_blob_filename(&jn.to_file_path());
+            assert!(path::Path::new(&blob_path).exists(), "{:?}", blob_path);
+        }
+        wal.close(false).unwrap();
+
+        let wal: Wal = Wal::open(config.clone(), OpenMode::ReadOnly).unwrap();
+        let items: Vec<entry::Entry> = wal.iter().unwrap().map(|e| e.unwrap()).collect();
+        assert_eq!(items.len(), ops.len());
+        for (item, op) in items.iter().zip(ops.iter()) {
+            assert_eq!(item.as_op(), op.as_op());
+        }
+        wal.close(false).unwrap();
+
+        // corrupted blob fails the checksum.
+        let blob_path = files::make_blob_filename(&indexes[0].to_file_path());
+        let mut data = fs::read(&blob_path).unwrap();
+        data[0] = !data[0];
+        fs::write(&blob_path, data).unwrap();
+
+        let wal: Wal = Wal::open(config, OpenMode::ReadOnly).unwrap();
+        let mut iter = wal.iter().unwrap();
+        assert!(iter.next().unwrap().is_err());
+        let items: Vec<entry::Entry> = iter.map(|e| e.unwrap()).collect();
+        assert_eq!(items.len(), ops.len() - 1);
+        wal.close(false).unwrap();
+    }
+}
+
+#[test]
+fn test_wal_journal_width() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-wal-journal-width", dir.path().as_ref());
+    config.set_journal_limit(1000).set_fsync(false);
+
+    let wal: Wal = Wal::create(config.clone(), state::NoState).unwrap();
+    for i in 0..100_u8 {
+        wal.add_op(&[i; 32]).unwrap();
+    }
+    let refs: Vec<entry::Entry> = wal.iter().unwrap().map(|e| e.unwrap()).collect();
+    wal.close(false).unwrap();
+
+    let file_names = |dir: &path::Path| -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(dir)
+            .unwrap()
+            .map(|item| item.unwrap().file_name().into_string().unwrap())
+            .filter(|name| name.ends_with(".dat"))
+            .collect();
+        names.sort();
+        names
+    };
+    let names = file_names(dir.path());
+    assert!(names
+        .iter()
+        .all(|name| name.len() == "test-wal-journal-width-journal-000.dat".len()));
+
+    // read-only instances leave the file names as is.
+    config.set_journal_width(8);
+    let wal: Wal = Wal::open(config.clone(), OpenMode::ReadOnly).unwrap();
+    assert_eq!(
+        wal.iter().unwrap().map(|e| e.unwrap()).collect::<Vec<entry::Entry>>(),
+        refs
+    );
+    wal.close(false).unwrap();
+    assert_eq!(file_names(dir.path()), names);
+
+    // loading for writes migrates to the new width, in numeric order.
+    let wal: Wal = Wal::load(config.clone()).unwrap();
+    assert_eq!(
+        wal.iter().unwrap().map(|e| e.unwrap()).collect::<Vec<entry::Entry>>(),
+        refs
+    );
+    let nums: Vec<usize> = file_names(dir.path())
+        .into_iter()
+        .map(|name| {
+            assert_eq!(name.len(), "test-wal-journal-width-journal-00000000.dat".len());
+            files::unwrap_filename(name.into()).unwrap().1
+        })
+        .collect();
+    assert!(nums.windows(2).all(|w| w[0] < w[1]), "{:?}", nums);
+    assert_eq!(nums.len(), names.len() + 1);
+    wal.close(false).unwrap();
+
+    // journal numbers beyond the width are parsed back.
+    let name = files::make_filename(config.name.clone(), 12345, 3);
+    assert_eq!(files::unwrap_filename(name), Some((config.name.clone(), 12345)));
+    let name = files::make_filename(config.name.clone(), 7, 0);
+    assert_eq!(files::unwrap_filename(name), Some((config.name, 7)));
+}
+
+#[test]
+fn test_wal_duplicate_journals() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-wal-duplicate-journals", dir.path().as_ref());
+    config.set_journal_limit(1000).set_fsync(false);
+
+    let wal: Wal = Wal::create(config.clone(), state::NoState).unwrap();
+    for i in 0..100_u8 {
+        wal.add_op(&[i; 32]).unwrap();
+    }
+    let indexes = wal.indexes().unwrap();
+    wal.close(false).unwrap();
+
+    // restored backup of journal 1 lands as journal 2, with a different
+    // file name.
+    let num = indexes[2].to_journal_number();
+    let dup: path::PathBuf = {
+        let file = format!("{}-journal-{}.dat", config.name, num);
+        [dir.path().as_os_str(), file.as_ref()].iter().collect()
+    };
+    fs::copy(indexes[1].to_file_path(), &dup).unwrap();
+
+    let wal: Wal = Wal::open(config.clone(), OpenMode::ReadOnly).unwrap();
+    let events: Vec<WalEvent> = wal.events().unwrap().try_iter().collect();
+    let file = dup.clone().into_os_string();
+    assert!(events.contains(&WalEvent::Quarantined { num, file, to: None }));
+    let seqnos: Vec<u64> = wal.iter().unwrap().map(|e| e.unwrap().to_seqno()).collect();
+    assert_eq!(seqnos, (1..=100).collect::<Vec<u64>>());
+    wal.close(false).unwrap();
+    assert!(dup.exists());
+
+    let wal: Wal = Wal::load(config).unwrap();
+    let events: Vec<WalEvent> = wal.events().unwrap().try_iter().collect();
+    let to = files::make_quarantine_filename(dup.as_os_str());
+    let file = dup.clone().into_os_string();
+    assert!(events.contains(&WalEvent::Quarantined { num, file, to: Some(to.clone()) }));
+    assert!(!dup.exists());
+    assert!(path::Path::new(&to).exists());
+    let seqnos: Vec<u64> = wal.iter().unwrap().map(|e| e.unwrap().to_seqno()).collect();
+    assert_eq!(seqnos, (1..=100).collect::<Vec<u64>>());
+    wal.close(true).unwrap();
+}
+
+#[test]
+fn test_wal_health() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-wal-health", dir.path().as_ref());
+    config.set_journal_limit(1000).set_fsync(false);
+
+    let wal: Wal = Wal::create(config, state::NoState).unwrap();
+    for i in 0..10_u8 {
+        wal.add_op(&[i; 32]).unwrap();
+    }
+    let health = wal.health().unwrap();
+    assert_eq!(health.state, HealthState::Running);
+    assert!(health.last_error.is_none());
+    assert_eq!(health.durable_seqno, 10);
+
+    // journal rotation fails once the directory is gone, which is fatal.
+    fs::remove_dir_all(dir.path()).unwrap();
+    let res: Vec<Result<u64>> = (0..100_u8).map(|i| wal.add_op(&[i; 32])).collect();
+    assert!(res.iter().any(|r| r.is_err()));
+    // requests fail as the writer exits, health is updated right after.
+    let mut health = wal.health().unwrap();
+    for _i in 0..100 {
+        if health.state == HealthState::Poisoned {
+            break;
+        }
+        std::thread::sleep(time::Duration::from_millis(10));
+        health = wal.health().unwrap();
+    }
+    assert_eq!(health.state, HealthState::Poisoned);
+    assert!(health.last_error.is_some());
+    assert!(health.durable_seqno >= 10);
+}
+
+#[test]
+fn test_wal_watermarks() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-wal-watermarks", dir.path().as_ref());
+    config.set_journal_limit(1000).set_fsync(false);
+
+    let wal: Wal = Wal::create(config.clone(), state::NoState).unwrap();
+    assert_eq!(wal.watermarks().unwrap(), Watermarks::default());
+    for i in 0..100_u8 {
+        wal.add_op(&[i; 32]).unwrap();
+    }
+    let marks = wal.watermarks().unwrap();
+    let refs = Watermarks {
+        appended: 100,
+        flushed: 100,
+        durable: 100,
+        purged: 0,
+    };
+    assert_eq!(marks, refs);
+
+    let tombstone = wal.purge_till(50, "retention").unwrap().unwrap();
+    let marks = wal.watermarks().unwrap();
+    assert!(tombstone.to_seqno() > 0 && tombstone.to_seqno() <= 50);
+    assert_eq!(marks, Watermarks { purged: tombstone.to_seqno(), ..refs });
+    wal.close(false).unwrap();
+
+    let wal: Wal = Wal::load(config).unwrap();
+    assert_eq!(
+        wal.watermarks().unwrap(),
+        Watermarks { purged: tombstone.to_seqno(), ..refs }
+    );
+
+    // frontiers restart along with seqnos in the new epoch.
+    wal.rebase(1).unwrap();
+    assert_eq!(wal.watermarks().unwrap(), Watermarks::default());
+    wal.add_op(b"op").unwrap();
+    let marks = wal.watermarks().unwrap();
+    assert_eq!(marks, Watermarks { appended: 1, flushed: 1, durable: 1, purged: 0 });
+    wal.close(true).unwrap();
+}
+
+#[test]
+fn test_wal_coverage() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-wal-coverage", dir.path().as_ref());
+    config.set_journal_limit(1000).set_fsync(false);
+
+    let wal: Wal = Wal::create(config.clone(), state::NoState).unwrap();
+    assert!(wal.coverage().unwrap().is_empty());
+    assert_eq!(wal.oldest_seqno().unwrap(), 1);
+    for i in 0..100_u8 {
+        wal.add_op(&[i; 32]).unwrap();
+    }
+    assert_eq!(wal.coverage().unwrap(), 1..=100);
+
+    let tombstone = wal.purge_till(50, "retention").unwrap().unwrap();
+    let oldest = tombstone.to_seqno() + 1;
+    assert_eq!(wal.coverage().unwrap(), oldest..=100);
+    assert_eq!(wal.range(..oldest).unwrap().count(), 0);
+    assert_eq!(wal.range(oldest..).unwrap().count() as u64, 101 - oldest);
+
+    // cold journals are retained on disk.
+    let num = wal.indexes().unwrap()[0].to_journal_number();
+    wal.freeze(num).unwrap();
+    assert_eq!(wal.oldest_seqno().unwrap(), oldest);
+    wal.close(false).unwrap();
+
+    let wal: Wal = Wal::load(config).unwrap();
+    assert_eq!(wal.coverage().unwrap(), oldest..=100);
+
+    // coverage restarts along with seqnos in the new epoch.
+    wal.rebase(1).unwrap();
+    assert!(wal.coverage().unwrap().is_empty());
+    wal.add_op(b"op").unwrap();
+    assert_eq!(wal.coverage().unwrap(), 1..=1);
+    wal.close(true).unwrap();
+}
+
+#[test]
+fn test_wal_rebuild_index() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-wal-rebuild-index", dir.path().as_ref());
+    config.set_journal_limit(1000).set_fsync(false);
+
+    let wal: Wal = Wal::create(config.clone(), state::NoState).unwrap();
+    for i in 0..100_u8 {
+        wal.add_op(&[i; 32]).unwrap();
+    }
+    let refs: Vec<entry::Entry> = wal.iter().unwrap().map(|e| e.unwrap()).collect();
+    let indexes = wal.indexes().unwrap();
+    wal.close(false).unwrap();
+
+    // damage a batch in the middle of an archived journal.
+    let jn = &indexes[1];
+    let batch = jn.iter().nth(jn.len() / 2).unwrap().clone();
+    let mut data = fs::read(jn.to_file_path()).unwrap();
+    let fpos = batch.to_fpos() as usize;
+    data[fpos..fpos + batch.to_length()].iter_mut().for_each(|b| *b = 0);
+    fs::write(jn.to_file_path(), data).unwrap();
+
+    let wal: Wal = Wal::load(config).unwrap();
+    let events: Vec<WalEvent> = wal.events().unwrap().try_iter().collect();
+    let file = jn.to_file_path();
+    assert!(events.contains(&WalEvent::Corruption { file }), "{:?}", events);
+    let span = jn.iter().next().unwrap().to_first_seqno()..=batch.to_last_seqno();
+    let mut iter = wal.iter().unwrap().map(|e| e.unwrap());
+    assert!(iter.all(|e| !span.contains(&e.to_seqno())));
+
+    let num = jn.to_journal_number();
+    let index = wal.rebuild_index(num).unwrap();
+    assert_eq!(index.len(), jn.len() - 1);
+    let items: Vec<entry::Entry> = wal.iter().unwrap().map(|e| e.unwrap()).collect();
+    let damaged = batch.to_first_seqno()..=batch.to_last_seqno();
+    let refs: Vec<entry::Entry> =
+        refs.into_iter().filter(|e| !damaged.contains(&e.to_seqno())).collect();
+    assert_eq!(items, refs);
+
+    let active = wal.indexes().unwrap().last().unwrap().to_journal_number();
+    assert!(matches!(wal.rebuild_index(active), Err(Error::Invalid(_, _))));
+    assert!(matches!(wal.rebuild_index(active + 1), Err(Error::NotFound(_, _))));
+    wal.close(true).unwrap();
+}
+
+#[test]
+fn test_wal_replay_from_cursor() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-wal-replay-cursor", dir.path().as_ref());
+    config.set_journal_limit(1000).set_fsync(false);
+
+    let wal: Wal = Wal::create(config.clone(), state::NoState).unwrap();
+    for i in 0..100_u8 {
+        wal.add_op(&[i; 32]).unwrap();
+    }
+
+    // consumer fails while applying seqno 40.
+    let mut applied = vec![];
+    let mut cursor = cursor::ReplayCursor::open(&config, "indexer").unwrap();
+    let res = wal.replay_from_cursor(&mut cursor, |e| match e.to_seqno() {
+        40 => err_at!(Fatal, msg: "crash"),
+        seqno => {
+            applied.push(seqno);
+            Ok(())
+        }
+    });
+    assert!(matches!(res, Err(Error::Fatal(_, _))));
+    assert_eq!(cursor.to_seqno(), 39);
+
+    // resume from the persisted cursor, after reopening the instance.
+    wal.close(false).unwrap();
+    let wal: Wal = Wal::load(config.clone()).unwrap();
+    let mut cursor = cursor::ReplayCursor::open(&config, "indexer").unwrap();
+    assert_eq!(cursor.to_seqno(), 39);
+    let n = wal
+        .replay_from_cursor(&mut cursor, |e| {
+            applied.push(e.to_seqno());
+            Ok(())
+        })
+        .unwrap();
+    assert_eq!(n, 61);
+    assert_eq!(applied, (1..=100).collect::<Vec<u64>>());
+    assert_eq!(wal.replay_from_cursor(&mut cursor, |_| Ok(())).unwrap(), 0);
+
+    // a new consumer, after entries are purged, cannot replay.
+    wal.purge_till(50, "retention").unwrap().unwrap();
+    let mut other = cursor::ReplayCursor::open(&config, "other").unwrap();
+    let res = wal.replay_from_cursor(&mut other, |_| Ok(()));
+    assert!(matches!(res, Err(Error::NotFound(_, _))));
+    assert!(cursor::ReplayCursor::open(&config, "").is_err());
+
+    // cursor from an older epoch restarts with the current epoch.
+    wal.rebase(1).unwrap();
+    wal.add_op(b"op").unwrap();
+    let n = wal.replay_from_cursor(&mut cursor, |_| Ok(())).unwrap();
+    assert_eq!(n, 1);
+    assert_eq!((cursor.to_epoch(), cursor.to_seqno()), (1, 1));
+
+    cursor.remove().unwrap();
+    let cursor = cursor::ReplayCursor::open(&config, "indexer").unwrap();
+    assert_eq!((cursor.to_epoch(), cursor.to_seqno()), (0, 0));
+    wal.close(true).unwrap();
+}
+
+#[test]
+fn test_wal_consumers() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-wal-consumers", dir.path().as_ref());
+    config.set_journal_limit(1000).set_fsync(false);
+
+    let wal: Wal = Wal::create(config.clone(), state::NoState).unwrap();
+    for i in 0..100_u8 {
+        wal.add_op(&[i; 32]).unwrap();
+    }
+    assert!(wal.consumers().unwrap().is_empty());
+    assert!(wal.ack("", 10).is_err());
+
+    wal.ack("archiver", 50).unwrap();
+    wal.ack("indexer", 10).unwrap();
+    let consumers = wal.consumers().unwrap();
+    let names: Vec<&str> = consumers.iter().map(|c| c.name.as_str()).collect();
+    assert_eq!(names, vec!["indexer", "archiver"]);
+    assert_eq!((consumers[0].seqno, consumers[0].entries), (10, 90));
+    assert_eq!((consumers[1].seqno, consumers[1].entries), (50, 50));
+    assert!(consumers[0].bytes > consumers[1].bytes, "{:?}", consumers);
+    assert!(consumers[1].bytes > 0, "{:?}", consumers);
+    assert_eq!(wal.stats().unwrap().consumers, consumers);
+
+    // replay acknowledges the cursor's progress.
+    let mut cursor = cursor::ReplayCursor::open(&config, "indexer").unwrap();
+    assert_eq!(wal.replay_from_cursor(&mut cursor, |_| Ok(())).unwrap(), 100);
+    let consumers = wal.consumers().unwrap();
+    assert_eq!(consumers[0].name, "archiver");
+    assert_eq!(
+        consumers[1],
+        ConsumerLag {
+            name: "indexer".to_string(),
+            seqno: 100,
+            ..ConsumerLag::default()
+        }
+    );
+
+    assert!(wal.forget("archiver").unwrap());
+    assert!(!wal.forget("archiver").unwrap());
+    assert_eq!(wal.consumers().unwrap().len(), 1);
+
+    // progress is persisted in the manifest.
+    wal.close(false).unwrap();
+    let wal: Wal = Wal::load(config.clone()).unwrap();
+    let consumers = wal.consumers().unwrap();
+    assert_eq!(consumers.len(), 1);
+    assert_eq!((consumers[0].name.as_str(), consumers[0].seqno), ("indexer", 100));
+
+    // consumers from an older epoch lag behind the whole of current epoch.
+    wal.rebase(1).unwrap();
+    wal.add_op(b"op").unwrap();
+    let consumers = wal.consumers().unwrap();
+    assert_eq!((consumers[0].epoch, consumers[0].entries), (0, 1));
+    assert!(consumers[0].bytes > 0, "{:?}", consumers);
+
+    wal.close(true).unwrap();
+}
+
+#[test]
+fn test_wal_consumer_retention() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-wal-consumer-retention", dir.path().as_ref());
+    config.set_journal_limit(1000).set_fsync(false).set_consumer_retention(true);
+
+    let wal: Wal = Wal::create(config.clone(), state::NoState).unwrap();
+    for i in 0..100_u8 {
+        wal.add_op(&[i; 32]).unwrap();
+    }
+    let n_journals = wal.stats().unwrap().n_journals;
+
+    wal.ack("a", 0).unwrap();
+    wal.ack("b", 60).unwrap();
+    assert_eq!(wal.watermarks().unwrap().purged, 0);
+    assert_eq!(wal.stats().unwrap().n_journals, n_journals);
+
+    // slowest consumer moves, journals upto the slowest are purged.
+    wal.ack("a", 80).unwrap();
+    let purged = wal.watermarks().unwrap().purged;
+    assert!(purged > 0 && purged <= 60, "{}", purged);
+    assert!(wal.stats().unwrap().n_journals < n_journals);
+    let seqnos: Vec<u64> =
+        wal.range(61..).unwrap().map(|e| e.unwrap().to_seqno()).collect();
+    assert_eq!(seqnos, (61..=100).collect::<Vec<u64>>());
+    let tombstone = wal.purge_history().unwrap().pop().unwrap();
+    assert_eq!(tombstone.as_reason(), "consumers");
+
+    // consumer behind the current epoch holds back retention.
+    wal.rebase(1).unwrap();
+    for i in 0..100_u8 {
+        wal.add_op(&[i; 32]).unwrap();
+    }
+    let n_journals = wal.stats().unwrap().n_journals;
+    wal.ack("b", 100).unwrap();
+    assert_eq!(wal.stats().unwrap().n_journals, n_journals);
+    wal.forget("a").unwrap();
+    wal.ack("b", 100).unwrap();
+    assert!(wal.stats().unwrap().n_journals < n_journals);
+
+    wal.close(true).unwrap();
+}
+
+#[test]
+fn test_wal_maintenance() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-wal-maintenance", dir.path().as_ref());
+    config
+        .set_journal_limit(1000)
+        .set_fsync(false)
+        .set_maintenance(Some(time::Duration::from_millis(50)));
+
+    let wal: Wal = Wal::create(config.clone(), state::NoState).unwrap();
+    let events = wal.events().unwrap();
+    for i in 0..100_u8 {
+        wal.add_op(&[i; 32]).unwrap();
+    }
+    let indexes = wal.indexes().unwrap();
+    let archived: Vec<usize> =
+        indexes[..indexes.len() - 1].iter().map(|i| i.to_journal_number()).collect();
+    assert!(archived.len() > 2);
+
+    // every archived journal is scrubbed once the writer is idle.
+    let mut scrubbed = vec![];
+    while scrubbed.len() < archived.len() {
+        let event = events.recv_timeout(time::Duration::from_secs(10)).unwrap();
+        if let WalEvent::Scrubbed { num, clean, .. } = event {
+            assert!(clean, "journal {}", num);
+            scrubbed.push(num);
+        }
+    }
+    scrubbed.sort_unstable();
+    assert_eq!(scrubbed, archived);
+
+    assert_eq!(wal.close(false).unwrap(), CloseOutcome::Closed(100));
+}
+
+#[test]
+fn test_wal_heartbeat() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-wal-heartbeat", dir.path().as_ref());
+    config.set_fsync(false);
+
+    let wal: Wal = Wal::create(config.clone(), state::NoState).unwrap();
+    assert_eq!(wal.last_batch_time().unwrap(), None);
+    for i in 0..10_u8 {
+        wal.add_op(&[i; 32]).unwrap();
+    }
+    let n_batches = wal.indexes().unwrap().last().unwrap().len();
+    let last = wal.last_batch_time().unwrap().unwrap();
+    std::thread::sleep(time::Duration::from_millis(50));
+    assert_eq!(wal.indexes().unwrap().last().unwrap().len(), n_batches);
+    assert_eq!(wal.last_batch_time().unwrap(), Some(last));
+    wal.close(false).unwrap();
+
+    // idle writer keeps writing heartbeat batches, seen by followers.
+    config.set_heartbeat(Some(time::Duration::from_millis(10)));
+    let wal: Wal = Wal::load(config.clone()).unwrap();
+    wal.add_op(b"op").unwrap();
+    let first = wal.last_batch_time().unwrap().unwrap();
+    std::thread::sleep(time::Duration::from_millis(100));
+    assert!(wal.last_batch_time().unwrap().unwrap() > first);
+    assert!(wal.indexes().unwrap().last().unwrap().len() > 2);
+
+    let follower: Wal = Wal::open(config.clone(), OpenMode::Attach).unwrap();
+    let seen = follower.last_batch_time().unwrap().unwrap();
+    assert!(seen > first);
+    std::thread::sleep(time::Duration::from_millis(100));
+    follower.refresh().unwrap();
+    assert!(follower.last_batch_time().unwrap().unwrap() > seen);
+
+    // heartbeat batches carry no entries.
+    let seqnos: Vec<u64> =
+        follower.iter().unwrap().map(|e| e.unwrap().to_seqno()).collect();
+    assert_eq!(seqnos, (1..=11).collect::<Vec<u64>>());
+    assert_eq!(wal.add_op(b"op").unwrap(), 12);
+    follower.close(false).unwrap();
+    wal.close(true).unwrap();
+}
+
+#[test]
+fn test_wal_dirs() {
+    let dir = tempfile::tempdir().unwrap();
+    let stripes: Vec<ffi::OsString> = (0..3)
+        .map(|i| dir.path().join(format!("stripe-{}", i)).into_os_string())
+        .collect();
+    let count = |dir: &ffi::OsStr| {
+        let dirs = vec![dir.to_os_string()];
+        files::find_journals(&dirs, "test-wal-dirs").unwrap().len()
+    };
+
+    let mut config = Config::new("test-wal-dirs", dir.path().as_ref());
+    let dirs: Vec<&ffi::OsStr> = stripes.iter().map(|d| d.as_os_str()).collect();
+    config
+        .set_journal_limit(1000)
+        .set_fsync(false)
+        .set_dirs(&dirs, Placement::RoundRobin);
+
+    let wal: Wal = Wal::create(config.clone(), state::NoState).unwrap();
+    for i in 0..100_u8 {
+        wal.add_op(&[i; 32]).unwrap();
+    }
+    let refs: Vec<entry::Entry> = wal.iter().unwrap().map(|e| e.unwrap()).collect();
+    wal.close(false).unwrap();
+
+    // journals are spread across stripes, none in the primary directory.
+    assert_eq!(count(dir.path().as_ref()), 0);
+    for stripe in stripes.iter() {
+        assert!(count(stripe) > 1, "{:?}", stripe);
+    }
+    let report = Wal::fsck(&config, fsck::FsckLevel::ReportOnly).unwrap();
+    let n: usize = stripes.iter().map(|s| count(s)).sum();
+    assert_eq!(report.journals.len(), n);
+
+    let wal: Wal = Wal::load(config.clone()).unwrap();
+    let items: Vec<entry::Entry> = wal.iter().unwrap().map(|e| e.unwrap()).collect();
+    assert_eq!(items, refs);
+    wal.purge_till(50, "retention").unwrap().unwrap();
+    let m: usize = stripes.iter().map(|s| count(s)).sum();
+    assert!(m < n, "{} {}", m, n);
+    wal.close(true).unwrap();
+    assert_eq!(stripes.iter().map(|s| count(s)).sum::<usize>(), 0);
+
+    // all journals go to the stripe with most capacity left.
+    let caps = Placement::Capacity(vec![0, u64::MAX, 0]);
+    config.set_dirs(&dirs, caps);
+    let wal: Wal = Wal::create(config.clone(), state::NoState).unwrap();
+    for i in 0..100_u8 {
+        wal.add_op(&[i; 32]).unwrap();
+    }
+    wal.close(false).unwrap();
+    assert_eq!(count(&stripes[0]) + count(&stripes[2]), 0);
+    assert!(count(&stripes[1]) > 1);
+
+    config.set_dirs(&dirs, Placement::Capacity(vec![0]));
+    assert!(matches!(Wal::<state::NoState>::load(config), Err(Error::Invalid(_, _))));
+}
+
+#[test]
+fn test_wal_entry_compression() {
+    let seed: u64 = random();
+    println!("test_wal_entry_compression {}", seed);
+    let mut rng = StdRng::seed_from_u64(seed);
+
+    let spills = [None, Some(1024)];
+    for (codec, spill) in [Codec::Cbor, Codec::Compact]
+        .iter()
+        .flat_map(|c| spills.iter().map(move |s| (c, s)))
+    {
+        let ops: Vec<Vec<u8>> = (0..100_u8)
+            .map(|i| match i % 3 {
+                0 => vec![i; 4096],
+                1 => (0..4096).map(|_| rng.gen::<u8>()).collect(),
+                _ => vec![i; 16],
+            })
+            .collect();
+
+        let mut sizes = vec![];
+        for threshold in [None, Some(64)].iter() {
+            let dir = tempfile::tempdir().unwrap();
+            let mut config =
+                Config::new("test-wal-entry-compression", dir.path().as_ref());
+            config.set_fsync(false).set_codec(*codec).set_blob_spill(*spill);
+            config.set_entry_compression(*threshold);
+
+            let wal: Wal = Wal::create(config.clone(), state::NoState).unwrap();
+            for op in ops.iter() {
+                wal.add_op(op).unwrap();
+            }
+            let check = |wal: &Wal| {
+                let items: Vec<Vec<u8>> =
+                    wal.iter().unwrap().map(|e| e.unwrap().unwrap().1).collect();
+                assert_eq!(items, ops);
+            };
+            check(&wal);
+
+            // journal and blob files.
+            let size: u64 = std::fs::read_dir(dir.path())
+                .unwrap()
+                .map(|item| item.unwrap().metadata().unwrap().len())
+                .sum();
+            sizes.push(size);
+            wal.close(false).unwrap();
+
+            let wal: Wal = Wal::open(config.clone(), OpenMode::ReadOnly).unwrap();
+            check(&wal);
+            wal.close(false).unwrap();
+        }
+        // compressible ops shrink, incompressible ops are written as is.
+        assert!(sizes[1] + 30 * 4096 < sizes[0], "{:?} {:?}", codec, sizes);
+    }
+}
+
+#[test]
+fn test_wal_diff() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-wal-diff-a", dir.path().as_ref());
+    config.set_journal_limit(1000).set_fsync(false);
+    let a: Wal = Wal::create(config, state::NoState).unwrap();
+
+    let mut config = Config::new("test-wal-diff-b", dir.path().as_ref());
+    config.set_fsync(false);
+    let b: Wal = Wal::create(config, state::NoState).unwrap();
+
+    // replica b lags behind, and diverged at seqno 30.
+    for i in 1..=100_u8 {
+        a.add_op(&[i; 32]).unwrap();
+        match i {
+            30 => b.add_op(&[0; 32]).unwrap(),
+            i if i <= 60 => b.add_op(&[i; 32]).unwrap(),
+            _ => 0,
+        };
+    }
+
+    let metas = a.batch_meta(..).unwrap();
+    assert_eq!(a.diff(&metas).unwrap(), diff::ShipPlan::default());
+    let n: u64 = metas.iter().map(|m| m.last_seqno - m.first_seqno + 1).sum();
+    assert_eq!(n, 100);
+
+    let plan = a.diff(&b.batch_meta(..).unwrap()).unwrap();
+    assert_eq!(plan.missing, vec![61..=100]);
+    assert_eq!(plan.mismatched.len(), 1, "{:?}", plan);
+    assert!(plan.mismatched[0].contains(&30));
+    assert!(*plan.mismatched[0].end() <= 60);
+
+    // nothing to ship from the lagging replica, except the divergent span.
+    let plan = b.diff(&a.batch_meta(..).unwrap()).unwrap();
+    assert!(plan.missing.is_empty());
+    assert!(plan.mismatched.iter().all(|r| *r.end() <= 60));
+    assert!(plan.mismatched.iter().any(|r| r.contains(&30)));
+
+    let remote = a.batch_meta(41..=50).unwrap();
+    assert_eq!(remote.first().map(|m| m.first_seqno), Some(41));
+    assert_eq!(remote.last().map(|m| m.last_seqno), Some(50));
+
+    a.close(true).unwrap();
+    b.close(true).unwrap();
+}
+
+#[test]
+fn test_wal_buffer_limit() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-wal-buffer-limit", dir.path().as_ref());
+    config.set_journal_limit(2000).set_fsync(false).set_buffer_limit(Some(64));
+
+    let wal = Wal::create(config.clone(), state::NoState).unwrap();
+    let writers: Vec<std::thread::JoinHandle<_>> = (0..4_u8)
+        .map(|id| {
+            let wal = wal.clone();
+            std::thread::spawn(move || {
+                let mut items = vec![];
+                for i in 0..100_u8 {
+                    let op = vec![id, i, 0, 0, 0, 0, 0, 0, 0, 0];
+                    items.push((wal.add_op(&op).unwrap(), op));
+                }
+                wal.close(false).unwrap();
+                items
+            })
+        })
+        .collect();
+    let mut items: Vec<(u64, Vec<u8>)> =
+        writers.into_iter().flat_map(|w| w.join().unwrap()).collect();
+    items.sort();
+
+    let entries: Vec<(u64, Vec<u8>)> =
+        wal.iter().unwrap().map(|e| e.unwrap().unwrap()).collect();
+    assert_eq!(entries, items);
+    wal.close(false).unwrap();
+
+    // spool files are transient.
+    for item in std::fs::read_dir(dir.path()).unwrap() {
+        let file_name = item.unwrap().file_name();
+        assert!(!file_name.to_str().unwrap().ends_with(".spool"), "{:?}", file_name);
+    }
+    let wal: Wal = Wal::load(config).unwrap();
+    assert_eq!(wal.iter().unwrap().count(), 400);
+    wal.close(true).unwrap();
+}
+
+#[test]
+fn test_wal_middleware() {
+    use crate::middleware::{
+        register_middleware, Middleware, MIDDLEWARE_CHECKSUM, MIDDLEWARE_COMPRESS,
+        MIDDLEWARE_USER,
+    };
+
+    struct Xor;
+
+    impl Middleware for Xor {
+        fn seal(&self, data: Vec<u8>) -> Result<Vec<u8>> {
+            Ok(data.into_iter().map(|b| b ^ 0xa5).collect())
+        }
+
+        fn unseal(&self, data: Vec<u8>) -> Result<Vec<u8>> {
+            self.seal(data)
+        }
+    }
+
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-wal-middleware", dir.path().as_ref());
+    config.set_journal_limit(2000).set_fsync(false).set_tag_index(true);
+
+    let id = MIDDLEWARE_USER + 2;
+    config.set_middleware(vec![MIDDLEWARE_COMPRESS, id, MIDDLEWARE_CHECKSUM]);
+    match Wal::create(config.clone(), state::NoState) {
+        Err(Error::NotFound(_, _)) => (),
+        Err(err) => panic!("{}", err),
+        Ok(_) => panic!("middleware {} is not registered", id),
+    }
+    register_middleware(id, Arc::new(Xor)).unwrap();
+
+    let wal = Wal::create(config.clone(), state::NoState).unwrap();
+    let mut refs = vec![];
+    for i in 0..200_u64 {
+        let op = format!("plaintext-op-{}", i).into_bytes();
+        let tag = if i % 10 == 0 { "ten" } else { "" };
+        let seqno = wal.add_op_tagged(tag, &op).unwrap();
+        refs.push((seqno, op));
+    }
+    wal.close(false).unwrap();
+
+    for (_, file_path) in
+        files::find_journals(&config.to_journal_dirs(), &config.name).unwrap()
+    {
+        let data = fs::read(&file_path).unwrap();
+        assert!(!data.windows(9).any(|w| w == b"plaintext"), "{:?}", file_path);
+    }
+
+    // chain can change across restarts, older batches stay readable.
+    config.set_middleware(vec![MIDDLEWARE_CHECKSUM]);
+    let wal: Wal = Wal::load(config.clone()).unwrap();
+    wal.add_op(b"plaintext-op-200").unwrap();
+    refs.push((201, b"plaintext-op-200".to_vec()));
+
+    let items: Vec<(u64, Vec<u8>)> = wal
+        .range(..)
+        .unwrap()
+        .map(|e| {
+            let e = e.unwrap();
+            (e.to_seqno(), e.as_op().to_vec())
+        })
+        .collect();
+    assert_eq!(items, refs);
+    let seqnos: Vec<u64> =
+        wal.find_by_tag("ten", ..).unwrap().map(|e| e.unwrap().to_seqno()).collect();
+    assert_eq!(seqnos, (0..20).map(|i| i * 10 + 1).collect::<Vec<u64>>());
+    wal.close(false).unwrap();
+
+    let report = Wal::fsck(&config, fsck::FsckLevel::ReportOnly).unwrap();
+    assert!(report.is_clean(), "{:?}", report);
+}
+
+// Middleware that blocks the first batch sealed with it, while `gate` is
+// set, and fails the second batch.
+#[derive(Default)]
+struct FailSecond {
+    calls: std::sync::atomic::AtomicUsize,
+    entered: std::sync::atomic::AtomicBool,
+    gate: std::sync::atomic::AtomicBool,
+}
+
+impl middleware::Middleware for FailSecond {
+    fn seal(&self, data: Vec<u8>) -> Result<Vec<u8>> {
+        match self.calls.fetch_add(1, SeqCst) {
+            0 => {
+                self.entered.store(true, SeqCst);
+                while self.gate.load(SeqCst) {
+                    std::thread::yield_now()
+                }
+                Ok(data)
+            }
+            1 => err_at!(IOError, msg: "injected flush failure"),
+            _ => Ok(data),
+        }
+    }
+
+    fn unseal(&self, data: Vec<u8>) -> Result<Vec<u8>> {
+        Ok(data)
+    }
+}
+
+// Add an op and then issue `control`, in the same batch, such that
+// flushing the op ahead of `control` fails. Return the instance, with seqno
+// 1 durable, along with the result of `control`.
+fn fail_pending_flush<F, T>(config: &mut Config, control: F) -> (Wal, Result<T>)
+where
+    F: 'static + Send + FnOnce(&Wal) -> Result<T>,
+    T: 'static + Send,
+{
+    // middleware are registered process-wide, each caller gets its own id.
+    static NEXT_ID: std::sync::atomic::AtomicU32 =
+        std::sync::atomic::AtomicU32::new(middleware::MIDDLEWARE_USER + 10);
+
+    let id = NEXT_ID.fetch_add(1, SeqCst);
+    let mw = Arc::new(FailSecond::default());
+    mw.gate.store(true, SeqCst);
+    middleware::register_middleware(id, mw.clone()).unwrap();
+    config.set_fsync(false).set_middleware(vec![id]);
+    let wal = Wal::create(config.clone(), state::NoState).unwrap();
+
+    // writer is held up flushing the first op, while the rest are queued.
+    let spawn_op = |op: u8| {
+        let wal = wal.clone();
+        std::thread::spawn(move || wal.add_op(&[op]))
+    };
+    let first = spawn_op(1);
+    while !mw.entered.load(SeqCst) {
+        std::thread::yield_now()
+    }
+    let second = spawn_op(2);
+    std::thread::sleep(time::Duration::from_millis(50));
+    let handle = {
+        let wal = wal.clone();
+        std::thread::spawn(move || control(&wal))
+    };
+    std::thread::sleep(time::Duration::from_millis(100));
+    mw.gate.store(false, SeqCst);
+
+    assert_eq!(first.join().unwrap().unwrap(), 1);
+    assert!(second.join().unwrap().is_err());
+    let res = handle.join().unwrap();
+    assert_eq!(wal.health().unwrap().state, HealthState::Degraded);
+    (wal, res)
+}
+
+#[test]
+fn test_wal_rebase_flush_failure() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-wal-rebase-flush-failure", dir.path().as_ref());
+    let (wal, res) = fail_pending_flush(&mut config, |wal| wal.rebase(1));
+    assert!(matches!(res, Err(Error::IOError(_, _))), "{:?}", res);
+
+    // writer survives, rolled back seqno is handed out again.
+    assert_eq!(wal.epoch().unwrap(), 0);
+    assert_eq!(wal.add_op(&[3]).unwrap(), 2);
+    assert_eq!(wal.health().unwrap().state, HealthState::Running);
+    assert_eq!(wal.rebase(1).unwrap(), 2);
+    wal.close(true).unwrap();
+}
+
+#[test]
+fn test_wal_relocate_flush_failure() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-wal-relocate-flush-failure", dir.path().as_ref());
+    let new_dir = tempfile::tempdir().unwrap();
+    let to: ffi::OsString = new_dir.path().into();
+    let (wal, res) = fail_pending_flush(&mut config, move |wal| wal.relocate(&to));
+    assert!(matches!(res, Err(Error::IOError(_, _))), "{:?}", res);
+    assert_eq!(std::fs::read_dir(new_dir.path()).unwrap().count(), 0);
+
+    // ops flushed ahead of relocate move along with the journal.
+    assert_eq!(wal.add_op(&[3]).unwrap(), 2);
+    wal.relocate(new_dir.path().as_ref()).unwrap();
+    assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+    let seqnos: Vec<u64> = wal.iter().unwrap().map(|e| e.unwrap().to_seqno()).collect();
+    assert_eq!(seqnos, vec![1, 2]);
+    wal.close(true).unwrap();
+}
+
+#[test]
+fn test_wal_purge_flush_failure() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-wal-purge-flush-failure", dir.path().as_ref());
+    let (wal, res) = fail_pending_flush(&mut config, |wal| wal.purge_till(1, "test"));
+    assert!(matches!(res, Err(Error::IOError(_, _))), "{:?}", res);
+
+    assert!(wal.purge_history().unwrap().is_empty());
+    assert_eq!(wal.add_op(&[3]).unwrap(), 2);
+    assert_eq!(wal.health().unwrap().state, HealthState::Running);
+    wal.close(true).unwrap();
+}
+
+#[test]
+fn test_wal_purge_failure() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-wal-purge-failure", dir.path().as_ref());
+    config.set_journal_limit(1000).set_fsync(false);
+
+    let wal = Wal::create(config, state::NoState).unwrap();
+    for _i in 0..100 {
+        wal.add_op(&[0; 32]).unwrap();
+    }
+    let indexes = wal.indexes().unwrap();
+    assert!(indexes.len() > 3, "{}", indexes.len());
+
+    // a non-empty directory in place of the second journal fails its purge.
+    let file_path = indexes[1].to_file_path();
+    fs::remove_file(&file_path).unwrap();
+    fs::create_dir(&file_path).unwrap();
+    fs::write(path::Path::new(&file_path).join("x"), b"x").unwrap();
+
+    let res = wal.purge_till(100, "test");
+    assert!(matches!(res, Err(Error::IOError(_, _))), "{:?}", res);
+    let nums: Vec<usize> =
+        wal.indexes().unwrap().iter().map(|j| j.to_journal_number()).collect();
+    assert_eq!(nums[0], indexes[1].to_journal_number());
+    assert!(!path::Path::new(&indexes[0].to_file_path()).exists());
+    assert!(path::Path::new(&indexes[2].to_file_path()).exists());
+    assert_eq!(wal.health().unwrap().state, HealthState::Running);
+
+    fs::remove_dir_all(&file_path).unwrap();
+    wal.purge_till(100, "test").unwrap().unwrap();
+    let nums: Vec<usize> =
+        wal.indexes().unwrap().iter().map(|j| j.to_journal_number()).collect();
+    assert!(!nums.contains(&indexes[1].to_journal_number()), "{:?}", nums);
+    assert!(!path::Path::new(&indexes[2].to_file_path()).exists());
+    assert_eq!(wal.add_op(&[1]).unwrap(), 101);
+    wal.close(true).unwrap();
+}
+
+#[test]
+fn test_wal_mask_flush_failure() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-wal-mask-flush-failure", dir.path().as_ref());
+    let (wal, res) = fail_pending_flush(&mut config, |wal| wal.mask_range(1..=1));
+    assert!(matches!(res, Err(Error::IOError(_, _))), "{:?}", res);
+
+    assert_eq!(wal.iter().unwrap().count(), 1);
+    assert_eq!(wal.add_op(&[3]).unwrap(), 2);
+    assert_eq!(wal.health().unwrap().state, HealthState::Running);
+    wal.close(true).unwrap();
+}
+
+#[test]
+fn test_wal_max_batch_entries() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-wal-max-batch-entries", dir.path().as_ref());
+    config.set_journal_limit(100_000).set_fsync(false);
+
+    let wal: Wal = Wal::create(config.clone(), state::NoState).unwrap();
+    let ops: Vec<(u64, Vec<u8>)> = (1..=10).map(|seqno| (seqno, vec![0; 200])).collect();
+    assert_eq!(wal.ingest(ops).unwrap(), Some(1..=10));
+    let first = wal.indexes().unwrap()[0].to_file_path();
+    for _i in 0..5 {
+        wal.add_op(&[1; 32]).unwrap();
+    }
+    wal.close(false).unwrap();
+
+    config.set_max_batch_entries(10);
+    let wal: Wal = Wal::open(config.clone(), OpenMode::ReadOnly).unwrap();
+    assert_eq!(wal.iter().unwrap().count(), 15);
+    wal.close(false).unwrap();
+
+    config.set_max_batch_entries(4);
+    let wal: Wal = Wal::open(config.clone(), OpenMode::ReadOnly).unwrap();
+    let events: Vec<WalEvent> = wal.events().unwrap().try_iter().collect();
+    assert!(events.contains(&WalEvent::Corruption { file: first }), "{:?}", events);
+    wal.close(false).unwrap();
+}
+
+#[test]
+fn test_wal_lsn() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-wal-lsn", dir.path().as_ref());
+    config.set_journal_limit(1000).set_fsync(false);
+
+    let wal: Wal = Wal::create(config, state::NoState).unwrap();
+    for _i in 0..100 {
+        wal.add_op(&[0; 32]).unwrap();
+    }
+
+    let indexes = wal.indexes().unwrap();
+    assert!(indexes.len() > 2);
+    for seqno in 1..=100 {
+        let lsn = wal.lsn(seqno).unwrap();
+        assert_eq!(lsn.to_seqno(), seqno);
+        let index = indexes
+            .iter()
+            .find(|index| Some(index.to_journal_number()) == lsn.to_journal_number())
+            .unwrap();
+        let batch = index.iter().find(|b| Some(b.to_fpos()) == lsn.to_fpos()).unwrap();
+        assert!(batch.to_first_seqno() <= seqno && seqno <= batch.to_last_seqno());
+        assert_eq!(lsn.to_string().parse::<Lsn>().unwrap(), lsn);
+    }
+    match wal.lsn(101) {
+        Err(Error::NotFound(_, _)) => (),
+        res => panic!("expected NotFound, {:?}", res),
+    }
+
+    let (start, end) = (wal.lsn(10).unwrap(), Lsn::from(20));
+    let seqnos: Vec<u64> =
+        wal.range_lsn(start..end).unwrap().map(|e| e.unwrap().to_seqno()).collect();
+    assert_eq!(seqnos, (10..20).collect::<Vec<u64>>());
+    let seqnos: Vec<u64> =
+        wal.range_lsn(start..).unwrap().map(|e| e.unwrap().to_seqno()).collect();
+    assert_eq!(seqnos, (10..=100).collect::<Vec<u64>>());
+
+    wal.close(false).unwrap();
+}
+
+#[test]
+fn test_wal_submit_ops() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-wal-submit-ops", dir.path().as_ref());
+    config
+        .set_fsync(false)
+        .set_quota("tenant-a", Quota { bytes_per_sec: 0, total_bytes: 100 });
+
+    let wal: Wal = Wal::create(config.clone(), state::NoState).unwrap();
+    let timeout = time::Duration::from_secs(10);
+
+    let mut handles = vec![];
+    for id in 0..8_u8 {
+        let wal = wal.clone();
+        handles.push(std::thread::spawn(move || {
+            let receipts: Vec<Receipt> = (0..100)
+                .map(|i| wal.submit_ops(vec![vec![id; 10]; 1 + (i % 3)]).unwrap())
+                .collect();
+            let seqnos: Vec<ops::RangeInclusive<u64>> =
+                receipts.iter().map(|r| r.wait(timeout).unwrap()).collect();
+            let durable = wal.durable_seqno().unwrap();
+            assert!(seqnos.iter().all(|s| *s.end() <= durable));
+            assert_eq!(receipts[0].try_wait().unwrap(), Some(seqnos[0].clone()));
+            wal.close(false).unwrap();
+            (id, seqnos)
+        }));
+    }
+    let mut seqnos: Vec<(u64, u8)> = vec![];
+    for handle in handles.into_iter() {
+        let (id, ranges) = handle.join().unwrap();
+        for (i, range) in ranges.into_iter().enumerate() {
+            assert_eq!(range.clone().count(), 1 + (i % 3));
+            seqnos.extend(range.map(|seqno| (seqno, id)));
+        }
+    }
+    seqnos.sort_unstable();
+    let n = seqnos.len() as u64;
+    assert_eq!(
+        seqnos.iter().map(|(s, _)| *s).collect::<Vec<u64>>(),
+        (1..=n).collect::<Vec<u64>>()
+    );
+    for (seqno, id) in seqnos.into_iter() {
+        let entry = wal.range(seqno..=seqno).unwrap().next().unwrap().unwrap();
+        assert_eq!(entry.as_op(), &[id; 10][..]);
+    }
+
+    // failed requests resolve to their error.
+    let a = wal.labeled_clone("tenant-a");
+    let receipt = a.submit_ops(vec![vec![0; 200]]).unwrap();
+    match receipt.wait(timeout) {
+        Err(Error::OverQuota(_, _)) => (),
+        res => panic!("expected OverQuota, {:?}", res),
+    }
+    assert!(matches!(receipt.try_wait(), Err(Error::OverQuota(_, _))));
+    assert_eq!(
+        a.submit_ops(vec![vec![0; 20]]).unwrap().wait(timeout).unwrap(),
+        n + 1..=n + 1
+    );
+    assert!(matches!(a.submit_ops(vec![]), Err(Error::Invalid(_, _))));
+    a.close(false).unwrap();
+
+    wal.close(false).unwrap();
+}
+
+#[test]
+fn test_wal_ordered_acks() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-wal-ordered-acks", dir.path().as_ref());
+    config.set_fsync(false).set_max_batch_requests(3).set_ordered_acks(true);
+
+    let wal: Wal = Wal::create(config, state::NoState).unwrap();
+    let timeout = time::Duration::from_secs(10);
+
+    // pipeline add_op requests, without waiting on their response, along
+    // with submitted requests, on the same handle.
+    enum Pending {
+        Tx(mpsc::Receiver<writer::Res>, Option<u64>),
+        Ack(Receipt),
+    }
+    let mut pending = vec![];
+    for i in 0..100_usize {
+        match i % 3 != 2 && i % 5 != 0 {
+            true => {
+                let (stx, srx) = mpsc::channel();
+                let req = writer::Req::AddEntry {
+                    client: wal.client,
+                    label: String::default(),
+                    topic: String::default(),
+                    tag: String::default(),
+                    op: vec![i as u8],
+                    queued: time::Instant::now(),
+                };
+                match &wal.tx {
+                    writer::Tx::Thread(thread::Tx::N(tx)) => {
+                        tx.send((req, Some(stx))).unwrap()
+                    }
+                    writer::Tx::Thread(thread::Tx::S(tx)) => {
+                        tx.send((req, Some(stx))).unwrap()
+                    }
+                    #[allow(unreachable_patterns)]
+                    _ => unreachable!(),
+                }
+                pending.push(Pending::Tx(srx, None));
+            }
+            false => {
+                pending.push(Pending::Ack(wal.submit_ops(vec![vec![i as u8]]).unwrap()))
+            }
+        }
+    }
+
+    // once a request is acknowledged, every request before it shall be
+    // acknowledged as well.
+    let mut seqnos = vec![];
+    for i in 0..pending.len() {
+        let seqno = match &mut pending[i] {
+            Pending::Tx(srx, seqno) => {
+                if seqno.is_none() {
+                    match srx.recv_timeout(timeout).unwrap() {
+                        writer::Res::Seqno(val) => *seqno = Some(val),
+                        res => panic!("unexpected response {:?}", res),
+                    }
+                }
+                seqno.unwrap()
+            }
+            Pending::Ack(receipt) => *receipt.wait(timeout).unwrap().start(),
+        };
+        for item in pending[..i].iter_mut() {
+            match item {
+                Pending::Tx(srx, seqno @ None) => match srx.try_recv() {
+                    Ok(writer::Res::Seqno(val)) => *seqno = Some(val),
+                    res => panic!("request {} acknowledged out of order {:?}", i, res),
+                },
+                Pending::Tx(_, _) => (),
+                Pending::Ack(receipt) => assert!(receipt.try_wait().unwrap().is_some()),
+            }
+        }
+        seqnos.push(seqno);
+    }
+    assert_eq!(seqnos, (1..=100).collect::<Vec<u64>>());
+
+    wal.close(false).unwrap();
+}
+
+#[test]
+fn test_wal_fixed_clock() {
+    // write the same sequence of operations under two directories.
+    let write = |dir: &path::Path| -> Vec<(ffi::OsString, Vec<u8>)> {
+        let mut config = Config::new("test-wal-fixed-clock", dir.as_os_str());
+        config
+            .set_journal_limit(1000)
+            .set_fsync(false)
+            .set_clock(Clock::fixed(1_000_000, 10));
+
+        let wal: Wal = Wal::create(config, state::NoState).unwrap();
+        for i in 0..50_u8 {
+            wal.add_op(&[i; 32]).unwrap();
+        }
+        wal.purge_till(20, "retention").unwrap();
+        for i in 50..100_u8 {
+            wal.add_op_tagged("tag", &[i; 32]).unwrap();
+        }
+        wal.close(false).unwrap();
+
+        let mut files: Vec<(ffi::OsString, Vec<u8>)> = fs::read_dir(dir)
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .map(|p| (p.file_name().unwrap().to_os_string(), fs::read(&p).unwrap()))
+            .collect();
+        files.sort();
+        files
+    };
+
+    let (dir1, dir2) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+    let (files1, files2) = (write(dir1.path()), write(dir2.path()));
+    assert!(files1.len() > 2, "{:?}", files1.len());
+    assert_eq!(files1, files2);
+
+    let mut config = Config::new("test-wal-fixed-clock", dir1.path().as_os_str());
+    config.set_clock(Clock::fixed(1_000_000, 10));
+    let wal: Wal = Wal::load(config).unwrap();
+    let instance = wal.instance_id().unwrap();
+    assert_eq!(instance, Clock::fixed(1_000_000, 10).new_uuid());
+    let ts = wal.last_batch_time().unwrap().unwrap();
+    let ts = ts.duration_since(time::UNIX_EPOCH).unwrap().as_nanos() as u64;
+    assert!(ts > 1_000_000 && ts < 1_000_000 + 10_000, "{}", ts);
+    wal.close(false).unwrap();
+}
+
+#[test]
+fn test_wal_space_policy() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-wal-space-policy", dir.path().as_ref());
+    config.set_journal_limit(1000).set_fsync(false);
+
+    let low_space = |events: &[WalEvent]| {
+        events.iter().filter(|e| matches!(e, WalEvent::LowSpace { .. })).count()
+    };
+
+    // space reserve that can't be met, rotation is refused and ops beyond
+    // the journal limit fail.
+    config.set_space_policy(SpacePolicy::Refuse, u64::MAX / 2);
+    let wal: Wal = Wal::create(config.clone(), state::NoState).unwrap();
+    let rx = wal.events().unwrap();
+    let mut n_fails = 0;
+    for _i in 0..100 {
+        match wal.add_op(&[0; 32]) {
+            Ok(_) => (),
+            Err(Error::NoSpace(_, _)) => n_fails += 1,
+            Err(err) => panic!("expected NoSpace, {}", err),
+        }
+    }
+    assert!(n_fails > 0 && n_fails < 100, "{}", n_fails);
+    assert_eq!(wal.indexes().unwrap().len(), 1);
+    let events: Vec<WalEvent> = rx.try_iter().collect();
+    assert_eq!(low_space(&events), 1, "{:?}", events);
+    match wal.health().unwrap().last_error {
+        Some(Error::NoSpace(_, _)) => (),
+        err => panic!("expected NoSpace, {:?}", err),
+    }
+    wal.close(false).unwrap();
+
+    // warn and rotate.
+    config.set_space_policy(SpacePolicy::Warn, u64::MAX / 2);
+    let wal: Wal = Wal::load(config.clone()).unwrap();
+    let rx = wal.events().unwrap();
+    for _i in 0..100 {
+        wal.add_op(&[0; 32]).unwrap();
+    }
+    assert!(wal.indexes().unwrap().len() > 2);
+    let events: Vec<WalEvent> = rx.try_iter().collect();
+    assert_eq!(low_space(&events), 1, "{:?}", events);
+    wal.close(false).unwrap();
+
+    // space is available without the reserve.
+    config.set_space_policy(SpacePolicy::Refuse, 0);
+    let wal: Wal = Wal::load(config).unwrap();
+    let rx = wal.events().unwrap();
+    let n = wal.indexes().unwrap().len();
+    for _i in 0..100 {
+        wal.add_op(&[0; 32]).unwrap();
+    }
+    assert!(wal.indexes().unwrap().len() > n);
+    let events: Vec<WalEvent> = rx.try_iter().collect();
+    assert_eq!(low_space(&events), 0, "{:?}", events);
+    wal.close(false).unwrap();
+}
+
+#[test]
+fn test_wal_state_size() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-wal-state-size", dir.path().as_ref());
+    config.set_fsync(false).set_state_size_warning(Some(100), None);
+
+    let oversize = |events: &[WalEvent]| -> Vec<(u64, usize, usize)> {
+        let iter = events.iter().filter_map(|e| match e {
+            WalEvent::StateOversize { seqno, size, length } => {
+                Some((*seqno, *size, *length))
+            }
+            _ => None,
+        });
+        iter.collect()
+    };
+
+    let wal = Wal::create(config.clone(), Trail::default()).unwrap();
+    let rx = wal.events().unwrap();
+    assert_eq!(wal.stats().unwrap().state_size, 0);
+    for i in 0..50_u8 {
+        wal.add_op(&[i]).unwrap();
+    }
+    let size = wal.stats().unwrap().state_size;
+    assert!(size > 50 && size <= 100, "{}", size);
+    assert!(oversize(&rx.try_iter().collect::<Vec<WalEvent>>()).is_empty());
+
+    // warned once, as state keeps growing beyond the limit.
+    for i in 50..150_u8 {
+        wal.add_op(&[i]).unwrap();
+    }
+    let events = oversize(&rx.try_iter().collect::<Vec<WalEvent>>());
+    assert_eq!(events.len(), 1, "{:?}", events);
+    let (seqno, size, length) = events[0];
+    assert!(seqno > 50 && size > 100 && length > size, "{:?}", events);
+    assert!(wal.stats().unwrap().state_size > 100);
+    wal.close(false).unwrap();
+
+    // state relative to the batch size.
+    let mut config = Config::new("test-wal-state-size-percent", dir.path().as_ref());
+    config.set_fsync(false).set_state_size_warning(None, Some(50));
+    let wal = Wal::create(config.clone(), state::NoState).unwrap();
+    let rx = wal.events().unwrap();
+    for i in 0..10_u8 {
+        wal.add_op(&[i]).unwrap();
+    }
+    assert!(oversize(&rx.try_iter().collect::<Vec<WalEvent>>()).is_empty());
+    wal.close(false).unwrap();
+
+    config.set_state_size_warning(None, Some(1));
+    let wal: Wal = Wal::load(config).unwrap();
+    let rx = wal.events().unwrap();
+    for i in 0..10_u8 {
+        wal.add_op(&[i]).unwrap();
+    }
+    assert_eq!(oversize(&rx.try_iter().collect::<Vec<WalEvent>>()).len(), 1);
+    wal.close(true).unwrap();
+}
+
+#[test]
+fn test_wal_config_history() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-wal-config-history", dir.path().as_ref());
+    config.set_journal_limit(1000);
+
+    let wal = Wal::create(config.clone(), state::NoState).unwrap();
+    wal.add_op(&[1; 32]).unwrap();
+    let history = wal.config_history().unwrap();
+    assert_eq!(history.len(), 1);
+    assert_eq!(history[0].get("fsync"), Some("true"));
+    assert_eq!(history[0].get("journal_limit"), Some("1000"));
+    wal.close(false).unwrap();
+
+    // same configuration is not recorded again.
+    let wal: Wal = Wal::load(config.clone()).unwrap();
+    assert_eq!(wal.config_history().unwrap().len(), 1);
+    wal.close(false).unwrap();
+
+    config.set_fsync(false).set_journal_limit(2000);
+    let wal: Wal = Wal::load(config.clone()).unwrap();
+    wal.rename("test-wal-config-renamed").unwrap();
+    let history = wal.config_history().unwrap();
+    assert_eq!(history.len(), 3);
+    assert_eq!(history[1].get("fsync"), Some("false"));
+    assert_eq!(history[1].get("journal_limit"), Some("2000"));
+    assert_eq!(history[2].get("name"), Some("\"test-wal-config-renamed\""));
+    assert!(history.windows(2).all(|v| v[0].to_timestamp() <= v[1].to_timestamp()));
+    wal.close(false).unwrap();
+
+    config.name = "test-wal-config-renamed".to_string();
+    config.set_fsync(true);
+    let wal: Wal = Wal::open(config.clone(), OpenMode::ReadOnly).unwrap();
+    assert_eq!(wal.config_history().unwrap(), history);
+    wal.close(false).unwrap();
+    let wal: Wal = Wal::load(config).unwrap();
+    assert_eq!(wal.config_history().unwrap().len(), 4);
+    wal.close(true).unwrap();
+}
+
+#[test]
+fn test_wal_migrate_format() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-wal-migrate-format", dir.path().as_ref());
+    config.set_journal_limit(2000).set_fsync(false).set_blob_spill(Some(500));
+
+    let op = |i: u64| match i % 10 {
+        0 => vec![(i % 256) as u8; 600],
+        _ => vec![(i % 256) as u8; 64],
+    };
+    let wal = Wal::create(config.clone(), state::NoState).unwrap();
+    wal.add_op_to("topic", &op(1)).unwrap();
+    for i in 2..=200 {
+        wal.add_op(&op(i)).unwrap();
+    }
+    let n_archived = wal.indexes().unwrap().len() - 1;
+    assert!(n_archived > 2, "{}", n_archived);
+
+    // appends continue while archived journals are rewritten.
+    let writer = {
+        let wal = wal.clone();
+        std::thread::spawn(move || {
+            for i in 201..=400 {
+                wal.add_op(&op(i)).unwrap();
+            }
+        })
+    };
+    let n = wal.migrate_format(Codec::Compact, Some(32)).unwrap();
+    writer.join().unwrap();
+    assert!(n >= n_archived, "{} {}", n, n_archived);
+    let history = wal.config_history().unwrap();
+    assert_eq!(history.last().unwrap().get("codec"), Some("Compact"));
+    assert_eq!(history.last().unwrap().get("compress_threshold"), Some("Some(32)"));
+
+    let entries: Vec<entry::Entry> = wal.iter().unwrap().map(|e| e.unwrap()).collect();
+    assert_eq!(entries.len(), 400);
+    for (i, entry) in (1..=400).zip(entries.iter()) {
+        assert_eq!(entry.to_seqno(), i);
+        assert_eq!(entry.as_op(), op(i).as_slice());
+    }
+    assert_eq!(wal.range_topic("topic", ..).unwrap().count(), 1);
+    wal.close(false).unwrap();
+
+    // journals carry batches in either format after reload.
+    let wal: Wal = Wal::load(config.clone()).unwrap();
+    let entries: Vec<entry::Entry> = wal.iter().unwrap().map(|e| e.unwrap()).collect();
+    assert_eq!(entries.len(), 400);
+    assert!(entries.iter().zip(1..).all(|(e, i)| e.as_op() == op(i).as_slice()));
+    wal.close(false).unwrap();
+
+    let wal: Wal = Wal::open(config, OpenMode::ReadOnly).unwrap();
+    match wal.migrate_format(Codec::Cbor, None) {
+        Err(Error::ReadOnly(_, _)) => (),
+        res => panic!("expected ReadOnly {:?}", res),
+    }
+    wal.close(false).unwrap();
+}
+
+#[test]
+fn test_wal_export() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-wal-export", dir.path().as_ref());
+    config.set_journal_limit(1000).set_fsync(false);
+
+    let wal = Wal::create(config, state::NoState).unwrap();
+    for i in 0..100_u8 {
+        match i % 2 {
+            0 => wal.add_op(&[i; 32]).unwrap(),
+            _ => wal.add_op_to("odd", &[i; 32]).unwrap(),
+        };
+    }
+
+    let mut out = vec![];
+    assert_eq!(wal.export_jsonl(11..=20, OpEncoding::Hex, &mut out).unwrap(), 10);
+    let lines: Vec<String> =
+        String::from_utf8(out).unwrap().lines().map(String::from).collect();
+    assert_eq!(lines.len(), 10);
+    for (seqno, line) in (11..=20_u64).zip(lines.iter()) {
+        let topic = if seqno % 2 == 0 { "odd" } else { "" };
+        let op: String = (0..32).map(|_| format!("{:02x}", seqno - 1)).collect();
+        let prefix = format!("{{\"seqno\":{},\"timestamp\":", seqno);
+        let suffix = format!(",\"topic\":\"{}\",\"tag\":\"\",\"op\":\"{}\"}}", topic, op);
+        assert!(line.starts_with(&prefix) && line.ends_with(&suffix), "{}", line);
+    }
+
+    let mut out = vec![];
+    assert_eq!(wal.export_cbor_seq(.., &mut out).unwrap(), 100);
+    let mut refs = vec![];
+    for entry in wal.iter().unwrap() {
+        export::write_cbor(&entry.unwrap(), &mut refs).unwrap();
+    }
+    assert_eq!(out, refs);
+    // each item is a map of 5 pairs, starting with seqno 1.
+    assert_eq!(&out[..8], b"\xa5\x65seqno\x01");
+
+    let mut out = vec![];
+    assert_eq!(wal.export_jsonl(200.., OpEncoding::Base64, &mut out).unwrap(), 0);
+    assert!(out.is_empty());
+    wal.close(true).unwrap();
+}
+
+#[test]
+fn test_wal_spawner() {
+    use std::sync::Mutex;
+
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-wal-spawner", dir.path().as_ref());
+    let names = Arc::new(Mutex::new(vec![]));
+    let spawner = {
+        let names = Arc::clone(&names);
+        Spawner::new(move |name, job| {
+            names.lock().unwrap().push(name.to_string());
+            std::thread::Builder::new()
+                .name(format!("app-{}", name))
+                .stack_size(256 * 1024)
+                .spawn(job)
+                .map(|_| ())
+        })
+    };
+    config.set_spawner(spawner);
+
+    let wal = Wal::create(config.clone(), state::NoState).unwrap();
+    assert_eq!(wal.add_op(&[1]).unwrap(), 1);
+    assert_eq!(wal.close(false).unwrap(), CloseOutcome::Closed(1));
+    let wal: Wal = Wal::load(config.clone()).unwrap();
+    assert_eq!(wal.add_op(&[2]).unwrap(), 2);
+    assert_eq!(wal.iter().unwrap().count(), 2);
+    wal.close(false).unwrap();
+    let refs = vec!["wral-writer-test-wal-spawner".to_string(); 2];
+    assert_eq!(*names.lock().unwrap(), refs);
+
+    // spawner failing to spawn fails the load.
+    config.set_spawner(Spawner::new(|_, _| Err(std::io::Error::other("no threads"))));
+    match Wal::<state::NoState>::load(config) {
+        Err(Error::ThreadFail(_, _)) => (),
+        Err(err) => panic!("expected ThreadFail {}", err),
+        Ok(_) => panic!("expected ThreadFail"),
+    }
+}
+
+#[cfg(feature = "inline-writer")]
+#[test]
+fn test_wal_inline_writer() {
+    use std::cell::Cell;
+
+    thread_local! {
+        static ADDED: Cell<usize> = const { Cell::new(0) };
+    }
+
+    // state that counts entries added on the current thread.
+    #[derive(Clone, Debug, Default, mkit::Cborize)]
+    struct OnThread {
+        n: u64,
+    }
+
+    impl OnThread {
+        const ID: u32 = 0x0;
+    }
+
+    impl state::State for OnThread {
+        fn on_add_entry(&mut self, _: &entry::Entry) -> Result<()> {
+            self.n += 1;
+            ADDED.with(|added| added.set(added.get() + 1));
+            Ok(())
+        }
+    }
+
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-wal-inline-writer", dir.path().as_ref());
+    config
+        .set_journal_limit(1000)
+        .set_fsync_interval(Some(FsyncInterval::Batches(1000)))
+        .set_spawner(Spawner::inline());
+
+    let wal = Wal::create(config.clone(), OnThread::default()).unwrap();
+    for i in 0..100_u64 {
+        assert_eq!(wal.add_op(&[1; 32]).unwrap(), i + 1);
+        assert_eq!(wal.watermarks().unwrap().flushed, i + 1);
+    }
+    assert!(ADDED.with(Cell::get) >= 100);
+    let receipt = wal.submit_ops(vec![vec![2; 32], vec![3; 32]]).unwrap();
+    assert_eq!(wal.watermarks().unwrap().flushed, 102);
+    let deadline = time::Instant::now() + time::Duration::from_secs(1);
+    assert_eq!(wal.add_op_deadline(&[4; 32], deadline).unwrap(), 103);
+    assert_eq!(wal.sync().unwrap(), 103);
+    assert_eq!(receipt.try_wait().unwrap(), Some(101..=102));
+    assert!(wal.indexes().unwrap().len() > 1);
+    assert_eq!(wal.close(false).unwrap(), CloseOutcome::Closed(103));
+
+    // dropped without close.
+    let wal: Wal<OnThread> = Wal::load(config.clone()).unwrap();
+    assert_eq!(wal.add_op(&[5; 32]).unwrap(), 104);
+    let other = wal.clone();
+    mem::drop(wal);
+    assert_eq!(other.add_op(&[6; 32]).unwrap(), 105);
+    mem::drop(other);
+    assert!(ADDED.with(Cell::get) >= 105);
+
+    let wal: Wal<OnThread> = Wal::open(config, OpenMode::ReadOnly).unwrap();
+    assert_eq!(wal.iter().unwrap().count(), 105);
+    wal.close(false).unwrap();
+}
+
+#[test]
+fn test_wal_seal() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-wal-seal", dir.path().as_ref());
+    config.set_journal_limit(1000).set_fsync(false);
+
+    let wal: Wal = Wal::create(config.clone(), state::NoState).unwrap();
+    for i in 0..100 {
+        wal.add_op(&[i as u8; 32]).unwrap();
+    }
+    assert_eq!(wal.seal().unwrap(), 100);
+    assert_eq!(wal.health().unwrap().state, HealthState::Sealed);
+    let n_journals = wal.indexes().unwrap().len();
+
+    let check_sealed = |wal: &Wal| {
+        match wal.add_op(&[1]) {
+            Err(Error::Sealed(_, _)) => (),
+            res => panic!("expected Sealed, {:?}", res),
+        }
+        match wal.purge_till(100, "test") {
+            Err(Error::Sealed(_, _)) => (),
+            res => panic!("expected Sealed, {:?}", res),
+        }
+        match wal.seal() {
+            Err(Error::Sealed(_, _)) => (),
+            res => panic!("expected Sealed, {:?}", res),
+        }
+        let seqnos: Vec<u64> =
+            wal.iter().unwrap().map(|e| e.unwrap().to_seqno()).collect();
+        assert_eq!(seqnos, (1..=100).collect::<Vec<u64>>());
+    };
+    check_sealed(&wal);
+    assert_eq!(wal.close(false).unwrap(), CloseOutcome::Closed(100));
+
+    // sealed instance stays sealed, without starting a new journal.
+    let wal: Wal = Wal::load(config).unwrap();
+    assert_eq!(wal.health().unwrap().state, HealthState::Sealed);
+    assert_eq!(wal.indexes().unwrap().len(), n_journals);
+    check_sealed(&wal);
+    wal.close(true).unwrap();
+}
+
+#[test]
+fn test_wal_seal_flush_failure() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-wal-seal-flush-failure", dir.path().as_ref());
+    let (wal, res) = fail_pending_flush(&mut config, |wal| wal.seal());
+    assert!(matches!(res, Err(Error::IOError(_, _))), "{:?}", res);
+
+    // instance is not sealed, and can be sealed once the flush succeeds.
+    assert_eq!(wal.add_op(&[3]).unwrap(), 2);
+    assert_eq!(wal.health().unwrap().state, HealthState::Running);
+    assert_eq!(wal.seal().unwrap(), 2);
+    wal.close(true).unwrap();
+}
+
+#[test]
+fn test_wal_fsync_interval() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-wal-fsync-interval", dir.path().as_ref());
+    let hour = time::Duration::from_secs(3600);
+    config.set_fsync_interval(Some(FsyncInterval::Elapsed(hour)));
+
+    // ops are acknowledged, but are not durable till fsync.
+    let wal: Wal = Wal::create(config.clone(), state::NoState).unwrap();
+    for i in 1..=10 {
+        assert_eq!(wal.add_op(&[i as u8; 32]).unwrap(), i);
+    }
+    let marks = wal.watermarks().unwrap();
+    assert_eq!((marks.flushed, marks.durable), (10, 0));
+
+    let receipt = wal.submit_ops(vec![vec![1; 32]; 2]).unwrap();
+    while wal.watermarks().unwrap().flushed < 12 {
+        std::thread::sleep(time::Duration::from_millis(1));
+    }
+    assert_eq!(receipt.try_wait().unwrap(), None);
+    match receipt.wait(time::Duration::from_millis(10)) {
+        Err(Error::Timeout(_, _)) => (),
+        res => panic!("expected Timeout, {:?}", res),
+    }
+    assert_eq!(wal.sync().unwrap(), 12);
+    assert_eq!(receipt.wait(hour).unwrap(), 11..=12);
+    assert_eq!(wal.durable_seqno().unwrap(), 12);
+
+    // rotation syncs the archived journal.
+    let n = wal.indexes().unwrap().len();
+    wal.close(false).unwrap();
+    config.set_journal_limit(1000);
+    let wal: Wal = Wal::load(config.clone()).unwrap();
+    let mut seqno = 12;
+    while wal.indexes().unwrap().len() < n + 2 {
+        seqno = wal.add_op(&[0; 32]).unwrap();
+    }
+    let durable = wal.durable_seqno().unwrap();
+    assert!(durable > 12 && durable < seqno, "{} {}", durable, seqno);
+    wal.close(false).unwrap();
+
+    // unsynced batches are synced on close.
+    let wal: Wal = Wal::load(config.clone()).unwrap();
+    assert_eq!(wal.durable_seqno().unwrap(), seqno);
+    assert_eq!(wal.iter().unwrap().count() as u64, seqno);
+    wal.close(false).unwrap();
+
+    // idle writer syncs once the interval elapses, or right away when
+    // counting batches.
+    for interval in [
+        FsyncInterval::Elapsed(time::Duration::from_millis(10)),
+        FsyncInterval::Batches(1000),
+    ]
+    .iter()
+    {
+        config.set_fsync_interval(Some(*interval));
+        let wal: Wal = Wal::load(config.clone()).unwrap();
+        let seqno = wal.add_op(&[1; 32]).unwrap();
+        assert_eq!(wal.wait_for(seqno, hour).unwrap(), seqno);
+        wal.close(false).unwrap();
+    }
+}
+
+#[test]
+fn test_wal_sync_flush_failure() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new("test-wal-sync-flush-failure", dir.path().as_ref());
+    let (wal, res) = fail_pending_flush(&mut config, |wal| wal.sync());
+    assert!(matches!(res, Err(Error::IOError(_, _))), "{:?}", res);
+
+    assert_eq!(wal.add_op(&[3]).unwrap(), 2);
+    assert_eq!(wal.health().unwrap().state, HealthState::Running);
+    assert_eq!(wal.sync().unwrap(), 2);
+    wal.close(true).unwrap();
+}
@@ -1,4 +1,4 @@
-use log::debug;
+use log::{debug, error, warn};
 use mkit::{
     cbor::{FromCbor, IntoCbor},
     thread,
@@ -6,69 +6,637 @@ use mkit::{
 
 use std::{
     borrow::BorrowMut,
-    mem,
+    collections::{BTreeMap, HashMap, VecDeque},
+    convert::TryFrom,
+    ffi, fs, mem, ops, path, result,
     sync::{
         atomic::{AtomicU64, Ordering::SeqCst},
-        Arc, RwLock,
+        mpsc, Arc, Condvar, Mutex, RwLock,
     },
+    time,
 };
 
-use crate::{entry, journal::Journal, state, wral, wral::Config, Error, Result};
+use crate::{
+    batch::Codec,
+    checkpoint::Checkpoint,
+    entry,
+    entry::EntryBuilder,
+    event::{Events, WalEvent},
+    journal::Journal,
+    manifest,
+    manifest::Manifest,
+    quota, receipt, spawner, state,
+    storage::{FsyncInterval, SpacePolicy},
+    tombstone,
+    tombstone::Tombstone,
+    util, wral,
+    wral::{Config, RotateCause},
+    Error, Result,
+};
 
+// Requests adding entries carry the time they were queued, for
+// observing the queue time.
 #[derive(Debug)]
 pub enum Req {
-    AddEntry { op: Vec<u8> },
+    AddEntry {
+        client: u64,
+        label: String,
+        topic: String,
+        tag: String,
+        op: Vec<u8>,
+        queued: time::Instant,
+    },
+    // op is added at a pre-assigned seqno.
+    AddEntryAt {
+        client: u64,
+        label: String,
+        seqno: u64,
+        op: Vec<u8>,
+        queued: time::Instant,
+    },
+    // ops are added as contiguous entries, in the same batch.
+    AddEntries {
+        client: u64,
+        label: String,
+        ops: Vec<(String, Vec<u8>)>,
+        queued: time::Instant,
+    },
+    // ops at pre-assigned seqnos, ops already in the log are skipped.
+    Ingest {
+        client: u64,
+        label: String,
+        entries: Vec<entry::Entry>,
+        queued: time::Instant,
+    },
+    Rebase {
+        epoch: u64,
+    },
+    // None keeps the current dir, or name, as seen by the writer.
+    Relocate {
+        dir: Option<ffi::OsString>,
+        name: Option<String>,
+    },
+    Purge {
+        seqno: u64,
+        reason: String,
+    },
+    Mask {
+        range: ops::RangeInclusive<u64>,
+        unmask: bool,
+    },
+    // set savepoint at the last seqno handed out, respond with its seqno.
+    Savepoint {
+        name: String,
+    },
+    // mask entries after savepoint, respond with its seqno.
+    Rollback {
+        name: String,
+    },
+    // reload the batch index of cold journal `num`, decoding its state
+    // requires the writer's bounds on `S`.
+    Thaw {
+        num: usize,
+    },
+    // flush and freeze the instance as read-only, respond with the final
+    // durable seqno.
+    Seal,
+    // flush and fsync, irrespective of fsync interval, respond with the
+    // durable seqno.
+    Sync,
+    // requests before shutdown are processed and flushed, requests after
+    // are rejected. Respond with the final durable seqno.
+    Shutdown,
+    // request posted without a response channel, response is filled in
+    // `ack`, refer to Wal::submit_ops.
+    Submit {
+        ack: Arc<receipt::Ack>,
+        req: Box<Req>,
+    },
+}
+
+impl Req {
+    fn to_queued(&self) -> Option<time::Instant> {
+        match self {
+            Req::AddEntry { queued, .. } => Some(*queued),
+            Req::AddEntryAt { queued, .. } => Some(*queued),
+            Req::AddEntries { queued, .. } => Some(*queued),
+            Req::Ingest { queued, .. } => Some(*queued),
+            _ => None,
+        }
+    }
+
+    // Size of each op, along with its pre-assigned seqno if any, else ZERO,
+    // for requests adding ops.
+    fn to_op_sizes(&self) -> Vec<(u64, usize)> {
+        match self {
+            Req::AddEntry { op, .. } => vec![(0, op.len())],
+            Req::AddEntryAt { seqno, op, .. } => vec![(*seqno, op.len())],
+            Req::AddEntries { ops, .. } => {
+                ops.iter().map(|(_, op)| (0, op.len())).collect()
+            }
+            Req::Ingest { entries, .. } => {
+                entries.iter().map(|e| (e.to_seqno(), e.as_op().len())).collect()
+            }
+            _ => vec![],
+        }
+    }
+
+    // Identity and op bytes to account, for requests adding ops.
+    fn to_quota(&self) -> Option<(&str, u64)> {
+        let bytes = match self {
+            Req::AddEntry { op, .. } | Req::AddEntryAt { op, .. } => op.len(),
+            Req::AddEntries { ops, .. } => ops.iter().map(|(_, op)| op.len()).sum(),
+            Req::Ingest { entries, .. } => entries.iter().map(|e| e.as_op().len()).sum(),
+            _ => return None,
+        };
+        match self {
+            Req::AddEntry { label, .. }
+            | Req::AddEntryAt { label, .. }
+            | Req::AddEntries { label, .. }
+            | Req::Ingest { label, .. } => Some((label, bytes as u64)),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug)]
 pub enum Res {
+    Ok,
     Seqno(u64),
+    Seqnos(ops::RangeInclusive<u64>),
+    // seqno span of `n` ingested entries, None if all were skipped.
+    Ingested {
+        seqnos: Option<ops::RangeInclusive<u64>>,
+        n: usize,
+    },
+    Purged(Option<Tombstone>),
+    Fail(Error),
+}
+
+/// Seqno upto which entries are flushed to disk, in the current epoch,
+/// applications can wait for the watermark to move past a seqno. Seqnos
+/// restart from ONE on rebase, waiting for a seqno fails once the epoch
+/// moves on, refer to [Wal::rebase][crate::Wal::rebase].
+pub struct Watermark {
+    // (epoch, seqno)
+    seqno: Mutex<(u64, u64)>,
+    cond: Condvar,
+}
+
+impl Watermark {
+    fn new(epoch: u64, seqno: u64) -> Watermark {
+        Watermark {
+            seqno: Mutex::new((epoch, seqno)),
+            cond: Condvar::new(),
+        }
+    }
+
+    fn set(&self, seqno: u64) -> Result<()> {
+        err_at!(Fatal, self.seqno.lock())?.1 = seqno;
+        self.cond.notify_all();
+        Ok(())
+    }
+
+    // Move the watermark to `seqno` in `epoch`, failing waiters from an
+    // older epoch.
+    fn reset(&self, epoch: u64, seqno: u64) -> Result<()> {
+        *err_at!(Fatal, self.seqno.lock())? = (epoch, seqno);
+        self.cond.notify_all();
+        Ok(())
+    }
+
+    pub fn to_seqno(&self) -> Result<u64> {
+        Ok(err_at!(Fatal, self.seqno.lock())?.1)
+    }
+
+    // Wake up all waiters, without moving the watermark.
+    fn notify(&self) -> Result<()> {
+        let _guard = err_at!(Fatal, self.seqno.lock())?;
+        self.cond.notify_all();
+        Ok(())
+    }
+
+    /// Block until `done` returns true, or `timeout` elapses, `done` is
+    /// evaluated with the watermark every time the watermark is set.
+    pub fn wait_until<F>(&self, timeout: time::Duration, mut done: F) -> Result<u64>
+    where
+        F: FnMut(u64) -> bool,
+    {
+        let guard = err_at!(Fatal, self.seqno.lock())?;
+        let (guard, _) = err_at!(
+            Fatal,
+            self.cond.wait_timeout_while(guard, timeout, |(_, val)| !done(*val))
+        )?;
+        Ok(guard.1)
+    }
+
+    /// Block until watermark reaches `seqno`, or `timeout` elapses. Fail
+    /// if the watermark moves to a new epoch before reaching `seqno`.
+    pub fn wait_for(&self, seqno: u64, timeout: time::Duration) -> Result<u64> {
+        let guard = err_at!(Fatal, self.seqno.lock())?;
+        let epoch = guard.0;
+        let (guard, _) = err_at!(
+            Fatal,
+            self.cond.wait_timeout_while(guard, timeout, |(e, val)| {
+                *e == epoch && *val < seqno
+            })
+        )?;
+        match *guard {
+            (e, _) if e != epoch => err_at!(
+                Invalid,
+                msg: "rebased to epoch {} while waiting for seqno {}", e, seqno
+            ),
+            (_, val) if val < seqno => {
+                err_at!(Timeout, msg: "watermark {} < {} after {:?}", val, seqno, timeout)
+            }
+            (_, val) => Ok(val),
+        }
+    }
+}
+
+/// Seqno frontiers, besides the durable watermark, maintained by the
+/// writer thread. Refer to [Wal::watermarks][crate::Wal::watermarks].
+#[derive(Debug, Default)]
+pub struct Frontiers {
+    appended: AtomicU64,
+    flushed: AtomicU64,
+    purged: AtomicU64,
+}
+
+impl Frontiers {
+    fn new(seqno: u64, purged: u64) -> Frontiers {
+        let frontiers = Frontiers::default();
+        frontiers.reset(seqno, purged);
+        frontiers
+    }
+
+    // all entries upto `seqno` are appended and flushed.
+    fn reset(&self, seqno: u64, purged: u64) {
+        self.appended.store(seqno, SeqCst);
+        self.flushed.store(seqno, SeqCst);
+        self.purged.store(purged, SeqCst);
+    }
+
+    pub fn to_appended(&self) -> u64 {
+        self.appended.load(SeqCst)
+    }
+
+    pub fn to_flushed(&self) -> u64 {
+        self.flushed.load(SeqCst)
+    }
+
+    pub fn to_purged(&self) -> u64 {
+        self.purged.load(SeqCst)
+    }
+}
+
+// Number of largest ops tracked, refer to Stats::largest_ops.
+const LARGEST_OPS: usize = 8;
+
+/// Observed group-commit cadence of the writer thread.
+#[derive(Debug, Default)]
+pub struct Cadence {
+    // number of requests pending, when the last batch was formed.
+    queue_depth: usize,
+    // number of flushes that committed entries, and the entries committed.
+    n_syncs: u64,
+    n_entries: u64,
+    first_sync: Option<time::Instant>,
+    last_sync: Option<time::Instant>,
+    // moving average of time taken to flush a batch, and the window to
+    // accumulate requests for the next batch, under adaptive commit.
+    fsync_latency: time::Duration,
+    window: time::Duration,
+    // moving average of time requests wait to be formed into a batch,
+    // and the longest wait in the last batch.
+    queue_time: time::Duration,
+    max_queue_time: time::Duration,
+    // histogram of op sizes in power-of-2 buckets, and the largest ops
+    // as (seqno, size), largest first.
+    op_sizes: Vec<u64>,
+    largest_ops: Vec<(u64, usize)>,
+}
+
+impl Cadence {
+    fn on_sync(&mut self, n_entries: usize) {
+        let now = time::Instant::now();
+        self.n_syncs += 1;
+        self.n_entries += n_entries as u64;
+        self.first_sync.get_or_insert(now);
+        self.last_sync = Some(now);
+    }
+
+    // Account op sizes of a request that succeeded with `res`.
+    fn on_ops(&mut self, res: &Res, sizes: Vec<(u64, usize)>) {
+        let ops: Vec<(u64, usize)> = match res {
+            Res::Seqno(seqno) => {
+                sizes.into_iter().map(|(_, size)| (*seqno, size)).collect()
+            }
+            Res::Seqnos(seqnos) => {
+                seqnos.clone().zip(sizes.into_iter().map(|(_, size)| size)).collect()
+            }
+            Res::Ingested { seqnos: Some(seqnos), .. } => {
+                sizes.into_iter().filter(|(seqno, _)| seqnos.contains(seqno)).collect()
+            }
+            _ => return,
+        };
+        for (seqno, size) in ops.into_iter() {
+            let bucket = (usize::BITS - size.leading_zeros()) as usize;
+            if bucket >= self.op_sizes.len() {
+                self.op_sizes.resize(bucket + 1, 0);
+            }
+            self.op_sizes[bucket] += 1;
+            let full = self.largest_ops.len() >= LARGEST_OPS;
+            if full && self.largest_ops.last().is_some_and(|(_, s)| *s >= size) {
+                continue;
+            }
+            let off = self.largest_ops.partition_point(|(_, s)| *s >= size);
+            self.largest_ops.insert(off, (seqno, size));
+            self.largest_ops.truncate(LARGEST_OPS);
+        }
+    }
+
+    fn on_drain(&mut self, reqs: &[Item]) {
+        let waits: Vec<time::Duration> = reqs
+            .iter()
+            .filter_map(|(req, _)| req.to_queued())
+            .map(|q| q.elapsed())
+            .collect();
+        let avg = match waits.len() {
+            0 => return,
+            n => waits.iter().sum::<time::Duration>() / (n as u32),
+        };
+        self.queue_time = match self.queue_time.is_zero() {
+            true => avg,
+            false => (self.queue_time * 7 + avg) / 8,
+        };
+        self.max_queue_time = waits.into_iter().max().unwrap_or_default();
+    }
+
+    // Window is sized to the observed flush latency, so that requests
+    // arriving while the writer waits are amortized over a single flush,
+    // bounded such that waiting plus flushing stays within `target`.
+    fn on_flush(&mut self, elapsed: time::Duration, target: Option<time::Duration>) {
+        self.fsync_latency = match self.fsync_latency.is_zero() {
+            true => elapsed,
+            false => (self.fsync_latency * 7 + elapsed) / 8,
+        };
+        self.window = match target {
+            Some(target) => {
+                self.fsync_latency.min(target.saturating_sub(self.fsync_latency))
+            }
+            None => time::Duration::default(),
+        };
+    }
+
+    /// Return moving average of time taken to flush a batch.
+    pub fn to_fsync_latency(&self) -> time::Duration {
+        self.fsync_latency
+    }
+
+    /// Return the window to accumulate requests for the next batch, ZERO
+    /// unless adaptive commit is enabled.
+    pub fn to_commit_window(&self) -> time::Duration {
+        self.window
+    }
+
+    pub fn to_queue_depth(&self) -> usize {
+        self.queue_depth
+    }
+
+    /// Return moving average of time requests wait to be formed into a
+    /// batch.
+    pub fn to_queue_time(&self) -> time::Duration {
+        self.queue_time
+    }
+
+    /// Return the longest time a request waited, among requests in the
+    /// last batch.
+    pub fn to_max_queue_time(&self) -> time::Duration {
+        self.max_queue_time
+    }
+
+    /// Return histogram of op sizes, refer to [wral::Stats::op_sizes].
+    pub fn to_op_sizes(&self) -> Vec<u64> {
+        self.op_sizes.clone()
+    }
+
+    /// Return the largest ops as (seqno, size), largest first.
+    pub fn to_largest_ops(&self) -> Vec<(u64, usize)> {
+        self.largest_ops.clone()
+    }
+
+    /// Return average number of entries committed per flush.
+    pub fn to_avg_batch_size(&self) -> usize {
+        match self.n_syncs {
+            0 => 0,
+            n => (self.n_entries / n) as usize,
+        }
+    }
+
+    /// Return average time between flushes that committed entries.
+    pub fn to_avg_sync_interval(&self) -> time::Duration {
+        match (self.first_sync, self.last_sync, self.n_syncs) {
+            (Some(first), Some(last), n) if n > 1 => {
+                last.duration_since(first) / ((n - 1) as u32)
+            }
+            _ => time::Duration::default(),
+        }
+    }
 }
 
 pub struct Writer<S> {
     config: Config,
     seqno: Arc<AtomicU64>,
+    pub durable: Arc<Watermark>,
+    pub frontiers: Arc<Frontiers>,
+    pub health: Arc<Mutex<wral::Health>>,
+    pub manifest: Manifest,
     pub journals: Vec<Journal<S>>,
     pub journal: Journal<S>,
+    // purge history and masked seqno ranges.
+    metadata: tombstone::Metadata,
+    pub events: Arc<Events>,
+    pub cadence: Cadence,
+    // decoded entries of archived journals, pinned in memory.
+    pub pinned: BTreeMap<usize, Arc<Vec<entry::Entry>>>,
+    // time of the last state checkpoint, and batches written since.
+    checkpoint_at: time::Instant,
+    checkpoint_batches: usize,
+    // writes are rejected with this error, refer to Writer::check_fence.
+    fenced: Option<Error>,
+    // writes are rejected with this error, refer to Writer::check_sealed.
+    sealed: Option<Error>,
+    // per identity accounting, refer to Config::set_quota.
+    quotas: quota::Accounts,
+    // whether space was low on the last check, refer to Writer::check_space.
+    low_space: bool,
+    // serialized size of state in the last batch, and whether it exceeded
+    // the warning threshold, refer to Writer::check_state_size.
+    state_size: usize,
+    state_oversize: bool,
+    // largest batch flushed since the instance was opened, in number of
+    // entries, across journals.
+    high_water: usize,
+    // batches written since the last fsync, and the time of the first one,
+    // refer to Config::set_fsync_interval.
+    unsynced: Option<(usize, time::Instant)>,
+    // rotations by cause, and the sum of fill percentages of rotated
+    // journals, refer to Stats::rotations.
+    rotations: wral::Rotations,
+    fill_total: usize,
+    // whether format migration is in progress, refer to Wal::migrate_format.
+    pub migrating: bool,
 }
 
-type SpawnWriter<S> = (
-    Arc<RwLock<Writer<S>>>,
-    thread::Thread<Req, Res, Result<u64>>,
-    thread::Tx<Req, Res>,
-);
+type SpawnWriter<S> = (Arc<RwLock<Writer<S>>>, spawner::Handle<Result<u64>>, Tx);
+
+/// Requests to the writer, over the channel to the writer thread, or, with
+/// the `inline-writer` feature, to the writer run on the caller's thread.
+#[derive(Clone)]
+pub enum Tx {
+    Thread(thread::Tx<Req, Res>),
+    // None, once the writer is shutdown or has failed.
+    #[cfg(feature = "inline-writer")]
+    Inline(Arc<Mutex<Option<Box<dyn Inline>>>>),
+}
+
+impl Tx {
+    pub fn post(&self, req: Req) -> Result<()> {
+        match self {
+            Tx::Thread(tx) => Ok(tx.post(req)?),
+            #[cfg(feature = "inline-writer")]
+            Tx::Inline(l) => Self::process(l, req, None),
+        }
+    }
+
+    pub fn request(&self, req: Req) -> Result<Res> {
+        match self {
+            Tx::Thread(tx) => Ok(tx.request(req)?),
+            #[cfg(feature = "inline-writer")]
+            Tx::Inline(l) => {
+                let (tx, rx) = mpsc::channel();
+                Self::process(l, req, Some(tx))?;
+                err_at!(IPCFail, rx.try_recv())
+            }
+        }
+    }
+
+    #[cfg(feature = "inline-writer")]
+    fn process(
+        l: &Mutex<Option<Box<dyn Inline>>>,
+        req: Req,
+        tx: Option<mpsc::Sender<Res>>,
+    ) -> Result<()> {
+        let mut l = err_at!(Fatal, l.lock())?;
+        let res = match l.as_mut() {
+            Some(inline) => inline.process(req, tx),
+            None => err_at!(IPCFail, msg: "writer is shutdown"),
+        };
+        // writer is dropped on shutdown and on failure, like the thread.
+        match res {
+            Ok(false) => Ok(()),
+            Ok(true) => {
+                l.take();
+                Ok(())
+            }
+            Err(err) => {
+                l.take();
+                Err(err)
+            }
+        }
+    }
+}
 
 impl<S> Writer<S> {
     pub(crate) fn start(
         config: Config,
+        manifest: Manifest,
         journals: Vec<Journal<S>>,
         journal: Journal<S>,
         seqno: u64,
-    ) -> SpawnWriter<S>
+        events: Arc<Events>,
+    ) -> Result<SpawnWriter<S>>
     where
         S: state::State,
     {
+        let durable = {
+            let epoch = manifest.to_epoch().as_ref().map(manifest::Epoch::to_epoch);
+            Arc::new(Watermark::new(epoch.unwrap_or(0), seqno.saturating_sub(1)))
+        };
+        let metadata = Self::find_metadata(&journals, &journal);
+        let purged = Self::find_purged(&manifest, &metadata);
+        let frontiers = Arc::new(Frontiers::new(seqno.saturating_sub(1), purged));
         let seqno = Arc::new(AtomicU64::new(seqno));
-        let w = Arc::new(RwLock::new(Writer {
+        let health = Arc::new(Mutex::new(wral::Health::default()));
+        let mut writer = Writer {
             config: config.clone(),
             seqno: Arc::clone(&seqno),
+            durable,
+            frontiers,
+            health: Arc::clone(&health),
+            manifest,
             journals,
             journal,
-        }));
+            metadata,
+            events,
+            cadence: Cadence::default(),
+            pinned: BTreeMap::default(),
+            checkpoint_at: time::Instant::now(),
+            checkpoint_batches: 0,
+            fenced: None,
+            sealed: None,
+            low_space: false,
+            state_size: 0,
+            state_oversize: false,
+            high_water: 0,
+            unsynced: None,
+            rotations: wral::Rotations::default(),
+            fill_total: 0,
+            migrating: false,
+            quotas: quota::Accounts::new(config.quotas.clone()),
+        };
+        writer.check_fence(seqno.load(SeqCst));
+        writer.check_sealed();
+        let w = Arc::new(RwLock::new(writer));
         let name = format!("wral-writer-{}", config.name);
+        #[cfg(feature = "inline-writer")]
+        if config.spawner.is_inline() {
+            let backlog = VecDeque::default();
+            let l = MainLoop {
+                seqno,
+                w: Arc::clone(&w),
+                backlog,
+                backpressure: false,
+                degraded: false,
+            };
+            let (res, t) = spawner::Handle::inline(&name);
+            let inline: Box<dyn Inline> = Box::new(InlineLoop { l, health, res });
+            return Ok((w, t, Tx::Inline(Arc::new(Mutex::new(Some(inline))))));
+        }
         let thread_w = Arc::clone(&w);
-        let (t, tx) = thread::Thread::new_sync(
-            &name,
-            wral::SYNC_BUFFER,
-            move |rx: thread::Rx<Req, Res>| {
-                || {
-                    let l = MainLoop { config, seqno, w: thread_w, rx };
-                    l.run()
+        let (tx, rx) = mpsc::sync_channel(wral::SYNC_BUFFER);
+        let t = config.spawner.spawn(&name, move || {
+            let backlog = VecDeque::default();
+            let l = MainLoop {
+                seqno,
+                w: thread_w,
+                backlog,
+                backpressure: false,
+                degraded: false,
+            };
+            let res = l.run(rx);
+            if let Err(err) = &res {
+                error!(target: "wral", "writer exited: {}", err);
+                if let Ok(mut health) = health.lock() {
+                    health.state = wral::HealthState::Poisoned;
+                    health.last_error = Some(err.clone());
                 }
-            },
-        );
+            }
+            res
+        })?;
 
-        (w, t, tx)
+        Ok((w, t, Tx::Thread(thread::Tx::S(tx))))
     }
 
     pub fn close(&self) -> Result<u64> {
@@ -89,89 +657,1694 @@ impl<S> Writer<S> {
     pub fn purge(mut self) -> Result<u64> {
         self.close()?;
 
-        for j in self.journals.drain(..) {
-            j.purge()?
+        let mut journals: Vec<Journal<S>> = self.journals.drain(..).collect();
+        journals.push(self.journal);
+        for journal in journals.into_iter() {
+            let (num, file) = (journal.to_journal_number(), journal.to_file_path());
+            journal.purge()?;
+            self.events.emit(WalEvent::JournalPurged { num, file });
         }
-        self.journal.purge()?;
+        Manifest::purge(&self.config.dir, &self.config.name)?;
+        Checkpoint::purge(&self.config.dir, &self.config.name)?;
 
         Ok(self.seqno.load(SeqCst).saturating_sub(1))
     }
 }
 
-struct MainLoop<S> {
-    config: Config,
-    seqno: Arc<AtomicU64>,
-    w: Arc<RwLock<Writer<S>>>,
-    rx: thread::Rx<Req, Res>,
-}
-
-impl<S> MainLoop<S>
-where
-    S: Clone + IntoCbor + FromCbor + state::State,
-{
-    fn run(self) -> Result<u64> {
-        use std::sync::mpsc::TryRecvError;
+impl<S> Writer<S> {
+    // Persist state as of the durable seqno, when batches are written since
+    // the last checkpoint, and the interval has elapsed or the number of
+    // batches is crossed, or when `force` is true. Failing to checkpoint
+    // is not fatal to the writer.
+    fn checkpoint(&mut self, force: bool)
+    where
+        S: state::State,
+    {
+        let (interval, batches) =
+            (self.config.checkpoint_interval, self.config.checkpoint_batches);
+        if (interval.is_none() && batches.is_none()) || self.checkpoint_batches == 0 {
+            return;
+        }
+        let due = force
+            || interval.is_some_and(|interval| self.checkpoint_at.elapsed() >= interval)
+            || batches.is_some_and(|n| self.checkpoint_batches >= n);
+        if !due {
+            return;
+        }
 
-        // block for the first request.
-        'a: while let Ok(req) = self.rx.recv() {
-            // then get as many outstanding requests as possible from
-            // the channel.
-            let mut reqs = vec![req];
-            loop {
-                match self.rx.try_recv() {
-                    Ok(req) => reqs.push(req),
-                    Err(TryRecvError::Empty) => break,
-                    Err(TryRecvError::Disconnected) => break 'a,
-                }
+        let res = self.durable.to_seqno().and_then(|seqno| {
+            let (name, epoch) = (&self.config.name, self.to_epoch());
+            Checkpoint::new(name, epoch, seqno, self.journal.to_state())?
+                .save(&self.config.dir)
+        });
+        match res {
+            Ok(()) => {
+                self.checkpoint_at = time::Instant::now();
+                self.checkpoint_batches = 0;
             }
-            // and then start processing it in batch.
-            let mut w = err_at!(Fatal, self.w.write())?;
-
-            let mut items = vec![];
-            for req in reqs.into_iter() {
-                match req {
-                    (Req::AddEntry { op }, tx) => {
-                        let seqno = self.seqno.fetch_add(1, SeqCst);
-                        w.journal.add_entry(entry::Entry::new(seqno, op))?;
-                        items.push((seqno, tx))
-                    }
-                }
+            Err(err) => {
+                let (dir, name) = (&self.config.dir, &self.config.name);
+                warn!(target: "wral", "{:?}/{} checkpoint failed {}", dir, name, err);
             }
-            w.journal.flush()?;
+        }
+    }
 
-            for (seqno, tx) in items.into_iter() {
-                if let Some(tx) = tx {
-                    err_at!(IPCFail, tx.send(Res::Seqno(seqno)))?;
-                }
-            }
+    // Check space available for the next journal under `dir`, as per
+    // space policy. LowSpace is emitted once, till space is available
+    // again. Fail with NoSpace if rotation shall be refused.
+    fn check_space(&mut self, dir: &ffi::OsStr) -> Result<()> {
+        let policy = self.config.space_policy;
+        let required = u64::try_from(self.config.journal_limit)
+            .unwrap_or(u64::MAX)
+            .saturating_add(self.config.space_reserve);
+        let available = match policy {
+            SpacePolicy::Ignore => return Ok(()),
+            _ => match util::available_space(path::Path::new(dir)) {
+                Some(available) => available,
+                None => return Ok(()),
+            },
+        };
+        if available >= required {
+            self.low_space = false;
+            return Ok(());
+        }
 
-            if w.journal.file_size()? > self.config.journal_limit {
-                Self::rotate(w.borrow_mut())?;
+        if !self.low_space {
+            self.low_space = true;
+            warn!(target: "wral", "{:?} has {} < {} bytes", dir, available, required);
+            let dir = dir.to_os_string();
+            self.events.emit(WalEvent::LowSpace { dir, available, required });
+        }
+        match policy {
+            SpacePolicy::Refuse => {
+                err_at!(NoSpace, msg: "{:?} has {} < {} bytes", dir, available, required)
             }
+            _ => Ok(()),
         }
+    }
 
-        Ok(self.seqno.load(SeqCst).saturating_sub(1))
+    // Check serialized size of state, in the batch just flushed, against
+    // the warning threshold. StateOversize is emitted once, till state
+    // shrinks within the threshold again.
+    fn check_state_size(&mut self) {
+        let (size, length) = match self.journal.to_state_size() {
+            Some(item) => item,
+            None => return,
+        };
+        self.state_size = size;
+
+        let over_limit = self.config.state_size_limit.is_some_and(|limit| size > limit);
+        let over_percent = self.config.state_size_percent.is_some_and(|percent| {
+            size.saturating_mul(100) > length.saturating_mul(usize::from(percent))
+        });
+        if !over_limit && !over_percent {
+            self.state_oversize = false;
+            return;
+        }
+
+        if !self.state_oversize {
+            self.state_oversize = true;
+            let seqno = self.journal.to_last_seqno().unwrap_or(0);
+            warn!(
+                target: "wral",
+                "{} state is {} bytes, in batch of {} bytes upto {}",
+                self.config.name, size, length, seqno
+            );
+            self.events.emit(WalEvent::StateOversize { seqno, size, length });
+        }
     }
-}
 
-impl<S> MainLoop<S>
-where
-    S: Clone,
-{
-    fn rotate(w: &mut Writer<S>) -> Result<()> {
-        // new journal
-        let journal = {
-            let num = w.journal.to_journal_number().saturating_add(1);
-            let state = w.journal.to_state();
-            Journal::start(&w.config.name, &w.config.dir, num, state)?
+    // Next seqno shall be strictly greater than the durable tail, that is,
+    // the last seqno retained in the current epoch, otherwise appends would
+    // reuse seqnos below retained entries, say after an older manifest or
+    // journal is restored. Once violated, the writer is fenced and rejects
+    // writes until rebased to a new epoch.
+    fn check_fence(&mut self, next: u64) {
+        if self.fenced.is_some() {
+            return;
+        }
+        let tail = match self.to_durable_tail() {
+            Some(tail) if next <= tail => tail,
+            _ => return,
         };
-        // replace with current journal
-        let journal = mem::replace(&mut w.journal, journal);
-        let (journal, entries, _) = journal.into_archive();
-        if !entries.is_empty() {
-            err_at!(Fatal, msg: "unflushed entries {}", entries.len())?
+
+        let (dir, name) = (&self.config.dir, &self.config.name);
+        warn!(target: "wral", "{:?}/{} fenced, next seqno {} <= {}", dir, name, next, tail);
+        let err = match err_at!(
+            Fenced,
+            msg: "{} next seqno {} <= durable seqno {}, rebase to a new epoch", name, next, tail
+        ) {
+            Ok(()) => unreachable!(),
+            Err(err) => err,
+        };
+        if let Ok(mut health) = self.health.lock() {
+            health.state = wral::HealthState::Fenced;
+            health.last_error = Some(err.clone());
         }
-        w.journals.push(journal);
+        self.fenced = Some(err);
+    }
+
+    // Sealed instance rejects writes for good, refer to Wal::seal.
+    fn check_sealed(&mut self) {
+        if self.sealed.is_some() || !self.manifest.is_sealed() {
+            return;
+        }
+
+        let name = &self.config.name;
+        let err = match err_at!(Sealed, msg: "wal {} is sealed", name) {
+            Ok(()) => unreachable!(),
+            Err(err) => err,
+        };
+        if let Ok(mut health) = self.health.lock() {
+            health.state = wral::HealthState::Sealed;
+        }
+        self.sealed = Some(err);
+    }
+
+    // Time left for the unsynced batches to be fsynced, None if there are
+    // none. Batches counted by number are synced as soon as the writer is
+    // idle, so that the durable watermark doesn't lag behind for long.
+    fn to_sync_timeout(&self) -> Option<time::Duration> {
+        let (_, since) = self.unsynced?;
+        match self.config.fsync_interval? {
+            FsyncInterval::Elapsed(interval) => {
+                Some(interval.saturating_sub(since.elapsed()))
+            }
+            FsyncInterval::Batches(_) => Some(time::Duration::default()),
+        }
+    }
+
+    // Batches are written without fsync under fsync interval, count them
+    // till the next fsync.
+    fn mark_unsynced(&mut self) {
+        if self.config.fsync_interval.is_some() {
+            let (n, since) = self.unsynced.unwrap_or((0, time::Instant::now()));
+            self.unsynced = Some((n + 1, since));
+        }
+    }
+
+    // Fenced and sealed writers reject writes.
+    fn is_writable(&self) -> bool {
+        self.fenced.is_none() && self.sealed.is_none()
+    }
+
+    // Last seqno retained in the current epoch, including pending entries.
+    fn to_durable_tail(&self) -> Option<u64> {
+        let iter = self.journals.iter().filter(|j| self.is_current_epoch(j));
+        let iter = iter.chain(std::iter::once(&self.journal));
+        iter.filter_map(Journal::to_last_seqno).max()
+    }
+
+    /// Replace journals and manifest with a fresh scan from disk, for
+    /// instances attached to a writer from another process.
+    pub fn refresh(
+        &mut self,
+        manifest: Manifest,
+        journals: Vec<Journal<S>>,
+        journal: Journal<S>,
+        seqno: u64,
+    ) -> Result<()> {
+        self.metadata = Self::find_metadata(&journals, &journal);
+        self.manifest = manifest;
+        self.journals = journals;
+        self.journal = journal;
+        self.pinned.clear();
+        self.seqno.store(seqno, SeqCst);
+        let purged = Self::find_purged(&self.manifest, &self.metadata);
+        self.frontiers.reset(seqno.saturating_sub(1), purged);
+        self.durable.reset(self.to_epoch(), seqno.saturating_sub(1))
+    }
+
+    // Latest seqno purged in the current epoch, ZERO if none.
+    fn find_purged(manifest: &Manifest, metadata: &tombstone::Metadata) -> u64 {
+        let epoch =
+            manifest.to_epoch().as_ref().map(manifest::Epoch::to_epoch).unwrap_or(0);
+        let iter = metadata.tombstones.iter().filter(|t| t.to_epoch() == epoch);
+        iter.map(Tombstone::to_seqno).max().unwrap_or(0)
+    }
+
+    // Every metadata batch carry the entire metadata, latest one wins.
+    // Read-only instances hold the latest journal as the active journal.
+    fn find_metadata(
+        journals: &[Journal<S>],
+        journal: &Journal<S>,
+    ) -> tombstone::Metadata {
+        let mut iter = journals.iter().chain(std::iter::once(journal)).rev();
+        iter.find_map(Journal::to_metadata).unwrap_or_default()
+    }
+
+    pub fn to_next_seqno(&self) -> u64 {
+        self.seqno.load(SeqCst)
+    }
+
+    pub fn to_config(&self) -> Config {
+        self.config.clone()
+    }
+
+    /// Encode entries with `codec`, and compress them as per `compress`,
+    /// in journals started hereafter. The new format is recorded in the
+    /// manifest.
+    pub fn set_format(&mut self, codec: Codec, compress: Option<usize>) -> Result<()> {
+        self.config.codec = codec;
+        self.config.compress_threshold = compress;
+        if self.manifest.add_config(self.config.to_config_version()) {
+            self.manifest.save(&self.config.dir)?;
+        }
+        Ok(())
+    }
+
+    pub fn to_state_size(&self) -> usize {
+        self.state_size
+    }
+
+    pub fn to_high_water(&self) -> (usize, usize) {
+        let (_, capacity) = self.journal.to_high_water();
+        (self.high_water, capacity)
+    }
+
+    pub fn to_rotations(&self) -> wral::Rotations {
+        self.rotations.clone()
+    }
+
+    // Account a rotation for `cause`, of journal `size` bytes.
+    fn on_rotate(&mut self, cause: RotateCause, size: usize) {
+        let limit = self.config.journal_limit.max(1);
+        let fill = size.saturating_mul(100) / limit;
+        let r = &mut self.rotations;
+        match cause {
+            RotateCause::SizeLimit => r.size_limit += 1,
+            RotateCause::Forced => r.forced += 1,
+        }
+        r.min_fill = if r.len() == 1 { fill } else { r.min_fill.min(fill) };
+        r.max_fill = r.max_fill.max(fill);
+        self.fill_total = self.fill_total.saturating_add(fill);
+        r.avg_fill = self.fill_total / r.len();
+    }
+
+    pub fn to_name(&self) -> String {
+        self.config.name.clone()
+    }
+
+    pub fn to_dir(&self) -> ffi::OsString {
+        self.config.dir.clone()
+    }
+
+    pub fn to_epoch(&self) -> u64 {
+        self.manifest.to_epoch().as_ref().map(manifest::Epoch::to_epoch).unwrap_or(0)
+    }
+
+    pub fn to_tombstones(&self) -> Vec<Tombstone> {
+        self.metadata.tombstones.clone()
+    }
+
+    /// Return masked seqno ranges in the current epoch.
+    pub fn to_masks(&self) -> Vec<ops::RangeInclusive<u64>> {
+        self.metadata.to_masks(self.to_epoch())
+    }
+
+    /// Journals numbered below the current epoch hold seqnos from an older
+    /// epoch, and are not visible to readers.
+    pub fn is_current_epoch(&self, journal: &Journal<S>) -> bool {
+        match self.manifest.to_epoch() {
+            Some(epoch) => journal.to_journal_number() >= epoch.to_journal_number(),
+            None => true,
+        }
+    }
+
+    /// Record `seqno`, in the current epoch, as acknowledged by `consumer`,
+    /// and persist it in the manifest.
+    pub fn ack(&mut self, consumer: &str, seqno: u64) -> Result<()> {
+        let mut manifest = self.manifest.clone();
+        if manifest.set_consumer(consumer, self.to_epoch(), seqno) {
+            manifest.save(&self.config.dir)?;
+            self.manifest = manifest;
+        }
+        Ok(())
+    }
+
+    pub fn forget(&mut self, consumer: &str) -> Result<bool> {
+        let mut manifest = self.manifest.clone();
+        if !manifest.remove_consumer(consumer) {
+            return Ok(false);
+        }
+        manifest.save(&self.config.dir)?;
+        self.manifest = manifest;
+        Ok(true)
+    }
+
+    /// Return the oldest seqno held by journals in the current epoch, None
+    /// if they hold no entries. Span of cold journals is looked up in the
+    /// manifest, if they were loaded without it.
+    pub fn to_oldest_seqno(&self) -> Option<u64> {
+        let journals = self.journals.iter().filter(|jn| self.is_current_epoch(jn));
+        journals
+            .chain(Some(&self.journal))
+            .filter_map(|jn| {
+                let num = jn.to_journal_number();
+                jn.to_first_seqno()
+                    .or_else(|| self.manifest.to_span(num).map(|span| *span.start()))
+            })
+            .min()
+    }
+
+    /// Return the seqno below which journals can be purged, retaining
+    /// entries not yet acknowledged by consumers. None if there are no
+    /// consumers, or if any of them is behind the current epoch.
+    pub fn to_retention_seqno(&self) -> Option<u64> {
+        let current = self.to_epoch();
+        let consumers = self.manifest.to_consumers();
+        match consumers.iter().all(|(_, epoch, _)| *epoch == current) {
+            true => consumers.iter().map(|(_, _, seqno)| *seqno).min(),
+            false => None,
+        }
+    }
+
+    /// Return the lag of consumers behind `durable` seqno, furthest behind
+    /// first. Consumers from an older epoch lag behind every entry in the
+    /// current epoch.
+    pub fn to_consumers(&self, durable: u64) -> Vec<wral::ConsumerLag> {
+        let current = self.to_epoch();
+        let mut consumers: Vec<wral::ConsumerLag> = self
+            .manifest
+            .to_consumers()
+            .into_iter()
+            .map(|(name, epoch, seqno)| {
+                let after = if epoch == current { seqno } else { 0 };
+                let journals = self.journals.iter().chain(Some(&self.journal));
+                let bytes = journals
+                    .filter(|j| self.is_current_epoch(j))
+                    .map(|j| {
+                        let index = j.to_journal_index();
+                        let iter = index.iter().filter(|i| i.to_last_seqno() > after);
+                        iter.map(|i| i.to_length() as u64).sum::<u64>()
+                    })
+                    .sum();
+                wral::ConsumerLag {
+                    name,
+                    epoch,
+                    seqno,
+                    entries: durable.saturating_sub(after),
+                    bytes,
+                }
+            })
+            .collect();
+        consumers.sort_by_key(|c| (c.epoch == current, c.seqno));
+        consumers
+    }
+
+    /// Whether archived `journal` can be purged by a purge upto `seqno`,
+    /// provided the journals before it are purged as well.
+    pub fn is_purgeable(&self, journal: &Journal<S>, seqno: u64) -> bool {
+        !self.is_current_epoch(journal)
+            || journal.to_last_seqno().is_none_or(|s| s <= seqno)
+    }
+}
+
+impl<S> Writer<S> {
+    /// Move journals under `config.dir`, and the manifest, under `dir`,
+    /// renaming all journals as `name`. Journals already moved are
+    /// skipped, so that a failed relocation can be retried.
+    fn relocate(&mut self, dir: &ffi::OsStr, name: &str) -> Result<()>
+    where
+        S: state::State,
+    {
+        if name.is_empty() {
+            err_at!(Invalid, msg: "empty name for relocation")?
+        }
+        if dir == self.config.dir && name == self.config.name {
+            return Ok(());
+        }
+
+        // try creating the directory, if it does not exist.
+        fs::create_dir_all(dir).ok();
+
+        // journals striped across other directories are renamed in place.
+        let width = self.config.journal_width;
+        let primary = path::Path::new(&self.config.dir);
+        for journal in self.journals.iter_mut().chain(Some(&mut self.journal)) {
+            match journal.to_dir() {
+                jdir if path::Path::new(&jdir) == primary => {
+                    journal.relocate(dir, name, width)?
+                }
+                jdir => journal.relocate(&jdir, name, width)?,
+            }
+        }
+
+        self.manifest.set_name(name);
+        let config = Config { name: name.to_string(), ..self.config.clone() };
+        self.manifest.add_config(config.to_config_version());
+        self.manifest.save(dir)?;
+        Manifest::purge(&self.config.dir, &self.config.name)?;
+        if let Some(mut checkpoint) =
+            Checkpoint::load(&self.config.dir, &self.config.name)?
+        {
+            checkpoint.set_name(name);
+            checkpoint.save(dir)?;
+            Checkpoint::purge(&self.config.dir, &self.config.name)?;
+        }
+        util::sync_dir(path::Path::new(&self.config.dir))?;
+
+        debug!(
+            target: "wral",
+            "{:?}/{} relocated to {:?}/{}", self.config.dir, self.config.name, dir, name
+        );
+
+        self.config.dir = dir.to_os_string();
+        self.config.name = name.to_string();
+
+        Ok(())
+    }
+
+    /// Reload the batch index of cold journal `num` from its file, turning
+    /// it back into an archived journal. Archived journals are left as is.
+    fn thaw(&mut self, num: usize) -> Result<()>
+    where
+        S: state::State,
+    {
+        let off = self.journals.iter().position(|j| j.to_journal_number() == num);
+        let off = match off {
+            Some(off) if self.journals[off].is_cold() => off,
+            Some(_) => return Ok(()),
+            None => err_at!(NotFound, msg: "journal {} for {:?}", num, self.config.name)?,
+        };
+
+        let file_path = self.journals[off].to_file_path();
+        let (policy, max) = (self.config.state_policy, self.config.max_batch_entries);
+        let mut journal = match Journal::load(&self.config.name, &file_path, policy, max)
+        {
+            Some((journal, _, _)) => journal,
+            None => err_at!(Corrupted, msg: "failed to thaw {:?}", file_path)?,
+        };
+        journal.set_mirror(self.config.mirror_dir.as_deref());
+        debug!(target: "wral", "thawed {:?}, {} batches", file_path, journal.len_batches());
+        self.journals[off] = journal;
+
+        Ok(())
+    }
+}
+
+struct MainLoop<S> {
+    seqno: Arc<AtomicU64>,
+    w: Arc<RwLock<Writer<S>>>,
+    // requests received but deferred to subsequent batches.
+    backlog: VecDeque<Item>,
+    // whether the writer is falling behind.
+    backpressure: bool,
+    // flush ahead of a control request failed in the current batch, refer
+    // to MainLoop::flush_pending.
+    degraded: bool,
+}
+
+type Item = (Req, Reply);
+
+// Destination for the response of a request, caller waiting on a channel,
+// if any, or an acknowledgement slot for submitted requests.
+enum Reply {
+    Tx(Option<mpsc::Sender<Res>>),
+    Ack(Arc<receipt::Ack>),
+}
+
+impl Reply {
+    fn to_item(item: (Req, Option<mpsc::Sender<Res>>)) -> Item {
+        match item {
+            (Req::Submit { ack, req }, _) => (*req, Reply::Ack(ack)),
+            (req, tx) => (req, Reply::Tx(tx)),
+        }
+    }
+
+    fn send(self, res: Res) {
+        match self {
+            Reply::Tx(Some(tx)) => {
+                tx.send(res).ok();
+            }
+            Reply::Tx(None) => (),
+            Reply::Ack(ack) => ack.set(res),
+        }
+    }
+}
+
+impl<S> MainLoop<S>
+where
+    S: Clone + IntoCbor + FromCbor + state::State,
+{
+    fn run(mut self, rx: thread::Rx<Req, Res>) -> Result<u64> {
+        use std::sync::mpsc::{RecvTimeoutError, TryRecvError};
+
+        // once disconnected, exit after processing the backlog.
+        let mut disconnected = false;
+        'a: loop {
+            // block for the first request, unless there are deferred requests,
+            // writing heartbeat batches and syncing unsynced batches while
+            // idle.
+            if self.backlog.is_empty() {
+                if disconnected {
+                    break 'a;
+                }
+                let (heartbeat, sync) = {
+                    let rd = err_at!(Fatal, self.w.read())?;
+                    (rd.config.heartbeat, rd.to_sync_timeout())
+                };
+                let res = match (heartbeat, sync) {
+                    (Some(interval), Some(timeout)) => {
+                        rx.recv_timeout(interval.min(timeout))
+                    }
+                    (Some(timeout), None) | (None, Some(timeout)) => {
+                        rx.recv_timeout(timeout)
+                    }
+                    (None, None) => rx.recv().map_err(|_| RecvTimeoutError::Disconnected),
+                };
+                match res {
+                    Ok(req) => self.backlog.push_back(Reply::to_item(req)),
+                    // heartbeat syncs as well.
+                    Err(RecvTimeoutError::Timeout)
+                        if sync.is_some_and(|t| heartbeat.is_none_or(|h| t <= h)) =>
+                    {
+                        let mut w = err_at!(Fatal, self.w.write())?;
+                        self.sync(&mut w, true)?;
+                        continue 'a;
+                    }
+                    Err(RecvTimeoutError::Timeout) => {
+                        self.heartbeat()?;
+                        continue 'a;
+                    }
+                    Err(RecvTimeoutError::Disconnected) => break 'a,
+                }
+            }
+            // then get as many outstanding requests as possible from
+            // the channel.
+            while !disconnected {
+                match rx.try_recv() {
+                    Ok(req) => self.backlog.push_back(Reply::to_item(req)),
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => disconnected = true,
+                }
+            }
+            // and under adaptive commit, wait for more requests to arrive.
+            let window = err_at!(Fatal, self.w.read())?.cadence.window;
+            let deadline = time::Instant::now() + window;
+            while !disconnected && self.backlog.len() < wral::SYNC_BUFFER {
+                let timeout = deadline.saturating_duration_since(time::Instant::now());
+                if timeout.is_zero() {
+                    break;
+                }
+                match rx.recv_timeout(timeout) {
+                    Ok(req) => self.backlog.push_back(Reply::to_item(req)),
+                    Err(RecvTimeoutError::Timeout) => break,
+                    Err(RecvTimeoutError::Disconnected) => disconnected = true,
+                }
+            }
+            // and then start processing it in batch.
+            let w = Arc::clone(&self.w);
+            let mut w = err_at!(Fatal, w.write())?;
+            if self.process(w.borrow_mut())? {
+                Self::reject_pending(&mut self.backlog, Some(&rx));
+                break 'a;
+            }
+        }
+
+        // batches left unsynced once all handles are dropped.
+        let mut w = err_at!(Fatal, self.w.write())?;
+        self.sync(&mut w, true)?;
+
+        Ok(self.seqno.load(SeqCst).saturating_sub(1))
+    }
+
+    // Process requests from the backlog as a single batch, and flush them.
+    // Return true on shutdown, after which deferred requests are to be
+    // rejected.
+    fn process(&mut self, w: &mut Writer<S>) -> Result<bool> {
+        w.cadence.queue_depth = self.backlog.len();
+        if !self.backpressure && self.backlog.len() >= wral::SYNC_BUFFER {
+            self.backpressure = true;
+            let backlog = self.backlog.len();
+            w.events.emit(WalEvent::BackpressureOn { backlog });
+        }
+        let reqs = Self::drain_backlog(
+            &mut self.backlog,
+            w.config.client_batch_limit,
+            w.config.max_batch_requests,
+        );
+        w.cadence.on_drain(&reqs);
+        w.check_fence(self.seqno.load(SeqCst));
+        self.degraded = false;
+
+        // items before `flushed` are already flushed to disk.
+        let (mut items, mut flushed) = (vec![], 0);
+        // op sizes of requests adding ops, by their index in items.
+        let mut op_sizes = vec![];
+        let mut shutdown = None;
+        for req in reqs.into_iter() {
+            // over-quota requests fail without being assigned seqnos.
+            let over = match (&req.0.to_quota(), shutdown.is_none() && w.is_writable()) {
+                (Some((label, bytes)), true) => {
+                    w.quotas.charge(label, *bytes, time::Instant::now()).err()
+                }
+                _ => None,
+            };
+            let sizes = req.0.to_op_sizes();
+            match req {
+                (_, tx) if shutdown.is_some() => {
+                    items.push((Res::Fail(shutdown_error()), tx))
+                }
+                (Req::Shutdown, tx) => shutdown = Some(tx),
+                // thawing journals does not write to the log.
+                (req, tx) if w.sealed.is_some() && !matches!(req, Req::Thaw { .. }) => {
+                    items.push((Res::Fail(w.sealed.clone().unwrap()), tx))
+                }
+                // fenced writer shall only accept rebase.
+                (req, tx)
+                    if w.fenced.is_some()
+                        && !matches!(req, Req::Rebase { .. } | Req::Thaw { .. }) =>
+                {
+                    items.push((Res::Fail(w.fenced.clone().unwrap()), tx))
+                }
+                (_, tx) if over.is_some() => items.push((Res::Fail(over.unwrap()), tx)),
+                (Req::AddEntry { topic, tag, op, .. }, tx) => match self.next_seqno() {
+                    Ok(seqno) => {
+                        let entry = EntryBuilder::new(seqno)
+                            .topic(topic)
+                            .tag(tag)
+                            .payload(op)
+                            .build();
+                        let res = match w.journal.add_entries(vec![entry]) {
+                            Ok(()) => Res::Seqno(seqno),
+                            Err(err) => self.reject(err, seqno)?,
+                        };
+                        items.push((res, tx))
+                    }
+                    Err(err) => items.push((Res::Fail(err), tx)),
+                },
+                (Req::AddEntryAt { seqno, op, .. }, tx) => match self.set_seqno(seqno) {
+                    Ok(next) => {
+                        let entry = EntryBuilder::new(seqno).payload(op).build();
+                        let res = match w.journal.add_entries(vec![entry]) {
+                            Ok(()) => Res::Seqno(seqno),
+                            Err(err) => self.reject(err, next)?,
+                        };
+                        items.push((res, tx))
+                    }
+                    Err(err) => items.push((Res::Fail(err), tx)),
+                },
+                (Req::Ingest { entries, .. }, tx) => {
+                    match self.ingest(w.borrow_mut(), entries)? {
+                        Ok(res) => items.push((res, tx)),
+                        Err(err) => items.push((Res::Fail(err), tx)),
+                    }
+                }
+                (Req::AddEntries { ops, .. }, tx) => match self.next_seqnos(ops.len()) {
+                    Ok(seqnos) => {
+                        let entries = ops
+                            .into_iter()
+                            .zip(seqnos.clone())
+                            .map(|((topic, op), seqno)| {
+                                EntryBuilder::new(seqno).topic(topic).payload(op).build()
+                            })
+                            .collect();
+                        let res = match w.journal.add_entries(entries) {
+                            Ok(()) => Res::Seqnos(seqnos),
+                            Err(err) => self.reject(err, *seqnos.start())?,
+                        };
+                        items.push((res, tx))
+                    }
+                    Err(err) => items.push((Res::Fail(err), tx)),
+                },
+                (Req::Rebase { epoch }, tx) => {
+                    let res = match self.rebase(
+                        w.borrow_mut(),
+                        &mut items[flushed..],
+                        epoch,
+                    )? {
+                        Ok(seqno) => Res::Seqno(seqno),
+                        Err(err) => Res::Fail(err),
+                    };
+                    items.push((res, tx));
+                    flushed = items.len();
+                }
+                (Req::Relocate { dir, name }, tx) => {
+                    let res = match self.relocate(
+                        w.borrow_mut(),
+                        &mut items[flushed..],
+                        dir,
+                        name,
+                    )? {
+                        Ok(()) => Res::Ok,
+                        Err(err) => Res::Fail(err),
+                    };
+                    items.push((res, tx));
+                    flushed = items.len();
+                }
+                (Req::Purge { seqno, reason }, tx) => {
+                    let pending = &mut items[flushed..];
+                    let res = match self.purge(w.borrow_mut(), pending, seqno, &reason)? {
+                        Ok(tombstone) => Res::Purged(tombstone),
+                        Err(err) => Res::Fail(err),
+                    };
+                    items.push((res, tx));
+                    flushed = items.len();
+                }
+                (Req::Mask { range, unmask }, tx) => {
+                    let pending = &mut items[flushed..];
+                    let res = match self.mask(w.borrow_mut(), pending, range, unmask)? {
+                        Ok(()) => Res::Ok,
+                        Err(err) => Res::Fail(err),
+                    };
+                    items.push((res, tx));
+                    flushed = items.len();
+                }
+                (Req::Savepoint { name }, tx) => {
+                    let pending = &mut items[flushed..];
+                    let res = match self.savepoint(w.borrow_mut(), pending, &name)? {
+                        Ok(seqno) => Res::Seqno(seqno),
+                        Err(err) => Res::Fail(err),
+                    };
+                    items.push((res, tx));
+                    flushed = items.len();
+                }
+                (Req::Rollback { name }, tx) => {
+                    let pending = &mut items[flushed..];
+                    let res = match self.rollback_to_savepoint(
+                        w.borrow_mut(),
+                        pending,
+                        &name,
+                    )? {
+                        Ok(seqno) => Res::Seqno(seqno),
+                        Err(err) => Res::Fail(err),
+                    };
+                    items.push((res, tx));
+                    flushed = items.len();
+                }
+                (Req::Thaw { num }, tx) => {
+                    let res = match w.thaw(num) {
+                        Ok(()) => Res::Ok,
+                        Err(err) => Res::Fail(err),
+                    };
+                    items.push((res, tx))
+                }
+                (Req::Seal, tx) => {
+                    let res = match self.seal(w.borrow_mut(), &mut items[flushed..])? {
+                        Ok(seqno) => Res::Seqno(seqno),
+                        Err(err) => Res::Fail(err),
+                    };
+                    items.push((res, tx));
+                    flushed = items.len();
+                }
+                (Req::Sync, tx) => {
+                    let res =
+                        match self.sync_all(w.borrow_mut(), &mut items[flushed..])? {
+                            Ok(seqno) => Res::Seqno(seqno),
+                            Err(err) => Res::Fail(err),
+                        };
+                    items.push((res, tx));
+                    flushed = items.len();
+                }
+                // submitted requests are unwrapped on receipt.
+                (Req::Submit { .. }, tx) => {
+                    match err_at!(Invalid, msg: "nested submit request") {
+                        Ok(()) => unreachable!(),
+                        Err(err) => items.push((Res::Fail(err), tx)),
+                    }
+                }
+            }
+            if !sizes.is_empty() {
+                op_sizes.push((items.len() - 1, sizes));
+            }
+        }
+        w.frontiers.appended.store(self.seqno.load(SeqCst).saturating_sub(1), SeqCst);
+
+        let start = time::Instant::now();
+        let res = Self::flush(w.borrow_mut())?;
+        let elapsed = start.elapsed();
+        {
+            let mut health = err_at!(Fatal, w.health.lock())?;
+            health.queue_depth = w.cadence.queue_depth;
+            match &res {
+                // health is already degraded, refer to MainLoop::flush_pending.
+                Ok(()) if self.degraded => (),
+                Ok(()) if w.sealed.is_some() => health.state = wral::HealthState::Sealed,
+                Ok(()) if w.fenced.is_some() => health.state = wral::HealthState::Fenced,
+                Ok(()) => health.state = wral::HealthState::Running,
+                Err((err, _)) => {
+                    health.state = wral::HealthState::Degraded;
+                    health.last_error = Some(err.clone());
+                }
+            }
+        }
+        if let Err((err, seqno)) = res {
+            self.rollback(&mut items[flushed..], err, seqno);
+        }
+        for (i, sizes) in op_sizes.into_iter() {
+            w.cadence.on_ops(&items[i].0, sizes);
+        }
+        let n_entries: usize = items
+            .iter()
+            .map(|(res, _)| match res {
+                Res::Seqno(_) => 1,
+                Res::Seqnos(seqnos) => seqnos.clone().count(),
+                Res::Ingested { n, .. } => *n,
+                _ => 0,
+            })
+            .sum();
+        if n_entries > 0 {
+            let target = w.config.commit_latency;
+            w.cadence.on_sync(n_entries);
+            w.cadence.on_flush(elapsed, target);
+            w.checkpoint_batches += 1;
+        }
+        // rolled back seqnos are handed out again.
+        let seqno = self.seqno.load(SeqCst).saturating_sub(1);
+        w.frontiers.appended.store(seqno, SeqCst);
+        w.frontiers.flushed.fetch_min(seqno, SeqCst);
+
+        // submitted requests are acknowledged before the watermark
+        // moves, which wakes up all of them with a single notification.
+        // Under ordered acks, they are acknowledged in order with the rest
+        // and woken up once more.
+        let mut replies = vec![];
+        for (res, reply) in items.into_iter() {
+            match reply {
+                Reply::Ack(ack) if !w.config.ordered_acks => ack.set(res),
+                reply => replies.push((res, reply)),
+            }
+        }
+        self.sync(w.borrow_mut(), shutdown.is_some())?;
+
+        // callers can stop waiting on a deadline, refer to
+        // Wal::add_op_deadline, their responses are dropped.
+        let acks = replies.iter().any(|(_, reply)| matches!(reply, Reply::Ack(_)));
+        for (res, reply) in replies.into_iter() {
+            reply.send(res)
+        }
+        if acks {
+            w.durable.notify()?;
+        }
+
+        w.checkpoint(shutdown.is_some());
+
+        if let Some(tx) = shutdown {
+            let seqno = w.durable.to_seqno()?;
+            debug!(target: "wral", "{:?}/{} shutdown at {}", w.config.dir, w.config.name, seqno);
+            match tx {
+                Reply::Tx(Some(tx)) => err_at!(IPCFail, tx.send(Res::Seqno(seqno)))?,
+                reply => reply.send(Res::Seqno(seqno)),
+            }
+            w.durable.notify()?;
+            return Ok(true);
+        }
+
+        // sealed writer's active journal is archived.
+        if w.sealed.is_none()
+            && w.journal.file_size()? > w.config.journal_limit
+            && Self::check_rotate(w.borrow_mut())?.is_ok()
+        {
+            Self::rotate(w.borrow_mut(), RotateCause::SizeLimit)?;
+        }
+
+        if self.backpressure && self.backlog.is_empty() {
+            self.backpressure = false;
+            w.events.emit(WalEvent::BackpressureOff);
+        }
+
+        Ok(false)
+    }
+
+    // Reject deferred requests and requests still in the channel, after
+    // shutdown.
+    fn reject_pending(backlog: &mut VecDeque<Item>, rx: Option<&thread::Rx<Req, Res>>) {
+        let pending = rx.into_iter().flat_map(|rx| rx.try_iter().map(Reply::to_item));
+        let items: Vec<Item> = backlog.drain(..).chain(pending).collect();
+        for (_, reply) in items.into_iter() {
+            reply.send(Res::Fail(shutdown_error()));
+        }
+    }
+
+    // Flush failed, entries are discarded by the journal, fail their requests
+    // and reuse their seqnos. Entries upto `flushed` seqno made it to disk
+    // before the failure, requests partially flushed are failed as well.
+    fn rollback(&self, items: &mut [(Res, Reply)], err: Error, flushed: Option<u64>) {
+        let mut seqno = None;
+        for (res, _) in items.iter_mut() {
+            let (first, last) = match res {
+                Res::Seqno(val) => (*val, *val),
+                Res::Seqnos(seqnos) => (*seqnos.start(), *seqnos.end()),
+                Res::Ingested { seqnos: Some(seqnos), .. } => {
+                    (*seqnos.start(), *seqnos.end())
+                }
+                _ => continue,
+            };
+            match flushed {
+                Some(flushed) if last <= flushed => continue,
+                Some(flushed) if first <= flushed => seqno.get_or_insert(flushed + 1),
+                _ => seqno.get_or_insert(first),
+            };
+            *res = Res::Fail(err.clone());
+        }
+        if let Some(seqno) = seqno {
+            error!(target: "wral", "flush failed, rollback to seqno {}: {}", seqno, err);
+            self.seqno.store(seqno, SeqCst);
+        }
+    }
+
+    // Pick requests from backlog for the next batch, limiting the number of
+    // ops from each client to `limit`, and the number of requests to
+    // `max_requests`. Once a control request is deferred, every request
+    // after it is also deferred, to preserve ordering.
+    //
+    // Client counts only grow, once a client's request is deferred, all
+    // its later requests are deferred as well. Along with responding to
+    // requests in batch order, this assigns seqnos to each client's
+    // requests in the order they were received, and under
+    // Config::set_ordered_acks, acknowledges them in that order.
+    fn drain_backlog(
+        backlog: &mut VecDeque<Item>,
+        limit: usize,
+        max_requests: usize,
+    ) -> Vec<Item> {
+        let mut counts: HashMap<u64, usize> = HashMap::default();
+        let mut blocked = false;
+
+        let mut reqs = vec![];
+        let mut deferred = VecDeque::default();
+        for item in backlog.drain(..) {
+            let ok = match &item.0 {
+                _ if blocked => false,
+                // rest of the backlog is deferred, once the batch is full.
+                _ if reqs.len() >= max_requests.max(1) => {
+                    blocked = true;
+                    false
+                }
+                Req::AddEntry { client, .. } | Req::AddEntryAt { client, .. } => {
+                    let n = counts.entry(*client).or_insert(0);
+                    *n += 1;
+                    *n <= limit
+                }
+                // vectored request is never split, and is admitted if it
+                // is the first from the client.
+                Req::AddEntries { client, ops, .. } => {
+                    let n = counts.entry(*client).or_insert(0);
+                    let ok = *n == 0 || *n + ops.len() <= limit;
+                    *n += ops.len();
+                    ok
+                }
+                Req::Ingest { client, entries, .. } => {
+                    let n = counts.entry(*client).or_insert(0);
+                    let ok = *n == 0 || *n + entries.len() <= limit;
+                    *n += entries.len();
+                    ok
+                }
+                _ => deferred.is_empty(),
+            };
+            match ok {
+                true => reqs.push(item),
+                false => {
+                    blocked = blocked
+                        || !matches!(
+                            &item.0,
+                            Req::AddEntry { .. }
+                                | Req::AddEntryAt { .. }
+                                | Req::AddEntries { .. }
+                                | Req::Ingest { .. }
+                        );
+                    deferred.push_back(item);
+                }
+            }
+        }
+        *backlog = deferred;
+
+        reqs
+    }
+
+    // Entries rejected by state are failed and their seqnos, which are the
+    // latest ones handed out, are reused. Any other error is fatal.
+    fn reject(&self, err: Error, seqno: u64) -> Result<Res> {
+        match err {
+            Error::Rejected(_, _) => {
+                self.seqno.store(seqno, SeqCst);
+                Ok(Res::Fail(err))
+            }
+            err => Err(err),
+        }
+    }
+
+    // seqno u64::MAX is never handed out, so that the next seqno can
+    // always be represented.
+    fn next_seqno(&self) -> Result<u64> {
+        match self.seqno.load(SeqCst) {
+            u64::MAX => err_at!(Overflow, msg: "seqno exhausted, rebase to a new epoch"),
+            seqno => {
+                self.seqno.store(seqno + 1, SeqCst);
+                Ok(seqno)
+            }
+        }
+    }
+
+    // Flush pending entries, splitting them into multiple batches and
+    // rotating mid-way, so that journal files don't grow beyond the journal
+    // limit plus tolerance. Outer result is fatal to the writer, inner
+    // result carry the flush error along with the last seqno flushed by
+    // this call, if any.
+    fn flush(w: &mut Writer<S>) -> Result<result::Result<(), (Error, Option<u64>)>> {
+        let limit = w.config.journal_limit.saturating_add(w.config.journal_tolerance);
+        let mut flushed = None;
+        loop {
+            let n_batches = w.journal.len_batches();
+            let res = w.journal.flush_upto(limit);
+            if w.journal.len_batches() > n_batches {
+                w.mark_unsynced();
+                w.check_state_size();
+                w.high_water = w.high_water.max(w.journal.to_high_water().0);
+            }
+            for m in w.journal.take_mismatches().into_iter() {
+                let (first_seqno, last_seqno) = (m.first_seqno, m.last_seqno);
+                let event = WalEvent::ShadowMismatch {
+                    first_seqno,
+                    last_seqno,
+                    reason: m.reason,
+                };
+                w.events.emit(event);
+            }
+            match res {
+                Ok(true) => {
+                    if let Some(seqno) = w.journal.to_last_seqno() {
+                        w.frontiers.flushed.store(seqno, SeqCst);
+                    }
+                    break Ok(Ok(()));
+                }
+                Ok(false) => {
+                    if let Err(err) = Self::check_rotate(w)? {
+                        w.journal.discard()?;
+                        let flushed = w.journal.to_last_seqno().or(flushed);
+                        break Ok(Err((err, flushed)));
+                    }
+                    Self::rotate(w, RotateCause::SizeLimit)?;
+                    flushed = w.journals.last().and_then(Journal::to_last_seqno);
+                    if let Some(seqno) = flushed {
+                        w.frontiers.flushed.store(seqno, SeqCst);
+                    }
+                }
+                Err(err) => break Ok(Err((err, flushed))),
+            }
+        }
+    }
+
+    // Write a batch without entries, carrying the state and the current
+    // metadata. Failing to write is not fatal to the writer, and is
+    // reported via health, same as a failed flush.
+    fn heartbeat(&self) -> Result<()> {
+        let mut w = err_at!(Fatal, self.w.write())?;
+        self.sync(&mut w, true)?;
+        if !w.is_writable() {
+            return Ok(());
+        }
+        let seqno = self.seqno.load(SeqCst).saturating_sub(1);
+        let metadata = w.metadata.clone();
+        let res = w.journal.add_metadata(metadata, seqno);
+        {
+            let mut health = err_at!(Fatal, w.health.lock())?;
+            match res {
+                Ok(()) => health.state = wral::HealthState::Running,
+                Err(err) => {
+                    let (dir, name) = (&w.config.dir, &w.config.name);
+                    warn!(target: "wral", "{:?}/{} heartbeat failed {}", dir, name, err);
+                    health.state = wral::HealthState::Degraded;
+                    health.last_error = Some(err);
+                }
+            }
+        }
+
+        w.checkpoint(false);
+
+        if w.journal.file_size()? > w.config.journal_limit
+            && Self::check_rotate(w.borrow_mut())?.is_ok()
+        {
+            Self::rotate(w.borrow_mut(), RotateCause::SizeLimit)?;
+        }
+        Ok(())
+    }
+
+    // Pre-assigned `seqno` shall be greater than seqnos handed out so far,
+    // return the next seqno prior to this call.
+    fn set_seqno(&self, seqno: u64) -> Result<u64> {
+        match self.seqno.load(SeqCst) {
+            _ if seqno == u64::MAX => {
+                err_at!(Overflow, msg: "seqno exhausted, rebase to a new epoch")
+            }
+            next if seqno < next => {
+                err_at!(Invalid, msg: "seqno {} <= last seqno {}", seqno, next - 1)
+            }
+            next => {
+                self.seqno.store(seqno + 1, SeqCst);
+                Ok(next)
+            }
+        }
+    }
+
+    // Skip entries whose seqno is already handed out, and add the rest as
+    // a single unit. Outer result is fatal to the writer, inner result is
+    // returned to the caller.
+    fn ingest(
+        &self,
+        w: &mut Writer<S>,
+        entries: Vec<entry::Entry>,
+    ) -> Result<Result<Res>> {
+        let next = self.seqno.load(SeqCst);
+        let mut prev = None;
+        for seqno in entries.iter().map(entry::Entry::to_seqno) {
+            match prev {
+                Some(prev) if seqno <= prev => {
+                    return Ok(
+                        err_at!(Invalid, msg: "seqno {} <= {} in batch", seqno, prev),
+                    );
+                }
+                _ if seqno == u64::MAX => {
+                    return Ok(err_at!(Overflow, msg: "seqno exhausted"));
+                }
+                _ => prev = Some(seqno),
+            }
+        }
+
+        let entries: Vec<entry::Entry> =
+            entries.into_iter().filter(|e| e.to_seqno() >= next).collect();
+        let seqnos = match (entries.first(), entries.last()) {
+            (Some(first), Some(last)) => first.to_seqno()..=last.to_seqno(),
+            _ => return Ok(Ok(Res::Ingested { seqnos: None, n: 0 })),
+        };
+
+        let n = entries.len();
+        self.seqno.store(seqnos.end() + 1, SeqCst);
+        let res = match w.journal.add_entries(entries) {
+            Ok(()) => Res::Ingested { seqnos: Some(seqnos), n },
+            Err(err) => self.reject(err, next)?,
+        };
+        Ok(Ok(res))
+    }
+
+    // Allocate `n` contiguous seqnos, `n` must be non-zero.
+    fn next_seqnos(&self, n: usize) -> Result<ops::RangeInclusive<u64>> {
+        let seqno = self.seqno.load(SeqCst);
+        match (n as u64).checked_add(seqno) {
+            Some(next) if n > 0 => {
+                self.seqno.store(next, SeqCst);
+                Ok(seqno..=(next - 1))
+            }
+            _ => err_at!(Overflow, msg: "seqno exhausted, rebase to a new epoch"),
+        }
+    }
+
+    // Flush entries pending ahead of a control request, whose requests are
+    // `items`. Failing to flush is not fatal to the writer, same as a failed
+    // batch, health is degraded and `items` not yet flushed are rolled back.
+    // Inner result is returned to the caller of the control request.
+    fn flush_pending(
+        &mut self,
+        w: &mut Writer<S>,
+        items: &mut [(Res, Reply)],
+    ) -> Result<Result<()>> {
+        match Self::flush(w)? {
+            Ok(()) => Ok(Ok(())),
+            Err((err, seqno)) => {
+                {
+                    let mut health = err_at!(Fatal, w.health.lock())?;
+                    health.state = wral::HealthState::Degraded;
+                    health.last_error = Some(err.clone());
+                }
+                self.rollback(items, err.clone(), seqno);
+                self.degraded = true;
+                Ok(Err(err))
+            }
+        }
+    }
+
+    // Outer result is fatal to the writer, inner result is returned to
+    // the caller. Entries pending in `items` are flushed ahead of rebase.
+    fn rebase(
+        &mut self,
+        w: &mut Writer<S>,
+        items: &mut [(Res, Reply)],
+        epoch: u64,
+    ) -> Result<Result<u64>> {
+        let current = w.to_epoch();
+        if epoch <= current {
+            return Ok(err_at!(Invalid, msg: "rebase epoch {} <= {}", epoch, current));
+        }
+
+        if let Err(err) = self.flush_pending(w, items)? {
+            return Ok(Err(err));
+        }
+        if w.journal.len_batches() > 0 {
+            if let Err(err) = Self::check_rotate(w)? {
+                return Ok(Err(err));
+            }
+            Self::rotate(w, RotateCause::Forced)?;
+        }
+
+        // fenced writer's seqno is behind the seqnos retained.
+        let tail = w.to_durable_tail().unwrap_or(0);
+        let seqno = self.seqno.swap(1, SeqCst).saturating_sub(1).max(tail);
+        let num = w.journal.to_journal_number();
+        w.manifest.add_epoch(manifest::Epoch::new(epoch, num, seqno))?;
+        w.manifest.save(&w.config.dir)?;
+        w.frontiers.reset(0, 0);
+        w.durable.reset(epoch, 0)?;
+        // seqnos retained so far are from older epochs, lift the fence.
+        w.fenced = None;
+
+        debug!(
+            target: "wral",
+            "{:?}/{} rebased to epoch {} at journal {}, after seqno {}",
+            w.config.dir, w.config.name, epoch, num, seqno
+        );
+
+        Ok(Ok(seqno))
+    }
+
+    // Relocate journals and manifest, refer to Writer::relocate. Entries
+    // pending in `items` are flushed ahead of relocate, so that they land
+    // in the journal being relocated. Outer result is fatal to the writer,
+    // inner result is returned to the caller.
+    fn relocate(
+        &mut self,
+        w: &mut Writer<S>,
+        items: &mut [(Res, Reply)],
+        dir: Option<ffi::OsString>,
+        name: Option<String>,
+    ) -> Result<Result<()>> {
+        if let Err(err) = self.flush_pending(w, items)? {
+            return Ok(Err(err));
+        }
+        let dir = dir.unwrap_or_else(|| w.config.dir.clone());
+        let name = name.unwrap_or_else(|| w.config.name.clone());
+        Ok(w.relocate(&dir, &name))
+    }
+
+    // Remove archived journals, oldest first, whose entries are all from
+    // older epochs or upto `seqno`. Purge is recorded before removing the
+    // files. Outer result is fatal to the writer, inner result is returned
+    // to the caller. Entries pending in `items` are flushed ahead of purge.
+    fn purge(
+        &mut self,
+        w: &mut Writer<S>,
+        items: &mut [(Res, Reply)],
+        seqno: u64,
+        reason: &str,
+    ) -> Result<Result<Option<Tombstone>>> {
+        if let Err(err) = self.flush_pending(w, items)? {
+            return Ok(Err(err));
+        }
+
+        let n = w.journals.iter().take_while(|j| w.is_purgeable(j, seqno)).count();
+        let last = match n {
+            0 => return Ok(Ok(None)),
+            n => &w.journals[n - 1],
+        };
+
+        let tombstone = {
+            let epoch = w.manifest.to_epoch_of(last.to_journal_number());
+            let seqno = last.to_last_seqno().unwrap_or(0);
+            Tombstone::new(epoch, seqno, reason, w.config.clock.to_nanos())
+        };
+        let mut metadata = w.metadata.clone();
+        metadata.tombstones.push(tombstone.clone());
+
+        let seqno = self.seqno.load(SeqCst).saturating_sub(1);
+        if let Err(err) = w.journal.add_metadata(metadata.clone(), seqno) {
+            return Ok(Err(err));
+        }
+        w.metadata = metadata;
+
+        // journals are dropped only after their files are purged, a journal
+        // that fails to purge, and those after it, are retained.
+        let mut res = Ok(());
+        let mut m = 0;
+        for journal in w.journals.iter().take(n) {
+            match journal.purge() {
+                Ok(()) => m += 1,
+                Err(err) => {
+                    res = Err(err);
+                    break;
+                }
+            }
+        }
+        let journals: Vec<Journal<S>> = w.journals.drain(..m).collect();
+        for journal in journals.into_iter() {
+            let (num, file) = (journal.to_journal_number(), journal.to_file_path());
+            w.manifest.remove_span(num);
+            w.pinned.remove(&num);
+            w.events.emit(WalEvent::JournalPurged { num, file });
+        }
+        w.manifest.save(&w.config.dir)?;
+        util::sync_dir(path::Path::new(&w.config.dir))?;
+        let purged = Writer::<S>::find_purged(&w.manifest, &w.metadata);
+        w.frontiers.purged.store(purged, SeqCst);
+
+        debug!(
+            target: "wral",
+            "{:?}/{} purged {}/{} journals, {}", w.config.dir, w.config.name, m, n, tombstone
+        );
+
+        match res {
+            Ok(()) => Ok(Ok(Some(tombstone))),
+            Err(err) => Ok(Err(err)),
+        }
+    }
+
+    // Mask, or unmask, seqno range in the current epoch. Outer result is
+    // fatal to the writer, inner result is returned to the caller. Entries
+    // pending in `items` are flushed ahead of mask.
+    fn mask(
+        &mut self,
+        w: &mut Writer<S>,
+        items: &mut [(Res, Reply)],
+        range: ops::RangeInclusive<u64>,
+        unmask: bool,
+    ) -> Result<Result<()>> {
+        if let Err(err) = self.flush_pending(w, items)? {
+            return Ok(Err(err));
+        }
+
+        let mut metadata = w.metadata.clone();
+        match unmask {
+            true => metadata.unmask(w.to_epoch(), range.clone()),
+            false => metadata.mask(w.to_epoch(), range.clone()),
+        }
+
+        let seqno = self.seqno.load(SeqCst).saturating_sub(1);
+        if let Err(err) = w.journal.add_metadata(metadata.clone(), seqno) {
+            return Ok(Err(err));
+        }
+        w.metadata = metadata;
+
+        let (dir, name) = (&w.config.dir, &w.config.name);
+        debug!(target: "wral", "{:?}/{} mask {:?} unmask:{}", dir, name, range, unmask);
+
+        Ok(Ok(()))
+    }
+
+    // Set savepoint `name` at the last seqno handed out and save the
+    // manifest. Outer result is fatal to the writer, inner result is
+    // returned to the caller. Entries pending in `items` are flushed ahead
+    // of savepoint.
+    fn savepoint(
+        &mut self,
+        w: &mut Writer<S>,
+        items: &mut [(Res, Reply)],
+        name: &str,
+    ) -> Result<Result<u64>> {
+        if let Err(err) = self.flush_pending(w, items)? {
+            return Ok(Err(err));
+        }
+
+        let seqno = self.seqno.load(SeqCst).saturating_sub(1);
+        let timestamp = w.config.clock.to_nanos();
+        let savepoint = manifest::Savepoint::new(name, w.to_epoch(), seqno, timestamp);
+        let mut manifest = w.manifest.clone();
+        manifest.set_savepoint(savepoint);
+        if let Err(err) = manifest.save(&w.config.dir) {
+            return Ok(Err(err));
+        }
+        w.manifest = manifest;
+
+        let (dir, name) = (&w.config.dir, &w.config.name);
+        debug!(target: "wral", "{:?}/{} savepoint at seqno {}", dir, name, seqno);
+
+        Ok(Ok(seqno))
+    }
+
+    // Mask entries after savepoint `name`, and remove savepoints set after
+    // it, in one step. Outer result is fatal to the writer, inner result
+    // is returned to the caller. Entries pending in `items` are flushed
+    // ahead of rollback, and masked along with the rest.
+    fn rollback_to_savepoint(
+        &mut self,
+        w: &mut Writer<S>,
+        items: &mut [(Res, Reply)],
+        name: &str,
+    ) -> Result<Result<u64>> {
+        if let Err(err) = self.flush_pending(w, items)? {
+            return Ok(Err(err));
+        }
+
+        let epoch = w.to_epoch();
+        let seqno = match w.manifest.to_savepoint(name) {
+            Some(savepoint) if savepoint.to_epoch() == epoch => savepoint.to_seqno(),
+            Some(savepoint) => {
+                let n = savepoint.to_epoch();
+                let res = err_at!(
+                    Invalid, msg: "savepoint {} in epoch {} < {}", name, n, epoch
+                );
+                return Ok(res);
+            }
+            None => return Ok(err_at!(NotFound, msg: "savepoint {}", name)),
+        };
+
+        let last = self.seqno.load(SeqCst).saturating_sub(1);
+        if seqno < last {
+            if let Err(err) = self.mask(w, &mut [], (seqno + 1)..=last, false)? {
+                return Ok(Err(err));
+            }
+        }
+
+        let mut manifest = w.manifest.clone();
+        manifest.remove_savepoints_after(epoch, seqno);
+        if manifest != w.manifest {
+            if let Err(err) = manifest.save(&w.config.dir) {
+                return Ok(Err(err));
+            }
+            w.manifest = manifest;
+        }
+
+        let (dir, name) = (&w.config.dir, &w.config.name);
+        debug!(target: "wral", "{:?}/{} rolled back to seqno {}", dir, name, seqno);
+
+        Ok(Ok(seqno))
+    }
+
+    // Flush pending entries, write the terminal batch, and archive the
+    // active journal, which is retained as the active journal, same as
+    // read-only instances. Outer result is fatal to the writer, inner
+    // result is returned to the caller. Entries pending in `items` are
+    // flushed ahead of seal.
+    fn seal(
+        &mut self,
+        w: &mut Writer<S>,
+        items: &mut [(Res, Reply)],
+    ) -> Result<Result<u64>> {
+        if let Err(err) = self.flush_pending(w, items)? {
+            return Ok(Err(err));
+        }
+
+        let seqno = self.seqno.load(SeqCst).saturating_sub(1);
+        let metadata = w.metadata.clone();
+        if let Err(err) = w.journal.add_metadata(metadata, seqno) {
+            return Ok(Err(err));
+        }
+        // metadata batch is synced, along with batches before it.
+        w.unsynced = None;
+
+        let journal = {
+            let (name, dir) = (&w.config.name, &w.config.dir);
+            let (num, width) = (w.journal.to_journal_number(), w.config.journal_width);
+            let empty =
+                Journal::empty_archive(name, dir, num, width, w.journal.to_state());
+            mem::replace(&mut w.journal, empty)
+        };
+        let (journal, _, _) = journal.into_archive()?;
+        if let Some(span) = journal.to_span() {
+            w.manifest.add_span(span);
+        }
+        w.journal = journal;
+        w.manifest.set_sealed();
+        w.manifest.save(&w.config.dir)?;
+        util::sync_dir(path::Path::new(&w.config.dir))?;
+        w.check_sealed();
+
+        let (dir, name) = (&w.config.dir, &w.config.name);
+        debug!(target: "wral", "{:?}/{} sealed at seqno {}", dir, name, seqno);
+
+        Ok(Ok(seqno))
+    }
+
+    // Flush entries pending in `items` and fsync, irrespective of fsync
+    // interval. Outer result is fatal to the writer, inner result is
+    // returned to the caller.
+    fn sync_all(
+        &mut self,
+        w: &mut Writer<S>,
+        items: &mut [(Res, Reply)],
+    ) -> Result<Result<u64>> {
+        if let Err(err) = self.flush_pending(w, items)? {
+            return Ok(Err(err));
+        }
+        self.sync(w, true)?;
+        Ok(Ok(w.durable.to_seqno()?))
+    }
+
+    // Fsync batches written since the last fsync, if `force` is true or
+    // fsync interval is due, and move the durable watermark upto the last
+    // seqno handed out. Watermark stays put while batches are unsynced.
+    // Failing to fsync is fatal to the writer, batches since the last
+    // fsync may be lost while their requests are already acknowledged.
+    fn sync(&self, w: &mut Writer<S>, force: bool) -> Result<()> {
+        if let Some((n, since)) = w.unsynced {
+            let due = force
+                || match w.config.fsync_interval {
+                    Some(FsyncInterval::Elapsed(interval)) => since.elapsed() >= interval,
+                    Some(FsyncInterval::Batches(batches)) => n >= batches,
+                    None => true,
+                };
+            if !due {
+                return w.durable.notify();
+            }
+            w.journal.sync()?;
+            w.unsynced = None;
+        }
+        w.durable.set(self.seqno.load(SeqCst).saturating_sub(1))
+    }
+}
+
+/// Writer run on the caller's thread, refer to
+/// [Spawner::inline][crate::Spawner::inline].
+#[cfg(feature = "inline-writer")]
+pub trait Inline: Send {
+    /// Process `req`, and respond on `tx`, as a batch of its own. Return
+    /// true once the writer is shutdown.
+    fn process(&mut self, req: Req, tx: Option<mpsc::Sender<Res>>) -> Result<bool>;
+}
+
+#[cfg(feature = "inline-writer")]
+struct InlineLoop<S>
+where
+    S: state::State,
+{
+    l: MainLoop<S>,
+    health: Arc<Mutex<wral::Health>>,
+    // result of the writer, like the thread's, refer to Wal::close.
+    res: mpsc::SyncSender<Result<u64>>,
+}
+
+#[cfg(feature = "inline-writer")]
+impl<S> Inline for InlineLoop<S>
+where
+    S: state::State,
+{
+    fn process(&mut self, req: Req, tx: Option<mpsc::Sender<Res>>) -> Result<bool> {
+        self.l.backlog.push_back(Reply::to_item((req, tx)));
+        let res = self.drain();
+        match &res {
+            Ok(false) => (),
+            Ok(true) => {
+                let seqno = self.l.seqno.load(SeqCst).saturating_sub(1);
+                self.res.try_send(Ok(seqno)).ok();
+            }
+            Err(err) => {
+                error!(target: "wral", "writer exited: {}", err);
+                if let Ok(mut health) = self.health.lock() {
+                    health.state = wral::HealthState::Poisoned;
+                    health.last_error = Some(err.clone());
+                }
+                self.res.try_send(Err(err.clone())).ok();
+            }
+        }
+        res
+    }
+}
+
+#[cfg(feature = "inline-writer")]
+impl<S> InlineLoop<S>
+where
+    S: state::State,
+{
+    // Process the backlog, in as many batches as it takes, refer to
+    // MainLoop::run.
+    fn drain(&mut self) -> Result<bool> {
+        let w = Arc::clone(&self.l.w);
+        while !self.l.backlog.is_empty() {
+            let mut w = err_at!(Fatal, w.write())?;
+            if self.l.process(w.borrow_mut())? {
+                MainLoop::<S>::reject_pending(&mut self.l.backlog, None);
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+}
+
+#[cfg(feature = "inline-writer")]
+impl<S> Drop for InlineLoop<S>
+where
+    S: state::State,
+{
+    // batches left unsynced once all handles are dropped.
+    fn drop(&mut self) {
+        if let Ok(mut w) = self.l.w.write() {
+            if let Err(err) = self.l.sync(&mut w, true) {
+                error!(target: "wral", "writer sync on drop: {}", err);
+            }
+        }
+    }
+}
+
+fn shutdown_error() -> Error {
+    match err_at!(IPCFail, msg: "writer is shutting down") {
+        Ok(()) => unreachable!(),
+        Err(err) => err,
+    }
+}
+
+impl<S> MainLoop<S>
+where
+    S: state::State,
+{
+    // Check space for the next journal before rotating, refer to
+    // Writer::check_space. Outer result is fatal to the writer, inner
+    // result is NoSpace if rotation is refused.
+    fn check_rotate(w: &mut Writer<S>) -> Result<Result<()>> {
+        let num = w.journal.to_journal_number().saturating_add(1);
+        let dir = w.config.to_journal_dir(num)?;
+        Ok(w.check_space(&dir))
+    }
+
+    // Pending entries, if any, are carried over to the new journal.
+    fn rotate(w: &mut Writer<S>, cause: RotateCause) -> Result<()> {
+        w.journal.add_trailer()?;
+        let size = w.journal.file_size()?;
+        // batches are synced before the journal is archived.
+        let synced = match w.unsynced.take() {
+            Some(_) => {
+                w.journal.sync()?;
+                true
+            }
+            None => false,
+        };
+        // new journal
+        let journal = {
+            let num = w.journal.to_journal_number().saturating_add(1);
+            let state = w.journal.to_state();
+            let (name, dir) = (&w.config.name, w.config.to_journal_dir(num)?);
+            let options = w.config.to_storage_options();
+            let mut journal = Journal::start(name, &dir, num, &options, state)?;
+            journal.set_instance(w.manifest.as_instance());
+            journal
+        };
+        // replace with current journal
+        let (num, next) = (w.journal.to_journal_number(), journal.to_journal_number());
+        let file = journal.to_file_path();
+        let journal = mem::replace(&mut w.journal, journal);
+        let (journal, entries, _) = journal.into_archive()?;
+        for entry in entries.into_iter() {
+            w.journal.add_entry(entry)?;
+        }
+        if let Some(span) = journal.to_span() {
+            w.manifest.add_span(span);
+            w.manifest.save(&w.config.dir)?;
+        }
+        if let Some(seqno) = journal.to_last_seqno().filter(|_| synced) {
+            w.durable.set(seqno)?;
+        }
+        w.journals.push(journal);
+
+        w.events.emit(WalEvent::JournalCreated { num: next, file });
+        w.on_rotate(cause, size);
+        w.events.emit(WalEvent::JournalRotated { num, next, cause });
         Ok(())
     }
 }